@@ -1,1960 +1,12460 @@
 use anyhow::Result;
-use chrono::Local;
+use chrono::{Datelike, Local, Timelike};
+use clap::{Arg, ArgAction, Command};
 use crossterm::{
-    event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEventKind, MouseButton, MouseEventKind},
+    event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEventKind, KeyModifiers, MouseButton, MouseEventKind},
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
 use ratatui::{
     backend::CrosstermBackend,
+    buffer::Buffer,
     layout::{Alignment, Constraint, Direction, Layout, Rect},
     style::{Color, Modifier, Style, Stylize},
     symbols,
     text::{Line, Span},
-    widgets::{Axis, Block, Borders, Cell, Chart, Clear, Dataset, GraphType, Paragraph, Row, Table, TableState, Tabs},
+    widgets::{Axis, Bar, BarChart, BarGroup, Block, Borders, Cell, Chart, Clear, Dataset, GraphType, Paragraph, Row, Table, TableState, Tabs},
     Frame, Terminal,
 };
+use serde::{Deserialize, Serialize};
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
     fs::{self, File},
     io::{self, BufRead, BufReader, Write},
-    path::PathBuf,
-    sync::mpsc::{self, Receiver, Sender},
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Mutex, MutexGuard, OnceLock,
+    },
     thread,
     time::{Duration, Instant},
 };
 
-const CACHE_DURATION_SECS: u64 = 60;
-const HISTORICAL_CACHE_DURATION_SECS: u64 = 6 * 60 * 60; // 6 hours for historical data
-
-/// Message sent from background fetch thread to main thread
-#[derive(Debug)]
-struct FetchResult {
-    symbol: String,
-    price_data: Option<PriceData>,
+const CACHE_DURATION_SECS: u64 = 60; // Default quote lifetime; `[cache] quotes` overrides it
+const OPTION_MULTIPLIER: f64 = 100.0; // Shares per US equity option contract
+const OPTION_EXPIRY_WARN_DAYS: i64 = 7;
+const FUTURES_SUFFIX: &str = "=F"; // Yahoo continuous futures, e.g. GC=F, CL=F
+const METAL_PREFIXES: [&str; 4] = ["XAU", "XAG", "XPT", "XPD"]; // Gold, silver, platinum, palladium
+const TROY_OUNCE_GRAMS: f64 = 31.1034768;
+const HISTORICAL_CACHE_DURATION_SECS: u64 = 6 * 60 * 60; // 6 hours for historical data unless `[cache] history` is set
+const BACKFILL_STALE_SECS: u64 = 24 * 60 * 60; // Stored long history is topped up daily
+const BACKFILL_MAX_DELAY_SECS: u64 = 10 * 60; // Backoff ceiling after rate-limit responses
+const HISTORY_RANGE: &str = "3mo"; // Fetched range; extra lookback feeds chart indicators
+const CHART_DAYS: i64 = 30; // Days shown in the detail chart
+const BOLLINGER_PERIOD: usize = 20;
+const BOLLINGER_STDDEV: f64 = 2.0;
+const SWING_WINDOW: usize = 3; // Candles on each side a pivot must dominate
+const MAX_LEVELS: usize = 2; // Support/resistance lines drawn on each side of price
+const LIQUIDITY_DAYS: usize = 20; // Sessions averaged for the liquidity warning
+const DIVIDEND_HISTORY_RANGE: &str = "5y"; // Fetched for the detail view's dividend panel
+const INSTITUTIONAL_DAYS: usize = 5; // TWSE sessions of three-institutional-investor flows shown
+const MARGIN_DAYS: usize = 5; // TWSE sessions of margin / short balances shown
+const TWSE_RETRY_SECS: u64 = 1800; // How long a TWSE report found unpublished for a session isn't asked for again
+const T86_REPORT: &str = "fund/T86";
+const MI_MARGN_REPORT: &str = "marginTrading/MI_MARGN";
+
+/// User settings from `~/.config/stock-tui/config.toml`; every field is optional
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+struct Config {
+    /// Currencies the summary reports totals in, e.g. ["TWD", "USD", "JPY"]
+    base_currencies: Vec<String>,
+    /// Default benchmark per market section; holdings can override with `bench=`
+    benchmarks: BenchmarkConfig,
+    /// How often live mode re-fetches a symbol: "live" (every tick) or e.g. "5m";
+    /// holdings can override with `refresh=`
+    default_refresh: String,
+    /// Quote requests in flight at once while refreshing a portfolio
+    fetch_concurrency: usize,
+    /// Quote and history sources and their failover order
+    providers: ProvidersConfig,
+    /// Shell commands run on app events
+    hooks: HooksConfig,
+    /// Computed table columns (`[[columns]]` entries)
+    columns: Vec<ColumnConfig>,
+    /// Shorthand computed columns: "name = expression", or a bare built-in field like "pct_of_cost"
+    computed: Vec<String>,
+    /// Change/Gain color intensity by magnitude
+    gradient: GradientConfig,
+    /// Color preset and per-role overrides
+    theme: ThemeConfig,
+    /// Leave rows hidden with `x` out of totals and subtotals too
+    exclude_hidden: bool,
+    /// Flag holdings larger than this fraction of their average daily volume (0 disables)
+    liquidity_fraction: f64,
+    /// Interface language: "en" or "zh-TW"
+    language: String,
+    /// Extra clocks and date/time formats
+    time: TimeConfig,
+    /// Exchange calendar additions and closed-market refresh behaviour
+    market: MarketConfig,
+    /// Risk-free rates and expected returns for Sharpe ratios and projections
+    assumptions: AssumptionsConfig,
+    /// Print a plain-text summary to the terminal after quitting
+    summary_on_exit: bool,
+    /// Where and how often `--daemon` writes its JSON snapshot
+    daemon: DaemonConfig,
+    /// Where the weekly summary goes and when `--daemon` sends it
+    report: ReportConfig,
+    /// Unix socket for JSON-RPC control while the TUI runs (empty disables)
+    control_socket: String,
+    /// Read-only HTTP view of the portfolio while the TUI runs
+    http: HttpConfig,
+    /// Per-stock shell commands bound to keys (`[[commands]]` entries)
+    commands: Vec<CommandConfig>,
+    /// RSS/Atom feeds aggregated on the news screen (`[[feeds]]` entries)
+    feeds: Vec<FeedConfig>,
+    /// US proxies whose overnight move estimates TW holdings' opening gap (`[[pairs]]` entries)
+    pairs: Vec<PairConfig>,
+    /// Background download of long daily history into the local store
+    backfill: BackfillConfig,
+    /// Footer items, by name from `FOOTER_ITEMS` (empty: the default set)
+    footer: Vec<String>,
+    /// Seconds between live-mode refresh ticks
+    refresh_interval: u64,
+    /// How long quotes and history are reused before fetching again
+    cache: CacheConfig,
+    /// Column the tables are sorted by at startup: a built-in name or a custom column's name
+    default_sort: String,
+    /// Sort `default_sort` ascending instead of descending
+    sort_ascending: bool,
+    /// Per-table startup sorts overriding `default_sort`
+    sort: SortConfig,
+    /// Portfolio opened at startup when `--portfolio` isn't given ("ALL" for the combined view)
+    default_portfolio: String,
+    /// Start with positions hidden, as if `H` had been pressed
+    hide_positions: bool,
+    /// Total returns (detail view, DCA simulator) reinvest dividends rather than hold them as cash
+    reinvest_dividends: bool,
 }
 
-/// Message indicating a batch fetch has completed
-#[derive(Debug)]
-enum FetchMessage {
-    /// Individual price result
-    Price(FetchResult),
-    /// Exchange rate result
-    ExchangeRate(f64),
-    /// All fetches in this batch are complete
-    BatchComplete,
+/// `[cache]` section: lifetimes as intervals like "60s", "5m" or "6h"
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+struct CacheConfig {
+    /// Quotes, in memory and in the cache database
+    quotes: String,
+    /// Daily history and fundamentals
+    history: String,
 }
 
-/// Tracks clickable UI regions for mouse interaction
-#[derive(Default, Clone)]
-struct ClickableRegions {
-    /// Portfolio tab areas: (rect, portfolio_index) - index 0 = "ALL" combined view
-    portfolio_tabs: Vec<(Rect, usize)>,
-    /// Taiwan stocks table area
-    tw_table: Rect,
-    /// US stocks table area
-    us_table: Rect,
-    /// Individual TW stock rows: (rect, row_index)
-    tw_rows: Vec<(Rect, usize)>,
-    /// Individual US stock rows: (rect, row_index)
-    us_rows: Vec<(Rect, usize)>,
-    /// Footer button regions: (rect, action_name)
-    footer_buttons: Vec<(Rect, &'static str)>,
+impl Default for CacheConfig {
+    fn default() -> Self {
+        CacheConfig {
+            quotes: format!("{}s", CACHE_DURATION_SECS),
+            history: format!("{}h", HISTORICAL_CACHE_DURATION_SECS / 3600),
+        }
+    }
 }
 
-#[derive(Clone, Debug)]
-struct Stock {
-    symbol: String,
-    display: String,
-    name: String,
-    quantity: f64,
-    cost_basis: f64,
-    price_data: Option<PriceData>,
-    historical: Option<HistoricalData>,
-    portfolio_name: String,
+impl CacheConfig {
+    /// (quote, history) lifetimes in seconds, or the setting that doesn't parse
+    fn ttls(&self) -> std::result::Result<(u64, u64), String> {
+        let parse = |value: &str| parse_refresh_interval(value).filter(|&secs| secs > 0).ok_or_else(|| value.to_string());
+        Ok((parse(&self.quotes)?, parse(&self.history)?))
+    }
 }
 
-#[derive(Clone, Debug)]
-struct PriceData {
-    price: f64,
-    #[allow(dead_code)]
-    change: f64, // Kept for potential future use (e.g., displaying absolute change)
-    change_percent: f64,
+/// `[sort]` section: a table's startup sort as a `default_sort` name, optionally followed by
+/// "asc" or "desc" (empty: `default_sort`)
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+struct SortConfig {
+    tw: String,
+    us: String,
+    crypto: String,
 }
 
-#[derive(Clone, Debug)]
-struct HistoricalData {
-    #[allow(dead_code)]
-    timestamps: Vec<i64>, // Kept for potential future use (e.g., date labels)
-    closes: Vec<f64>,
-    last_fetched: Instant,
+/// Cache lifetimes in seconds (quotes, history), fixed at startup from `[cache]`
+static CACHE_TTLS: OnceLock<(u64, u64)> = OnceLock::new();
+
+/// Seconds a fetched quote is reused
+fn quote_ttl() -> u64 {
+    CACHE_TTLS.get().map_or(CACHE_DURATION_SECS, |ttls| ttls.0)
 }
 
-#[derive(Clone, Debug)]
-struct Portfolio {
-    name: String,
-    file_path: PathBuf,
+/// Seconds fetched history and fundamentals are reused
+fn history_ttl() -> u64 {
+    CACHE_TTLS.get().map_or(HISTORICAL_CACHE_DURATION_SECS, |ttls| ttls.1)
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
-enum SortColumn {
-    Price,
-    Change,
-    Quantity,
-    Gain,
-    GainPercent,
+/// Tables of the cache database: the latest quote per symbol, candles per symbol, interval
+/// (`1d` daily, `5m` intraday) and time, dividends per symbol and day, when each (symbol,
+/// range) history was last fetched and from which candle it starts, so a range is read back
+/// with one indexed query, the span and previous close of each cached intraday session, and
+/// the TWSE daily reports already downloaded
+const CACHE_SCHEMA: &str = "
+    PRAGMA journal_mode = WAL;
+    CREATE TABLE IF NOT EXISTS quotes (
+        symbol TEXT PRIMARY KEY,
+        fetched_at INTEGER NOT NULL,
+        price REAL NOT NULL,
+        change REAL NOT NULL,
+        change_percent REAL NOT NULL,
+        day_high REAL,
+        day_low REAL,
+        year_high REAL,
+        year_low REAL,
+        prev_close REAL,
+        open REAL,
+        currency TEXT
+    );
+    CREATE TABLE IF NOT EXISTS candles (
+        symbol TEXT NOT NULL,
+        interval TEXT NOT NULL,
+        ts INTEGER NOT NULL,
+        open REAL NOT NULL,
+        high REAL NOT NULL,
+        low REAL NOT NULL,
+        close REAL NOT NULL,
+        volume INTEGER NOT NULL,
+        PRIMARY KEY (symbol, interval, ts)
+    ) WITHOUT ROWID;
+    CREATE TABLE IF NOT EXISTS dividends (
+        symbol TEXT NOT NULL,
+        ts INTEGER NOT NULL,
+        amount REAL NOT NULL,
+        PRIMARY KEY (symbol, ts)
+    ) WITHOUT ROWID;
+    CREATE TABLE IF NOT EXISTS history_fetches (
+        symbol TEXT NOT NULL,
+        range TEXT NOT NULL,
+        fetched_at INTEGER NOT NULL,
+        first_ts INTEGER NOT NULL,
+        PRIMARY KEY (symbol, range)
+    );
+    CREATE TABLE IF NOT EXISTS fetch_claims (
+        symbol TEXT PRIMARY KEY,
+        pid INTEGER NOT NULL,
+        claimed_at INTEGER NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS intraday_sessions (
+        symbol TEXT NOT NULL,
+        day TEXT NOT NULL,
+        prev_close REAL NOT NULL,
+        first_ts INTEGER NOT NULL,
+        last_ts INTEGER NOT NULL,
+        PRIMARY KEY (symbol, day)
+    );
+    CREATE TABLE IF NOT EXISTS twse_reports (
+        report TEXT NOT NULL,
+        day TEXT NOT NULL,
+        data TEXT NOT NULL,
+        PRIMARY KEY (report, day)
+    );
+";
+
+/// Connection to the cache database shared by every thread; None if it couldn't be opened,
+/// in which case quotes and history are only cached in memory
+static CACHE_DB: OnceLock<Option<Mutex<rusqlite::Connection>>> = OnceLock::new();
+
+fn open_cache_db(path: &Path) -> rusqlite::Result<rusqlite::Connection> {
+    if let Some(dir) = path.parent() {
+        let _ = fs::create_dir_all(dir);
+    }
+    let conn = rusqlite::Connection::open(path)?;
+    // The daemon and a TUI may write at the same time
+    conn.busy_timeout(Duration::from_secs(5))?;
+    // Candles from before intervals were stored are daily ones without the column; they
+    // are only a cache, so drop them with their fetch records and let them refetch
+    if conn.prepare("SELECT interval FROM candles LIMIT 0").is_err() {
+        conn.execute_batch("DROP TABLE IF EXISTS candles; DROP TABLE IF EXISTS history_fetches;")?;
+    }
+    conn.execute_batch(CACHE_SCHEMA)?;
+    Ok(conn)
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
-enum SortDirection {
-    Ascending,
-    Descending,
+/// Candles of `symbol` at `interval` stamped from `from` to `to` (Unix seconds), oldest first,
+/// without dividends
+fn query_candles(db: &rusqlite::Connection, symbol: &str, interval: &str, from: i64, to: i64) -> Option<HistoricalData> {
+    let mut historical = HistoricalData {
+        timestamps: Vec::new(),
+        opens: Vec::new(),
+        highs: Vec::new(),
+        lows: Vec::new(),
+        closes: Vec::new(),
+        volumes: Vec::new(),
+        dividends: Vec::new(),
+        last_fetched: Instant::now(),
+    };
+    let mut candles = db
+        .prepare("SELECT ts, open, high, low, close, volume FROM candles WHERE symbol = ?1 AND interval = ?2 AND ts BETWEEN ?3 AND ?4 ORDER BY ts")
+        .ok()?;
+    let rows = candles
+        .query_map(rusqlite::params![symbol, interval, from, to], |row| {
+            Ok((row.get::<_, i64>(0)?, row.get::<_, f64>(1)?, row.get::<_, f64>(2)?, row.get::<_, f64>(3)?, row.get::<_, f64>(4)?, row.get::<_, i64>(5)?))
+        })
+        .ok()?;
+    for (ts, open, high, low, close, volume) in rows.flatten() {
+        historical.timestamps.push(ts);
+        historical.opens.push(open);
+        historical.highs.push(high);
+        historical.lows.push(low);
+        historical.closes.push(close);
+        historical.volumes.push(volume.max(0) as u64);
+    }
+    (!historical.closes.is_empty()).then_some(historical)
 }
 
-#[derive(Debug)]
-enum InputMode {
-    Normal,
-    AddStock(AddStockState),
-    EditStock(EditStockState),
-    DeleteConfirm(String),
-    NewPortfolio(String),
-    DetailView(String), // Symbol being viewed in detail
+/// Replace the stored candles of `symbol` at `interval` over the span `historical` covers
+/// (so a session's candle stamped at different times by successive fetches isn't kept twice)
+fn store_candles(tx: &rusqlite::Transaction, symbol: &str, interval: &str, historical: &HistoricalData) -> rusqlite::Result<()> {
+    let (Some(&first_ts), Some(&last_ts)) = (historical.timestamps.first(), historical.timestamps.last()) else {
+        return Ok(());
+    };
+    tx.execute(
+        "DELETE FROM candles WHERE symbol = ?1 AND interval = ?2 AND ts BETWEEN ?3 AND ?4",
+        rusqlite::params![symbol, interval, first_ts, last_ts],
+    )?;
+    for i in 0..historical.timestamps.len() {
+        tx.execute(
+            "INSERT OR REPLACE INTO candles (symbol, interval, ts, open, high, low, close, volume) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            rusqlite::params![
+                symbol,
+                interval,
+                historical.timestamps[i],
+                historical.opens[i],
+                historical.highs[i],
+                historical.lows[i],
+                historical.closes[i],
+                historical.volumes[i].min(i64::MAX as u64) as i64,
+            ],
+        )?;
+    }
+    Ok(())
 }
 
-#[derive(Debug, Default)]
-struct AddStockState {
-    step: usize,
-    symbol: String,
-    display: String,
-    name: String,
-    quantity: String,
-    cost_basis: String,
+fn cache_db() -> Option<MutexGuard<'static, rusqlite::Connection>> {
+    CACHE_DB
+        .get_or_init(|| open_cache_db(&App::cache_db_path()).ok().map(Mutex::new))
+        .as_ref()?
+        .lock()
+        .ok()
 }
 
-#[derive(Debug, Default)]
-struct EditStockState {
-    symbol: String,
-    quantity: String,
-    cost_basis: String,
-    step: usize, // 0 = quantity, 1 = cost_basis
+/// How long a running copy's claim on fetching a quote holds the others off (`claim_quotes`)
+const FETCH_CLAIM_SECS: i64 = 15;
+
+/// The cached quote for `symbol` if it was fetched after `since` (Unix seconds), by this or
+/// another running copy
+fn query_quote(db: &rusqlite::Connection, symbol: &str, since: i64) -> Option<PriceData> {
+    db.query_row(
+        "SELECT price, change, change_percent, day_high, day_low, year_high, year_low, prev_close, open, currency
+         FROM quotes WHERE symbol = ?1 AND fetched_at > ?2",
+        rusqlite::params![symbol, since],
+        |row| {
+            Ok(PriceData {
+                price: row.get(0)?,
+                change: row.get(1)?,
+                change_percent: row.get(2)?,
+                day_high: row.get(3)?,
+                day_low: row.get(4)?,
+                year_high: row.get(5)?,
+                year_low: row.get(6)?,
+                prev_close: row.get(7)?,
+                open: row.get(8)?,
+                currency: row.get(9)?,
+            })
+        },
+    )
+    .ok()
 }
 
-struct App {
-    portfolios: Vec<Portfolio>,
-    current_portfolio_idx: usize,
-    view_combined: bool,
-    stocks: Vec<Stock>,
-    combined_stocks: Vec<Stock>,
-    tw_stocks: Vec<Stock>,
-    us_stocks: Vec<Stock>,
-    combined_tw_stocks: Vec<Stock>,
-    combined_us_stocks: Vec<Stock>,
-    usd_twd_rate: f64,
-    active_section: usize, // 0 = TW, 1 = US
-    table_state_tw: TableState,
-    table_state_us: TableState,
-    last_update: Instant,
-    input_mode: InputMode,
-    cache: HashMap<String, (PriceData, Instant)>,
-    historical_cache: HashMap<String, HistoricalData>,
-    sort_column: Option<SortColumn>,
-    sort_direction: SortDirection,
-    hide_positions: bool,   // Toggle with 'H' to hide cost/quantity/gain for privacy
-    live_mode: bool,        // Toggle with 'L' for auto-refresh every 5 seconds
-    show_gain_amount: bool, // Toggle with 'T' to switch between gain amount and percentage in titles
-    last_live_refresh: Instant,
-    clickable_regions: ClickableRegions,
-    // Async fetch infrastructure
-    fetch_receiver: Receiver<FetchMessage>,
-    fetch_sender: Sender<FetchMessage>,
-    is_fetching: bool, // True when background fetch is in progress
+/// Record a freshly fetched quote for later runs and other running copies
+fn store_cached_quote(symbol: &str, price_data: &PriceData) {
+    if let Some(db) = cache_db() {
+        let _ = db.execute(
+            "INSERT OR REPLACE INTO quotes
+             (symbol, fetched_at, price, change, change_percent, day_high, day_low, year_high, year_low, prev_close, open, currency)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+            rusqlite::params![
+                symbol,
+                Local::now().timestamp(),
+                price_data.price,
+                price_data.change,
+                price_data.change_percent,
+                price_data.day_high,
+                price_data.day_low,
+                price_data.year_high,
+                price_data.year_low,
+                price_data.prev_close,
+                price_data.open,
+                price_data.currency,
+            ],
+        );
+    }
 }
 
-impl App {
-    fn new() -> Result<Self> {
-        let (fetch_sender, fetch_receiver) = mpsc::channel();
-        let mut app = App {
-            portfolios: Vec::new(),
-            current_portfolio_idx: 0,
-            view_combined: false,
-            stocks: Vec::new(),
-            combined_stocks: Vec::new(),
-            tw_stocks: Vec::new(),
-            us_stocks: Vec::new(),
-            combined_tw_stocks: Vec::new(),
-            combined_us_stocks: Vec::new(),
-            usd_twd_rate: 32.0,
-            active_section: 0,
-            table_state_tw: TableState::default(),
-            table_state_us: TableState::default(),
-            last_update: Instant::now(),
-            input_mode: InputMode::Normal,
-            cache: HashMap::new(),
-            historical_cache: HashMap::new(),
-            sort_column: Some(SortColumn::Change), // Default sort by change %
-            sort_direction: SortDirection::Descending,
-            hide_positions: false,
-            live_mode: false,
-            show_gain_amount: false, // Start with percentage display
-            last_live_refresh: Instant::now(),
-            clickable_regions: ClickableRegions::default(),
-            fetch_receiver,
-            fetch_sender,
-            is_fetching: false,
-        };
-        app.load_portfolios()?;
-        app.refresh_data()?;
-        Ok(app)
+/// Split a batch of quotes with other running copies (two TUIs in tmux panes, a TUI and the
+/// daemon) through the cache database. Quotes anyone fetched within `fresh_secs(symbol)` go
+/// straight to `on_quote`; symbols another copy has claimed in the last `FETCH_CLAIM_SECS`
+/// are returned second, with their claim time, for `await_claimed_quotes`; the rest are
+/// claimed for this process and returned first. Without the database everything is ours.
+fn claim_quotes(
+    symbols: Vec<String>,
+    fresh_secs: impl Fn(&str) -> i64,
+    mut on_quote: impl FnMut(String, PriceData),
+) -> (Vec<String>, Vec<(String, i64)>) {
+    let Some(mut db) = cache_db() else {
+        return (symbols, Vec::new());
+    };
+    let pid = i64::from(std::process::id());
+    let now = Local::now().timestamp();
+    let (mut mine, mut theirs, mut fresh) = (Vec::new(), Vec::new(), Vec::new());
+    // An immediate transaction takes the write lock up front, so two copies can't both claim
+    let Ok(tx) = db.transaction_with_behavior(rusqlite::TransactionBehavior::Immediate) else {
+        return (symbols, Vec::new());
+    };
+    for symbol in symbols {
+        if let Some(data) = query_quote(&tx, &symbol, now - fresh_secs(&symbol)) {
+            fresh.push((symbol, data));
+            continue;
+        }
+        let claim: Option<(i64, i64)> = tx
+            .query_row(
+                "SELECT pid, claimed_at FROM fetch_claims WHERE symbol = ?1",
+                rusqlite::params![symbol],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .ok();
+        match claim {
+            Some((owner, claimed_at)) if owner != pid && claimed_at > now - FETCH_CLAIM_SECS => theirs.push((symbol, claimed_at)),
+            _ => {
+                let _ = tx.execute(
+                    "INSERT OR REPLACE INTO fetch_claims (symbol, pid, claimed_at) VALUES (?1, ?2, ?3)",
+                    rusqlite::params![symbol, pid, now],
+                );
+                mine.push(symbol);
+            }
+        }
+    }
+    let _ = tx.commit();
+    drop(db);
+    for (symbol, data) in fresh {
+        on_quote(symbol, data);
     }
+    (mine, theirs)
+}
 
-    fn is_demo_mode() -> bool {
-        std::env::var("DEMO").map(|v| v == "true" || v == "1").unwrap_or(false)
+/// Drop this process's fetch claims once its quotes are stored, so copies waiting on a
+/// symbol it failed to fetch stop waiting
+fn release_quote_claims() {
+    if let Some(db) = cache_db() {
+        let _ = db.execute("DELETE FROM fetch_claims WHERE pid = ?1", rusqlite::params![i64::from(std::process::id())]);
     }
+}
 
-    fn portfolios_dir() -> PathBuf {
-        dirs::home_dir()
-            .unwrap_or_default()
-            .join(".config/stock-tui/portfolios")
+/// Wait for the quotes other copies claimed, passing each to `on_quote` once it lands in the
+/// cache database; symbols whose claim is released or lapses without a quote are returned
+/// for fetching here
+fn await_claimed_quotes(mut claimed: Vec<(String, i64)>, mut on_quote: impl FnMut(String, PriceData)) -> Vec<String> {
+    let mut unanswered = Vec::new();
+    while !claimed.is_empty() {
+        let now = Local::now().timestamp();
+        let mut arrived = Vec::new();
+        match cache_db() {
+            Some(db) => claimed.retain(|(symbol, claimed_at)| {
+                if let Some(data) = query_quote(&db, symbol, claimed_at - 1) {
+                    arrived.push((symbol.clone(), data));
+                    return false;
+                }
+                let held = db
+                    .query_row(
+                        "SELECT claimed_at FROM fetch_claims WHERE symbol = ?1",
+                        rusqlite::params![symbol],
+                        |row| row.get::<_, i64>(0),
+                    )
+                    .is_ok_and(|at| at == *claimed_at);
+                if !held || *claimed_at <= now - FETCH_CLAIM_SECS {
+                    unanswered.push(symbol.clone());
+                    return false;
+                }
+                true
+            }),
+            None => unanswered.extend(claimed.drain(..).map(|(symbol, _)| symbol)),
+        }
+        for (symbol, data) in arrived {
+            on_quote(symbol, data);
+        }
+        if !claimed.is_empty() {
+            thread::sleep(Duration::from_millis(250));
+        }
     }
+    unanswered
+}
 
-    fn cache_dir() -> PathBuf {
-        PathBuf::from("/tmp/stock-tui")
+/// `[backfill]` section: while the TUI runs, every holding's daily history over `range` is
+/// fetched one symbol at a time into `~/.cache/stock-tui/history`, where long-range charts
+/// and the DCA simulator read it instead of waiting on the network
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+struct BackfillConfig {
+    enabled: bool,
+    /// Yahoo range to keep per symbol: "5y", "10y" or "max"
+    range: String,
+    /// Pause between requests; doubled (up to 10 minutes) after a rate-limit response
+    delay_secs: u64,
+}
+
+impl Default for BackfillConfig {
+    fn default() -> Self {
+        BackfillConfig { enabled: true, range: "10y".to_string(), delay_secs: 15 }
     }
+}
 
-    fn load_portfolios(&mut self) -> Result<()> {
-        // Demo mode: load from demo.conf in current directory or next to executable
-        if Self::is_demo_mode() {
-            let demo_path = std::env::current_exe()
-                .ok()
-                .and_then(|p| p.parent().map(|p| p.join("demo.conf")))
-                .filter(|p| p.exists())
-                .unwrap_or_else(|| PathBuf::from("demo.conf"));
+/// A TW holding and the US-traded proxy it follows overnight: its ADR (2330.TW and TSM)
+/// or an index future (0050.TW and NQ=F)
+#[derive(Debug, Clone, Deserialize)]
+struct PairConfig {
+    symbol: String,
+    proxy: String,
+    /// Sensitivity to the proxy's move (1.0 follows it one to one)
+    #[serde(default = "default_pair_beta")]
+    beta: f64,
+}
 
-            if demo_path.exists() {
-                self.portfolios = vec![Portfolio {
-                    name: "demo".to_string(),
-                    file_path: demo_path,
-                }];
-                return Ok(());
-            }
-        }
+fn default_pair_beta() -> f64 {
+    1.0
+}
 
-        let dir = Self::portfolios_dir();
-        fs::create_dir_all(&dir)?;
+#[derive(Debug, Clone, Deserialize)]
+struct FeedConfig {
+    name: String,
+    url: String,
+}
 
-        self.portfolios = fs::read_dir(&dir)?
-            .filter_map(|e| e.ok())
-            .filter(|e| e.path().extension().map(|ext| ext == "conf").unwrap_or(false))
-            .map(|e| {
-                let path = e.path();
-                let name = path.file_stem().unwrap().to_string_lossy().to_string();
-                Portfolio {
-                    name,
-                    file_path: path,
-                }
-            })
-            .collect();
+/// Keys the main table handles itself (`handle_input`); `[[commands]]` on them never run there
+const BUILTIN_KEYS: &str = " `/0123456789<=>[]ABCDEFGHIKLMNOPRSTUVWXabcdefghijklmnpqrstvxyz";
 
-        // Sort with 'main' first
-        self.portfolios.sort_by(|a, b| {
-            if a.name == "main" {
-                std::cmp::Ordering::Less
-            } else if b.name == "main" {
-                std::cmp::Ordering::Greater
-            } else {
-                a.name.cmp(&b.name)
-            }
-        });
+/// A key that runs a shell command for the selected stock, e.g. opening a chart site.
+/// `{symbol}`, `{code}` (symbol without the `.TW`/`.TWO` suffix), `{display}` and `{market}`
+/// ("TW" or "US") are replaced with shell-quoted values.
+#[derive(Debug, Clone, Deserialize)]
+struct CommandConfig {
+    key: char,
+    name: String,
+    command: String,
+}
 
-        if self.portfolios.is_empty() {
-            let main_path = dir.join("main.conf");
-            fs::write(&main_path, "# Stock Portfolio Configuration\n# Format: SYMBOL|Display Name|Description|Quantity|Cost Basis\n")?;
-            self.portfolios.push(Portfolio {
-                name: "main".to_string(),
-                file_path: main_path,
-            });
-        }
+impl CommandConfig {
+    fn expand(&self, stock: &Stock) -> String {
+        let quote = |s: &str| format!("'{}'", s.replace('\'', "'\\''"));
+        let code = stock.symbol.trim_end_matches(".TWO").trim_end_matches(".TW");
+        self.command
+            .replace("{symbol}", &quote(&stock.symbol))
+            .replace("{code}", &quote(code))
+            .replace("{display}", &quote(&stock.display))
+            .replace("{market}", if stock.is_tw() { "TW" } else { "US" })
+    }
+}
 
-        Ok(())
+/// `[http]` section: serves `/portfolio.json` and an HTML page at `/`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+struct HttpConfig {
+    /// Port to listen on (0 disables)
+    port: u16,
+    /// Address to bind; "0.0.0.0" makes the page reachable from the LAN
+    bind: String,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        HttpConfig { port: 0, bind: "127.0.0.1".to_string() }
     }
+}
 
-    fn load_stocks_from_file(path: &PathBuf) -> Result<Vec<Stock>> {
-        let mut stocks = Vec::new();
-        if !path.exists() {
-            return Ok(stocks);
+/// A market data source
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+enum Provider {
+    /// TWSE MIS real-time quotes; Taiwan listings (`.TW`) and TPEx OTC (`.TWO`) only
+    #[serde(rename = "twse")]
+    Twse,
+    #[serde(rename = "yahoo")]
+    Yahoo,
+    #[serde(rename = "alphavantage")]
+    AlphaVantage,
+}
+
+/// `[providers]` section: where quotes and daily history come from
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+struct ProvidersConfig {
+    /// Tried in turn until one answers
+    order: Vec<Provider>,
+    /// Alpha Vantage API key; the provider is skipped without one
+    alphavantage_key: String,
+}
+
+impl Default for ProvidersConfig {
+    fn default() -> Self {
+        ProvidersConfig {
+            order: vec![Provider::Twse, Provider::Yahoo, Provider::AlphaVantage],
+            alphavantage_key: String::new(),
         }
+    }
+}
 
-        let file = File::open(path)?;
-        let reader = BufReader::new(file);
+/// How long Alpha Vantage is left alone after it answers that the key's quota is spent
+const ALPHA_VANTAGE_BACKOFF_SECS: u64 = 3600;
 
-        for line in reader.lines() {
-            let line = line?;
-            let line = line.trim();
-            if line.is_empty() || line.starts_with('#') {
-                continue;
-            }
+/// When Alpha Vantage last reported the quota spent, in this process
+static ALPHA_VANTAGE_LIMITED: Mutex<Option<Instant>> = Mutex::new(None);
 
-            let parts: Vec<&str> = line.split('|').collect();
-            if parts.len() >= 3 {
-                stocks.push(Stock {
-                    symbol: parts[0].trim().to_string(),
-                    display: parts[1].trim().to_string(),
-                    name: parts[2].trim().to_string(),
-                    quantity: parts.get(3).and_then(|s| s.trim().parse().ok()).unwrap_or(0.0),
-                    cost_basis: parts.get(4).and_then(|s| s.trim().parse().ok()).unwrap_or(0.0),
-                    price_data: None,
-                    historical: None,
-                    portfolio_name: String::new(),
-                });
+impl ProvidersConfig {
+    /// Providers in failover order, leaving out ones that aren't configured
+    fn enabled(&self) -> impl Iterator<Item = Provider> + '_ {
+        self.order.iter().copied().filter(|p| *p != Provider::AlphaVantage || !self.alphavantage_key.is_empty())
+    }
+
+    /// Whether `provider` is worth asking for `symbol`: TWSE only lists Taiwan symbols, and
+    /// Alpha Vantage has no Taiwan listings, indices, futures or crypto and is skipped for
+    /// `ALPHA_VANTAGE_BACKOFF_SECS` after a quota reply (the free key allows 25 calls a day)
+    fn serves(provider: Provider, symbol: &str) -> bool {
+        match provider {
+            Provider::Twse => Self::twse_channel(symbol).is_some(),
+            Provider::Yahoo => true,
+            Provider::AlphaVantage => {
+                let limited = ALPHA_VANTAGE_LIMITED.lock().unwrap_or_else(|e| e.into_inner())
+                    .is_some_and(|at| at.elapsed().as_secs() < ALPHA_VANTAGE_BACKOFF_SECS);
+                !limited
+                    && !symbol.contains(".TW")
+                    && !symbol.starts_with('^')
+                    && !symbol.ends_with(FUTURES_SUFFIX)
+                    && !symbol.ends_with("-USD")
             }
         }
-
-        Ok(stocks)
     }
 
-    fn save_stocks(&self, portfolio_name: &str, stocks: &[Stock]) -> Result<()> {
-        let path = Self::portfolios_dir().join(format!("{}.conf", portfolio_name));
-        let mut file = File::create(&path)?;
+    /// Note an Alpha Vantage reply: a `Note` or `Information` message instead of data means
+    /// the key is over its per-minute or daily quota
+    fn check_alpha_vantage_quota(data: &serde_json::Value) {
+        if data.get("Note").or_else(|| data.get("Information")).is_some() {
+            *ALPHA_VANTAGE_LIMITED.lock().unwrap_or_else(|e| e.into_inner()) = Some(Instant::now());
+        }
+    }
 
-        writeln!(file, "# Stock Portfolio Configuration")?;
-        writeln!(file, "# Format: SYMBOL|Display Name|Description|Quantity|Cost Basis")?;
-        writeln!(file)?;
+    /// TWSE MIS channel of a Taiwan symbol: listed and OTC stocks use `tse_`/`otc_` channels
+    fn twse_channel(symbol: &str) -> Option<String> {
+        if let Some(code) = symbol.strip_suffix(".TWO") {
+            Some(format!("otc_{}.tw", code))
+        } else {
+            Some(format!("tse_{}.tw", symbol.strip_suffix(".TW")?))
+        }
+    }
 
-        let tw_stocks: Vec<_> = stocks.iter().filter(|s| s.symbol.contains(".TW")).collect();
-        let us_stocks: Vec<_> = stocks.iter().filter(|s| !s.symbol.contains(".TW")).collect();
+    /// One TWSE MIS request for the quotes of several channels, joined with `|`
+    fn twse_quote_url(channels: &[String]) -> String {
+        format!("https://mis.twse.com.tw/stock/api/getStockInfo.jsp?ex_ch={}&json=1&delay=0", channels.join("|"))
+    }
 
-        if !tw_stocks.is_empty() {
-            writeln!(file, "# Taiwan Stocks")?;
-            for s in tw_stocks {
-                writeln!(file, "{}|{}|{}|{}|{}", s.symbol, s.display, s.name, s.quantity, s.cost_basis)?;
-            }
-            writeln!(file)?;
+    /// Alpha Vantage request for a quote: FX pairs like `USDTWD=X` use the exchange rate call
+    fn alpha_vantage_quote_url(&self, symbol: &str) -> String {
+        match symbol.strip_suffix("=X").filter(|pair| pair.len() == 6) {
+            Some(pair) => format!(
+                "https://www.alphavantage.co/query?function=CURRENCY_EXCHANGE_RATE&from_currency={}&to_currency={}&apikey={}",
+                &pair[..3], &pair[3..], self.alphavantage_key
+            ),
+            None => format!(
+                "https://www.alphavantage.co/query?function=GLOBAL_QUOTE&symbol={}&apikey={}",
+                symbol, self.alphavantage_key
+            ),
         }
+    }
 
-        if !us_stocks.is_empty() {
-            writeln!(file, "# US Stocks")?;
-            for s in us_stocks {
-                writeln!(file, "{}|{}|{}|{}|{}", s.symbol, s.display, s.name, s.quantity, s.cost_basis)?;
-            }
-        }
+    /// Alpha Vantage daily candles; the compact series (100 sessions) covers ranges up to 3 months
+    fn alpha_vantage_history_url(&self, symbol: &str, range: &str) -> String {
+        let size = if matches!(range, "1mo" | "3mo") { "compact" } else { "full" };
+        format!(
+            "https://www.alphavantage.co/query?function=TIME_SERIES_DAILY&symbol={}&outputsize={}&apikey={}",
+            symbol, size, self.alphavantage_key
+        )
+    }
+}
 
-        Ok(())
+/// `[daemon]` section for `stock-tui --daemon`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+struct DaemonConfig {
+    /// Snapshot file; `~/` expands to the home directory
+    path: String,
+    /// Refresh interval, e.g. "90s", "5m" or "1h"
+    interval: String,
+}
+
+impl Default for DaemonConfig {
+    fn default() -> Self {
+        DaemonConfig { path: "~/.cache/stock-tui/portfolio.json".to_string(), interval: "5m".to_string() }
     }
+}
 
-    fn fetch_price(&mut self, symbol: &str) -> Option<PriceData> {
-        // Check cache first
-        if let Some((data, time)) = self.cache.get(symbol) {
-            if time.elapsed().as_secs() < CACHE_DURATION_SECS {
-                return Some(data.clone());
-            }
-        }
+/// `[report]` section for the weekly summary (`stock-tui report --send`, and `--daemon`
+/// on schedule)
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+struct ReportConfig {
+    /// File the report is written to; `~/` expands and strftime codes like `%Y-%m-%d` are
+    /// filled in with the date (empty: no file)
+    path: String,
+    /// Shell command the report is piped to, e.g. "mail -s 'Weekly portfolio' me@example.com"
+    command: String,
+    /// Day `--daemon` sends the report, e.g. "fri" (empty: only on demand)
+    weekday: String,
+    /// Local hour on that day from which the daemon sends it
+    hour: u32,
+}
 
-        // Try file cache
-        fs::create_dir_all(Self::cache_dir()).ok();
-        let cache_file = Self::cache_dir().join(format!("{}.cache", symbol.replace('.', "_")));
+impl Default for ReportConfig {
+    fn default() -> Self {
+        ReportConfig { path: String::new(), command: String::new(), weekday: String::new(), hour: 18 }
+    }
+}
 
-        if let Ok(metadata) = fs::metadata(&cache_file) {
-            if let Ok(modified) = metadata.modified() {
-                if modified.elapsed().map(|d| d.as_secs() < CACHE_DURATION_SECS).unwrap_or(false) {
-                    if let Ok(content) = fs::read_to_string(&cache_file) {
-                        if let Ok(data) = serde_json::from_str::<serde_json::Value>(&content) {
-                            let price_data = PriceData {
-                                price: data["price"].as_f64().unwrap_or(0.0),
-                                change: data["change"].as_f64().unwrap_or(0.0),
-                                change_percent: data["change_percent"].as_f64().unwrap_or(0.0),
-                            };
-                            self.cache.insert(symbol.to_string(), (price_data.clone(), Instant::now()));
-                            return Some(price_data);
-                        }
-                    }
-                }
-            }
+impl ReportConfig {
+    fn weekday(&self) -> Option<chrono::Weekday> {
+        self.weekday.trim().parse().ok()
+    }
+
+    /// Report file for `date`, None when no path is set
+    fn path(&self, date: chrono::NaiveDate) -> Option<PathBuf> {
+        if self.path.trim().is_empty() {
+            return None;
+        }
+        let mut path = String::new();
+        if std::fmt::Write::write_fmt(&mut path, format_args!("{}", date.format(self.path.trim()))).is_err() {
+            path = self.path.trim().to_string();
         }
+        Some(expand_home(&path))
+    }
+}
 
-        // Use chart API (v7 quote API is restricted by Yahoo)
-        let urls = [
-            format!("https://query2.finance.yahoo.com/v8/finance/chart/{}", symbol),
-            format!("https://query1.finance.yahoo.com/v8/finance/chart/{}", symbol),
-        ];
+/// Replace `path` with `content` so a crash leaves either the old or the new file, never a
+/// torn one: write a temp file beside it, fsync it, rename it over `path`, then fsync the
+/// directory so the rename itself survives a power cut
+fn write_atomic(path: &Path, content: &str) -> io::Result<()> {
+    let name = path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+    let tmp = path.with_file_name(format!(".{}.tmp", name));
+    let written = File::create(&tmp)
+        .and_then(|mut file| file.write_all(content.as_bytes()).and_then(|_| file.sync_all()))
+        .and_then(|_| fs::rename(&tmp, path));
+    if written.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    written?;
+    if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+        if let Ok(dir) = File::open(dir) {
+            let _ = dir.sync_all();
+        }
+    }
+    Ok(())
+}
 
-        for url in &urls {
-            if let Ok(response) = reqwest::blocking::Client::new()
-                .get(url)
-                .header("User-Agent", "Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36")
-                .timeout(Duration::from_secs(5))
-                .send()
-            {
-                if let Ok(data) = response.json::<serde_json::Value>() {
-                    if let Some(result) = data["chart"]["result"].get(0) {
-                        let meta = &result["meta"];
-                        let price = meta["regularMarketPrice"].as_f64()
-                            .or_else(|| meta["previousClose"].as_f64());
-                        let prev_close = meta["previousClose"].as_f64()
-                            .or_else(|| meta["chartPreviousClose"].as_f64());
-
-                        if let (Some(price), Some(prev)) = (price, prev_close) {
-                            let change = price - prev;
-                            let change_percent = (change / prev) * 100.0;
-
-                            let price_data = PriceData { price, change, change_percent };
-
-                            // Save to file cache
-                            let cache_json = serde_json::json!({
-                                "price": price,
-                                "change": change,
-                                "change_percent": change_percent
-                            });
-                            let _ = fs::write(&cache_file, cache_json.to_string());
+/// Seconds to wait for another running copy to finish a portfolio write
+const PORTFOLIO_LOCK_WAIT_SECS: u64 = 5;
+/// Age at which a portfolio lock file is taken to be left behind by a copy that crashed
+const PORTFOLIO_LOCK_STALE_SECS: u64 = 30;
+
+/// Depth of `PortfolioLock`s held in this process; only the outermost owns the lock file
+static PORTFOLIO_LOCK_DEPTH: Mutex<usize> = Mutex::new(0);
+
+/// Serializes portfolio and ledger read-modify-writes between running copies: `.lock` in the
+/// portfolios directory, created exclusively and removed on drop. Reentrant, so a save can
+/// run inside an edit that already holds it.
+struct PortfolioLock {
+    path: PathBuf,
+}
 
-                            self.cache.insert(symbol.to_string(), (price_data.clone(), Instant::now()));
-                            return Some(price_data);
+impl PortfolioLock {
+    fn acquire() -> Result<Self> {
+        let dir = App::portfolios_dir();
+        let path = dir.join(".lock");
+        let mut depth = PORTFOLIO_LOCK_DEPTH.lock().unwrap_or_else(|e| e.into_inner());
+        if *depth == 0 {
+            fs::create_dir_all(&dir)?;
+            let started = Instant::now();
+            loop {
+                match fs::OpenOptions::new().write(true).create_new(true).open(&path) {
+                    Ok(mut file) => {
+                        let _ = write!(file, "{}", std::process::id());
+                        break;
+                    }
+                    Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {
+                        let age = fs::metadata(&path).and_then(|m| m.modified()).ok().and_then(|t| t.elapsed().ok());
+                        if age.is_some_and(|age| age.as_secs() >= PORTFOLIO_LOCK_STALE_SECS) {
+                            let _ = fs::remove_file(&path);
+                        } else if started.elapsed().as_secs() >= PORTFOLIO_LOCK_WAIT_SECS {
+                            anyhow::bail!("portfolios are being saved by another stock-tui ({} is held)", path.display());
+                        } else {
+                            thread::sleep(Duration::from_millis(20));
                         }
                     }
+                    Err(err) => return Err(err.into()),
                 }
             }
         }
-
-        None
+        *depth += 1;
+        Ok(PortfolioLock { path })
     }
+}
 
-    fn fetch_exchange_rate(&mut self) -> f64 {
-        if let Some(data) = self.fetch_price("USDTWD=X") {
-            data.price
-        } else {
-            32.0
+impl Drop for PortfolioLock {
+    fn drop(&mut self) {
+        let mut depth = PORTFOLIO_LOCK_DEPTH.lock().unwrap_or_else(|e| e.into_inner());
+        *depth -= 1;
+        if *depth == 0 {
+            let _ = fs::remove_file(&self.path);
         }
     }
+}
 
-    /// Start an async background refresh of all stock prices
-    /// Results will be sent through the fetch_receiver channel
-    fn start_async_refresh(&mut self) {
-        if self.is_fetching {
-            return; // Already fetching
-        }
+/// Each portfolio file as this process last read or wrote it, so a save can tell when
+/// another running copy changed it in between
+static PORTFOLIO_READS: Mutex<BTreeMap<PathBuf, String>> = Mutex::new(BTreeMap::new());
 
-        self.is_fetching = true;
-        let sender = self.fetch_sender.clone();
+/// Config path with a leading `~/` expanded to the home directory
+fn expand_home(path: &str) -> PathBuf {
+    match path.strip_prefix("~/") {
+        Some(rest) => dirs::home_dir().unwrap_or_default().join(rest),
+        None => PathBuf::from(path),
+    }
+}
 
-        // Collect all symbols we need to fetch
-        let symbols: Vec<String> = if self.view_combined {
-            self.combined_stocks.iter().map(|s| s.symbol.clone()).collect()
-        } else {
-            self.stocks.iter().map(|s| s.symbol.clone()).collect()
-        };
+impl DaemonConfig {
+    fn path(&self) -> PathBuf {
+        expand_home(&self.path)
+    }
 
-        // Spawn background thread
-        thread::spawn(move || {
-            // Fetch exchange rate first
-            if let Some(rate) = fetch_price_blocking("USDTWD=X") {
-                let _ = sender.send(FetchMessage::ExchangeRate(rate.price));
-            }
+    /// Seconds between refreshes; at least a minute so the price cache never serves stale data
+    fn interval_secs(&self) -> u64 {
+        parse_refresh_interval(&self.interval).unwrap_or(5 * 60).max(quote_ttl())
+    }
+}
 
-            // Fetch each stock price
-            for symbol in symbols {
-                let price_data = fetch_price_blocking(&symbol);
-                let _ = sender.send(FetchMessage::Price(FetchResult {
-                    symbol,
-                    price_data,
-                }));
-            }
+/// `[assumptions]` section: annual rates in percent keyed by currency ("TWD", "USD", ...);
+/// currencies left out use built-in defaults
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+struct AssumptionsConfig {
+    risk_free: HashMap<String, f64>,
+    expected_return: HashMap<String, f64>,
+}
 
-            // Signal completion
-            let _ = sender.send(FetchMessage::BatchComplete);
-        });
+impl AssumptionsConfig {
+    fn risk_free(&self, currency: &str) -> f64 {
+        self.risk_free.get(currency).copied().unwrap_or(match currency {
+            "TWD" => 1.5,
+            "USD" => 4.0,
+            _ => 2.0,
+        })
     }
 
-    /// Process any pending fetch results from background thread
-    /// Returns true if any updates were received
-    fn process_fetch_results(&mut self) -> bool {
-        let mut updated = false;
-
-        // Non-blocking receive of all pending messages
-        while let Ok(msg) = self.fetch_receiver.try_recv() {
-            match msg {
-                FetchMessage::Price(result) => {
-                    // Update price in all stock vectors
-                    if let Some(ref price_data) = result.price_data {
-                        // Update cache
-                        self.cache.insert(result.symbol.clone(), (price_data.clone(), Instant::now()));
+    fn expected_return(&self, currency: &str) -> f64 {
+        self.expected_return.get(currency).copied().unwrap_or(match currency {
+            "TWD" => 6.0,
+            "USD" => 7.0,
+            _ => 5.0,
+        })
+    }
+}
 
-                        // Update all stock vectors
-                        for stock in self.stocks.iter_mut()
-                            .chain(self.tw_stocks.iter_mut())
-                            .chain(self.us_stocks.iter_mut())
-                            .chain(self.combined_stocks.iter_mut())
-                            .chain(self.combined_tw_stocks.iter_mut())
-                            .chain(self.combined_us_stocks.iter_mut())
-                        {
-                            if stock.symbol == result.symbol {
-                                stock.price_data = Some(price_data.clone());
-                            }
-                        }
-                    }
-                    updated = true;
-                }
-                FetchMessage::ExchangeRate(rate) => {
-                    self.usd_twd_rate = rate;
-                    updated = true;
-                }
-                FetchMessage::BatchComplete => {
-                    self.is_fetching = false;
-                    self.last_update = Instant::now();
-                    self.sort_stocks(); // Re-sort after all prices updated
-                    updated = true;
-                }
-            }
-        }
+/// `[market]` section: holidays beyond the built-in calendars (YYYY-MM-DD)
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+struct MarketConfig {
+    tw_holidays: Vec<String>,
+    us_holidays: Vec<String>,
+    /// Keep live-refreshing symbols whose exchange is closed
+    refresh_when_closed: bool,
+}
 
-        updated
+impl Default for MarketConfig {
+    fn default() -> Self {
+        MarketConfig { tw_holidays: Vec::new(), us_holidays: Vec::new(), refresh_when_closed: true }
     }
+}
 
-    fn fetch_historical(&mut self, symbol: &str) -> Option<HistoricalData> {
-        // Check in-memory cache first
-        if let Some(data) = self.historical_cache.get(symbol) {
-            if data.last_fetched.elapsed().as_secs() < HISTORICAL_CACHE_DURATION_SECS {
-                return Some(data.clone());
-            }
+impl MarketConfig {
+    /// Settings (`tw_holidays`, `us_holidays`) with no closures for `year` either built in or
+    /// configured, so every weekday of it would count as a trading day
+    fn missing_calendars(&self, year: i32) -> Vec<&'static str> {
+        let prefix = format!("{}-", year);
+        let covered = |built_in: &[&str], extra: &[String]| {
+            built_in.iter().any(|d| d.starts_with(&prefix)) || extra.iter().any(|d| d.starts_with(&prefix))
+        };
+        let mut missing = Vec::new();
+        if !covered(TWSE_HOLIDAYS, &self.tw_holidays) {
+            missing.push("tw_holidays");
         }
-
-        // Try file cache
-        fs::create_dir_all(Self::cache_dir()).ok();
-        let cache_file = Self::cache_dir().join(format!("{}_history.json", symbol.replace('.', "_")));
-
-        if let Ok(metadata) = fs::metadata(&cache_file) {
-            if let Ok(modified) = metadata.modified() {
-                if modified.elapsed().map(|d| d.as_secs() < HISTORICAL_CACHE_DURATION_SECS).unwrap_or(false) {
-                    if let Ok(content) = fs::read_to_string(&cache_file) {
-                        if let Ok(data) = serde_json::from_str::<serde_json::Value>(&content) {
-                            let timestamps: Vec<i64> = data["timestamps"]
-                                .as_array()
-                                .map(|arr| arr.iter().filter_map(|v| v.as_i64()).collect())
-                                .unwrap_or_default();
-                            let closes: Vec<f64> = data["closes"]
-                                .as_array()
-                                .map(|arr| arr.iter().filter_map(|v| v.as_f64()).collect())
-                                .unwrap_or_default();
-
-                            if !timestamps.is_empty() && !closes.is_empty() {
-                                let historical = HistoricalData {
-                                    timestamps,
-                                    closes,
-                                    last_fetched: Instant::now(),
-                                };
-                                self.historical_cache.insert(symbol.to_string(), historical.clone());
-                                return Some(historical);
-                            }
-                        }
-                    }
-                }
-            }
+        if !covered(NYSE_HOLIDAYS, &self.us_holidays) {
+            missing.push("us_holidays");
         }
+        missing
+    }
+}
 
-        // Fetch from Yahoo Finance API
-        let url = format!(
-            "https://query2.finance.yahoo.com/v8/finance/chart/{}?interval=1d&range=1mo",
-            symbol
-        );
+/// TWSE full-day closures (weekdays only)
+const TWSE_HOLIDAYS: &[&str] = &[
+    "2026-01-01", "2026-02-12", "2026-02-13", "2026-02-16", "2026-02-17", "2026-02-18", "2026-02-19",
+    "2026-02-20", "2026-02-27", "2026-04-03", "2026-04-06", "2026-05-01", "2026-06-19", "2026-09-25",
+    "2026-09-28", "2026-10-09", "2026-10-26", "2026-12-25",
+];
+
+/// NYSE full-day closures
+const NYSE_HOLIDAYS: &[&str] = &[
+    "2025-01-01", "2025-01-09", "2025-01-20", "2025-02-17", "2025-04-18", "2025-05-26", "2025-06-19",
+    "2025-07-04", "2025-09-01", "2025-11-27", "2025-12-25",
+    "2026-01-01", "2026-01-19", "2026-02-16", "2026-04-03", "2026-05-25", "2026-06-19", "2026-07-03",
+    "2026-09-07", "2026-11-26", "2026-12-25",
+    "2027-01-01", "2027-01-18", "2027-02-15", "2027-03-26", "2027-05-31", "2027-06-18", "2027-07-05",
+    "2027-09-06", "2027-11-25", "2027-12-24",
+];
+
+/// NYSE 13:00 early closes
+const NYSE_EARLY_CLOSES: &[&str] = &[
+    "2025-07-03", "2025-11-28", "2025-12-24", "2026-11-27", "2026-12-24", "2027-11-26",
+];
+
+/// Exchange a holding trades on, for session-aware refresh and the open/closed indicator
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Market {
+    Tw,
+    Us,
+}
 
-        if let Ok(response) = reqwest::blocking::Client::new()
-            .get(&url)
-            .header("User-Agent", "Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36")
-            .timeout(Duration::from_secs(10))
-            .send()
-        {
-            if let Ok(data) = response.json::<serde_json::Value>() {
-                if let Some(result) = data["chart"]["result"].get(0) {
-                    let timestamps: Vec<i64> = result["timestamp"]
-                        .as_array()
-                        .map(|arr| arr.iter().filter_map(|v| v.as_i64()).collect())
-                        .unwrap_or_default();
-
-                    let closes: Vec<f64> = result["indicators"]["quote"][0]["close"]
-                        .as_array()
-                        .map(|arr| arr.iter().filter_map(|v| v.as_f64()).collect())
-                        .unwrap_or_default();
-
-                    if !timestamps.is_empty() && !closes.is_empty() {
-                        // Save to file cache
-                        let cache_json = serde_json::json!({
-                            "timestamps": timestamps,
-                            "closes": closes
-                        });
-                        let _ = fs::write(&cache_file, cache_json.to_string());
+/// Where an exchange is in its trading calendar
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum MarketSession {
+    Open { closes: chrono::DateTime<chrono::Utc> },
+    Closed { opens: chrono::DateTime<chrono::Utc>, holiday: bool },
+}
 
-                        let historical = HistoricalData {
-                            timestamps,
-                            closes,
-                            last_fetched: Instant::now(),
-                        };
-                        self.historical_cache.insert(symbol.to_string(), historical.clone());
-                        return Some(historical);
-                    }
-                }
-            }
+impl Market {
+    fn label(&self) -> &'static str {
+        match self {
+            Market::Tw => "TW",
+            Market::Us => "US",
         }
-
-        None
     }
 
-    /// Calculate trend from historical data: compare first 5 days avg vs last 5 days avg
-    fn calculate_trend(closes: &[f64]) -> (&'static str, Color) {
-        if closes.len() < 10 {
-            return ("→", Color::Gray);
-        }
+    fn offset(&self, now: chrono::DateTime<chrono::Utc>) -> chrono::FixedOffset {
+        let zone = match self {
+            Market::Tw => "Asia/Taipei",
+            Market::Us => "America/New_York",
+        };
+        zone_offset(zone, now).map(|(_, offset)| offset).unwrap_or_else(|| chrono::FixedOffset::east_opt(0).unwrap())
+    }
 
-        let first_avg: f64 = closes.iter().take(5).sum::<f64>() / 5.0;
-        let last_avg: f64 = closes.iter().rev().take(5).sum::<f64>() / 5.0;
-        let change_pct = ((last_avg - first_avg) / first_avg) * 100.0;
+    fn is_holiday(&self, date: chrono::NaiveDate, config: &MarketConfig) -> bool {
+        let (built_in, extra) = match self {
+            Market::Tw => (TWSE_HOLIDAYS, &config.tw_holidays),
+            Market::Us => (NYSE_HOLIDAYS, &config.us_holidays),
+        };
+        let key = date.format("%Y-%m-%d").to_string();
+        built_in.contains(&key.as_str()) || extra.contains(&key)
+    }
 
-        if change_pct > 1.0 {
-            ("⬆", Color::Green)
-        } else if change_pct < -1.0 {
-            ("⬇", Color::Red)
-        } else {
-            ("→", Color::Gray)
+    /// Local open and close times on a trading day
+    fn hours(&self, date: chrono::NaiveDate) -> (chrono::NaiveTime, chrono::NaiveTime) {
+        let hm = |h, m| chrono::NaiveTime::from_hms_opt(h, m, 0).unwrap_or_default();
+        match self {
+            Market::Tw => (hm(9, 0), hm(13, 30)),
+            Market::Us if NYSE_EARLY_CLOSES.contains(&date.format("%Y-%m-%d").to_string().as_str()) => (hm(9, 30), hm(13, 0)),
+            Market::Us => (hm(9, 30), hm(16, 0)),
         }
     }
 
-    fn refresh_data(&mut self) -> Result<()> {
-        self.usd_twd_rate = self.fetch_exchange_rate();
-
-        // Load current portfolio stocks with prices
-        let (file_path, portfolio_name) = if let Some(portfolio) = self.portfolios.get(self.current_portfolio_idx) {
-            (portfolio.file_path.clone(), portfolio.name.clone())
-        } else {
-            return Ok(());
+    /// Open now (with its close time) or closed (with the next open), skipping weekends and holidays
+    fn session(&self, now: chrono::DateTime<chrono::Utc>, config: &MarketConfig) -> MarketSession {
+        use chrono::TimeZone;
+        let offset = self.offset(now);
+        let local = now.with_timezone(&offset);
+        // At the offset of that day, which differs from today's across a DST change
+        let to_utc = |date: chrono::NaiveDate, time: chrono::NaiveTime| {
+            let local = date.and_time(time);
+            let guess = chrono::Utc.from_utc_datetime(&(local - chrono::Duration::seconds(offset.local_minus_utc() as i64)));
+            self.offset(guess).from_local_datetime(&local).single().map(|t| t.with_timezone(&chrono::Utc)).unwrap_or(now)
         };
+        let trading = |date: chrono::NaiveDate| date.weekday().num_days_from_monday() < 5 && !self.is_holiday(date, config);
 
-        let mut stocks = Self::load_stocks_from_file(&file_path)?;
-        for stock in &mut stocks {
-            stock.price_data = self.fetch_price(&stock.symbol);
-            stock.portfolio_name = portfolio_name.clone();
+        let today = local.date_naive();
+        let (open, close) = self.hours(today);
+        if trading(today) && local.time() >= open && local.time() < close {
+            return MarketSession::Open { closes: to_utc(today, close) };
         }
-        self.stocks = stocks;
+        let mut date = if trading(today) && local.time() < open { today } else { today.succ_opt().unwrap_or(today) };
+        for _ in 0..30 {
+            if trading(date) {
+                break;
+            }
+            date = date.succ_opt().unwrap_or(date);
+        }
+        let is_weekday = today.weekday().num_days_from_monday() < 5;
+        MarketSession::Closed { opens: to_utc(date, self.hours(date).0), holiday: is_weekday && self.is_holiday(today, config) }
+    }
+}
 
-        // Split into TW and US
-        self.tw_stocks = self.stocks.iter().filter(|s| s.symbol.contains(".TW")).cloned().collect();
-        self.us_stocks = self.stocks.iter().filter(|s| !s.symbol.contains(".TW")).cloned().collect();
+/// `[time]` section: clocks shown next to the update time, and strftime formats
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+struct TimeConfig {
+    /// Zones shown beside the local time, e.g. ["America/New_York"] or ["+09:00"]
+    clocks: Vec<String>,
+    time_format: String,
+    /// Dates in the detail view and reports
+    date_format: String,
+    /// First/last date labels under the detail chart
+    chart_date_format: String,
+}
 
-        // Load combined stocks (aggregated)
-        self.load_combined_stocks()?;
+impl Default for TimeConfig {
+    fn default() -> Self {
+        TimeConfig {
+            clocks: Vec::new(),
+            time_format: "%H:%M:%S".to_string(),
+            date_format: "%Y-%m-%d".to_string(),
+            chart_date_format: "%m/%d".to_string(),
+        }
+    }
+}
 
-        self.last_update = Instant::now();
-        Ok(())
+impl TimeConfig {
+    fn date(&self, date: chrono::NaiveDate) -> String {
+        date.format(&self.date_format).to_string()
     }
 
-    fn load_combined_stocks(&mut self) -> Result<()> {
-        let mut aggregated: HashMap<String, Stock> = HashMap::new();
-        let mut portfolio_map: HashMap<String, Vec<String>> = HashMap::new();
+    /// Replace formats chrono can't display with the defaults (it panics when displaying
+    /// them); returns the offending format. Each is tried on the kind of value it formats,
+    /// so a time specifier such as `%H` or `%z` in `date_format` is caught too.
+    fn sanitize(&mut self) -> Option<String> {
+        use std::fmt::Write as _;
+        let defaults = TimeConfig::default();
+        let moment = chrono::DateTime::from_timestamp(0, 0).unwrap_or_default().fixed_offset();
+        let day = moment.date_naive();
+        let mut sample = String::new();
+        let displays = [
+            write!(sample, "{}", moment.format(&self.time_format)).is_ok(),
+            write!(sample, "{}", day.format(&self.date_format)).is_ok(),
+            write!(sample, "{}", moment.format(&self.chart_date_format)).is_ok(),
+        ];
+        let mut invalid = None;
+        for ((format, default), displays) in [
+            (&mut self.time_format, defaults.time_format),
+            (&mut self.date_format, defaults.date_format),
+            (&mut self.chart_date_format, defaults.chart_date_format),
+        ]
+        .into_iter()
+        .zip(displays)
+        {
+            if !displays {
+                invalid = Some(std::mem::replace(format, default));
+            }
+        }
+        invalid
+    }
+}
 
-        for portfolio in &self.portfolios {
-            let stocks = Self::load_stocks_from_file(&portfolio.file_path)?;
-            for stock in stocks {
-                portfolio_map
-                    .entry(stock.symbol.clone())
-                    .or_default()
-                    .push(portfolio.name.clone());
+/// Short label and current offset for a clock zone. Without a tz database this covers
+/// fixed offsets ("+09:00"), UTC and common market zones with their DST rules.
+fn zone_offset(zone: &str, now: chrono::DateTime<chrono::Utc>) -> Option<(String, chrono::FixedOffset)> {
+    use chrono::{NaiveDate, TimeZone, Weekday};
+    let hours = |h: i32| chrono::FixedOffset::east_opt(h * 3600);
+    // nth (1-based) Sunday of a month, or the last one with n = 0
+    let sunday = |month: u32, n: u32| -> NaiveDate {
+        let year = now.year();
+        if n == 0 {
+            let next = if month == 12 { NaiveDate::from_ymd_opt(year + 1, 1, 1) } else { NaiveDate::from_ymd_opt(year, month + 1, 1) };
+            let last = next.and_then(|d| d.pred_opt()).unwrap_or_default();
+            last - chrono::Duration::days(last.weekday().num_days_from_sunday() as i64)
+        } else {
+            NaiveDate::from_weekday_of_month_opt(year, month, Weekday::Sun, n as u8).unwrap_or_default()
+        }
+    };
+    // US: second Sunday of March to first Sunday of November, 2:00 local standard time
+    let us = |label: &str, std: i32| {
+        let start = chrono::Utc.from_utc_datetime(&sunday(3, 2).and_hms_opt((2 - std) as u32, 0, 0)?);
+        let end = chrono::Utc.from_utc_datetime(&sunday(11, 1).and_hms_opt((1 - std) as u32, 0, 0)?);
+        Some((label.to_string(), hours(if now >= start && now < end { std + 1 } else { std })?))
+    };
+    // EU: last Sunday of March to last Sunday of October, 01:00 UTC
+    let eu = |label: &str, std: i32| {
+        let start = chrono::Utc.from_utc_datetime(&sunday(3, 0).and_hms_opt(1, 0, 0)?);
+        let end = chrono::Utc.from_utc_datetime(&sunday(10, 0).and_hms_opt(1, 0, 0)?);
+        Some((label.to_string(), hours(if now >= start && now < end { std + 1 } else { std })?))
+    };
+    match zone {
+        "UTC" => Some(("UTC".to_string(), hours(0)?)),
+        "Asia/Taipei" => Some(("TPE".to_string(), hours(8)?)),
+        "Asia/Hong_Kong" => Some(("HKG".to_string(), hours(8)?)),
+        "Asia/Shanghai" => Some(("SHA".to_string(), hours(8)?)),
+        "Asia/Tokyo" => Some(("TYO".to_string(), hours(9)?)),
+        "America/New_York" => us("NY", -5),
+        "America/Chicago" => us("CHI", -6),
+        "America/Los_Angeles" => us("LA", -8),
+        "Europe/London" => eu("LON", 0),
+        "Europe/Berlin" | "Europe/Paris" => eu("CET", 1),
+        _ => {
+            let (sign, rest) = match zone.as_bytes().first()? {
+                b'+' => (1, &zone[1..]),
+                b'-' => (-1, &zone[1..]),
+                _ => return None,
+            };
+            let (h, m) = rest.split_once(':').unwrap_or((rest, "0"));
+            let secs = sign * (h.parse::<i32>().ok()? * 3600 + m.parse::<i32>().ok()? * 60);
+            Some((zone.to_string(), chrono::FixedOffset::east_opt(secs)?))
+        }
+    }
+}
 
-                if let Some(existing) = aggregated.get_mut(&stock.symbol) {
-                    let old_qty = existing.quantity;
-                    let old_cost = existing.cost_basis;
-                    let new_qty = stock.quantity;
-                    let new_cost = stock.cost_basis;
+/// `[gradient]` section: moves at or below `pale` percent get the faintest color,
+/// at or above `full` (Change) or `gain_full` (Gain) the most saturated
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+struct GradientConfig {
+    enabled: bool,
+    pale: f64,
+    full: f64,
+    gain_full: f64,
+}
 
-                    let combined_qty = old_qty + new_qty;
-                    let weighted_cost = if combined_qty > 0.0 {
-                        ((old_qty * old_cost) + (new_qty * new_cost)) / combined_qty
-                    } else {
-                        0.0
-                    };
+impl Default for GradientConfig {
+    fn default() -> Self {
+        GradientConfig { enabled: true, pale: 0.2, full: 5.0, gain_full: 50.0 }
+    }
+}
 
-                    existing.quantity = combined_qty;
-                    existing.cost_basis = weighted_cost;
-                } else {
-                    aggregated.insert(stock.symbol.clone(), stock);
-                }
+impl GradientConfig {
+    /// The theme's gain/loss color shaded by how far `pct` is between `pale` and `full`;
+    /// themes without RGB or green/red gain and loss colors get them unshaded
+    fn color(&self, pct: f64, full: f64) -> Color {
+        let flat = if pct >= 0.0 { theme().gain } else { theme().loss };
+        if !self.enabled {
+            return flat;
+        }
+        let t = ((pct.abs() - self.pale) / (full - self.pale).max(f64::EPSILON)).clamp(0.0, 1.0);
+        // Pale (180) fades to saturated (0) on the off channels; the main channel stays bright
+        let faded = (180.0 * (1.0 - t)) as u8;
+        let main = (255.0 - 55.0 * t) as u8;
+        match flat {
+            Color::Green => Color::Rgb(faded, main, faded),
+            Color::Red => Color::Rgb(main, faded, faded),
+            // Blend from near-white towards the theme's own color
+            Color::Rgb(r, g, b) => {
+                let shade = |c: u8| (c as f64 + (255.0 - c as f64) * 0.7 * (1.0 - t)) as u8;
+                Color::Rgb(shade(r), shade(g), shade(b))
             }
+            other => other,
         }
-
-        // Fetch prices for combined stocks
-        self.combined_stocks = aggregated
-            .into_iter()
-            .map(|(symbol, mut stock)| {
-                stock.price_data = self.fetch_price(&symbol);
-                let portfolios = portfolio_map.get(&symbol).unwrap();
-                stock.portfolio_name = if portfolios.len() > 1 {
-                    portfolios.join("+")
-                } else {
-                    portfolios.first().cloned().unwrap_or_default()
-                };
-                stock
-            })
-            .collect();
-        self.combined_tw_stocks = self.combined_stocks.iter().filter(|s| s.symbol.contains(".TW")).cloned().collect();
-        self.combined_us_stocks = self.combined_stocks.iter().filter(|s| !s.symbol.contains(".TW")).cloned().collect();
-
-        self.sort_stocks();
-
-        Ok(())
     }
+}
 
-    fn sort_stocks(&mut self) {
-        let sort_col = self.sort_column;
-        let sort_dir = self.sort_direction;
-        let usd_twd = self.usd_twd_rate;
-
-        let sorter = |a: &Stock, b: &Stock| -> std::cmp::Ordering {
-            let cmp = match sort_col {
-                Some(SortColumn::Price) => {
-                    let a_val = a.price_data.as_ref().map(|d| d.price).unwrap_or(0.0);
-                    let b_val = b.price_data.as_ref().map(|d| d.price).unwrap_or(0.0);
-                    a_val.partial_cmp(&b_val).unwrap_or(std::cmp::Ordering::Equal)
-                }
-                Some(SortColumn::Change) => {
-                    let a_val = a.price_data.as_ref().map(|d| d.change_percent).unwrap_or(f64::NEG_INFINITY);
-                    let b_val = b.price_data.as_ref().map(|d| d.change_percent).unwrap_or(f64::NEG_INFINITY);
-                    a_val.partial_cmp(&b_val).unwrap_or(std::cmp::Ordering::Equal)
-                }
-                Some(SortColumn::Quantity) => {
-                    a.quantity.partial_cmp(&b.quantity).unwrap_or(std::cmp::Ordering::Equal)
-                }
-                Some(SortColumn::Gain) => {
-                    let a_gain = if a.quantity > 0.0 && a.cost_basis > 0.0 {
-                        if let Some(ref d) = a.price_data {
-                            let mut g = a.quantity * d.price - a.quantity * a.cost_basis;
-                            if !a.symbol.contains(".TW") { g *= usd_twd; }
-                            g
-                        } else { 0.0 }
-                    } else { 0.0 };
-                    let b_gain = if b.quantity > 0.0 && b.cost_basis > 0.0 {
-                        if let Some(ref d) = b.price_data {
-                            let mut g = b.quantity * d.price - b.quantity * b.cost_basis;
-                            if !b.symbol.contains(".TW") { g *= usd_twd; }
-                            g
-                        } else { 0.0 }
-                    } else { 0.0 };
-                    a_gain.partial_cmp(&b_gain).unwrap_or(std::cmp::Ordering::Equal)
-                }
-                Some(SortColumn::GainPercent) => {
-                    let a_pct = if a.quantity > 0.0 && a.cost_basis > 0.0 {
-                        if let Some(ref d) = a.price_data {
-                            ((d.price - a.cost_basis) / a.cost_basis) * 100.0
-                        } else { 0.0 }
-                    } else { 0.0 };
-                    let b_pct = if b.quantity > 0.0 && b.cost_basis > 0.0 {
-                        if let Some(ref d) = b.price_data {
-                            ((d.price - b.cost_basis) / b.cost_basis) * 100.0
-                        } else { 0.0 }
-                    } else { 0.0 };
-                    a_pct.partial_cmp(&b_pct).unwrap_or(std::cmp::Ordering::Equal)
-                }
-                None => std::cmp::Ordering::Equal,
-            };
+/// `[theme]` section: a preset (default, solarized, monochrome) with optional overrides per
+/// role, each a color name, `#rrggbb` or a 0-255 palette index
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+struct ThemeConfig {
+    preset: String,
+    gain: Option<String>,
+    loss: Option<String>,
+    header: Option<String>,
+    border: Option<String>,
+    highlight: Option<String>,
+    muted: Option<String>,
+    dim: Option<String>,
+    alert: Option<String>,
+    marked: Option<String>,
+}
 
-            match sort_dir {
-                SortDirection::Ascending => cmp,
-                SortDirection::Descending => cmp.reverse(),
-            }
-        };
+/// Colors every view draws with, by role; fixed at startup from `[theme]`
+#[derive(Debug, Clone, Copy)]
+struct Theme {
+    gain: Color,      // Rises, gains and confirmations
+    loss: Color,      // Falls, losses and errors
+    header: Color,    // Column headers, prompts and warnings
+    border: Color,    // Active table and dialog borders, accents
+    highlight: Color, // Selected row background
+    muted: Color,     // Hints and secondary text
+    dim: Color,       // Labels and axes, a step brighter than `muted`
+    alert: Color,     // Triggered price alerts
+    marked: Color,    // Background of rows marked for batch operations
+}
 
-        self.tw_stocks.sort_by(sorter);
-        self.us_stocks.sort_by(sorter);
-        self.combined_tw_stocks.sort_by(sorter);
-        self.combined_us_stocks.sort_by(sorter);
+const THEME_PRESETS: [&str; 3] = ["default", "solarized", "monochrome"];
+
+impl Theme {
+    fn preset(name: &str) -> Option<Theme> {
+        Some(match name {
+            "" | "default" => Theme {
+                gain: Color::Green,
+                loss: Color::Red,
+                header: Color::Yellow,
+                border: Color::Cyan,
+                highlight: Color::DarkGray,
+                muted: Color::DarkGray,
+                dim: Color::Gray,
+                alert: Color::Magenta,
+                marked: Color::Indexed(17),
+            },
+            "solarized" => Theme {
+                gain: Color::Rgb(133, 153, 0),
+                loss: Color::Rgb(220, 50, 47),
+                header: Color::Rgb(181, 137, 0),
+                border: Color::Rgb(38, 139, 210),
+                highlight: Color::Rgb(7, 54, 66),
+                muted: Color::Rgb(88, 110, 117),
+                dim: Color::Rgb(147, 161, 161),
+                alert: Color::Rgb(211, 54, 130),
+                marked: Color::Rgb(40, 42, 94),
+            },
+            // Grayscale only; signs still tell gains from losses
+            "monochrome" => Theme {
+                gain: Color::White,
+                loss: Color::Gray,
+                header: Color::White,
+                border: Color::White,
+                highlight: Color::DarkGray,
+                muted: Color::DarkGray,
+                dim: Color::Gray,
+                alert: Color::White,
+                marked: Color::Indexed(238),
+            },
+            _ => return None,
+        })
     }
 
-    fn toggle_sort(&mut self, column: SortColumn) {
-        if self.sort_column == Some(column) {
-            // Toggle direction
-            self.sort_direction = match self.sort_direction {
-                SortDirection::Ascending => SortDirection::Descending,
-                SortDirection::Descending => SortDirection::Ascending,
-            };
-        } else {
-            // New column, default to descending
-            self.sort_column = Some(column);
-            self.sort_direction = SortDirection::Descending;
+    /// The configured preset with its overrides applied
+    fn from_config(config: &ThemeConfig) -> Result<Theme, String> {
+        let preset = config.preset.trim().to_lowercase();
+        let mut theme = Theme::preset(&preset)
+            .ok_or_else(|| format!("Unknown theme preset '{}' (choose from {})", config.preset, THEME_PRESETS.join(", ")))?;
+        let overrides = [
+            ("gain", &config.gain, &mut theme.gain),
+            ("loss", &config.loss, &mut theme.loss),
+            ("header", &config.header, &mut theme.header),
+            ("border", &config.border, &mut theme.border),
+            ("highlight", &config.highlight, &mut theme.highlight),
+            ("muted", &config.muted, &mut theme.muted),
+            ("dim", &config.dim, &mut theme.dim),
+            ("alert", &config.alert, &mut theme.alert),
+            ("marked", &config.marked, &mut theme.marked),
+        ];
+        for (role, value, color) in overrides {
+            if let Some(value) = value {
+                *color = value.trim().parse().map_err(|_| format!("Theme {}: invalid color '{}'", role, value))?;
+            }
         }
-        self.sort_stocks();
+        Ok(theme)
     }
+}
 
-    fn get_active_tw_stocks(&self) -> &[Stock] {
-        if self.view_combined {
-            &self.combined_tw_stocks
-        } else {
-            &self.tw_stocks
-        }
-    }
+static THEME: OnceLock<Theme> = OnceLock::new();
 
-    fn get_active_us_stocks(&self) -> &[Stock] {
-        if self.view_combined {
-            &self.combined_us_stocks
-        } else {
-            &self.us_stocks
-        }
-    }
+/// The startup theme (the default preset before the config is read)
+fn theme() -> &'static Theme {
+    THEME.get_or_init(|| Theme::preset("default").unwrap())
+}
 
-    fn calculate_summary(&self) -> (f64, f64, f64, f64, usize, usize) {
-        let stocks = if self.view_combined {
-            &self.combined_stocks
-        } else {
-            &self.stocks
-        };
+#[derive(Debug, Clone, Deserialize)]
+struct ColumnConfig {
+    name: String,
+    expr: String,
+    #[serde(default = "default_column_decimals")]
+    decimals: usize,
+    /// Draw the value (0–100) as a bar instead of a number
+    #[serde(default)]
+    gauge: bool,
+    /// Print the value as a percentage after a gauge's bar
+    #[serde(default)]
+    percent: bool,
+    /// Draw the last `SPARKLINE_DAYS` daily closes before the value, loading history in the background
+    #[serde(default)]
+    sparkline: bool,
+}
 
-        let mut total_cost = 0.0;
-        let mut total_value = 0.0;
-        let mut holdings = 0;
+fn default_column_decimals() -> usize {
+    2
+}
 
-        for stock in stocks {
-            if stock.quantity > 0.0 {
-                if let Some(ref data) = stock.price_data {
-                    let mut cost = stock.quantity * stock.cost_basis;
-                    let mut value = stock.quantity * data.price;
+/// Width of a gauge column's bar, in cells
+const GAUGE_WIDTH: usize = 8;
+/// Daily closes drawn by a sparkline column
+const SPARKLINE_DAYS: usize = 7;
 
-                    if !stock.symbol.contains(".TW") {
-                        cost *= self.usd_twd_rate;
-                        value *= self.usd_twd_rate;
-                    }
+/// A config column with its parsed expression
+#[derive(Debug, Clone)]
+struct CustomColumn {
+    name: String,
+    expr: Expr,
+    decimals: usize,
+    gauge: bool,
+    percent: bool,
+    sparkline: bool,
+}
 
-                    total_cost += cost;
-                    total_value += value;
-                    holdings += 1;
-                }
+impl CustomColumn {
+    /// Switch a `range` gauge between today's range and the 52-week range
+    fn toggle_range(&mut self, year: bool) {
+        if let Expr::Var(var) = &self.expr {
+            if self.gauge && matches!(var.as_str(), "day_pos" | "year_pos") {
+                let (var, name) = if year { ("year_pos", "52w range") } else { ("day_pos", "Day range") };
+                self.expr = Expr::Var(var.to_string());
+                self.name = name.to_string();
             }
         }
-
-        let total_gain = total_value - total_cost;
-        let total_gain_percent = if total_cost > 0.0 {
-            (total_gain / total_cost) * 100.0
-        } else {
-            0.0
-        };
-
-        (total_cost, total_value, total_gain, total_gain_percent, stocks.len(), holdings)
     }
+}
 
-    // Returns: (tw_value, tw_gain, tw_gain_pct, us_value_usd, us_gain_usd, us_gain_pct)
-    fn calculate_market_summary(&self) -> (f64, f64, f64, f64, f64, f64) {
-        let stocks = if self.view_combined {
-            &self.combined_stocks
-        } else {
-            &self.stocks
-        };
+/// Currency code as given by the user or a quote, keeping the case of minor units
+/// (`GBp` pence, `ZAc` cents) that would otherwise read as their major currency
+fn normalize_currency(code: &str) -> String {
+    match code.trim() {
+        "GBp" | "GBX" | "GBx" => "GBp".to_string(),
+        "ZAc" | "ZAC" => "ZAc".to_string(),
+        "ILA" => "ILA".to_string(),
+        other => other.to_uppercase(),
+    }
+}
 
-        let mut tw_cost = 0.0;
-        let mut tw_value = 0.0;
-        let mut us_cost = 0.0;
-        let mut us_value = 0.0;
+/// Major currency of a code and the size of one unit of it (0.01 for pence and cents)
+fn currency_unit(code: &str) -> (String, f64) {
+    match code {
+        "GBp" => ("GBP".to_string(), 0.01),
+        "ZAc" => ("ZAR".to_string(), 0.01),
+        "ILA" => ("ILS".to_string(), 0.01),
+        other => (other.to_string(), 1.0),
+    }
+}
 
-        for stock in stocks {
-            if stock.quantity > 0.0 {
-                if let Some(ref data) = stock.price_data {
-                    let cost = stock.quantity * stock.cost_basis;
-                    let value = stock.quantity * data.price;
+/// First days of the month and year containing `today`, for MTD and YTD returns
+fn period_starts(today: chrono::NaiveDate) -> (chrono::NaiveDate, chrono::NaiveDate) {
+    (today.with_day(1).unwrap_or(today), today.with_ordinal(1).unwrap_or(today))
+}
 
-                    if stock.symbol.contains(".TW") {
-                        tw_cost += cost;
-                        tw_value += value;
-                    } else {
-                        us_cost += cost;
-                        us_value += value;
-                    }
-                }
-            }
-        }
+/// Approximate days spanned by a Yahoo range ("3mo", "5y"); None for "max"
+fn range_days(range: &str) -> Option<i64> {
+    if let Some(months) = range.strip_suffix("mo") {
+        return months.parse::<i64>().ok().map(|m| m * 31);
+    }
+    if let Some(days) = range.strip_suffix('d') {
+        return days.parse().ok();
+    }
+    range.strip_suffix('y')?.parse::<i64>().ok().map(|y| y * 366)
+}
 
-        let tw_gain = tw_value - tw_cost;
-        let tw_gain_pct = if tw_cost > 0.0 { (tw_gain / tw_cost) * 100.0 } else { 0.0 };
+/// Days of total value shown in the summary sparkline (and of position snapshots kept)
+const VALUE_HISTORY_DAYS: i64 = 30;
+/// Days of total value kept, so year-to-date returns have a baseline
+const VALUE_HISTORY_KEEP_DAYS: i64 = 400;
+
+/// One block character per value, scaled between the lowest and highest
+fn sparkline(values: &[f64]) -> String {
+    const LEVELS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+    let (low, high) = values.iter().fold((f64::MAX, f64::MIN), |(lo, hi), v| (lo.min(*v), hi.max(*v)));
+    let span = (high - low).max(f64::EPSILON);
+    values.iter().map(|v| LEVELS[((v - low) / span * 7.0).round() as usize]).collect()
+}
 
-        let us_gain = us_value - us_cost;
-        let us_gain_pct = if us_cost > 0.0 { (us_gain / us_cost) * 100.0 } else { 0.0 };
+/// Bar of `GAUGE_WIDTH` cells filled to `pct` percent, in eighth-block steps
+fn gauge_bar(pct: f64) -> String {
+    fill_bar(pct, GAUGE_WIDTH)
+}
 
-        (tw_value, tw_gain, tw_gain_pct, us_value, us_gain, us_gain_pct)
+/// Bar of `width` cells filled to `pct` percent, in eighth-block steps
+fn fill_bar(pct: f64, width: usize) -> String {
+    const PARTIAL: [char; 8] = [' ', '▏', '▎', '▍', '▌', '▋', '▊', '▉'];
+    let eighths = (pct.clamp(0.0, 100.0) / 100.0 * (width * 8) as f64).round() as usize;
+    let mut bar = "█".repeat(eighths / 8);
+    if eighths / 8 < width {
+        bar.push(PARTIAL[eighths % 8]);
+        bar.push_str(&"·".repeat(width - eighths / 8 - 1));
     }
+    bar
+}
 
-    fn next_row(&mut self) {
-        let len = if self.active_section == 0 {
-            if self.view_combined { self.combined_tw_stocks.len() } else { self.tw_stocks.len() }
-        } else {
-            if self.view_combined { self.combined_us_stocks.len() } else { self.us_stocks.len() }
-        };
+/// Expression for computed columns: numbers, variables, + - * / ^, parentheses and function calls
+#[derive(Debug, Clone)]
+enum Expr {
+    Num(f64),
+    Var(String),
+    Neg(Box<Expr>),
+    Binary(char, Box<Expr>, Box<Expr>),
+    Call(String, Vec<Expr>),
+}
 
-        if len == 0 {
-            return;
+impl Expr {
+    fn parse(input: &str) -> Result<Expr, String> {
+        let tokens = Self::tokenize(input)?;
+        let mut pos = 0;
+        let expr = Self::parse_sum(&tokens, &mut pos)?;
+        match tokens.get(pos) {
+            None => Ok(expr),
+            Some(token) => Err(format!("unexpected '{}'", token)),
         }
+    }
 
-        let state = if self.active_section == 0 {
-            &mut self.table_state_tw
-        } else {
-            &mut self.table_state_us
-        };
-
-        let i = match state.selected() {
-            Some(i) => (i + 1).min(len - 1),
-            None => 0,
-        };
-        state.select(Some(i));
+    fn tokenize(input: &str) -> Result<Vec<String>, String> {
+        let mut tokens = Vec::new();
+        let mut chars = input.chars().peekable();
+        while let Some(&c) = chars.peek() {
+            if c.is_whitespace() {
+                chars.next();
+            } else if c.is_ascii_digit() || c == '.' {
+                let mut number = String::new();
+                while let Some(&d) = chars.peek().filter(|d| d.is_ascii_digit() || **d == '.') {
+                    number.push(d);
+                    chars.next();
+                }
+                tokens.push(number);
+            } else if c.is_alphabetic() || c == '_' {
+                let mut ident = String::new();
+                while let Some(&d) = chars.peek().filter(|d| d.is_alphanumeric() || **d == '_') {
+                    ident.push(d);
+                    chars.next();
+                }
+                tokens.push(ident);
+            } else if "+-*/^(),".contains(c) {
+                tokens.push(c.to_string());
+                chars.next();
+            } else {
+                return Err(format!("unexpected character '{}'", c));
+            }
+        }
+        Ok(tokens)
     }
 
-    fn prev_row(&mut self) {
-        let state = if self.active_section == 0 {
-            &mut self.table_state_tw
-        } else {
-            &mut self.table_state_us
-        };
+    fn parse_sum(tokens: &[String], pos: &mut usize) -> Result<Expr, String> {
+        let mut left = Self::parse_product(tokens, pos)?;
+        while let Some(op) = tokens.get(*pos).filter(|t| *t == "+" || *t == "-") {
+            let op = op.chars().next().unwrap_or('+');
+            *pos += 1;
+            left = Expr::Binary(op, Box::new(left), Box::new(Self::parse_product(tokens, pos)?));
+        }
+        Ok(left)
+    }
 
-        let i = match state.selected() {
-            Some(i) => i.saturating_sub(1),
-            None => 0,
-        };
-        state.select(Some(i));
+    fn parse_product(tokens: &[String], pos: &mut usize) -> Result<Expr, String> {
+        let mut left = Self::parse_power(tokens, pos)?;
+        while let Some(op) = tokens.get(*pos).filter(|t| *t == "*" || *t == "/") {
+            let op = op.chars().next().unwrap_or('*');
+            *pos += 1;
+            left = Expr::Binary(op, Box::new(left), Box::new(Self::parse_power(tokens, pos)?));
+        }
+        Ok(left)
     }
 
-    fn get_selected_stock(&self) -> Option<&Stock> {
-        let (stocks, state) = if self.active_section == 0 {
-            (self.get_active_tw_stocks(), &self.table_state_tw)
-        } else {
-            (self.get_active_us_stocks(), &self.table_state_us)
-        };
+    fn parse_power(tokens: &[String], pos: &mut usize) -> Result<Expr, String> {
+        let base = Self::parse_unary(tokens, pos)?;
+        if tokens.get(*pos).is_some_and(|t| t == "^") {
+            *pos += 1;
+            // Right-associative
+            return Ok(Expr::Binary('^', Box::new(base), Box::new(Self::parse_power(tokens, pos)?)));
+        }
+        Ok(base)
+    }
 
-        state.selected().and_then(|i| stocks.get(i))
+    fn parse_unary(tokens: &[String], pos: &mut usize) -> Result<Expr, String> {
+        let token = tokens.get(*pos).ok_or("unexpected end of expression")?;
+        *pos += 1;
+        match token.as_str() {
+            "-" => Ok(Expr::Neg(Box::new(Self::parse_unary(tokens, pos)?))),
+            "(" => {
+                let inner = Self::parse_sum(tokens, pos)?;
+                Self::expect(tokens, pos, ")")?;
+                Ok(inner)
+            }
+            t if t.starts_with(|c: char| c.is_ascii_digit() || c == '.') => {
+                t.parse().map(Expr::Num).map_err(|_| format!("bad number '{}'", t))
+            }
+            t if t.starts_with(|c: char| c.is_alphabetic() || c == '_') => {
+                if tokens.get(*pos).is_some_and(|t| t == "(") {
+                    *pos += 1;
+                    let mut args = Vec::new();
+                    if tokens.get(*pos).is_some_and(|t| t == ")") {
+                        *pos += 1;
+                    } else {
+                        loop {
+                            args.push(Self::parse_sum(tokens, pos)?);
+                            match tokens.get(*pos).map(|t| t.as_str()) {
+                                Some(",") => *pos += 1,
+                                _ => break,
+                            }
+                        }
+                        Self::expect(tokens, pos, ")")?;
+                    }
+                    Ok(Expr::Call(t.to_lowercase(), args))
+                } else {
+                    Ok(Expr::Var(t.to_lowercase()))
+                }
+            }
+            t => Err(format!("unexpected '{}'", t)),
+        }
     }
 
-    fn add_stock(&mut self, symbol: String, display: String, name: String, quantity: f64, cost_basis: f64) -> Result<()> {
-        if let Some(portfolio) = self.portfolios.get(self.current_portfolio_idx) {
-            let mut stocks = Self::load_stocks_from_file(&portfolio.file_path)?;
-            stocks.push(Stock {
-                symbol,
-                display,
-                name,
-                quantity,
-                cost_basis,
-                price_data: None,
-                historical: None,
-                portfolio_name: portfolio.name.clone(),
-            });
-            self.save_stocks(&portfolio.name, &stocks)?;
+    fn expect(tokens: &[String], pos: &mut usize, expected: &str) -> Result<(), String> {
+        if tokens.get(*pos).is_some_and(|t| t == expected) {
+            *pos += 1;
+            Ok(())
+        } else {
+            Err(format!("expected '{}'", expected))
         }
-        Ok(())
     }
 
-    fn edit_stock(&mut self, symbol: &str, quantity: f64, cost_basis: f64) -> Result<()> {
-        if let Some(portfolio) = self.portfolios.get(self.current_portfolio_idx) {
-            let mut stocks = Self::load_stocks_from_file(&portfolio.file_path)?;
-            if let Some(stock) = stocks.iter_mut().find(|s| s.symbol == symbol) {
-                stock.quantity = quantity;
-                stock.cost_basis = cost_basis;
+    /// True if the expression calls any history-based function
+    fn uses_history(&self) -> bool {
+        match self {
+            Expr::Num(_) | Expr::Var(_) => false,
+            Expr::Neg(inner) => inner.uses_history(),
+            Expr::Binary(_, a, b) => a.uses_history() || b.uses_history(),
+            Expr::Call(name, args) => {
+                matches!(name.as_str(), "sma" | "high" | "low" | "ret" | "vol") || args.iter().any(|a| a.uses_history())
             }
-            self.save_stocks(&portfolio.name, &stocks)?;
         }
-        Ok(())
     }
 
-    fn delete_stock(&mut self, symbol: &str) -> Result<()> {
-        if let Some(portfolio) = self.portfolios.get(self.current_portfolio_idx) {
-            let mut stocks = Self::load_stocks_from_file(&portfolio.file_path)?;
-            stocks.retain(|s| s.symbol != symbol);
-            self.save_stocks(&portfolio.name, &stocks)?;
+    /// True if the expression reads trailing dividends (a year of history)
+    fn uses_dividends(&self) -> bool {
+        match self {
+            Expr::Num(_) => false,
+            Expr::Var(name) => matches!(name.as_str(), "div_ttm" | "div_yield" | "yoc"),
+            Expr::Neg(inner) => inner.uses_dividends(),
+            Expr::Binary(_, a, b) => a.uses_dividends() || b.uses_dividends(),
+            Expr::Call(_, args) => args.iter().any(|a| a.uses_dividends()),
         }
-        Ok(())
     }
 
-    fn create_portfolio(&mut self, name: &str) -> Result<()> {
-        let path = Self::portfolios_dir().join(format!("{}.conf", name));
-        fs::write(&path, "# Stock Portfolio Configuration\n# Format: SYMBOL|Display Name|Description|Quantity|Cost Basis\n")?;
-        self.load_portfolios()?;
-        Ok(())
+    /// Evaluate for a holding; None when a value is unavailable (no price, no history, unknown name)
+    fn eval(&self, stock: &Stock, usd_twd: f64) -> Option<f64> {
+        let value = match self {
+            Expr::Num(n) => *n,
+            Expr::Var(name) => stock_variable(stock, name, usd_twd)?,
+            Expr::Neg(inner) => -inner.eval(stock, usd_twd)?,
+            Expr::Binary(op, a, b) => {
+                let (a, b) = (a.eval(stock, usd_twd)?, b.eval(stock, usd_twd)?);
+                match op {
+                    '+' => a + b,
+                    '-' => a - b,
+                    '*' => a * b,
+                    '/' => a / b,
+                    _ => a.powf(b),
+                }
+            }
+            Expr::Call(name, args) => {
+                let args = args.iter().map(|a| a.eval(stock, usd_twd)).collect::<Option<Vec<f64>>>()?;
+                stock_function(stock, name, &args)?
+            }
+        };
+        Some(value).filter(|v| v.is_finite())
     }
 }
 
-/// Standalone blocking price fetch for use in background threads
-/// Does not use any caching - always fetches fresh data
-fn fetch_price_blocking(symbol: &str) -> Option<PriceData> {
-    // Use chart API (v7 quote API is restricted by Yahoo)
-    let urls = [
-        format!("https://query2.finance.yahoo.com/v8/finance/chart/{}", symbol),
-        format!("https://query1.finance.yahoo.com/v8/finance/chart/{}", symbol),
-    ];
-
-    for url in &urls {
-        if let Ok(response) = reqwest::blocking::Client::new()
-            .get(url)
-            .header("User-Agent", "Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36")
-            .timeout(Duration::from_secs(5))
-            .send()
-        {
-            if let Ok(data) = response.json::<serde_json::Value>() {
-                if let Some(result) = data["chart"]["result"].get(0) {
-                    let meta = &result["meta"];
-                    let price = meta["regularMarketPrice"].as_f64()
-                        .or_else(|| meta["previousClose"].as_f64());
-                    let prev_close = meta["previousClose"].as_f64()
-                        .or_else(|| meta["chartPreviousClose"].as_f64());
+/// Built-in variables available to column expressions
+fn stock_variable(stock: &Stock, name: &str, usd_twd: f64) -> Option<f64> {
+    let price = stock.price_data.as_ref().map(|d| d.price);
+    Some(match name {
+        "price" => price?,
+        "change" => stock.price_data.as_ref()?.change_percent,
+        "qty" | "quantity" => stock.quantity,
+        "cost" => stock.cost_basis,
+        "multiplier" => stock.multiplier(),
+        "value" => stock.value_local()?,
+        "cost_value" => stock.cost_local(),
+        "gain" => stock.value_local()? - stock.cost_local(),
+        "gain_pct" => (price? / stock.cost_basis - 1.0) * 100.0,
+        "pct_of_cost" => stock.value_local()? / stock.cost_local() * 100.0,
+        "change_amount" => stock.price_data.as_ref()?.change,
+        "realized" => stock.realized,
+        "day_high" => stock.price_data.as_ref()?.day_high?,
+        "day_low" => stock.price_data.as_ref()?.day_low?,
+        "day_pos" => stock.price_data.as_ref()?.day_position()? * 100.0,
+        "year_high" => stock.price_data.as_ref()?.year_high?,
+        "year_low" => stock.price_data.as_ref()?.year_low?,
+        "year_pos" => stock.price_data.as_ref()?.year_position()? * 100.0,
+        "prev_close" => stock.price_data.as_ref()?.prev_close?,
+        "open" => stock.price_data.as_ref()?.open?,
+        "gap" => stock.price_data.as_ref()?.gap_percent()?,
+        "pre_gap" => stock.pre_gap?,
+        "held" => stock.held_days()? as f64,
+        "div_ttm" => stock.dividends_ttm?,
+        "div_yield" => stock.dividends_ttm? / price? * 100.0,
+        "yoc" => stock.dividends_ttm? / Some(stock.cost_basis).filter(|c| *c > 0.0)? * 100.0,
+        "target" => stock.target?,
+        "target_pct" => price? / stock.target? * 100.0,
+        "fx" => usd_twd,
+        _ => return None,
+    })
+}
 
-                    if let (Some(price), Some(prev)) = (price, prev_close) {
-                        let change = price - prev;
-                        let change_percent = (change / prev) * 100.0;
-                        return Some(PriceData { price, change, change_percent });
-                    }
-                }
+/// Built-in functions: history stats over the last `n` daily closes, plus abs/min/max
+fn stock_function(stock: &Stock, name: &str, args: &[f64]) -> Option<f64> {
+    let closes = |n: f64| -> Option<&[f64]> {
+        let closes = &stock.historical.as_ref()?.closes;
+        let n = (n.max(1.0) as usize).min(closes.len());
+        Some(&closes[closes.len() - n..]).filter(|c| !c.is_empty())
+    };
+    Some(match (name, args) {
+        ("abs", [x]) => x.abs(),
+        ("min", [a, b]) => a.min(*b),
+        ("max", [a, b]) => a.max(*b),
+        ("sma", [n]) => {
+            let c = closes(*n)?;
+            c.iter().sum::<f64>() / c.len() as f64
+        }
+        ("high", [n]) => closes(*n)?.iter().cloned().fold(f64::MIN, f64::max),
+        ("low", [n]) => closes(*n)?.iter().cloned().fold(f64::MAX, f64::min),
+        ("ret", [n]) => {
+            let c = closes(*n + 1.0)?;
+            (c[c.len() - 1] / c[0] - 1.0) * 100.0
+        }
+        ("vol", [n]) => {
+            // Annualized volatility of daily returns, percent
+            let c = closes(*n + 1.0)?;
+            let returns: Vec<f64> = c.windows(2).map(|w| w[1] / w[0] - 1.0).collect();
+            if returns.len() < 2 {
+                return None;
             }
+            let mean = returns.iter().sum::<f64>() / returns.len() as f64;
+            let var = returns.iter().map(|r| (r - mean).powi(2)).sum::<f64>() / (returns.len() - 1) as f64;
+            var.sqrt() * 252f64.sqrt() * 100.0
         }
-    }
+        _ => return None,
+    })
+}
 
-    None
+/// `[hooks]` section: each command runs via `sh -c` with event details in `STOCK_TUI_*`
+/// env vars and the current holdings as JSON on stdin
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+struct HooksConfig {
+    refresh_complete: Option<String>,
+    alert_fired: Option<String>,
+    daily_snapshot: Option<String>, // After each view's first daily snapshot is recorded
 }
 
-fn main() -> Result<()> {
-    enable_raw_mode()?;
-    let mut stdout = io::stdout();
-    execute!(stdout, EnterAlternateScreen, EnableMouseCapture)?;
-    let backend = CrosstermBackend::new(stdout);
-    let mut terminal = Terminal::new(backend)?;
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+struct BenchmarkConfig {
+    tw: String,
+    us: String,
+    /// Index the summary's alpha line compares against; empty picks the bigger market's
+    summary: String,
+}
 
-    let mut app = App::new()?;
-    let res = run_app(&mut terminal, &mut app);
+impl Default for BenchmarkConfig {
+    fn default() -> Self {
+        BenchmarkConfig {
+            tw: "^TWII".to_string(),
+            us: "^GSPC".to_string(),
+            summary: String::new(),
+        }
+    }
+}
 
-    disable_raw_mode()?;
-    execute!(
-        terminal.backend_mut(),
-        LeaveAlternateScreen,
-        DisableMouseCapture
-    )?;
-    terminal.show_cursor()?;
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            base_currencies: vec!["TWD".to_string(), "USD".to_string()],
+            benchmarks: BenchmarkConfig::default(),
+            default_refresh: "live".to_string(),
+            fetch_concurrency: 8,
+            providers: ProvidersConfig::default(),
+            hooks: HooksConfig::default(),
+            columns: Vec::new(),
+            computed: Vec::new(),
+            gradient: GradientConfig::default(),
+            theme: ThemeConfig::default(),
+            exclude_hidden: false,
+            liquidity_fraction: 0.1,
+            language: "en".to_string(),
+            time: TimeConfig::default(),
+            market: MarketConfig::default(),
+            assumptions: AssumptionsConfig::default(),
+            summary_on_exit: false,
+            daemon: DaemonConfig::default(),
+            report: ReportConfig::default(),
+            control_socket: String::new(),
+            http: HttpConfig::default(),
+            commands: Vec::new(),
+            feeds: Vec::new(),
+            pairs: Vec::new(),
+            backfill: BackfillConfig::default(),
+            footer: Vec::new(),
+            refresh_interval: LIVE_REFRESH_INTERVAL_SECS,
+            cache: CacheConfig::default(),
+            default_sort: "change".to_string(),
+            sort_ascending: false,
+            sort: SortConfig::default(),
+            default_portfolio: String::new(),
+            hide_positions: false,
+            reinvest_dividends: true,
+        }
+    }
+}
 
-    if let Err(err) = res {
-        eprintln!("Error: {err:?}");
+/// Interface language, fixed at startup from `language` in the config
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Language {
+    En,
+    ZhTw,
+}
+
+static LANGUAGE: OnceLock<Language> = OnceLock::new();
+
+/// zh-TW interface strings, keyed by the English text they replace
+const ZH_TW_STRINGS: &str = include_str!("../locales/zh-TW.toml");
+
+/// The zh-TW translation of `text`, if the locale file has one
+fn translation(text: &str) -> Option<&'static str> {
+    static ZH_TW: OnceLock<HashMap<String, String>> = OnceLock::new();
+    ZH_TW.get_or_init(|| toml::from_str(ZH_TW_STRINGS).unwrap_or_default())
+        .get(text)
+        .map(String::as_str)
+}
+
+/// Translate a UI string into the configured language; English text is the key,
+/// and strings without a translation are shown as-is
+fn tr(text: &'static str) -> &'static str {
+    if LANGUAGE.get() != Some(&Language::ZhTw) {
+        return text;
     }
+    translation(text).unwrap_or(text)
+}
 
-    Ok(())
+/// Parse a refresh interval: "live" (every tick), "90s", "10m", "2h", or bare minutes
+fn parse_refresh_interval(s: &str) -> Option<u64> {
+    let s = s.trim().to_lowercase();
+    if s == "live" {
+        return Some(0);
+    }
+    let (number, unit) = match s.char_indices().last()? {
+        (i, 's') => (&s[..i], 1),
+        (i, 'm') => (&s[..i], 60),
+        (i, 'h') => (&s[..i], 60 * 60),
+        _ => (s.as_str(), 60),
+    };
+    number.trim().parse::<u64>().ok().map(|n| n * unit)
 }
 
-enum Action {
-    None,
-    Quit,
-    AddStock(String, String, String, f64, f64),
-    EditStock(String, f64, f64),
-    DeleteStock(String),
-    CreatePortfolio(String),
-    Refresh,
-    SwitchPortfolio(usize),
-    Sort(SortColumn),
-    ToggleLive,
-    ToggleHide,
-    SelectTwRow(usize),
-    SelectUsRow(usize),
-    ViewCombined,
-    OpenDetail,
+impl Config {
+    fn path() -> PathBuf {
+        dirs::home_dir()
+            .unwrap_or_default()
+            .join(".config/stock-tui/config.toml")
+    }
+
+    /// Load the config file, falling back to defaults when it is absent
+    fn load() -> Result<Self> {
+        let path = Self::path();
+        if !path.exists() {
+            return Ok(Config::default());
+        }
+        let content = fs::read_to_string(&path)?;
+        let mut config: Config = toml::from_str(&content)
+            .map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))?;
+        for currency in config.base_currencies.iter_mut() {
+            *currency = currency.trim().to_uppercase();
+        }
+        config.base_currencies.retain(|c| !c.is_empty());
+        Ok(config)
+    }
+
+    /// `[[commands]]` keys a built-in key of the main table already uses, so they never run there
+    fn shadowed_command_keys(&self) -> String {
+        self.commands.iter().map(|c| c.key).filter(|key| BUILTIN_KEYS.contains(*key)).collect()
+    }
 }
 
-const LIVE_REFRESH_INTERVAL_SECS: u64 = 5;
+/// Message sent from background fetch thread to main thread
+#[derive(Debug)]
+struct FetchResult {
+    symbol: String,
+    price_data: Option<PriceData>,
+}
 
-fn run_app<B: ratatui::backend::Backend>(terminal: &mut Terminal<B>, app: &mut App) -> Result<()> {
-    loop {
-        // Process any pending fetch results from background thread (non-blocking)
-        app.process_fetch_results();
+/// Message indicating a batch fetch has completed
+#[derive(Debug)]
+enum FetchMessage {
+    /// Individual price result
+    Price(FetchResult),
+    /// Exchange rate result
+    ExchangeRate(f64),
+    /// USD to another base currency (currency code, rate)
+    CurrencyRate(String, f64),
+    /// All fetches in this batch are complete
+    BatchComplete,
+    /// History backfill progress (symbols done, total)
+    Backfill(usize, usize),
+    /// Daily history loaded in the background for sparkline columns and the detail view,
+    /// keyed like `historical_cache`
+    History(String, HistoricalData),
+    /// Earnings and analyst data loaded in the background for the detail view
+    Fundamentals(String, Option<Fundamentals>),
+    /// One news feed's headlines, or "feed: error", for the load numbered by the first field
+    Feed(u64, Result<Vec<NewsItem>, String>),
+    /// TWSE reports loaded in the background for the detail view
+    Twse(TwseReports),
+}
 
-        terminal.draw(|f| ui(f, app))?;
-        // Note: clickable_regions are updated during ui() rendering
+/// Sessions of the TWSE daily reports fetched for the detail view, and the sessions a
+/// report wasn't published for yet (by report path)
+#[derive(Debug)]
+struct TwseReports {
+    institutional: Vec<(chrono::NaiveDate, HashMap<String, InstitutionalFlow>)>,
+    margin: Vec<(chrono::NaiveDate, HashMap<String, MarginBalance>)>,
+    unpublished: Vec<(&'static str, chrono::NaiveDate)>,
+}
 
-        // Live mode: start async refresh every 5 seconds (non-blocking)
-        if app.live_mode
-            && !app.is_fetching
-            && matches!(app.input_mode, InputMode::Normal)
-            && app.last_live_refresh.elapsed().as_secs() >= LIVE_REFRESH_INTERVAL_SECS
-        {
-            app.last_live_refresh = Instant::now();
-            app.start_async_refresh();
+/// Tracks clickable UI regions for mouse interaction
+#[derive(Default, Clone)]
+struct ClickableRegions {
+    /// Portfolio tab areas: (rect, portfolio_index) - index 0 = "ALL" combined view
+    portfolio_tabs: Vec<(Rect, usize)>,
+    /// Taiwan stocks table area
+    tw_table: Rect,
+    /// US stocks table area
+    us_table: Rect,
+    /// Crypto table area (empty while there are no crypto holdings)
+    crypto_table: Rect,
+    /// Individual TW stock rows: (rect, row_index)
+    tw_rows: Vec<(Rect, usize)>,
+    /// Individual US stock rows: (rect, row_index)
+    us_rows: Vec<(Rect, usize)>,
+    /// Individual crypto rows: (rect, row_index)
+    crypto_rows: Vec<(Rect, usize)>,
+    /// Footer button regions: (rect, action_name)
+    footer_buttons: Vec<(Rect, KeyCode)>, // Footer items and the key a click replays
+}
+
+#[derive(Clone, Debug)]
+struct Stock {
+    symbol: String,
+    display: String,
+    name: String,
+    quantity: f64,
+    cost_basis: f64,
+    price_data: Option<PriceData>,
+    historical: Option<HistoricalData>,
+    portfolio_name: String,
+    cost_fx: Option<f64>, // USD/TWD rate at purchase for US holdings (`fx=` field)
+    realized: f64,        // Realized P/L from partial sells, local currency (`realized=` field)
+    manual_price: Option<f64>, // Fixed mark used instead of fetching (`mark=` field)
+    benchmark: Option<String>, // Index compared against in the detail view (`bench=` field)
+    contract: Option<OptionContract>, // Set when the symbol is an OCC option symbol
+    contract_size: Option<f64>, // Futures multiplier override (`size=` field)
+    asset_class: Option<AssetClass>, // Allocation class override (`class=` field)
+    bond: Option<BondTerms>, // Individual bond terms (`face=`, `coupon=`, `maturity=` fields)
+    unit: Option<String>, // Quantity unit for metals: g, kg or oz (`unit=` field)
+    deposit: Option<DepositTerms>, // Time deposit terms (`rate=`, `start=`, `maturity=` fields)
+    refresh: Option<String>, // Live-mode refresh interval override (`refresh=` field)
+    alert_above: Option<f64>, // Alert when price rises to this level (`alert_above=` field)
+    alert_below: Option<f64>, // Alert when price falls to this level (`alert_below=` field)
+    tags: Vec<String>, // Free-form labels (`tags=` field, comma separated)
+    note: Option<String>, // Free-form note shown in the expanded row (`note=` field)
+    target: Option<f64>, // Planned exit price (`target=` field)
+    currency: Option<String>, // Currency of the quote and cost basis outside TWD/USD (`currency=` field)
+    fx_usd: Option<f64>, // USD per unit of `currency`, set once its rate is known
+    pre_gap: Option<f64>, // Opening gap % implied by the `[[pairs]]` proxy while the TW market is closed
+    purchased: Option<chrono::NaiveDate>, // Purchase date (`bought=` field)
+    first_buy: Option<chrono::NaiveDate>, // First ledger buy of the open position, when `bought=` is absent
+    dividends_ttm: Option<f64>, // Dividends per share over the trailing year, loaded for dividend columns
+    lots: usize, // Portfolio file lines merged into this row
+    lot: u32, // Which of the file's lines for this symbol, keying its ledger trades (`lot=` field)
+    ledger: bool, // Quantity and cost are replayed from the ledger and saved as `-`
+}
+
+/// Asset class used for the summary allocation breakdown
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum AssetClass {
+    Equity,
+    Option,
+    Futures,
+    Bond,
+    Metal,
+    Crypto,
+    Cash,
+}
+
+impl AssetClass {
+    const ALL: [AssetClass; 7] = [
+        AssetClass::Equity, AssetClass::Option, AssetClass::Futures,
+        AssetClass::Bond, AssetClass::Metal, AssetClass::Crypto, AssetClass::Cash,
+    ];
+
+    fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "equity" | "stock" => Some(AssetClass::Equity),
+            "option" => Some(AssetClass::Option),
+            "futures" | "future" | "commodity" => Some(AssetClass::Futures),
+            "bond" | "fixed" => Some(AssetClass::Bond),
+            "metal" | "gold" => Some(AssetClass::Metal),
+            "crypto" | "coin" => Some(AssetClass::Crypto),
+            "cash" | "deposit" => Some(AssetClass::Cash),
+            _ => None,
         }
+    }
 
-        if event::poll(Duration::from_millis(100))? {
-            let event = event::read()?;
+    fn label(&self) -> &'static str {
+        match self {
+            AssetClass::Equity => "Equity",
+            AssetClass::Option => "Options",
+            AssetClass::Futures => "Futures",
+            AssetClass::Bond => "Bonds",
+            AssetClass::Metal => "Metals",
+            AssetClass::Crypto => "Crypto",
+            AssetClass::Cash => "Cash",
+        }
+    }
 
-            let action = match event {
-                Event::Key(key) if key.kind == KeyEventKind::Press => {
-                    handle_input(app, key.code)
-                }
-                Event::Mouse(mouse) => {
-                    handle_mouse(app, mouse.kind, mouse.column, mouse.row)
-                }
-                _ => Action::None,
-            };
+    fn key(&self) -> &'static str {
+        match self {
+            AssetClass::Equity => "equity",
+            AssetClass::Option => "option",
+            AssetClass::Futures => "futures",
+            AssetClass::Bond => "bond",
+            AssetClass::Metal => "metal",
+            AssetClass::Crypto => "crypto",
+            AssetClass::Cash => "cash",
+        }
+    }
+}
 
-            match action {
-                    Action::Quit => return Ok(()),
-                    Action::AddStock(symbol, display, name, qty, cost) => {
-                        app.add_stock(symbol, display, name, qty, cost)?;
-                        app.refresh_data()?;
-                        app.input_mode = InputMode::Normal;
-                    }
-                    Action::EditStock(symbol, qty, cost) => {
-                        app.edit_stock(&symbol, qty, cost)?;
-                        app.refresh_data()?;
-                        app.input_mode = InputMode::Normal;
-                    }
-                    Action::DeleteStock(symbol) => {
-                        app.delete_stock(&symbol)?;
-                        app.refresh_data()?;
-                        app.input_mode = InputMode::Normal;
-                    }
-                    Action::CreatePortfolio(name) => {
-                        app.create_portfolio(&name)?;
-                        app.input_mode = InputMode::Normal;
-                    }
-                    Action::Refresh => {
-                        if !app.is_fetching {
-                            app.cache.clear();
-                            app.historical_cache.clear();
-                            app.start_async_refresh();
-                        }
-                    }
-                    Action::SwitchPortfolio(idx) => {
-                        app.view_combined = false;
-                        app.current_portfolio_idx = idx;
-                        app.refresh_data()?;
-                        app.table_state_tw.select(Some(0));
-                        app.table_state_us.select(Some(0));
-                    }
-                    Action::Sort(column) => {
-                        app.toggle_sort(column);
-                    }
-                    Action::ToggleLive => {
-                        app.live_mode = !app.live_mode;
-                        if app.live_mode {
-                            app.last_live_refresh = Instant::now();
-                        }
-                    }
-                    Action::ToggleHide => {
-                        app.hide_positions = !app.hide_positions;
-                    }
-                    Action::SelectTwRow(idx) => {
-                        app.active_section = 0;
-                        app.table_state_tw.select(Some(idx));
-                    }
-                    Action::SelectUsRow(idx) => {
-                        app.active_section = 1;
-                        app.table_state_us.select(Some(idx));
-                    }
-                    Action::ViewCombined => {
-                        app.view_combined = true;
-                        app.table_state_tw.select(Some(0));
-                        app.table_state_us.select(Some(0));
-                    }
-                    Action::OpenDetail => {
-                        if let Some(stock) = app.get_selected_stock() {
-                            let symbol = stock.symbol.clone();
-                            let historical = app.fetch_historical(&symbol);
-                            // Update historical data in all vectors
-                            for s in app.stocks.iter_mut().chain(app.tw_stocks.iter_mut())
-                                .chain(app.us_stocks.iter_mut()).chain(app.combined_stocks.iter_mut())
-                                .chain(app.combined_tw_stocks.iter_mut()).chain(app.combined_us_stocks.iter_mut())
-                            {
-                                if s.symbol == symbol {
-                                    s.historical = historical.clone();
-                                }
-                            }
-                            app.input_mode = InputMode::DetailView(symbol);
+/// Terms of an individual bond. Quantity counts bonds; cost basis and price are quoted
+/// in percent of par, so one bond is worth price × face / 100.
+#[derive(Clone, Debug, PartialEq)]
+struct BondTerms {
+    face: f64,
+    coupon: f64, // Annual coupon rate, percent
+    maturity: Option<chrono::NaiveDate>,
+}
+
+impl Default for BondTerms {
+    fn default() -> Self {
+        BondTerms { face: 1000.0, coupon: 0.0, maturity: None }
+    }
+}
+
+/// Time deposit earning simple interest from `start` until `maturity`. Quantity is the
+/// principal and the price is the accrued value of one unit, so cost basis is 1.
+#[derive(Clone, Debug, PartialEq)]
+struct DepositTerms {
+    rate: f64, // Annual interest rate, percent
+    start: chrono::NaiveDate,
+    maturity: Option<chrono::NaiveDate>,
+}
+
+impl DepositTerms {
+    /// Value of one unit of principal with interest accrued daily up to today (or maturity)
+    fn accrued_price(&self) -> f64 {
+        let today = Local::now().date_naive();
+        let end = self.maturity.map_or(today, |m| m.min(today));
+        let days = (end - self.start).num_days().max(0) as f64;
+        1.0 + self.rate / 100.0 * days / 365.0
+    }
+}
+
+impl BondTerms {
+    fn years_to_maturity(&self) -> Option<f64> {
+        self.maturity.map(|m| (m - Local::now().date_naive()).num_days() as f64 / 365.25)
+    }
+}
+
+/// Dollar value of a one-point move for common Yahoo futures roots (grains are quoted in cents)
+fn futures_contract_size(symbol: &str) -> Option<f64> {
+    let root = symbol.strip_suffix(FUTURES_SUFFIX)?;
+    Some(match root {
+        "GC" => 100.0,     // Gold, 100 troy oz
+        "SI" => 5000.0,    // Silver, 5,000 troy oz
+        "HG" => 25000.0,   // Copper, 25,000 lb
+        "PL" => 50.0,      // Platinum, 50 troy oz
+        "PA" => 100.0,     // Palladium, 100 troy oz
+        "CL" | "BZ" => 1000.0, // Crude oil, 1,000 bbl
+        "NG" => 10000.0,   // Natural gas, 10,000 MMBtu
+        "RB" | "HO" => 42000.0, // Gasoline / heating oil, 42,000 gal
+        "ZC" | "ZS" | "ZW" => 50.0, // Corn / soybeans / wheat, 5,000 bu in cents
+        "ES" => 50.0,      // E-mini S&P 500
+        "NQ" => 20.0,      // E-mini Nasdaq-100
+        "YM" => 5.0,       // E-mini Dow
+        "RTY" => 50.0,     // E-mini Russell 2000
+        "MES" => 5.0,
+        "MNQ" => 2.0,
+        "MGC" => 10.0,     // Micro gold
+        _ => 1.0,
+    })
+}
+
+/// Option contract details parsed from an OCC symbol, e.g. AAPL250117C00150000
+/// (root, expiry YYMMDD, C/P, strike × 1000 zero-padded to 8 digits)
+#[derive(Clone, Debug, PartialEq)]
+struct OptionContract {
+    underlying: String,
+    expiry: chrono::NaiveDate,
+    is_call: bool,
+    strike: f64,
+}
+
+impl OptionContract {
+    fn parse_occ(symbol: &str) -> Option<Self> {
+        if symbol.len() < 16 || !symbol.is_ascii() {
+            return None;
+        }
+        let (rest, strike) = symbol.split_at(symbol.len() - 8);
+        let (rest, kind) = rest.split_at(rest.len() - 1);
+        let (underlying, expiry) = rest.split_at(rest.len() - 6);
+        if underlying.is_empty() || !strike.chars().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+        let is_call = match kind {
+            "C" => true,
+            "P" => false,
+            _ => return None,
+        };
+        Some(OptionContract {
+            underlying: underlying.to_string(),
+            expiry: chrono::NaiveDate::parse_from_str(expiry, "%y%m%d").ok()?,
+            is_call,
+            strike: strike.parse::<f64>().ok()? / 1000.0,
+        })
+    }
+
+    fn days_to_expiry(&self) -> i64 {
+        (self.expiry - Local::now().date_naive()).num_days()
+    }
+
+    /// Short label such as "AAPL 150C 01/17"
+    fn label(&self) -> String {
+        format!(
+            "{} {}{} {}",
+            self.underlying,
+            self.strike,
+            if self.is_call { "C" } else { "P" },
+            self.expiry.format("%m/%d")
+        )
+    }
+}
+
+/// A fully sold holding kept in the portfolio file's archive section (lines prefixed with `~`)
+/// Format: ~SYMBOL|Display Name|Description|Quantity|Cost Basis|exit=..|closed=YYYY-MM-DD|realized=..[|opened=YYYY-MM-DD]
+#[derive(Clone, Debug)]
+struct ClosedPosition {
+    symbol: String,
+    display: String,
+    name: String,
+    quantity: f64, // Shares in the closing sale
+    cost_basis: f64,
+    exit_price: f64,
+    closed_on: chrono::NaiveDate,
+    realized: f64, // Total realized P/L including earlier partial sells, local currency
+    opened: Option<chrono::NaiveDate>, // First ledger buy of the position, when known
+    lot: Option<u32>, // The lot sold out, or None when every line of the symbol was closed
+}
+
+impl ClosedPosition {
+    fn parse(line: &str) -> Option<Self> {
+        let parts: Vec<&str> = line.strip_prefix('~')?.split('|').collect();
+        if parts.len() < 5 {
+            return None;
+        }
+        let mut closed = ClosedPosition {
+            symbol: parts[0].trim().to_string(),
+            display: parts[1].trim().to_string(),
+            name: parts[2].trim().to_string(),
+            quantity: parts[3].trim().parse().unwrap_or(0.0),
+            cost_basis: parts[4].trim().parse().unwrap_or(0.0),
+            exit_price: 0.0,
+            closed_on: Local::now().date_naive(),
+            realized: 0.0,
+            opened: None,
+            lot: None,
+        };
+        for extra in parts.iter().skip(5) {
+            if let Some((key, value)) = extra.split_once('=') {
+                let value = value.trim();
+                match key.trim() {
+                    "exit" => closed.exit_price = value.parse().unwrap_or(0.0),
+                    "closed" => {
+                        if let Ok(date) = chrono::NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+                            closed.closed_on = date;
                         }
                     }
-                    Action::None => {}
+                    "realized" => closed.realized = value.parse().unwrap_or(0.0),
+                    "opened" => closed.opened = chrono::NaiveDate::parse_from_str(value, "%Y-%m-%d").ok(),
+                    "lot" => closed.lot = value.parse().ok(),
+                    _ => {}
                 }
+            }
+        }
+        Some(closed)
+    }
+
+    fn to_conf_line(&self) -> String {
+        let mut line = format!(
+            "~{}|{}|{}|{}|{}|exit={}|closed={}|realized={}",
+            self.symbol, self.display, self.name, self.quantity, self.cost_basis,
+            self.exit_price, self.closed_on.format("%Y-%m-%d"), self.realized
+        );
+        if let Some(opened) = self.opened {
+            line.push_str(&format!("|opened={}", opened.format("%Y-%m-%d")));
+        }
+        if let Some(lot) = self.lot {
+            line.push_str(&format!("|lot={}", lot));
         }
+        line
+    }
+
+    /// Days from the first buy to the close, when the open date is known
+    fn holding_days(&self) -> Option<i64> {
+        self.opened.map(|opened| (self.closed_on - opened).num_days())
     }
 }
 
-fn handle_input(app: &mut App, key: KeyCode) -> Action {
-    match &mut app.input_mode {
-        InputMode::Normal => match key {
-            KeyCode::Char('q') => Action::Quit,
-            KeyCode::Char('0') | KeyCode::Char('`') => {
-                app.view_combined = true;
-                app.table_state_tw.select(Some(0));
-                app.table_state_us.select(Some(0));
-                Action::None
-            }
-            KeyCode::Char(c) if c.is_ascii_digit() => {
-                let idx = c.to_digit(10).unwrap() as usize - 1;
-                if idx < app.portfolios.len() {
-                    Action::SwitchPortfolio(idx)
-                } else {
-                    Action::None
-                }
-            }
-            KeyCode::Tab => {
-                app.active_section = (app.active_section + 1) % 2;
-                Action::None
-            }
-            KeyCode::Down | KeyCode::Char('j') => {
-                app.next_row();
-                Action::None
-            }
-            KeyCode::Up | KeyCode::Char('k') => {
-                app.prev_row();
-                Action::None
-            }
-            KeyCode::Right | KeyCode::Char('l') => {
-                if !app.view_combined && app.portfolios.len() > 1 {
-                    let idx = (app.current_portfolio_idx + 1) % app.portfolios.len();
-                    Action::SwitchPortfolio(idx)
-                } else {
-                    Action::None
-                }
-            }
-            KeyCode::Left | KeyCode::Char('h') => {
-                if !app.view_combined && app.portfolios.len() > 1 {
-                    let idx = if app.current_portfolio_idx == 0 {
-                        app.portfolios.len() - 1
-                    } else {
-                        app.current_portfolio_idx - 1
-                    };
-                    Action::SwitchPortfolio(idx)
-                } else {
-                    Action::None
-                }
-            }
-            KeyCode::Char('r') => Action::Refresh,
-            KeyCode::Char('a') if !app.view_combined => {
-                app.input_mode = InputMode::AddStock(AddStockState::default());
-                Action::None
-            }
-            KeyCode::Char('e') if !app.view_combined => {
-                if let Some(stock) = app.get_selected_stock() {
-                    app.input_mode = InputMode::EditStock(EditStockState {
-                        symbol: stock.symbol.clone(),
-                        quantity: stock.quantity.to_string(),
-                        cost_basis: stock.cost_basis.to_string(),
-                        step: 0,
-                    });
-                }
-                Action::None
-            }
-            KeyCode::Char('d') if !app.view_combined => {
-                if let Some(stock) = app.get_selected_stock() {
-                    app.input_mode = InputMode::DeleteConfirm(stock.symbol.clone());
-                }
-                Action::None
-            }
-            KeyCode::Char('n') => {
-                app.input_mode = InputMode::NewPortfolio(String::new());
-                Action::None
-            }
-            // Sorting keys: F1/p=Price, F2/c=Change, F3/y=Qty, F4/g=Gain, F5/G=Gain%
-            KeyCode::F(1) | KeyCode::Char('p') => Action::Sort(SortColumn::Price),
-            KeyCode::F(2) | KeyCode::Char('c') => Action::Sort(SortColumn::Change),
-            KeyCode::F(3) | KeyCode::Char('y') => Action::Sort(SortColumn::Quantity),
-            KeyCode::F(4) | KeyCode::Char('g') => Action::Sort(SortColumn::Gain),
-            KeyCode::F(5) | KeyCode::Char('G') => Action::Sort(SortColumn::GainPercent),
-            // Toggle hide positions for privacy
-            KeyCode::Char('H') => {
-                app.hide_positions = !app.hide_positions;
-                Action::None
-            }
-            // Toggle live mode (auto-refresh every 5 seconds)
-            KeyCode::Char('L') => {
-                app.live_mode = !app.live_mode;
-                if app.live_mode {
-                    app.last_live_refresh = Instant::now();
-                }
-                Action::None
-            }
-            // Toggle between gain amount and percentage in table titles
-            KeyCode::Char('T') => {
-                app.show_gain_amount = !app.show_gain_amount;
-                Action::None
-            }
-            // Enter to view stock detail - fetch historical on demand
-            KeyCode::Enter => {
-                if let Some(stock) = app.get_selected_stock() {
-                    let symbol = stock.symbol.clone();
+impl Stock {
+    fn is_tw(&self) -> bool {
+        self.symbol.contains(".TW")
+    }
 
-                    // Fetch historical on-demand for chart
-                    let historical = app.fetch_historical(&symbol);
+    /// Cryptocurrencies: Yahoo's `-USD` tickers (BTC-USD, ETH-USD) or `class=crypto`
+    fn is_crypto(&self) -> bool {
+        self.asset_class() == AssetClass::Crypto
+    }
 
-                    // Update the stock's historical data in all vectors
-                    for s in app.stocks.iter_mut() {
-                        if s.symbol == symbol {
-                            s.historical = historical.clone();
-                        }
-                    }
-                    for s in app.tw_stocks.iter_mut() {
-                        if s.symbol == symbol {
-                            s.historical = historical.clone();
-                        }
-                    }
-                    for s in app.us_stocks.iter_mut() {
-                        if s.symbol == symbol {
-                            s.historical = historical.clone();
-                        }
-                    }
-                    for s in app.combined_stocks.iter_mut() {
-                        if s.symbol == symbol {
-                            s.historical = historical.clone();
-                        }
-                    }
-                    for s in app.combined_tw_stocks.iter_mut() {
-                        if s.symbol == symbol {
-                            s.historical = historical.clone();
-                        }
-                    }
-                    for s in app.combined_us_stocks.iter_mut() {
-                        if s.symbol == symbol {
-                            s.historical = historical.clone();
-                        }
-                    }
+    /// Table the holding is listed in: 0 = Taiwan, 1 = US, 2 = crypto
+    fn section(&self) -> usize {
+        if self.is_crypto() {
+            2
+        } else if self.is_tw() {
+            0
+        } else {
+            1
+        }
+    }
 
-                    app.input_mode = InputMode::DetailView(symbol);
-                }
-                Action::None
-            }
-            _ => Action::None,
-        },
-        InputMode::DetailView(_) => match key {
-            KeyCode::Esc | KeyCode::Enter | KeyCode::Char('q') => {
-                app.input_mode = InputMode::Normal;
-                Action::None
-            }
-            _ => Action::None,
-        },
-        InputMode::AddStock(state) => match key {
-            KeyCode::Esc => {
-                app.input_mode = InputMode::Normal;
-                Action::None
-            }
-            KeyCode::Enter => {
-                if state.step < 4 {
-                    state.step += 1;
-                    Action::None
-                } else {
-                    let mut symbol = state.symbol.trim().to_uppercase();
-                    if symbol.chars().all(|c| c.is_ascii_digit()) && symbol.len() >= 4 && symbol.len() <= 6 {
-                        symbol = format!("{}.TW", symbol);
-                    }
-                    let display = if state.display.is_empty() {
-                        symbol.replace(".TW", "")
-                    } else {
-                        state.display.clone()
-                    };
-                    let name = if state.name.is_empty() {
-                        symbol.clone()
-                    } else {
-                        state.name.clone()
-                    };
-                    let quantity: f64 = state.quantity.parse().unwrap_or(0.0);
-                    let cost_basis: f64 = state.cost_basis.parse().unwrap_or(0.0);
-                    Action::AddStock(symbol, display, name, quantity, cost_basis)
-                }
-            }
-            KeyCode::Backspace => {
-                let field = match state.step {
-                    0 => &mut state.symbol,
-                    1 => &mut state.display,
-                    2 => &mut state.name,
-                    3 => &mut state.quantity,
-                    _ => &mut state.cost_basis,
-                };
-                field.pop();
-                Action::None
-            }
-            KeyCode::Char(c) => {
-                let field = match state.step {
-                    0 => &mut state.symbol,
-                    1 => &mut state.display,
-                    2 => &mut state.name,
-                    3 => &mut state.quantity,
-                    _ => &mut state.cost_basis,
-                };
-                field.push(c);
-                Action::None
-            }
-            _ => Action::None,
-        },
-        InputMode::EditStock(state) => match key {
-            KeyCode::Esc => {
-                app.input_mode = InputMode::Normal;
-                Action::None
-            }
-            KeyCode::Tab => {
-                state.step = (state.step + 1) % 2;
-                Action::None
-            }
-            KeyCode::Enter => {
-                let symbol = state.symbol.clone();
-                let quantity: f64 = state.quantity.parse().unwrap_or(0.0);
-                let cost_basis: f64 = state.cost_basis.parse().unwrap_or(0.0);
-                Action::EditStock(symbol, quantity, cost_basis)
-            }
-            KeyCode::Backspace => {
-                let field = match state.step {
-                    0 => &mut state.quantity,
-                    _ => &mut state.cost_basis,
-                };
-                field.pop();
-                Action::None
-            }
-            KeyCode::Char(c) if c.is_ascii_digit() || c == '.' => {
-                let field = match state.step {
-                    0 => &mut state.quantity,
-                    _ => &mut state.cost_basis,
-                };
-                field.push(c);
-                Action::None
-            }
-            _ => Action::None,
-        },
-        InputMode::DeleteConfirm(symbol) => match key {
-            KeyCode::Char('y') | KeyCode::Char('Y') => {
-                Action::DeleteStock(symbol.clone())
-            }
-            _ => {
-                app.input_mode = InputMode::Normal;
-                Action::None
-            }
-        },
-        InputMode::NewPortfolio(name) => match key {
-            KeyCode::Esc => {
-                app.input_mode = InputMode::Normal;
-                Action::None
-            }
-            KeyCode::Enter => {
-                if !name.is_empty() {
-                    Action::CreatePortfolio(name.clone())
-                } else {
-                    Action::None
-                }
-            }
-            KeyCode::Backspace => {
-                name.pop();
-                Action::None
-            }
-            KeyCode::Char(c) if c.is_alphanumeric() || c == '_' => {
-                name.push(c.to_ascii_lowercase());
-                Action::None
-            }
-            _ => Action::None,
-        },
+    /// Futures and commodity contracts (USD-quoted, listed in the US section)
+    fn is_future(&self) -> bool {
+        self.symbol.ends_with(FUTURES_SUFFIX)
     }
-}
 
-/// Check if a point (x, y) is inside a Rect
-fn point_in_rect(x: u16, y: u16, rect: Rect) -> bool {
-    x >= rect.x && x < rect.x + rect.width && y >= rect.y && y < rect.y + rect.height
-}
+    /// Precious metals: spot quotes such as XAUUSD=X (USD per troy ounce) or a
+    /// TW bank passbook entry such as XAU.TW (TWD per gram, priced with `mark=`)
+    fn is_metal(&self) -> bool {
+        METAL_PREFIXES.iter().any(|p| self.symbol.starts_with(p))
+    }
 
-fn handle_mouse(app: &mut App, kind: MouseEventKind, x: u16, y: u16) -> Action {
-    // Only handle left clicks
-    let is_click = matches!(kind, MouseEventKind::Down(MouseButton::Left));
+    /// Grams per unit of the metal's price quote
+    fn metal_quote_grams(&self) -> f64 {
+        if self.is_tw() { 1.0 } else { TROY_OUNCE_GRAMS }
+    }
 
-    if !is_click {
-        return Action::None;
+    /// Units per quantity: options are quoted per share but held in contracts,
+    /// futures are quoted per unit of the underlying, bonds in percent of par,
+    /// metals per ounce or gram while held in the `unit=` of choice; holdings in another
+    /// `currency=` also convert to USD here
+    fn multiplier(&self) -> f64 {
+        self.units_multiplier() * if self.is_tw() { 1.0 } else { self.fx_usd.unwrap_or(1.0) }
     }
 
-    // In detail view, any click closes it
-    if matches!(app.input_mode, InputMode::DetailView(_)) {
-        app.input_mode = InputMode::Normal;
-        return Action::None;
+    fn units_multiplier(&self) -> f64 {
+        if self.contract.is_some() {
+            OPTION_MULTIPLIER
+        } else if let Some(bond) = &self.bond {
+            bond.face / 100.0
+        } else if self.is_metal() {
+            let quote_grams = self.metal_quote_grams();
+            let unit_grams = match self.unit.as_deref() {
+                Some("g") => 1.0,
+                Some("kg") => 1000.0,
+                Some("oz") => TROY_OUNCE_GRAMS,
+                _ => quote_grams,
+            };
+            unit_grams / quote_grams
+        } else if self.is_future() {
+            self.contract_size.or_else(|| futures_contract_size(&self.symbol)).unwrap_or(1.0)
+        } else {
+            1.0
+        }
     }
 
-    // Only handle mouse in Normal mode
-    if !matches!(app.input_mode, InputMode::Normal) {
-        return Action::None;
+    /// Cost in the holding's own currency
+    fn cost_local(&self) -> f64 {
+        self.quantity * self.cost_basis * self.multiplier()
     }
 
-    let regions = &app.clickable_regions;
+    /// Explicit `class=` or the class implied by the symbol and terms
+    fn asset_class(&self) -> AssetClass {
+        if let Some(class) = self.asset_class {
+            class
+        } else if self.contract.is_some() {
+            AssetClass::Option
+        } else if self.bond.is_some() {
+            AssetClass::Bond
+        } else if self.is_metal() {
+            AssetClass::Metal
+        } else if self.deposit.is_some() {
+            AssetClass::Cash
+        } else if self.is_future() {
+            AssetClass::Futures
+        } else if self.symbol.ends_with("-USD") {
+            AssetClass::Crypto
+        } else {
+            AssetClass::Equity
+        }
+    }
 
-    // Check portfolio tabs
-    for (rect, idx) in &regions.portfolio_tabs {
-        if point_in_rect(x, y, *rect) {
-            if *idx == 0 {
-                return Action::ViewCombined;
-            } else {
-                return Action::SwitchPortfolio(*idx - 1);
-            }
+    /// Market value in the holding's own currency; unknown while a `currency=` rate is pending
+    fn value_local(&self) -> Option<f64> {
+        if self.currency.is_some() && self.fx_usd.is_none() && !self.is_tw() {
+            return None;
         }
+        self.price_data.as_ref().map(|d| self.quantity * d.price * self.multiplier())
     }
 
-    // Check TW stock table rows
-    // Click on already-selected row opens detail view
-    for (rect, row_idx) in &regions.tw_rows {
-        if point_in_rect(x, y, *rect) {
-            let currently_selected = app.table_state_tw.selected() == Some(*row_idx) && app.active_section == 0;
-            if currently_selected {
-                return Action::OpenDetail;
-            }
-            return Action::SelectTwRow(*row_idx);
+    /// Today's price change on the whole position, in TWD
+    fn day_gain_twd(&self, usd_twd: f64) -> Option<f64> {
+        let change = self.price_data.as_ref()?.change * self.quantity * self.multiplier();
+        Some(if self.is_tw() { change } else { change * usd_twd })
+    }
+
+    /// Market value converted to TWD at today's rate
+    fn value_twd(&self, usd_twd: f64) -> Option<f64> {
+        self.value_local().map(|v| if self.is_tw() { v } else { v * usd_twd })
+    }
+
+    /// Currency the holding is valued in: TWD for Taiwan listings, else `currency=` or USD
+    fn implied_currency(&self) -> String {
+        if self.is_tw() {
+            "TWD".to_string()
+        } else {
+            self.currency.clone().unwrap_or_else(|| "USD".to_string())
         }
     }
 
-    // Check US stock table rows
-    for (rect, row_idx) in &regions.us_rows {
+    /// Quote currency when it differs from the one the holding is valued in, which would
+    /// make its value and gain wrong (e.g. an LSE listing quoted in GBp held without `currency=`)
+    fn currency_mismatch(&self) -> Option<String> {
+        if self.is_priced_locally() {
+            return None;
+        }
+        let quoted = normalize_currency(self.price_data.as_ref()?.currency.as_deref()?);
+        (quoted != self.implied_currency()).then_some(quoted)
+    }
+
+    /// Description of the alert threshold the current price has crossed, if any
+    fn triggered_alert(&self) -> Option<String> {
+        let price = self.price_data.as_ref()?.price;
+        match (self.alert_above, self.alert_below) {
+            (Some(above), _) if price >= above => Some(format!("price ≥ {}", above)),
+            (_, Some(below)) if price <= below => Some(format!("price ≤ {}", below)),
+            _ => None,
+        }
+    }
+
+    /// Exchange whose sessions govern the quote; None for futures, metals, bonds and cash
+    fn market(&self) -> Option<Market> {
+        match self.asset_class() {
+            AssetClass::Equity | AssetClass::Option if self.is_tw() => Some(Market::Tw),
+            AssetClass::Equity | AssetClass::Option => Some(Market::Us),
+            _ => None,
+        }
+    }
+
+    /// `bought=` date, else the first ledger buy
+    fn purchase_date(&self) -> Option<chrono::NaiveDate> {
+        self.purchased.or(self.first_buy)
+    }
+
+    /// Merge another lot of the same symbol: quantities add, cost and purchase FX are
+    /// weighted by cost, and the holding dates from the earliest lot
+    fn absorb(&mut self, lot: Stock) {
+        let old_qty = self.quantity;
+        let old_cost = self.cost_basis;
+        let new_qty = lot.quantity;
+        let new_cost = lot.cost_basis;
+
+        let combined_qty = old_qty + new_qty;
+        let weighted_cost = if combined_qty > 0.0 {
+            ((old_qty * old_cost) + (new_qty * new_cost)) / combined_qty
+        } else {
+            0.0
+        };
+
+        // Purchase FX weighted by USD cost; unknown if either lot lacks it
+        let combined_cost = (old_qty * old_cost) + (new_qty * new_cost);
+        self.cost_fx = match (self.cost_fx, lot.cost_fx) {
+            (Some(old_fx), Some(new_fx)) if combined_cost > 0.0 => {
+                Some(((old_qty * old_cost * old_fx) + (new_qty * new_cost * new_fx)) / combined_cost)
+            }
+            _ => None,
+        };
+
+        self.quantity = combined_qty;
+        self.cost_basis = weighted_cost;
+        self.realized += lot.realized;
+        // Held since the earliest lot
+        self.purchased = self.purchase_date().into_iter().chain(lot.purchase_date()).min();
+        self.lots += lot.lots;
+    }
+
+    /// Days since purchase, when the date is known
+    fn held_days(&self) -> Option<i64> {
+        self.purchase_date().map(|d| (Local::now().date_naive() - d).num_days())
+    }
+
+    /// Average daily volume over the last `LIQUIDITY_DAYS` sessions
+    fn avg_volume(&self) -> Option<f64> {
+        let volumes = &self.historical.as_ref()?.volumes;
+        let recent = &volumes[volumes.len().saturating_sub(LIQUIDITY_DAYS)..];
+        let avg = recent.iter().sum::<u64>() as f64 / recent.len().max(1) as f64;
+        (avg > 0.0).then_some(avg)
+    }
+
+    /// Position size as a fraction of average daily volume; exchange-traded equities only
+    fn volume_fraction(&self) -> Option<f64> {
+        if self.asset_class() != AssetClass::Equity || self.quantity <= 0.0 {
+            return None;
+        }
+        self.avg_volume().map(|avg| self.quantity / avg)
+    }
+
+    /// Priced from the manual mark or deposit terms rather than a fetched quote
+    fn is_priced_locally(&self) -> bool {
+        self.manual_price.is_some() || self.deposit.is_some()
+    }
+
+    /// Replace fetched quotes with the manual mark or accrued deposit value
+    fn apply_manual_price(&mut self) {
+        let price = self.manual_price.or_else(|| self.deposit.as_ref().map(|d| d.accrued_price()));
+        if let Some(price) = price {
+            self.price_data = Some(PriceData { price, change: 0.0, change_percent: 0.0, day_high: None, day_low: None, year_high: None, year_low: None, prev_close: None, open: None, currency: None });
+        }
+    }
+
+    /// Cost in TWD. US cost converts at the purchase FX rate when recorded, so gains include
+    /// currency movement; `fx_neutral` converts at today's rate to isolate stock performance.
+    fn cost_twd(&self, usd_twd: f64, fx_neutral: bool) -> f64 {
+        let cost = self.cost_local();
+        if self.is_tw() {
+            cost
+        } else if fx_neutral {
+            cost * usd_twd
+        } else {
+            cost * self.cost_fx.unwrap_or(usd_twd)
+        }
+    }
+
+    /// Gain in TWD attributable to USD/TWD moving since purchase (zero for TW holdings)
+    fn fx_effect_twd(&self, usd_twd: f64) -> f64 {
+        if self.is_tw() {
+            0.0
+        } else {
+            self.cost_local() * (usd_twd - self.cost_fx.unwrap_or(usd_twd))
+        }
+    }
+
+    /// Portfolio file line: the five positional fields followed by optional `key=value` fields
+    fn to_conf_line(&self) -> String {
+        let mut line = if self.ledger {
+            format!("{}|{}|{}|-|-", self.symbol, self.display, self.name)
+        } else {
+            format!("{}|{}|{}|{}|{}", self.symbol, self.display, self.name, self.quantity, self.cost_basis)
+        };
+        if self.lot > 0 {
+            line.push_str(&format!("|lot={}", self.lot));
+        }
+        if let Some(fx) = self.cost_fx {
+            line.push_str(&format!("|fx={}", fx));
+        }
+        if self.realized != 0.0 {
+            line.push_str(&format!("|realized={}", self.realized));
+        }
+        if let Some(mark) = self.manual_price {
+            line.push_str(&format!("|mark={}", mark));
+        }
+        if let Some(benchmark) = &self.benchmark {
+            line.push_str(&format!("|bench={}", benchmark));
+        }
+        if let Some(size) = self.contract_size {
+            line.push_str(&format!("|size={}", size));
+        }
+        if let Some(class) = self.asset_class {
+            line.push_str(&format!("|class={}", class.key()));
+        }
+        if let Some(unit) = &self.unit {
+            line.push_str(&format!("|unit={}", unit));
+        }
+        if let Some(refresh) = &self.refresh {
+            line.push_str(&format!("|refresh={}", refresh));
+        }
+        if let Some(above) = self.alert_above {
+            line.push_str(&format!("|alert_above={}", above));
+        }
+        if let Some(below) = self.alert_below {
+            line.push_str(&format!("|alert_below={}", below));
+        }
+        if !self.tags.is_empty() {
+            line.push_str(&format!("|tags={}", self.tags.join(",")));
+        }
+        if let Some(note) = &self.note {
+            line.push_str(&format!("|note={}", note));
+        }
+        if let Some(currency) = &self.currency {
+            line.push_str(&format!("|currency={}", currency));
+        }
+        if let Some(target) = self.target {
+            line.push_str(&format!("|target={}", target));
+        }
+        if let Some(purchased) = self.purchased {
+            line.push_str(&format!("|bought={}", purchased.format("%Y-%m-%d")));
+        }
+        if let Some(deposit) = &self.deposit {
+            line.push_str(&format!("|rate={}|start={}", deposit.rate, deposit.start.format("%Y-%m-%d")));
+            if let Some(maturity) = deposit.maturity {
+                line.push_str(&format!("|maturity={}", maturity.format("%Y-%m-%d")));
+            }
+        }
+        if let Some(bond) = &self.bond {
+            line.push_str(&format!("|face={}|coupon={}", bond.face, bond.coupon));
+            if let Some(maturity) = bond.maturity {
+                line.push_str(&format!("|maturity={}", maturity.format("%Y-%m-%d")));
+            }
+        }
+        line
+    }
+}
+
+#[derive(Clone, Debug)]
+struct PriceData {
+    price: f64,
+    #[allow(dead_code)]
+    change: f64, // Kept for potential future use (e.g., displaying absolute change)
+    change_percent: f64,
+    /// Today's session high/low from the chart meta (absent for manual prices)
+    day_high: Option<f64>,
+    day_low: Option<f64>,
+    /// 52-week high/low from the chart meta
+    year_high: Option<f64>,
+    year_low: Option<f64>,
+    /// Previous session close and today's open, for judging the opening gap
+    prev_close: Option<f64>,
+    open: Option<f64>,
+    /// Currency the quote is in, from the chart meta (`GBp` for pence and the like)
+    currency: Option<String>,
+}
+
+impl PriceData {
+    /// Quote from a chart API `result`; the open falls back to the first intraday candle
+    fn from_chart(result: &serde_json::Value) -> Option<Self> {
+        let meta = &result["meta"];
+        let open = meta["regularMarketOpen"].as_f64().or_else(|| {
+            result["indicators"]["quote"][0]["open"].as_array()?.iter().find_map(|v| v.as_f64())
+        });
+        let price = meta["regularMarketPrice"].as_f64()
+            .or_else(|| meta["previousClose"].as_f64())?;
+        let prev = meta["previousClose"].as_f64()
+            .or_else(|| meta["chartPreviousClose"].as_f64())?;
+        let change = price - prev;
+        Some(PriceData {
+            price,
+            change,
+            change_percent: (change / prev) * 100.0,
+            day_high: meta["regularMarketDayHigh"].as_f64(),
+            day_low: meta["regularMarketDayLow"].as_f64(),
+            year_high: meta["fiftyTwoWeekHigh"].as_f64(),
+            year_low: meta["fiftyTwoWeekLow"].as_f64(),
+            prev_close: Some(prev),
+            open,
+            currency: meta["currency"].as_str().map(str::to_string),
+        })
+    }
+
+    /// Quote from one `msgArray` entry of a TWSE MIS `getStockInfo` response. Before the first
+    /// trade of a session (`z` is "-") the best bid stands in for the last price, then
+    /// yesterday's close. MIS has no 52-week range; `fetch_twse_quotes` fills it in.
+    fn from_twse_mis(info: &serde_json::Value) -> Option<Self> {
+        let number = |key: &str| info[key].as_str().and_then(|s| s.split('_').next()?.parse::<f64>().ok()).filter(|v| *v > 0.0);
+        let prev = number("y")?;
+        let price = number("z").or_else(|| number("b")).unwrap_or(prev);
+        let change = price - prev;
+        Some(PriceData {
+            price,
+            change,
+            change_percent: change / prev * 100.0,
+            day_high: number("h"),
+            day_low: number("l"),
+            year_high: None,
+            year_low: None,
+            prev_close: Some(prev),
+            open: number("o"),
+            currency: Some("TWD".to_string()),
+        })
+    }
+
+    /// Quote from an Alpha Vantage GLOBAL_QUOTE or CURRENCY_EXCHANGE_RATE response
+    fn from_alpha_vantage(data: &serde_json::Value) -> Option<Self> {
+        let number = |v: &serde_json::Value| v.as_str().and_then(|s| s.trim_end_matches('%').parse::<f64>().ok());
+        let rate = &data["Realtime Currency Exchange Rate"];
+        if let Some(price) = number(&rate["5. Exchange Rate"]) {
+            return Some(PriceData {
+                price,
+                change: 0.0,
+                change_percent: 0.0,
+                day_high: None,
+                day_low: None,
+                year_high: None,
+                year_low: None,
+                prev_close: None,
+                open: None,
+                currency: None,
+            });
+        }
+        let quote = &data["Global Quote"];
+        let price = number(&quote["05. price"])?;
+        Some(PriceData {
+            price,
+            change: number(&quote["09. change"]).unwrap_or(0.0),
+            change_percent: number(&quote["10. change percent"]).unwrap_or(0.0),
+            day_high: number(&quote["03. high"]),
+            day_low: number(&quote["04. low"]),
+            year_high: None,
+            year_low: None,
+            prev_close: number(&quote["08. previous close"]),
+            open: number(&quote["02. open"]),
+            currency: None,
+        })
+    }
+
+    /// Opening gap against the previous close, in percent
+    fn gap_percent(&self) -> Option<f64> {
+        Some((self.open? / self.prev_close? - 1.0) * 100.0)
+    }
+
+    /// Where the price sits within `low..high`, 0.0 at the low and 1.0 at the high
+    fn range_position(&self, high: Option<f64>, low: Option<f64>) -> Option<f64> {
+        let (high, low) = (high?, low?);
+        Some(if high > low { ((self.price - low) / (high - low)).clamp(0.0, 1.0) } else { 0.5 })
+    }
+
+    fn day_position(&self) -> Option<f64> {
+        self.range_position(self.day_high, self.day_low)
+    }
+
+    fn year_position(&self) -> Option<f64> {
+        self.range_position(self.year_high, self.year_low)
+    }
+}
+
+/// Net shares bought (+) or sold (-) by each institutional group in one TWSE session
+#[derive(Clone, Copy, Debug, Default)]
+struct InstitutionalFlow {
+    foreign: f64, // Foreign and mainland investors, excluding foreign dealers
+    trust: f64,   // Investment trusts
+    dealer: f64,  // Securities dealers
+    total: f64,
+}
+
+impl InstitutionalFlow {
+    /// Rows of the TWSE T86 report keyed by stock code. Columns: code, name, foreign
+    /// buy/sell/net, foreign dealer buy/sell/net, trust buy/sell/net, dealer net, ..., total
+    fn parse_t86(data: &serde_json::Value) -> Option<HashMap<String, InstitutionalFlow>> {
+        if data["stat"].as_str() != Some("OK") {
+            return None; // No session that day, or not published yet (after ~16:00 Taipei)
+        }
+        let shares = |v: &serde_json::Value| v.as_str().and_then(|s| s.trim().replace(',', "").parse::<f64>().ok()).unwrap_or(0.0);
+        let rows = data["data"].as_array()?;
+        Some(rows.iter().filter_map(|row| {
+            let cells = row.as_array()?;
+            let code = cells.first()?.as_str()?.trim().to_string();
+            Some((code, InstitutionalFlow {
+                foreign: shares(cells.get(4)?),
+                trust: shares(cells.get(10)?),
+                dealer: shares(cells.get(11)?),
+                total: shares(cells.last()?),
+            }))
+        }).collect())
+    }
+}
+
+/// Margin purchase and short sale balances (in lots) of one stock at the end of a TWSE session
+#[derive(Clone, Copy, Debug, Default)]
+struct MarginBalance {
+    margin: f64, // 融資 balance
+    short: f64,  // 融券 balance
+}
+
+impl MarginBalance {
+    /// Rows of the TWSE MI_MARGN report keyed by stock code. Columns: code, name, margin
+    /// buy/sell/cash repay/previous/today/limit, short buy/sell/stock repay/previous/today/limit, ...
+    fn parse_mi_margn(data: &serde_json::Value) -> Option<HashMap<String, MarginBalance>> {
+        if data["stat"].as_str() != Some("OK") {
+            return None;
+        }
+        let lots = |v: &serde_json::Value| v.as_str().and_then(|s| s.trim().replace(',', "").parse::<f64>().ok()).unwrap_or(0.0);
+        // The per-stock table is the longest one; the other is the market-wide summary
+        let rows = data["tables"].as_array().into_iter().flatten()
+            .filter_map(|table| table["data"].as_array())
+            .chain(data["data"].as_array())
+            .max_by_key(|rows| rows.len())?;
+        let balances: HashMap<String, MarginBalance> = rows.iter().filter_map(|row| {
+            let cells = row.as_array()?;
+            let code = cells.first()?.as_str()?.trim().to_string();
+            Some((code, MarginBalance { margin: lots(cells.get(6)?), short: lots(cells.get(12)?) }))
+        }).collect();
+        Some(balances).filter(|b| !b.is_empty())
+    }
+
+    /// Short balance as a percentage of the margin balance (券資比)
+    fn short_ratio(&self) -> Option<f64> {
+        (self.margin > 0.0).then(|| self.short / self.margin * 100.0)
+    }
+}
+
+/// EPS estimate vs actual for one reported quarter
+#[derive(Clone, Debug)]
+struct EarningsQuarter {
+    label: String, // e.g. "3Q2025"
+    estimate: Option<f64>,
+    actual: Option<f64>,
+    surprise_pct: Option<f64>,
+}
+
+/// Analyst view from the quoteSummary `earningsHistory`, `financialData` and `calendarEvents` modules
+#[derive(Clone, Debug, Default)]
+struct Fundamentals {
+    quarters: Vec<EarningsQuarter>, // Oldest first
+    target_mean: Option<f64>,
+    target_low: Option<f64>,
+    target_high: Option<f64>,
+    recommendation: Option<String>, // "buy", "hold", ...
+    analysts: Option<u64>,
+    next_earnings: Option<chrono::NaiveDate>, // Next scheduled report (first day of the window)
+}
+
+impl Fundamentals {
+    fn from_summary(result: &serde_json::Value) -> Self {
+        let raw = |v: &serde_json::Value| v["raw"].as_f64();
+        let financial = &result["financialData"];
+        let quarters = result["earningsHistory"]["history"]
+            .as_array()
+            .map(|history| {
+                history.iter().map(|q| EarningsQuarter {
+                    label: q["quarter"]["fmt"].as_str()
+                        .and_then(|date| chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d").ok())
+                        .map(|d| format!("{}Q{}", (d.month() - 1) / 3 + 1, d.year()))
+                        .unwrap_or_else(|| q["period"].as_str().unwrap_or("?").to_string()),
+                    estimate: raw(&q["epsEstimate"]),
+                    actual: raw(&q["epsActual"]),
+                    surprise_pct: raw(&q["surprisePercent"]).map(|s| s * 100.0),
+                }).collect()
+            })
+            .unwrap_or_default();
+        Fundamentals {
+            quarters,
+            target_mean: raw(&financial["targetMeanPrice"]),
+            target_low: raw(&financial["targetLowPrice"]),
+            target_high: raw(&financial["targetHighPrice"]),
+            recommendation: financial["recommendationKey"].as_str().filter(|r| *r != "none").map(|r| r.replace('_', " ")),
+            analysts: financial["numberOfAnalystOpinions"]["raw"].as_u64(),
+            next_earnings: result["calendarEvents"]["earnings"]["earningsDate"][0]["raw"].as_i64()
+                .and_then(|ts| chrono::DateTime::from_timestamp(ts, 0))
+                .map(|d| d.with_timezone(&Local).date_naive()),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.quarters.is_empty() && self.target_mean.is_none() && self.next_earnings.is_none()
+    }
+}
+
+/// Daily OHLCV candles; all series are index-aligned with `timestamps`
+#[derive(Clone, Debug)]
+struct HistoricalData {
+    timestamps: Vec<i64>,
+    opens: Vec<f64>,
+    highs: Vec<f64>,
+    lows: Vec<f64>,
+    closes: Vec<f64>,
+    volumes: Vec<u64>,
+    dividends: Vec<(i64, f64)>, // (ex-date timestamp, amount per share), oldest first
+    last_fetched: Instant,
+}
+
+impl HistoricalData {
+    /// Parse aligned candles from JSON arrays, skipping days without a close
+    /// Accepts both Yahoo's `indicators.quote[0]` layout and our cache layout
+    fn from_series(
+        timestamps: &serde_json::Value,
+        opens: &serde_json::Value,
+        highs: &serde_json::Value,
+        lows: &serde_json::Value,
+        closes: &serde_json::Value,
+        volumes: &serde_json::Value,
+    ) -> Option<Self> {
+        let ts = timestamps.as_array()?;
+        let mut data = HistoricalData {
+            timestamps: Vec::new(),
+            opens: Vec::new(),
+            highs: Vec::new(),
+            lows: Vec::new(),
+            closes: Vec::new(),
+            volumes: Vec::new(),
+            dividends: Vec::new(),
+            last_fetched: Instant::now(),
+        };
+
+        for (i, t) in ts.iter().enumerate() {
+            let (Some(t), Some(close)) = (t.as_i64(), closes[i].as_f64()) else {
+                continue;
+            };
+            data.timestamps.push(t);
+            data.opens.push(opens[i].as_f64().unwrap_or(close));
+            data.highs.push(highs[i].as_f64().unwrap_or(close));
+            data.lows.push(lows[i].as_f64().unwrap_or(close));
+            data.closes.push(close);
+            data.volumes.push(volumes[i].as_u64().unwrap_or(0));
+        }
+
+        if data.closes.is_empty() {
+            None
+        } else {
+            Some(data)
+        }
+    }
+
+    /// Candles from an Alpha Vantage TIME_SERIES_DAILY response, trimmed to a Yahoo-style `range`
+    fn from_alpha_vantage(data: &serde_json::Value, range: &str) -> Option<Self> {
+        let series = data["Time Series (Daily)"].as_object()?;
+        let days = match range {
+            "1mo" => 31,
+            "3mo" => 92,
+            "6mo" => 183,
+            "1y" => 366,
+            "2y" => 731,
+            "5y" => 1827,
+            "10y" => 3653,
+            _ => i64::MAX / 86_400,
+        };
+        let cutoff = Local::now().timestamp().saturating_sub(days * 86_400);
+        let number = |v: &serde_json::Value| v.as_str().and_then(|s| s.parse::<f64>().ok());
+        let mut candles: Vec<(i64, &serde_json::Value)> = series
+            .iter()
+            .filter_map(|(date, candle)| {
+                let date = chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d").ok()?;
+                Some((date.and_hms_opt(0, 0, 0)?.and_utc().timestamp(), candle))
+            })
+            .filter(|(ts, _)| *ts >= cutoff)
+            .collect();
+        candles.sort_by_key(|(ts, _)| *ts);
+        let column = |key: &str| -> serde_json::Value {
+            candles.iter().map(|(_, c)| number(&c[key]).map_or(serde_json::Value::Null, serde_json::Value::from)).collect()
+        };
+        let timestamps: serde_json::Value = candles.iter().map(|(ts, _)| *ts).collect();
+        let volumes: serde_json::Value = candles.iter()
+            .map(|(_, c)| c["5. volume"].as_str().and_then(|v| v.parse::<u64>().ok()).unwrap_or(0))
+            .collect();
+        Self::from_series(&timestamps, &column("1. open"), &column("2. high"), &column("3. low"), &column("4. close"), &volumes)
+    }
+
+    /// Attach dividends from Yahoo's `events.dividends` map or our cached `[[ts, amount]]` list
+    fn with_dividends(mut self, events: &serde_json::Value) -> Self {
+        let mut dividends: Vec<(i64, f64)> = match events {
+            serde_json::Value::Object(map) => map
+                .values()
+                .filter_map(|d| Some((d["date"].as_i64()?, d["amount"].as_f64()?)))
+                .collect(),
+            serde_json::Value::Array(list) => list
+                .iter()
+                .filter_map(|d| Some((d[0].as_i64()?, d[1].as_f64()?)))
+                .collect(),
+            _ => Vec::new(),
+        };
+        dividends.sort_by_key(|&(t, _)| t);
+        self.dividends = dividends;
+        self
+    }
+
+    /// Dividends per share summed by calendar year, oldest first
+    fn dividends_by_year(&self) -> Vec<(i32, f64)> {
+        let mut years: Vec<(i32, f64)> = Vec::new();
+        for &(ts, amount) in &self.dividends {
+            let Some(date) = chrono::DateTime::from_timestamp(ts, 0) else {
+                continue;
+            };
+            let year = date.with_timezone(&Local).year();
+            match years.last_mut() {
+                Some((y, total)) if *y == year => *total += amount,
+                _ => years.push((year, amount)),
+            }
+        }
+        years
+    }
+
+    /// Return (%) of holding one share from `start` to the last close, optionally reinvesting
+    /// each dividend at the first close on or after its ex-date
+    fn total_return(&self, start: usize, reinvest: bool) -> f64 {
+        let Some(&first) = self.closes.get(start) else {
+            return 0.0;
+        };
+        let first_ts = self.timestamps[start];
+        let mut shares = 1.0;
+        let mut cash = 0.0;
+        let mut pending = self.dividends.iter().filter(|&&(t, _)| t > first_ts).peekable();
+
+        for i in start..self.closes.len() {
+            while let Some(&&(t, amount)) = pending.peek() {
+                if t > self.timestamps[i] {
+                    break;
+                }
+                if reinvest {
+                    shares += shares * amount / self.closes[i];
+                } else {
+                    cash += shares * amount;
+                }
+                pending.next();
+            }
+        }
+
+        let last = self.closes[self.closes.len() - 1];
+        ((shares * last + cash) / first - 1.0) * 100.0
+    }
+
+    /// Annualized return and volatility (%) of daily closes, and the Sharpe ratio
+    /// against `risk_free` (annual %)
+    fn sharpe(&self, risk_free: f64) -> Option<(f64, f64, f64)> {
+        let returns: Vec<f64> = self.closes.windows(2).map(|w| w[1] / w[0] - 1.0).collect();
+        if returns.len() < 2 {
+            return None;
+        }
+        let mean = returns.iter().sum::<f64>() / returns.len() as f64;
+        let var = returns.iter().map(|r| (r - mean).powi(2)).sum::<f64>() / (returns.len() - 1) as f64;
+        let vol = var.sqrt() * 252f64.sqrt() * 100.0;
+        let growth = self.closes[self.closes.len() - 1] / self.closes[0];
+        let annual = (growth.powf(252.0 / returns.len() as f64) - 1.0) * 100.0;
+        Some((annual, vol, (annual - risk_free) / vol)).filter(|(a, _, s)| a.is_finite() && s.is_finite())
+    }
+
+    /// Index of the first candle inside the detail chart window (last `CHART_DAYS` days)
+    fn chart_start(&self) -> usize {
+        let cutoff = Local::now().timestamp() - CHART_DAYS * 24 * 60 * 60;
+        self.timestamps.iter().position(|&t| t >= cutoff).unwrap_or(0)
+    }
+
+    /// Daily candles and dividends from a Yahoo chart API response
+    fn from_yahoo_chart(data: &serde_json::Value) -> Option<Self> {
+        let result = data["chart"]["result"].get(0)?;
+        let quote = &result["indicators"]["quote"][0];
+        HistoricalData::from_series(
+            &result["timestamp"],
+            &quote["open"],
+            &quote["high"],
+            &quote["low"],
+            &quote["close"],
+            &quote["volume"],
+        ).map(|h| h.with_dividends(&result["events"]["dividends"]))
+    }
+
+    /// Keep only the first `len` candles
+    fn truncate(&mut self, len: usize) {
+        self.timestamps.truncate(len);
+        self.opens.truncate(len);
+        self.highs.truncate(len);
+        self.lows.truncate(len);
+        self.closes.truncate(len);
+        self.volumes.truncate(len);
+    }
+
+    /// Candles and dividends from `start` (a Unix timestamp) on
+    fn since(&self, start: i64) -> Self {
+        let first = self.timestamps.partition_point(|t| *t < start);
+        HistoricalData {
+            timestamps: self.timestamps[first..].to_vec(),
+            opens: self.opens[first..].to_vec(),
+            highs: self.highs[first..].to_vec(),
+            lows: self.lows[first..].to_vec(),
+            closes: self.closes[first..].to_vec(),
+            volumes: self.volumes[first..].to_vec(),
+            dividends: self.dividends.iter().filter(|(t, _)| *t >= start).copied().collect(),
+            last_fetched: self.last_fetched,
+        }
+    }
+
+    fn to_cache_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "timestamps": self.timestamps,
+            "opens": self.opens,
+            "highs": self.highs,
+            "lows": self.lows,
+            "closes": self.closes,
+            "volumes": self.volumes,
+            "dividends": self.dividends
+        })
+    }
+
+    /// Write candles as CSV (Date,Open,High,Low,Close,Volume)
+    fn write_csv(&self, path: &PathBuf) -> Result<()> {
+        let mut file = File::create(path)?;
+        writeln!(file, "Date,Open,High,Low,Close,Volume")?;
+        for i in 0..self.closes.len() {
+            let date = chrono::DateTime::from_timestamp(self.timestamps[i], 0)
+                .map(|d| d.with_timezone(&Local).format("%Y-%m-%d").to_string())
+                .unwrap_or_default();
+            writeln!(
+                file,
+                "{},{},{},{},{},{}",
+                date, self.opens[i], self.highs[i], self.lows[i], self.closes[i], self.volumes[i]
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum TradeSide {
+    Buy,
+    Sell,
+}
+
+/// One trade recorded in a portfolio's `.ledger` file
+/// Format: DATE|BUY or SELL|SYMBOL|Shares|Price|Fees[|lot=N]
+#[derive(Clone, Debug)]
+struct Transaction {
+    date: chrono::NaiveDate,
+    side: TradeSide,
+    symbol: String,
+    shares: f64,
+    price: f64,
+    fees: f64,
+    lot: u32, // The portfolio line of the symbol it belongs to (`Stock::lot`)
+}
+
+/// Ledger trades of one lot of `symbol` since it was last closed out, in date order
+fn open_trades<'a>(ledger: &'a [Transaction], symbol: &str, lot: u32, closed: &[ClosedPosition]) -> Vec<&'a Transaction> {
+    let since = closed.iter()
+        .filter(|c| c.symbol == symbol && c.lot.is_none_or(|l| l == lot))
+        .map(|c| c.closed_on)
+        .max();
+    let mut trades: Vec<&Transaction> = ledger
+        .iter()
+        .filter(|t| t.symbol == symbol && t.lot == lot && since.is_none_or(|d| t.date >= d))
+        .collect();
+    trades.sort_by_key(|t| t.date);
+    trades
+}
+
+/// Quantity and weighted-average cost after replaying `trades` on top of an opening
+/// (quantity, cost) lot. Buy fees are added to the cost; sells leave the average unchanged.
+fn ledger_position(trades: &[&Transaction], opening: (f64, f64), multiplier: f64) -> (f64, f64) {
+    let (mut quantity, mut cost) = opening;
+    for trade in trades {
+        match trade.side {
+            TradeSide::Buy => {
+                let total = quantity * cost + trade.shares * trade.price + trade.fees / multiplier;
+                quantity += trade.shares;
+                cost = if quantity > 0.0 { total / quantity } else { 0.0 };
+            }
+            TradeSide::Sell => {
+                quantity -= trade.shares.min(quantity);
+                if quantity <= 0.0 {
+                    quantity = 0.0;
+                    cost = 0.0;
+                }
+            }
+        }
+    }
+    (quantity, cost)
+}
+
+/// Opening BUY lot that makes the ledger agree with a holding's recorded quantity and
+/// cost, for positions held before their first ledger trade. None when the ledger
+/// already accounts for the whole quantity.
+fn opening_lot(stock: &Stock, trades: &[&Transaction]) -> Option<Transaction> {
+    let quantity = stock.quantity - net_shares(trades);
+    if quantity <= 1e-9 {
+        return None;
+    }
+    // The final average cost is linear in the opening price, so solve it from two replays
+    let multiplier = stock.multiplier();
+    let (_, at_zero) = ledger_position(trades, (quantity, 0.0), multiplier);
+    let (_, at_one) = ledger_position(trades, (quantity, 1.0), multiplier);
+    let price = if (at_one - at_zero).abs() > f64::EPSILON {
+        ((stock.cost_basis - at_zero) / (at_one - at_zero)).max(0.0)
+    } else {
+        stock.cost_basis
+    };
+    let first = trades.first().map(|t| t.date);
+    let date = match (stock.purchase_date(), first) {
+        (Some(bought), Some(first)) => bought.min(first),
+        (bought, first) => bought.or(first).unwrap_or_else(|| Local::now().date_naive()),
+    };
+    Some(Transaction {
+        date,
+        side: TradeSide::Buy,
+        symbol: stock.symbol.clone(),
+        shares: quantity,
+        price,
+        fees: 0.0,
+        lot: stock.lot,
+    })
+}
+
+/// Shares bought less shares sold across `trades`
+fn net_shares(trades: &[&Transaction]) -> f64 {
+    trades.iter().map(|t| match t.side {
+        TradeSide::Buy => t.shares,
+        TradeSide::Sell => -t.shares,
+    }).sum()
+}
+
+impl Transaction {
+    fn parse(line: &str) -> Option<Self> {
+        let parts: Vec<&str> = line.split('|').map(|p| p.trim()).collect();
+        if parts.len() < 6 || line.starts_with('#') {
+            return None;
+        }
+        Some(Transaction {
+            date: chrono::NaiveDate::parse_from_str(parts[0], "%Y-%m-%d").ok()?,
+            side: match parts[1] {
+                "BUY" => TradeSide::Buy,
+                "SELL" => TradeSide::Sell,
+                _ => return None,
+            },
+            symbol: parts[2].to_string(),
+            shares: parts[3].parse().ok()?,
+            price: parts[4].parse().ok()?,
+            fees: parts[5].parse().unwrap_or(0.0),
+            lot: parts.iter()
+                .skip(6)
+                .find_map(|p| p.strip_prefix("lot=")?.parse().ok())
+                .unwrap_or(0),
+        })
+    }
+
+    fn to_ledger_line(&self) -> String {
+        let side = match self.side {
+            TradeSide::Buy => "BUY",
+            TradeSide::Sell => "SELL",
+        };
+        let mut line = format!("{}|{}|{}|{}|{}|{}", self.date.format("%Y-%m-%d"), side, self.symbol, self.shares, self.price, self.fees);
+        if self.lot > 0 {
+            line.push_str(&format!("|lot={}", self.lot));
+        }
+        line
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Portfolio {
+    name: String,
+    file_path: PathBuf,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SortColumn {
+    Price,
+    Change,
+    Quantity,
+    Gain,
+    GainPercent,
+    Symbol,
+    DayGain,
+    Weight,
+    Custom(usize), // Index into `App::custom_columns`
+}
+
+impl SortColumn {
+    /// Built-in columns in the order the sort menu lists them, with their shortcut keys
+    const MENU: [(SortColumn, &'static str, &'static str); 8] = [
+        (SortColumn::Symbol, "Symbol", ""),
+        (SortColumn::Price, "Price", "p"),
+        (SortColumn::Change, "Change %", "c"),
+        (SortColumn::DayGain, "Day gain", ""),
+        (SortColumn::Quantity, "Quantity", "y"),
+        (SortColumn::Gain, "Gain", "g"),
+        (SortColumn::GainPercent, "Gain %", "G"),
+        (SortColumn::Weight, "Weight (share of value)", ""),
+    ];
+
+    /// Column for a `default_sort` name: a built-in or a custom column's name
+    fn from_name(name: &str, custom: &[CustomColumn]) -> Option<SortColumn> {
+        let builtin = match name.trim().to_lowercase().as_str() {
+            "symbol" => Some(SortColumn::Symbol),
+            "price" => Some(SortColumn::Price),
+            "change" => Some(SortColumn::Change),
+            "day_gain" => Some(SortColumn::DayGain),
+            "quantity" => Some(SortColumn::Quantity),
+            "gain" => Some(SortColumn::Gain),
+            "gain_pct" => Some(SortColumn::GainPercent),
+            "weight" => Some(SortColumn::Weight),
+            _ => None,
+        };
+        builtin.or_else(|| custom.iter().position(|c| c.name.eq_ignore_ascii_case(name.trim())).map(SortColumn::Custom))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SortDirection {
+    Ascending,
+    Descending,
+}
+
+#[derive(Debug)]
+enum InputMode {
+    Normal,
+    AddStock(AddStockState),
+    EditStock(EditStockState),
+    AlertEdit(AlertEditState),
+    Palette(PaletteState),
+    SortMenu(usize), // Highlighted entry
+    Find(String), // Type-ahead query after `f`
+    Filter, // Editing `App::filter` after `/`
+    BatchDelete,
+    BatchMove(String), // Target portfolio number or name being typed
+    BatchTag(String),
+
+    DeleteConfirm(String),
+    NewPortfolio(NewPortfolioState),
+    DetailView(String), // Symbol being viewed in detail
+    DcaSetup(DcaState),
+    Trade(TradeState),
+    Exposure(ExposureState),
+    Allocation(AllocationState),
+    Archive(ArchiveState),
+    Changes(ChangesState),
+    Equity(EquityState),
+    Reconcile(ReconcileState),
+    ReplaySetup(String), // Session date being typed, YYYY-MM-DD
+    Replay(ReplayState),
+    News(NewsState),
+    DcaReport(DcaReport),
+}
+
+#[derive(Debug, Default)]
+struct AddStockState {
+    step: usize,
+    symbol: String,
+    display: String,
+    name: String,
+    quantity: String,
+    cost_basis: String,
+}
+
+#[derive(Debug, Default)]
+struct EditStockState {
+    symbol: String,
+    quantity: String,
+    cost_basis: String,
+    step: usize, // 0 = quantity, 1 = cost_basis
+}
+
+/// Persisted alert suppression (`~/.config/stock-tui/alerts.json`) so a relaunch
+/// doesn't re-fire alerts that were already dealt with
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+struct AlertState {
+    muted: Vec<String>, // "SYMBOL|rule" keys; dropped once the alert stops triggering
+    snoozed_until: Option<i64>, // Unix timestamp
+}
+
+/// A line in a stock table: a group header (grouped combined view) or a holding
+#[derive(Debug, Clone, PartialEq)]
+enum DisplayRow {
+    Group(String),            // Portfolio name
+    Stock(usize),             // Index into the section's stock list
+    Subtotal(Option<String>), // Group subtotal, or the whole table's when `None`
+}
+
+/// Operation applied to every marked holding in the current portfolio
+#[derive(Debug, Clone)]
+enum BatchOp {
+    Delete,
+    Move(usize), // Target portfolio index
+    Tag(String),
+}
+
+/// Ctrl+P command palette: fuzzy search over actions, portfolios and symbols
+#[derive(Debug, Default)]
+struct PaletteState {
+    query: String,
+    selected: usize,
+}
+
+#[derive(Debug, Clone)]
+enum PaletteCommand {
+    Key(KeyCode), // Replayed as a normal-mode key press
+    SwitchPortfolio(usize),
+    Sort(SortColumn),
+    Detail(String),
+    Export(String),
+}
+
+/// Every palette entry for the current view, in display order before filtering
+fn palette_commands(app: &App) -> Vec<(String, PaletteCommand)> {
+    let keys = [
+        ("Refresh prices", 'r'),
+        ("Toggle live mode", 'L'),
+        ("Toggle hide positions", 'H'),
+        ("Toggle FX-neutral gains", 'F'),
+        ("Toggle individual lots", 'K'),
+        ("Toggle gain amount/percent in titles", 'T'),
+        ("Expand selected row in place", 'i'),
+        ("Widen resize column", '>'),
+        ("Narrow resize column", '<'),
+        ("Next column to resize", ']'),
+        ("Sort by price", 'p'),
+        ("Sort by change %", 'c'),
+        ("Sort by quantity", 'y'),
+        ("Sort by gain", 'g'),
+        ("Sort by gain %", 'G'),
+        ("Sort menu", 'S'),
+        ("Filter rows by symbol or name", '/'),
+        ("Add stock", 'a'),
+        ("Edit selected stock", 'e'),
+        ("Set price alerts", 'A'),
+        ("Buy more of selected stock", 'b'),
+        ("Sell selected stock", 's'),
+        ("Delete selected stock", 'd'),
+        ("New portfolio", 'n'),
+        ("DCA simulator", 'D'),
+        ("Intraday replay of a past session", 'I'),
+        ("Equity curve (daily portfolio value)", 'v'),
+        ("ETF exposure view", 'E'),
+        ("Allocation breakdown (weights by symbol and market)", 'B'),
+        ("Toggle range gauge day/52-week", 'R'),
+        ("Save screen to text/ANSI file", 'W'),
+        ("Mute alert", 'm'),
+        ("Snooze alerts", 'z'),
+        ("View all portfolios combined", '0'),
+        ("News feeds", 'N'),
+        ("Changes since yesterday", 'U'),
+        ("Quit", 'q'),
+    ];
+    let mut commands: Vec<(String, PaletteCommand)> = keys
+        .iter()
+        .map(|(label, key)| (format!("{}  [{}]", label, key), PaletteCommand::Key(KeyCode::Char(*key))))
+        .collect();
+    for command in &app.config.commands {
+        commands.push((format!("{}  [{}]", command.name, command.key), PaletteCommand::Key(KeyCode::Char(command.key))));
+    }
+    for (idx, column) in app.custom_columns.iter().enumerate() {
+        commands.push((format!("Sort by {}", column.name), PaletteCommand::Sort(SortColumn::Custom(idx))));
+    }
+    for (idx, portfolio) in app.portfolios.iter().enumerate() {
+        commands.push((format!("Switch portfolio: {}", portfolio.name), PaletteCommand::SwitchPortfolio(idx)));
+    }
+    let stocks = if app.view_combined { &app.combined_stocks } else { &app.stocks };
+    for stock in stocks {
+        commands.push((format!("Open detail: {} ({})", stock.symbol, stock.display), PaletteCommand::Detail(stock.symbol.clone())));
+    }
+    for stock in stocks {
+        commands.push((format!("Export chart: {}", stock.symbol), PaletteCommand::Export(stock.symbol.clone())));
+    }
+    commands
+}
+
+/// Case-insensitive subsequence match; higher scores for consecutive and early matches
+fn fuzzy_score(query: &str, text: &str) -> Option<i32> {
+    let text: Vec<char> = text.to_lowercase().chars().collect();
+    let mut score = 0;
+    let mut pos = 0;
+    let mut last_match: Option<usize> = None;
+    for q in query.to_lowercase().chars().filter(|c| !c.is_whitespace()) {
+        let idx = pos + text[pos..].iter().position(|c| *c == q)?;
+        score += match last_match {
+            Some(last) if idx == last + 1 => 5,
+            _ => 1 - (idx as i32).min(10) / 5,
+        };
+        last_match = Some(idx);
+        pos = idx + 1;
+    }
+    Some(score)
+}
+
+/// Palette entries matching the query, best first
+fn palette_matches(app: &App, query: &str) -> Vec<(String, PaletteCommand)> {
+    let mut scored: Vec<(i32, usize, (String, PaletteCommand))> = palette_commands(app)
+        .into_iter()
+        .enumerate()
+        .filter_map(|(i, entry)| fuzzy_score(query, &entry.0).map(|score| (score, i, entry)))
+        .collect();
+    scored.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+    scored.into_iter().map(|(_, _, entry)| entry).collect()
+}
+
+/// An alert that fired during the session, shown as a dismissible popup
+#[derive(Debug, Clone)]
+struct AlertPopup {
+    symbol: String,
+    rule: String,
+    price: f64,
+    time: chrono::DateTime<Local>,
+}
+
+#[derive(Debug, Default)]
+struct AlertEditState {
+    symbol: String,
+    above: String, // Empty = no alert
+    below: String,
+    step: usize, // 0 = above, 1 = below
+}
+
+impl AlertEditState {
+    fn field_mut(&mut self) -> &mut String {
+        if self.step == 0 { &mut self.above } else { &mut self.below }
+    }
+}
+
+#[derive(Debug)]
+struct TradeState {
+    side: TradeSide,
+    symbol: String,
+    shares: String,
+    price: String,
+    fees: String,
+    date: String,
+    step: usize, // 0 = shares, 1 = price, 2 = fees, 3 = date
+    lot: u32,    // Lot of the selected row; the first one for a row merging several
+}
+
+impl TradeState {
+    fn new(side: TradeSide, stock: &Stock) -> Self {
+        TradeState {
+            side,
+            symbol: stock.symbol.clone(),
+            shares: match side {
+                TradeSide::Buy => String::new(),
+                TradeSide::Sell => stock.quantity.to_string(),
+            },
+            price: stock.price_data.as_ref().map(|d| d.price.to_string()).unwrap_or_default(),
+            fees: "0".to_string(),
+            date: Local::now().format("%Y-%m-%d").to_string(),
+            step: 0,
+            lot: stock.lot,
+        }
+    }
+
+    fn field_mut(&mut self) -> &mut String {
+        match self.step {
+            0 => &mut self.shares,
+            1 => &mut self.price,
+            2 => &mut self.fees,
+            _ => &mut self.date,
+        }
+    }
+
+    fn to_transaction(&self) -> Option<Transaction> {
+        let shares: f64 = self.shares.parse().ok().filter(|&v: &f64| v > 0.0)?;
+        let price: f64 = self.price.parse().ok().filter(|&v: &f64| v > 0.0)?;
+        if off_tick(&self.symbol, price).is_some() {
+            return None; // TW fills always land on the tick
+        }
+        let fees: f64 = if self.fees.is_empty() { 0.0 } else { self.fees.parse().ok()? };
+        let date = chrono::NaiveDate::parse_from_str(&self.date, "%Y-%m-%d").ok()?;
+        Some(Transaction {
+            date,
+            side: self.side,
+            symbol: self.symbol.clone(),
+            shares,
+            price,
+            fees,
+            lot: self.lot,
+        })
+    }
+}
+
+/// One underlying in the ETF look-through view; values in TWD
+#[derive(Debug, Clone)]
+struct ExposureRow {
+    symbol: String,
+    direct: f64,
+    via_etfs: f64,
+    etfs: Vec<String>, // ETFs contributing indirect exposure
+}
+
+#[derive(Debug, Default)]
+struct ExposureState {
+    rows: Vec<ExposureRow>,
+    total_value: f64,
+    missing: Vec<String>, // Held ETFs without a holdings file
+    scroll: usize,
+}
+
+/// Allocation breakdown (`B`): the view's holdings by weight, merged across portfolios
+#[derive(Debug, Default)]
+struct AllocationState {
+    holdings: Vec<(String, usize, f64)>, // Display, section and TWD value, largest first
+    total_value: f64,
+    treemap: bool,
+    scroll: usize,
+}
+
+/// Column the closed positions view is ordered by
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+enum ArchiveSort {
+    Symbol,
+    #[default]
+    Closed,
+    Held,
+    Realized,
+}
+
+/// Candles per second at each replay speed; `+`/`-` step through them
+const REPLAY_SPEEDS: [u64; 4] = [1, 2, 5, 10];
+const INTRADAY_INTERVAL_SECS: i64 = 5 * 60; // Yahoo `5m` candles, kept for about 60 days
+
+/// Replay of a past session from 5-minute candles (`I`): every holding is stepped along
+/// one timeline, priced at its last close at or before the cursor
+#[derive(Debug)]
+struct ReplayState {
+    date: chrono::NaiveDate,
+    holdings: Vec<(Stock, f64, HistoricalData)>, // Holding, previous session close, the day's candles
+    times: Vec<i64>, // Every candle time across the holdings, ascending
+    cursor: usize,
+    playing: bool,
+    speed: usize, // Index into REPLAY_SPEEDS
+    last_step: Instant,
+    usd_twd: f64,
+}
+
+impl ReplayState {
+    fn new(date: chrono::NaiveDate, holdings: Vec<(Stock, f64, HistoricalData)>, usd_twd: f64) -> Self {
+        let mut times: Vec<i64> = holdings.iter().flat_map(|(_, _, candles)| candles.timestamps.iter().copied()).collect();
+        times.sort_unstable();
+        times.dedup();
+        ReplayState { date, holdings, times, cursor: 0, playing: true, speed: 0, last_step: Instant::now(), usd_twd }
+    }
+
+    /// Holdings as if quoted at timeline step `step`: change is against the previous close
+    fn holdings_at(&self, step: usize) -> Vec<Stock> {
+        let t = self.times[step];
+        self.holdings.iter().map(|(stock, prev_close, candles)| {
+            let seen = candles.timestamps.partition_point(|&c| c <= t);
+            let price = if seen == 0 { *prev_close } else { candles.closes[seen - 1] };
+            let mut stock = stock.clone();
+            stock.price_data = Some(PriceData {
+                price,
+                change: price - prev_close,
+                change_percent: if *prev_close > 0.0 { (price / prev_close - 1.0) * 100.0 } else { 0.0 },
+                day_high: candles.highs[..seen].iter().copied().reduce(f64::max),
+                day_low: candles.lows[..seen].iter().copied().reduce(f64::min),
+                year_high: None,
+                year_low: None,
+                prev_close: Some(*prev_close),
+                open: candles.opens.first().copied().filter(|_| seen > 0),
+                currency: stock.price_data.as_ref().and_then(|d| d.currency.clone()),
+            });
+            stock
+        }).collect()
+    }
+
+    /// Day gain in TWD (today's USD/TWD rate) at each step up to the cursor
+    fn gain_curve(&self) -> Vec<(f64, f64)> {
+        (0..=self.cursor)
+            .map(|step| {
+                let gain: f64 = self.holdings_at(step).iter().filter_map(|s| s.day_gain_twd(self.usd_twd)).sum();
+                (step as f64, gain)
+            })
+            .collect()
+    }
+
+    /// Advance while playing, at the chosen speed; stops on the last candle
+    fn tick(&mut self) {
+        if !self.playing || self.last_step.elapsed() < Duration::from_millis(1000 / REPLAY_SPEEDS[self.speed]) {
+            return;
+        }
+        self.last_step = Instant::now();
+        if self.cursor + 1 < self.times.len() {
+            self.cursor += 1;
+        } else {
+            self.playing = false;
+        }
+    }
+}
+
+/// A template's watch entry: symbol, display name and description
+type TemplateEntry = (&'static str, &'static str, &'static str);
+
+/// Built-in starting points for new portfolios
+const PORTFOLIO_TEMPLATES: [(&str, &[TemplateEntry]); 4] = [
+    ("TW ETFs", &[
+        ("0050.TW", "元大台灣50", "Yuanta Taiwan Top 50"),
+        ("006208.TW", "富邦台50", "Fubon Taiwan 50"),
+        ("0056.TW", "元大高股息", "Yuanta Taiwan Dividend Plus"),
+        ("00878.TW", "國泰永續高股息", "Cathay Sustainable High Dividend"),
+        ("00919.TW", "群益台灣精選高息", "Capital Taiwan Select High Dividend"),
+        ("00692.TW", "富邦公司治理", "Fubon Taiwan Corporate Governance 100"),
+    ]),
+    ("TW large caps", &[
+        ("2330.TW", "台積電", "Taiwan Semiconductor"),
+        ("2317.TW", "鴻海", "Hon Hai Precision"),
+        ("2454.TW", "聯發科", "MediaTek"),
+        ("2308.TW", "台達電", "Delta Electronics"),
+        ("2382.TW", "廣達", "Quanta Computer"),
+        ("2412.TW", "中華電", "Chunghwa Telecom"),
+        ("2881.TW", "富邦金", "Fubon Financial"),
+        ("2882.TW", "國泰金", "Cathay Financial"),
+    ]),
+    ("US mega caps", &[
+        ("AAPL", "Apple", "Apple Inc"),
+        ("MSFT", "Microsoft", "Microsoft Corp"),
+        ("NVDA", "NVIDIA", "NVIDIA Corporation"),
+        ("GOOGL", "Google", "Alphabet Inc"),
+        ("AMZN", "Amazon", "Amazon.com Inc"),
+        ("META", "Meta", "Meta Platforms"),
+        ("AVGO", "Broadcom", "Broadcom Inc"),
+        ("TSLA", "Tesla", "Tesla Inc"),
+        ("BRK-B", "Berkshire", "Berkshire Hathaway B"),
+    ]),
+    ("US index ETFs", &[
+        ("SPY", "S&P 500", "SPDR S&P 500 ETF"),
+        ("VOO", "Vanguard 500", "Vanguard S&P 500 ETF"),
+        ("VTI", "Total Market", "Vanguard Total Stock Market ETF"),
+        ("QQQ", "Nasdaq 100", "Invesco QQQ Trust"),
+        ("DIA", "Dow 30", "SPDR Dow Jones Industrial Average ETF"),
+        ("IWM", "Russell 2000", "iShares Russell 2000 ETF"),
+    ]),
+];
+
+/// Position-specific `key=value` fields left out when a portfolio is cloned; the rest
+/// (class, currency, unit, alerts, tags, ...) describe the instrument and are kept
+const CLONE_DROPPED_FIELDS: [&str; 6] = ["fx", "realized", "bought", "note", "rate", "start"];
+
+/// What a new portfolio starts with
+#[derive(Debug, Clone, PartialEq)]
+enum PortfolioSource {
+    Empty,
+    Clone(String), // Existing portfolio: its symbols with zero quantities
+    Template(usize), // Index into `PORTFOLIO_TEMPLATES`
+}
+
+impl PortfolioSource {
+    fn label(&self) -> String {
+        match self {
+            PortfolioSource::Empty => "Empty".to_string(),
+            PortfolioSource::Clone(name) => format!("Symbols of {} (zero quantities)", name),
+            PortfolioSource::Template(idx) => {
+                let (name, entries) = PORTFOLIO_TEMPLATES[*idx];
+                format!("Template: {} ({} symbols)", name, entries.len())
+            }
+        }
+    }
+}
+
+/// New portfolio dialog (`n`): its name and what it starts from
+#[derive(Debug, Default)]
+struct NewPortfolioState {
+    name: String,
+    sources: Vec<PortfolioSource>,
+    selected: usize,
+}
+
+/// Closed positions browser (`O`)
+#[derive(Debug, Default)]
+struct ArchiveState {
+    rows: Vec<(String, ClosedPosition)>, // Portfolio name, archived position
+    scroll: usize,
+    sort: ArchiveSort,
+    ascending: bool,
+}
+
+impl ArchiveState {
+    /// Order by `column`; choosing the current column again flips the direction
+    fn sort_by(&mut self, column: ArchiveSort) {
+        if self.sort == column {
+            self.ascending = !self.ascending;
+        } else {
+            self.sort = column;
+            self.ascending = column == ArchiveSort::Symbol;
+        }
+        self.rows.sort_by(|(_, a), (_, b)| {
+            let ord = match column {
+                ArchiveSort::Symbol => a.symbol.cmp(&b.symbol),
+                ArchiveSort::Closed => a.closed_on.cmp(&b.closed_on),
+                ArchiveSort::Held => a.holding_days().cmp(&b.holding_days()),
+                ArchiveSort::Realized => a.realized.partial_cmp(&b.realized).unwrap_or(std::cmp::Ordering::Equal),
+            };
+            if self.ascending { ord } else { ord.reverse() }
+        });
+        self.scroll = 0;
+    }
+}
+
+/// A portfolio file line the loader would misread
+#[derive(Debug, Clone)]
+struct LineIssue {
+    portfolio: String,
+    path: PathBuf,
+    line: usize, // 0-based line index in the file
+    text: String,
+    problem: String,
+}
+
+/// Reconciliation screen listing malformed portfolio lines, opened on load when there are any
+#[derive(Debug, Default)]
+struct ReconcileState {
+    issues: Vec<LineIssue>,
+    selected: usize,
+    editing: Option<String>, // Replacement line being typed; None while browsing
+}
+
+/// A holding in a daily positions snapshot
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SnapshotHolding {
+    symbol: String,
+    display: String,
+    quantity: f64,
+    price: f64,
+    value_twd: f64,
+    tw: bool,
+}
+
+/// One view's holdings at the last completed refresh of a day
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct PositionsSnapshot {
+    usd_twd: f64,
+    holdings: Vec<SnapshotHolding>,
+}
+
+/// Equity curve screen (`V`): the recorded daily value and cost of one view at a time
+#[derive(Debug, Default)]
+struct EquityState {
+    keys: Vec<String>, // Value history keys, the current view's first
+    selected: usize,
+}
+
+/// "What changed since yesterday" screen (`U`): today's holdings against the latest earlier snapshot
+#[derive(Debug, Default)]
+struct ChangesState {
+    since: Option<chrono::NaiveDate>, // Date of the snapshot compared against; None when there is none yet
+    previous_total: f64,
+    current_total: f64,
+    previous_rate: f64,
+    current_rate: f64,
+    fx_impact: f64, // TWD change from USD/TWD moving on USD holdings kept since the snapshot
+    new: Vec<SnapshotHolding>,
+    closed: Vec<SnapshotHolding>,
+    changed: Vec<(SnapshotHolding, SnapshotHolding)>, // (then, now), biggest value change first
+    scroll: usize,
+}
+
+const NEWS_CACHE_SECS: u64 = 10 * 60;
+
+/// One headline from an RSS or Atom feed
+#[derive(Debug, Clone)]
+struct NewsItem {
+    source: String,
+    title: String,
+    link: String,
+    published: Option<chrono::DateTime<chrono::FixedOffset>>,
+}
+
+/// Contents of each `<tag>…</tag>` element (attributes allowed on the opening tag)
+fn xml_elements<'a>(xml: &'a str, tag: &str) -> Vec<&'a str> {
+    let (open, close) = (format!("<{}", tag), format!("</{}>", tag));
+    let mut elements = Vec::new();
+    let mut rest = xml;
+    while let Some(start) = rest.find(&open) {
+        let after = &rest[start + open.len()..];
+        // `<item>` must not match `<itemCount>`
+        if !after.starts_with(|c: char| c == '>' || c.is_whitespace() || c == '/') {
+            rest = after;
+            continue;
+        }
+        let Some(body_start) = after.find('>') else {
+            break;
+        };
+        if after[..body_start].ends_with('/') {
+            elements.push(&after[..body_start]); // Self-closing: keep the attributes
+            rest = &after[body_start + 1..];
+            continue;
+        }
+        let body = &after[body_start + 1..];
+        let Some(end) = body.find(&close) else {
+            break;
+        };
+        elements.push(&body[..end]);
+        rest = &body[end + close.len()..];
+    }
+    elements
+}
+
+/// Text of the first `<tag>`, with CDATA unwrapped and common entities decoded
+fn xml_text(xml: &str, tag: &str) -> Option<String> {
+    let raw = xml_elements(xml, tag).into_iter().next()?.trim();
+    let text = match raw.strip_prefix("<![CDATA[").and_then(|r| r.strip_suffix("]]>")) {
+        Some(cdata) => cdata.to_string(),
+        None => raw
+            .replace("&lt;", "<")
+            .replace("&gt;", ">")
+            .replace("&quot;", "\"")
+            .replace("&apos;", "'")
+            .replace("&#39;", "'")
+            .replace("&amp;", "&"),
+    };
+    Some(text.trim().to_string()).filter(|t| !t.is_empty())
+}
+
+/// Items of an RSS 2.0 (`<item>`) or Atom (`<entry>`) feed; a deliberately small reader
+/// that only needs title, link and date
+fn parse_feed(source: &str, xml: &str) -> Vec<NewsItem> {
+    let rss = xml_elements(xml, "item");
+    let (entries, atom) = if rss.is_empty() { (xml_elements(xml, "entry"), true) } else { (rss, false) };
+    entries
+        .into_iter()
+        .filter_map(|entry| {
+            let title = xml_text(entry, "title")?;
+            let link = if atom {
+                // <link href="…"/>
+                xml_elements(entry, "link").into_iter().find_map(|attrs| {
+                    let start = attrs.find("href=\"")? + 6;
+                    attrs[start..].split('"').next().map(|href| href.replace("&amp;", "&"))
+                })
+            } else {
+                xml_text(entry, "link")
+            };
+            let published = if atom {
+                xml_text(entry, "published").or_else(|| xml_text(entry, "updated"))
+                    .and_then(|d| chrono::DateTime::parse_from_rfc3339(&d).ok())
+            } else {
+                xml_text(entry, "pubDate").and_then(|d| chrono::DateTime::parse_from_rfc2822(&d).ok())
+            };
+            Some(NewsItem { source: source.to_string(), title, link: link.unwrap_or_default(), published })
+        })
+        .collect()
+}
+
+fn fetch_feed(feed: &FeedConfig) -> Result<Vec<NewsItem>> {
+    let xml = reqwest::blocking::Client::new()
+        .get(&feed.url)
+        .header("User-Agent", "Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36")
+        .timeout(Duration::from_secs(10))
+        .send()?
+        .error_for_status()?
+        .text()?;
+    let items = parse_feed(&feed.name, &xml);
+    if items.is_empty() {
+        anyhow::bail!("no RSS or Atom items");
+    }
+    Ok(items)
+}
+
+/// News screen (`N`): headlines from all feeds, newest first, optionally filtered to
+/// ones mentioning holdings
+#[derive(Debug, Default)]
+struct NewsState {
+    items: Vec<NewsItem>,
+    errors: Vec<String>, // "feed: error" for feeds that failed to load
+    pending: usize,      // Feeds still loading in the background
+    /// (label, lower-case keywords): "All", "Holdings", then one per holding
+    filters: Vec<(String, Vec<String>)>,
+    filter: usize,
+    selected: usize,
+}
+
+impl NewsState {
+    fn visible(&self) -> Vec<&NewsItem> {
+        let keywords = self.filters.get(self.filter).map(|(_, k)| k.as_slice()).unwrap_or_default();
+        self.items
+            .iter()
+            .filter(|item| {
+                let title = item.title.to_lowercase();
+                self.filter == 0 || keywords.iter().any(|k| title.contains(k.as_str()))
+            })
+            .collect()
+    }
+
+    fn cycle_filter(&mut self) {
+        self.filter = (self.filter + 1) % self.filters.len().max(1);
+        self.selected = 0;
+    }
+}
+
+#[derive(Debug, Default)]
+struct DcaState {
+    step: usize, // 0 = symbol, 1 = monthly amount, 2 = years, 3 = reinvest dividends
+    symbol: String,
+    amount: String,
+    years: String,
+    drip: bool,
+}
+
+/// Result of backtesting a monthly purchase plan over historical closes
+#[derive(Debug)]
+struct DcaReport {
+    symbol: String,
+    monthly_amount: f64,
+    years: u32,
+    purchases: usize,
+    invested: f64,
+    shares: f64,
+    final_value: f64,
+    drip: bool,
+    dividends: f64, // Total dividends received (reinvested when `drip`)
+    expected_return: f64, // Annual % assumed when projecting the plan forward
+    curve: Vec<(i64, f64, f64)>, // (timestamp, invested, value) for each day since first purchase
+}
+
+impl DcaReport {
+    /// Buy `monthly_amount` worth (fractional shares) at the first close of each calendar month
+    /// from `since` onward, marking the position to market every day. With `drip`, dividends
+    /// are reinvested at the first close on or after the ex-date; otherwise they are held as cash.
+    fn simulate(symbol: &str, historical: &HistoricalData, monthly_amount: f64, years: u32, since: i64, drip: bool) -> Option<Self> {
+        let mut report = DcaReport {
+            symbol: symbol.to_string(),
+            monthly_amount,
+            years,
+            purchases: 0,
+            invested: 0.0,
+            shares: 0.0,
+            final_value: 0.0,
+            drip,
+            dividends: 0.0,
+            expected_return: 0.0,
+            curve: Vec::new(),
+        };
+        let mut last_month = None;
+        let mut cash = 0.0;
+        let mut pending = historical.dividends.iter().filter(|&&(t, _)| t >= since).peekable();
+
+        for (&t, &close) in historical.timestamps.iter().zip(historical.closes.iter()) {
+            if t < since || close <= 0.0 {
+                continue;
+            }
+            let Some(date) = chrono::DateTime::from_timestamp(t, 0) else {
+                continue;
+            };
+
+            while let Some(&&(ex_date, amount)) = pending.peek() {
+                if ex_date > t {
+                    break;
+                }
+                let payout = report.shares * amount;
+                report.dividends += payout;
+                if drip {
+                    report.shares += payout / close;
+                } else {
+                    cash += payout;
+                }
+                pending.next();
+            }
+
+            let month = (date.year(), date.month());
+            if last_month != Some(month) {
+                last_month = Some(month);
+                report.shares += monthly_amount / close;
+                report.invested += monthly_amount;
+                report.purchases += 1;
+            }
+            report.final_value = report.shares * close + cash;
+            report.curve.push((t, report.invested, report.final_value));
+        }
+
+        if report.purchases == 0 {
+            None
+        } else {
+            Some(report)
+        }
+    }
+
+    fn gain(&self) -> f64 {
+        self.final_value - self.invested
+    }
+
+    fn return_percent(&self) -> f64 {
+        if self.invested > 0.0 { self.gain() / self.invested * 100.0 } else { 0.0 }
+    }
+
+    fn average_cost(&self) -> f64 {
+        if self.shares > 0.0 { self.invested / self.shares } else { 0.0 }
+    }
+
+    /// Value after continuing the plan for another `years` at `expected_return`,
+    /// compounding monthly: today's value grows and each new purchase grows from its month
+    fn projected_value(&self) -> f64 {
+        let monthly = self.expected_return / 100.0 / 12.0;
+        let months = self.years as i32 * 12;
+        let growth = (1.0 + monthly).powi(months);
+        let contributions = if monthly.abs() < 1e-12 {
+            self.monthly_amount * months as f64
+        } else {
+            self.monthly_amount * (growth - 1.0) / monthly
+        };
+        self.final_value * growth + contributions
+    }
+}
+
+struct App {
+    config: Config,
+    portfolios: Vec<Portfolio>,
+    current_portfolio_idx: usize,
+    view_combined: bool,
+    stocks: Vec<Stock>,
+    combined_stocks: Vec<Stock>,
+    tw_stocks: Vec<Stock>,
+    us_stocks: Vec<Stock>,
+    combined_tw_stocks: Vec<Stock>,
+    combined_us_stocks: Vec<Stock>,
+    crypto_stocks: Vec<Stock>,
+    combined_crypto_stocks: Vec<Stock>,
+    usd_twd_rate: f64,
+    currency_rates: HashMap<String, f64>, // USD to each extra base currency
+    active_section: usize, // 0 = TW, 1 = US, 2 = crypto
+    table_state_tw: TableState,
+    table_state_us: TableState,
+    table_state_crypto: TableState,
+    last_update: Instant,
+    input_mode: InputMode,
+    cache: HashMap<String, (PriceData, Instant)>,
+    historical_cache: HashMap<String, HistoricalData>,
+    fundamentals_cache: HashMap<String, (Instant, Option<Fundamentals>)>, // Misses cached too
+    institutional_cache: HashMap<chrono::NaiveDate, HashMap<String, InstitutionalFlow>>, // TWSE T86 by session
+    margin_cache: HashMap<chrono::NaiveDate, HashMap<String, MarginBalance>>, // TWSE MI_MARGN by session
+    twse_loading: bool, // TWSE reports are being fetched in the background
+    twse_unpublished: HashMap<(&'static str, chrono::NaiveDate), Instant>, // Report sessions found unpublished, and when
+    sorts: [(Option<SortColumn>, SortDirection); 3], // Per section: TW, US, crypto
+    hide_positions: bool,   // Toggle with 'H' to hide cost/quantity/gain for privacy
+    live_mode: bool,        // Toggle with 'L' for auto-refresh every 5 seconds
+    show_gain_amount: bool, // Toggle with 'T' to switch between gain amount and percentage in titles
+    show_bollinger: bool,   // Toggle with 'b' in detail view to overlay Bollinger Bands
+    show_levels: bool,      // Toggle with 'v' in detail view to draw support/resistance levels
+    reinvest_dividends: bool, // Toggle with 'd' in detail view: total return with dividends reinvested or as cash
+    fx_neutral: bool,       // Toggle with 'F' to show US gains in USD, excluding USD/TWD movement
+    expand_selected: bool,  // Toggle with 'i' to show extra lines under the selected row
+    last_live_refresh: Instant,
+    clickable_regions: ClickableRegions,
+    // Async fetch infrastructure
+    fetch_receiver: Receiver<FetchMessage>,
+    rpc_receiver: Option<Receiver<RpcRequest>>, // Control-socket and HTTP requests (TUI only)
+    fetch_sender: Sender<FetchMessage>,
+    is_fetching: bool, // True when background fetch is in progress
+    status_message: Option<(String, Instant)>, // Transient message shown in the footer
+    alert_state: AlertState,
+    column_widths: HashMap<String, u16>, // Widths changed with `<`/`>`, by column name (~/.config/stock-tui/columns.json)
+    closed_positions: Vec<(String, ClosedPosition)>, // Archived positions of every portfolio, by portfolio name
+    backfill_progress: Option<(usize, usize)>, // History backfill (done, total) while it runs
+    benchmark_returns: Option<(String, [Option<f64>; 3])>, // Summary benchmark and its day/MTD/YTD returns
+    value_history: HashMap<String, BTreeMap<String, f64>>, // Daily total value (TWD) by view, then date (~/.config/stock-tui/value_history.json)
+    cost_history: HashMap<String, BTreeMap<String, f64>>, // Daily total cost (TWD), keyed the same way (~/.config/stock-tui/cost_history.json)
+    resize_column: usize, // Column `<`/`>` resize, picked with `[`/`]`
+    custom_columns: Vec<CustomColumn>, // Parsed `[[columns]]` from config
+    group_by_portfolio: bool,       // Combined view: rows under portfolio headers instead of aggregated
+    show_lots: bool,                // One row per portfolio file line instead of per symbol
+    range_year: bool,               // `range` gauge columns show the 52-week range instead of today's
+    snapshot_requested: bool,       // Dump the next rendered frame to a file (`W`)
+    collapsed_groups: Vec<String>,  // Group headers whose holdings are hidden
+    hidden_symbols: Vec<String>,    // Rows hidden from the tables for this session (x / X)
+    filter: String,                 // Fuzzy row filter typed after `/`; empty shows every row
+    marked: Vec<String>,            // Symbols marked for batch operations (Space / V)
+    mark_anchor: Option<(usize, usize)>, // (section, row) of the last Space toggle, start of a V range
+    fired_alerts: Vec<String>,      // Alert keys already announced this session
+    alert_popups: Vec<AlertPopup>,  // Pending popups, oldest first
+    news_cache: Option<(Instant, Vec<NewsItem>, Vec<String>)>, // Fetched headlines and feed errors
+    news_load: (u64, usize), // Number of the latest feed load, and its feeds still loading
+    history_requested: Vec<String>, // History cache keys loading in the background
+    fundamentals_requested: Vec<String>, // Symbols whose fundamentals are loading in the background
+    price_ticks: HashMap<String, (bool, Instant)>, // Live price moves being flashed: up?, when
+    watch_only: bool, // Ad-hoc symbols from the command line instead of the saved portfolios
+    render_generation: u64, // Bumped whenever the tables may have changed (`invalidate_rows`)
+    row_cache: HashMap<usize, SectionTable>, // Built tables by section, reused while the generation holds
+}
+
+impl App {
+    /// Load config, portfolios and quotes, opening `portfolio` (or the combined view for "ALL")
+    /// instead of the first portfolio when given
+    fn new(portfolio: Option<&str>) -> Result<Self> {
+        let (mut app, mut config_error) = Self::unloaded();
+        app.load_portfolios()?;
+        if let Some(name) = portfolio {
+            app.open_portfolio_named(name)?;
+        } else if !app.config.default_portfolio.is_empty() {
+            let name = app.config.default_portfolio.clone();
+            if let Err(err) = app.open_portfolio_named(&name) {
+                config_error = Some(format!("default_portfolio: {}", err));
+            }
+        }
+        app.finish_loading(config_error)
+    }
+
+    /// Start on the portfolio called `name`, or the combined view for "ALL"
+    fn open_portfolio_named(&mut self, name: &str) -> Result<()> {
+        if name.eq_ignore_ascii_case("ALL") {
+            self.view_combined = true;
+        } else {
+            let names: Vec<&str> = self.portfolios.iter().map(|p| p.name.as_str()).collect();
+            self.current_portfolio_idx = names.iter().position(|n| *n == name)
+                .ok_or_else(|| anyhow::anyhow!("no portfolio named '{}' (have: {}, ALL)", name, names.join(", ")))?;
+        }
+        Ok(())
+    }
+
+    /// Unsaved session over just `symbols` (`stock-tui 2330 AAPL`): a throwaway `watch`
+    /// portfolio in the temp dir instead of the saved ones, shown price-only and removed by
+    /// `end_watch` on exit; nothing is recorded to value history or snapshots
+    fn new_watch(symbols: &[String]) -> Result<Self> {
+        let (mut app, config_error) = Self::unloaded();
+        let dir = std::env::temp_dir().join("stock-tui");
+        fs::create_dir_all(&dir)?;
+        let path = dir.join(format!("watch-{}.conf", std::process::id()));
+        let lines: Vec<String> = symbols.iter()
+            .map(|s| normalize_symbol(s))
+            .map(|symbol| format!("{}|{}|{}|0|0", symbol, default_display(&symbol), default_display(&symbol)))
+            .collect();
+        fs::write(&path, lines.join("\n"))?;
+        app.portfolios = vec![Portfolio { name: "watch".to_string(), file_path: path }];
+        app.watch_only = true;
+        app.hide_positions = true;
+        app.finish_loading(config_error)
+    }
+
+    /// Fetch the first quotes and surface any config error once the app is up
+    fn finish_loading(mut self, config_error: Option<String>) -> Result<Self> {
+        self.refresh_data()?;
+        let issues = self.find_line_issues();
+        if !issues.is_empty() {
+            self.input_mode = InputMode::Reconcile(ReconcileState { issues, ..Default::default() });
+        }
+        if let Some(err) = config_error {
+            self.set_status(err);
+        }
+        Ok(self)
+    }
+
+    /// Remove a watch session's temporary portfolio
+    fn end_watch(&self) {
+        if self.watch_only {
+            for portfolio in &self.portfolios {
+                let _ = fs::remove_file(&portfolio.file_path);
+                let _ = fs::remove_file(Self::backup_path(&portfolio.file_path));
+                let _ = fs::remove_file(portfolio.file_path.with_extension("ledger"));
+            }
+        }
+    }
+
+    /// App with config and saved state but no portfolios or quotes yet, plus any config
+    /// error to report once it is up
+    fn unloaded() -> (Self, Option<String>) {
+        let (fetch_sender, fetch_receiver) = mpsc::channel();
+        let (mut config, mut config_error) = match Config::load() {
+            Ok(config) => (config, None),
+            Err(err) => (Config::default(), Some(format!("Config error, using defaults: {}", err))),
+        };
+        if let Some(format) = config.time.sanitize() {
+            config_error = Some(format!("Invalid time format '{}', using the default", format));
+        }
+        let shadowed = config.shadowed_command_keys();
+        if !shadowed.is_empty() && config_error.is_none() {
+            config_error = Some(format!("[[commands]] keys '{}' are built-in keys; pick others", shadowed));
+        }
+        let year = Local::now().year();
+        let missing = config.market.missing_calendars(year);
+        if !missing.is_empty() && config_error.is_none() {
+            config_error = Some(format!("No {} market holidays built in; add them to [market] {}", year, missing.join(" and ")));
+        }
+        let language = if config.language.eq_ignore_ascii_case("zh-TW") { Language::ZhTw } else { Language::En };
+        LANGUAGE.get_or_init(|| language);
+        let theme = Theme::from_config(&config.theme).unwrap_or_else(|err| {
+            config_error = Some(err);
+            *theme()
+        });
+        THEME.get_or_init(|| theme);
+        let mut custom_columns = Vec::new();
+        let shorthand = config.computed.iter().map(|line| match line.split_once('=') {
+            Some((name, expr)) => ColumnConfig { name: name.trim().to_string(), expr: expr.trim().to_string(), decimals: default_column_decimals(), gauge: false, percent: false, sparkline: false },
+            // `range` is a gauge of today's range; `R` switches it to the 52-week range
+            None if line.trim() == "range" => ColumnConfig { name: "Day range".to_string(), expr: "day_pos".to_string(), decimals: 0, gauge: true, percent: false, sparkline: false },
+            // `target` shows progress towards each holding's `target=` price
+            None if line.trim() == "target" => ColumnConfig { name: "Target".to_string(), expr: "target_pct".to_string(), decimals: 0, gauge: true, percent: true, sparkline: false },
+            // `spark` draws the last week's closes with the return over them
+            None if line.trim() == "spark" => ColumnConfig { name: "7d".to_string(), expr: format!("ret({})", SPARKLINE_DAYS - 1), decimals: 1, gauge: false, percent: false, sparkline: true },
+            None => ColumnConfig { name: line.trim().to_string(), expr: line.trim().to_string(), decimals: default_column_decimals(), gauge: false, percent: false, sparkline: false },
+        });
+        for column in &config.columns.iter().cloned().chain(shorthand).collect::<Vec<_>>() {
+            match Expr::parse(&column.expr) {
+                Ok(expr) => custom_columns.push(CustomColumn { name: column.name.clone(), expr, decimals: column.decimals, gauge: column.gauge, percent: column.percent, sparkline: column.sparkline }),
+                Err(err) => config_error = Some(format!("Column '{}': {}", column.name, err)),
+            }
+        }
+        if let Some(unknown) = config.footer.iter().find(|name| !FOOTER_ITEMS.contains(&name.as_str())) {
+            config_error = Some(format!("Unknown footer item '{}' (choose from {})", unknown, FOOTER_ITEMS.join(", ")));
+        }
+        match config.cache.ttls() {
+            Ok(ttls) => {
+                CACHE_TTLS.get_or_init(|| ttls);
+            }
+            Err(value) => config_error = Some(format!("Invalid [cache] lifetime '{}', using the default", value)),
+        }
+        config.refresh_interval = config.refresh_interval.max(1);
+        let sort_column = SortColumn::from_name(&config.default_sort, &custom_columns).unwrap_or_else(|| {
+            config_error = Some(format!("Unknown default_sort '{}', sorting by change %", config.default_sort));
+            SortColumn::Change
+        });
+        let sort_direction = if config.sort_ascending { SortDirection::Ascending } else { SortDirection::Descending };
+        let mut sorts = [(Some(sort_column), sort_direction); 3];
+        for (section, setting) in [&config.sort.tw, &config.sort.us, &config.sort.crypto].into_iter().enumerate() {
+            if setting.trim().is_empty() {
+                continue;
+            }
+            let mut words = setting.split_whitespace();
+            let column = words.next().and_then(|name| SortColumn::from_name(name, &custom_columns));
+            let direction = match words.next() {
+                None => Some(sort_direction),
+                Some(word) if word.eq_ignore_ascii_case("asc") => Some(SortDirection::Ascending),
+                Some(word) if word.eq_ignore_ascii_case("desc") => Some(SortDirection::Descending),
+                Some(_) => None,
+            };
+            match (column, direction, words.next()) {
+                (Some(column), Some(direction), None) => sorts[section] = (Some(column), direction),
+                _ => config_error = Some(format!("Invalid [sort] setting '{}', using default_sort", setting)),
+            }
+        }
+        let hide_positions = config.hide_positions;
+        let reinvest_dividends = config.reinvest_dividends;
+        let app = App {
+            config,
+            portfolios: Vec::new(),
+            current_portfolio_idx: 0,
+            view_combined: false,
+            stocks: Vec::new(),
+            combined_stocks: Vec::new(),
+            tw_stocks: Vec::new(),
+            us_stocks: Vec::new(),
+            combined_tw_stocks: Vec::new(),
+            combined_us_stocks: Vec::new(),
+            crypto_stocks: Vec::new(),
+            combined_crypto_stocks: Vec::new(),
+            usd_twd_rate: 32.0,
+            currency_rates: HashMap::new(),
+            active_section: 0,
+            table_state_tw: TableState::default(),
+            table_state_us: TableState::default(),
+            table_state_crypto: TableState::default(),
+            last_update: Instant::now(),
+            input_mode: InputMode::Normal,
+            cache: HashMap::new(),
+            historical_cache: HashMap::new(),
+            fundamentals_cache: HashMap::new(),
+            institutional_cache: HashMap::new(),
+            margin_cache: HashMap::new(),
+            twse_loading: false,
+            twse_unpublished: HashMap::new(),
+            sorts,
+            hide_positions,
+            live_mode: false,
+            show_gain_amount: false, // Start with percentage display
+            show_bollinger: false,
+            show_levels: false,
+            reinvest_dividends,
+            fx_neutral: false,
+            expand_selected: false,
+            last_live_refresh: Instant::now(),
+            clickable_regions: ClickableRegions::default(),
+            fetch_receiver,
+            rpc_receiver: None,
+            fetch_sender,
+            is_fetching: false,
+            status_message: None,
+            alert_state: Self::load_alert_state(),
+            column_widths: Self::load_column_widths(),
+            closed_positions: Vec::new(),
+            backfill_progress: None,
+            benchmark_returns: None,
+            value_history: Self::load_daily_totals(&Self::value_history_path()),
+            cost_history: Self::load_daily_totals(&Self::cost_history_path()),
+            resize_column: 1, // Name
+            custom_columns,
+            group_by_portfolio: false,
+            show_lots: false,
+            range_year: false,
+            snapshot_requested: false,
+            collapsed_groups: Vec::new(),
+            hidden_symbols: Vec::new(),
+            filter: String::new(),
+            marked: Vec::new(),
+            mark_anchor: None,
+            fired_alerts: Vec::new(),
+            alert_popups: Vec::new(),
+            news_cache: None,
+            news_load: (0, 0),
+            history_requested: Vec::new(),
+            fundamentals_requested: Vec::new(),
+            price_ticks: HashMap::new(),
+            watch_only: false,
+            render_generation: 0,
+            row_cache: HashMap::new(),
+        };
+        (app, config_error)
+    }
+
+    /// Forget price flashes older than `PRICE_FLASH`; true when any ended
+    fn expire_price_ticks(&mut self) -> bool {
+        let before = self.price_ticks.len();
+        self.price_ticks.retain(|_, (_, at)| at.elapsed() < PRICE_FLASH);
+        self.price_ticks.len() != before
+    }
+
+    /// Drop the cached section tables so the next frame rebuilds them
+    fn invalidate_rows(&mut self) {
+        self.render_generation += 1;
+    }
+
+    /// Show a transient message in the footer
+    fn set_status(&mut self, message: impl Into<String>) {
+        self.status_message = Some((message.into(), Instant::now()));
+    }
+
+    fn is_demo_mode() -> bool {
+        std::env::var("DEMO").map(|v| v == "true" || v == "1").unwrap_or(false)
+    }
+
+    fn portfolios_dir() -> PathBuf {
+        dirs::home_dir()
+            .unwrap_or_default()
+            .join(".config/stock-tui/portfolios")
+    }
+
+    fn cache_dir() -> PathBuf {
+        dirs::home_dir().unwrap_or_default().join(".cache/stock-tui")
+    }
+
+    /// Quotes, candles and TWSE reports, kept across reboots
+    fn cache_db_path() -> PathBuf {
+        Self::cache_dir().join("cache.db")
+    }
+
+    fn load_portfolios(&mut self) -> Result<()> {
+        // Demo mode: load from demo.conf in current directory or next to executable
+        if Self::is_demo_mode() {
+            let demo_path = std::env::current_exe()
+                .ok()
+                .and_then(|p| p.parent().map(|p| p.join("demo.conf")))
+                .filter(|p| p.exists())
+                .unwrap_or_else(|| PathBuf::from("demo.conf"));
+
+            if demo_path.exists() {
+                self.portfolios = vec![Portfolio {
+                    name: "demo".to_string(),
+                    file_path: demo_path,
+                }];
+                return Ok(());
+            }
+        }
+
+        let dir = Self::portfolios_dir();
+        fs::create_dir_all(&dir)?;
+
+        self.portfolios = fs::read_dir(&dir)?
+            .filter_map(|e| e.ok())
+            .filter(|e| e.path().extension().map(|ext| ext == "conf").unwrap_or(false))
+            .map(|e| {
+                let path = e.path();
+                let name = path.file_stem().unwrap().to_string_lossy().to_string();
+                Portfolio {
+                    name,
+                    file_path: path,
+                }
+            })
+            .collect();
+
+        // A portfolio emptied by a crash mid-save (before saves were atomic) comes back from its backup
+        let mut restored = Vec::new();
+        for portfolio in &self.portfolios {
+            let backup = Self::backup_path(&portfolio.file_path);
+            let empty = fs::metadata(&portfolio.file_path).map(|m| m.len() == 0).unwrap_or(false);
+            if let Some(previous) = fs::read_to_string(&backup).ok().filter(|p| empty && !p.trim().is_empty()) {
+                write_atomic(&portfolio.file_path, &previous)?;
+                restored.push(portfolio.name.clone());
+            }
+        }
+        if !restored.is_empty() {
+            self.set_status(format!("Restored empty portfolio {} from backup", restored.join(", ")));
+        }
+
+        // Sort with 'main' first
+        self.portfolios.sort_by(|a, b| {
+            if a.name == "main" {
+                std::cmp::Ordering::Less
+            } else if b.name == "main" {
+                std::cmp::Ordering::Greater
+            } else {
+                a.name.cmp(&b.name)
+            }
+        });
+
+        if self.portfolios.is_empty() {
+            let main_path = dir.join("main.conf");
+            write_atomic(&main_path, "# Stock Portfolio Configuration\n# Format: SYMBOL|Display Name|Description|Quantity|Cost Basis\n")?;
+            self.portfolios.push(Portfolio {
+                name: "main".to_string(),
+                file_path: main_path,
+            });
+        }
+
+        Ok(())
+    }
+
+    fn load_stocks_from_file(path: &PathBuf) -> Result<Vec<Stock>> {
+        if !path.exists() {
+            PORTFOLIO_READS.lock().unwrap_or_else(|e| e.into_inner()).insert(path.clone(), String::new());
+            return Ok(Vec::new());
+        }
+        let content = fs::read_to_string(path)?;
+        let mut stocks = Self::parse_stocks(&content);
+        PORTFOLIO_READS.lock().unwrap_or_else(|e| e.into_inner()).insert(path.clone(), content);
+
+        // A lot saved as `-` takes its quantity and average cost from its trades in the ledger
+        // beside the file. A lot still saved with numbers switches over once its trades account
+        // for the whole quantity; until then (held before its first trade, or trades logged
+        // before lots were numbered) the numbers stand, and the missing opening lot is only
+        // written out by the next trade on it.
+        let ledger = Self::load_ledger(&path.with_extension("ledger"));
+        if !ledger.is_empty() {
+            let closed = Self::load_closed_from_file(path)?;
+            for stock in stocks.iter_mut() {
+                let trades = open_trades(&ledger, &stock.symbol, stock.lot, &closed);
+                if trades.is_empty() {
+                    continue;
+                }
+                let opening = opening_lot(stock, &trades);
+                if stock.purchased.is_none() {
+                    stock.first_buy = opening.as_ref()
+                        .or(trades.iter().copied().find(|t| t.side == TradeSide::Buy))
+                        .map(|t| t.date);
+                }
+                if stock.ledger || (opening.is_none() && (net_shares(&trades) - stock.quantity).abs() < 1e-9) {
+                    (stock.quantity, stock.cost_basis) = ledger_position(&trades, (0.0, 0.0), stock.multiplier());
+                    stock.ledger = true;
+                }
+            }
+        }
+
+        Ok(stocks)
+    }
+
+    /// Holdings from a portfolio file's lines, skipping comments, archive lines and lines
+    /// with fewer than three fields
+    fn parse_stocks(content: &str) -> Vec<Stock> {
+        let mut stocks = Vec::new();
+        let mut lots: Vec<Option<u32>> = Vec::new(); // `lot=` of each line, when given
+        for line in content.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with('~') {
+                continue;
+            }
+
+            let parts: Vec<&str> = line.split('|').collect();
+            if parts.len() >= 3 {
+                let mut stock = Stock {
+                    symbol: parts[0].trim().to_string(),
+                    display: parts[1].trim().to_string(),
+                    name: parts[2].trim().to_string(),
+                    quantity: parts.get(3).and_then(|s| s.trim().parse().ok()).unwrap_or(0.0),
+                    cost_basis: parts.get(4).and_then(|s| s.trim().parse().ok()).unwrap_or(0.0),
+                    price_data: None,
+                    historical: None,
+                    portfolio_name: String::new(),
+                    cost_fx: None,
+                    realized: 0.0,
+                    manual_price: None,
+                    benchmark: None,
+                    contract: OptionContract::parse_occ(parts[0].trim()),
+                    contract_size: None,
+                    asset_class: None,
+                    bond: None,
+                    unit: None,
+                    deposit: None,
+                    refresh: None,
+                    alert_above: None,
+                    alert_below: None,
+                    tags: Vec::new(),
+                    note: None,
+                    target: None,
+                    currency: None,
+                    fx_usd: None,
+                    pre_gap: None,
+                    purchased: None,
+                    first_buy: None,
+                    dividends_ttm: None,
+                    lots: 1,
+                    lot: 0,
+                    ledger: parts.get(3).is_some_and(|q| q.trim() == "-"),
+                };
+
+                // Optional key=value fields after the positional ones
+                let mut maturity = None;
+                let mut start = None;
+                let mut lot = None;
+                for extra in parts.iter().skip(5) {
+                    if let Some((key, value)) = extra.split_once('=') {
+                        match key.trim() {
+                            "fx" => stock.cost_fx = value.trim().parse().ok(),
+                            "realized" => stock.realized = value.trim().parse().unwrap_or(0.0),
+                            "mark" => stock.manual_price = value.trim().parse().ok(),
+                            "bench" => stock.benchmark = Some(value.trim().to_string()).filter(|b| !b.is_empty()),
+                            "size" => stock.contract_size = value.trim().parse().ok(),
+                            "class" => stock.asset_class = AssetClass::parse(value),
+                            "bought" => stock.purchased = chrono::NaiveDate::parse_from_str(value.trim(), "%Y-%m-%d").ok(),
+                            "tags" => stock.tags = value.split(',').map(|t| t.trim().to_string()).filter(|t| !t.is_empty()).collect(),
+                            "note" => stock.note = Some(value.trim().to_string()).filter(|n| !n.is_empty()),
+                            "currency" => stock.currency = Some(normalize_currency(value)).filter(|c| !c.is_empty() && c != "USD"),
+                            "target" => stock.target = value.trim().parse().ok().filter(|t: &f64| *t > 0.0),
+                            "alert_above" => stock.alert_above = value.trim().parse().ok(),
+                            "alert_below" => stock.alert_below = value.trim().parse().ok(),
+                            "refresh" => stock.refresh = Some(value.trim().to_lowercase()).filter(|r| parse_refresh_interval(r).is_some()),
+                            "unit" => stock.unit = Some(value.trim().to_lowercase()).filter(|u| !u.is_empty()),
+                            "lot" => lot = value.trim().parse().ok(),
+                            "face" => {
+                                let bond = stock.bond.get_or_insert_with(BondTerms::default);
+                                bond.face = value.trim().parse().unwrap_or(bond.face);
+                            }
+                            "coupon" => {
+                                stock.bond.get_or_insert_with(BondTerms::default).coupon =
+                                    value.trim().trim_end_matches('%').parse().unwrap_or(0.0);
+                            }
+                            "maturity" => maturity = chrono::NaiveDate::parse_from_str(value.trim(), "%Y-%m-%d").ok(),
+                            "start" => start = chrono::NaiveDate::parse_from_str(value.trim(), "%Y-%m-%d").ok(),
+                            "rate" => {
+                                stock.deposit = Some(DepositTerms {
+                                    rate: value.trim().trim_end_matches('%').parse().unwrap_or(0.0),
+                                    start: Local::now().date_naive(),
+                                    maturity: None,
+                                });
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+                // `maturity=` belongs to a deposit when `rate=` is present, otherwise to a bond
+                if let Some(deposit) = stock.deposit.as_mut() {
+                    deposit.start = start.unwrap_or(deposit.start);
+                    deposit.maturity = maturity;
+                } else if maturity.is_some() {
+                    stock.bond.get_or_insert_with(BondTerms::default).maturity = maturity;
+                }
+
+                stocks.push(stock);
+                lots.push(lot);
+            }
+        }
+
+        // Repeated lines of a symbol are separate lots: unnumbered ones take the lowest
+        // numbers not already given by a `lot=` field, so the first line is lot 0
+        let mut taken: HashMap<String, Vec<u32>> = HashMap::new();
+        for (stock, lot) in stocks.iter_mut().zip(&lots) {
+            if let Some(lot) = *lot {
+                stock.lot = lot;
+                taken.entry(stock.symbol.clone()).or_default().push(lot);
+            }
+        }
+        for (stock, _) in stocks.iter_mut().zip(&lots).filter(|(_, lot)| lot.is_none()) {
+            let taken = taken.entry(stock.symbol.clone()).or_default();
+            stock.lot = (0..).find(|n| !taken.contains(n)).unwrap_or_default();
+            taken.push(stock.lot);
+        }
+        stocks
+    }
+
+    fn load_ledger(path: &Path) -> Vec<Transaction> {
+        fs::read_to_string(path)
+            .map(|content| content.lines().filter_map(Transaction::parse).collect())
+            .unwrap_or_default()
+    }
+
+    /// Archived (fully sold) positions from a portfolio file's `~` lines
+    fn load_closed_from_file(path: &PathBuf) -> Result<Vec<ClosedPosition>> {
+        if !path.exists() {
+            return Ok(Vec::new());
+        }
+        let content = fs::read_to_string(path)?;
+        Ok(content.lines().filter_map(|l| ClosedPosition::parse(l.trim())).collect())
+    }
+
+    /// Every malformed holding line across the loaded portfolios
+    fn find_line_issues(&self) -> Vec<LineIssue> {
+        let mut issues = Vec::new();
+        for portfolio in &self.portfolios {
+            let content = fs::read_to_string(&portfolio.file_path).unwrap_or_default();
+            for (line, text) in content.lines().enumerate() {
+                let text = text.trim();
+                if text.is_empty() || text.starts_with('#') || text.starts_with('~') {
+                    continue;
+                }
+                if let Some(problem) = line_problem(text) {
+                    issues.push(LineIssue {
+                        portfolio: portfolio.name.clone(),
+                        path: portfolio.file_path.clone(),
+                        line,
+                        text: text.to_string(),
+                        problem,
+                    });
+                }
+            }
+        }
+        issues
+    }
+
+    /// Replace a malformed line with `fixed`, or drop it when None
+    fn rewrite_line(&self, issue: &LineIssue, fixed: Option<&str>) -> Result<()> {
+        let _lock = PortfolioLock::acquire()?;
+        let content = fs::read_to_string(&issue.path)?;
+        let mut lines: Vec<String> = content.lines().map(str::to_string).collect();
+        if lines.get(issue.line).map(|l| l.trim()) != Some(issue.text.as_str()) {
+            anyhow::bail!("{} changed on disk; reopen to review it again", issue.path.display());
+        }
+        match fixed {
+            Some(text) => lines[issue.line] = text.to_string(),
+            None => {
+                lines.remove(issue.line);
+            }
+        }
+        write_atomic(&issue.path, &(lines.join("\n") + "\n"))?;
+        Ok(())
+    }
+
+    /// File a loaded portfolio was read from, so a watch session's edits stay in its temp file
+    fn portfolio_path(&self, portfolio_name: &str) -> PathBuf {
+        self.portfolios
+            .iter()
+            .find(|p| p.name == portfolio_name)
+            .map(|p| p.file_path.clone())
+            .unwrap_or_else(|| Self::portfolios_dir().join(format!("{}.conf", portfolio_name)))
+    }
+
+    /// Rewrite a portfolio file, keeping its archive section
+    fn save_stocks(&self, portfolio_name: &str, stocks: &[Stock]) -> Result<()> {
+        let path = self.portfolio_path(portfolio_name);
+        let closed = Self::load_closed_from_file(&path)?;
+        self.save_portfolio(portfolio_name, stocks, &closed)
+    }
+
+    /// Write a portfolio file atomically, keeping the previous version as a backup. Refuses
+    /// when another running copy changed the file since it was last read here, rather than
+    /// overwrite that copy's edits; callers read it again under the same lock before saving.
+    fn save_portfolio(&self, portfolio_name: &str, stocks: &[Stock], closed: &[ClosedPosition]) -> Result<()> {
+        use std::fmt::Write as _;
+        let _lock = PortfolioLock::acquire()?;
+        let path = self.portfolio_path(portfolio_name);
+        let on_disk = fs::read_to_string(&path).unwrap_or_default();
+        let read = PORTFOLIO_READS.lock().unwrap_or_else(|e| e.into_inner()).get(&path).cloned();
+        if read.is_some_and(|read| read != on_disk) {
+            anyhow::bail!("{} was changed by another stock-tui; not saved, try again", path.display());
+        }
+        let mut file = String::new();
+
+        writeln!(file, "# Stock Portfolio Configuration")?;
+        writeln!(file, "# Format: SYMBOL|Display Name|Description|Quantity|Cost Basis")?;
+        writeln!(file)?;
+
+        let tw_stocks: Vec<_> = stocks.iter().filter(|s| s.section() == 0).collect();
+        let us_stocks: Vec<_> = stocks.iter().filter(|s| s.section() == 1).collect();
+        let crypto_stocks: Vec<_> = stocks.iter().filter(|s| s.section() == 2).collect();
+
+        if !tw_stocks.is_empty() {
+            writeln!(file, "# Taiwan Stocks")?;
+            for s in tw_stocks {
+                writeln!(file, "{}", s.to_conf_line())?;
+            }
+            writeln!(file)?;
+        }
+
+        if !us_stocks.is_empty() {
+            writeln!(file, "# US Stocks")?;
+            for s in us_stocks {
+                writeln!(file, "{}", s.to_conf_line())?;
+            }
+        }
+
+        if !crypto_stocks.is_empty() {
+            writeln!(file)?;
+            writeln!(file, "# Crypto")?;
+            for s in crypto_stocks {
+                writeln!(file, "{}", s.to_conf_line())?;
+            }
+        }
+
+        if !closed.is_empty() {
+            writeln!(file)?;
+            writeln!(file, "# Closed Positions")?;
+            for c in closed {
+                writeln!(file, "{}", c.to_conf_line())?;
+            }
+        }
+
+        // Keep the last good version, and read the new one back: a short or mismatched file
+        // (full disk, flaky network mount) is put back from that copy
+        let backup = Self::backup_path(&path);
+        if path.exists() {
+            fs::copy(&path, &backup)?;
+        }
+        write_atomic(&path, &file)?;
+        if fs::read_to_string(&path).ok().as_deref() != Some(file.as_str()) {
+            if let Ok(previous) = fs::read_to_string(&backup) {
+                write_atomic(&path, &previous)?;
+            }
+            anyhow::bail!("{} did not read back as written; kept the previous version", path.display());
+        }
+        PORTFOLIO_READS.lock().unwrap_or_else(|e| e.into_inner()).insert(path, file);
+        Ok(())
+    }
+
+    /// Copy of a portfolio file as it was before the last save (`main.conf.bak`)
+    fn backup_path(path: &Path) -> PathBuf {
+        let name = path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+        path.with_file_name(format!("{}.bak", name))
+    }
+
+    fn fetch_price(&mut self, symbol: &str) -> Option<PriceData> {
+        if let Some(price_data) = self.cached_price(symbol) {
+            return Some(price_data);
+        }
+        let price_data = fetch_price_blocking(symbol, &self.config.providers)?;
+        self.store_price(symbol, &price_data);
+        Some(price_data)
+    }
+
+    /// A quote younger than `quote_ttl()` from memory or the cache database
+    fn cached_price(&mut self, symbol: &str) -> Option<PriceData> {
+        if let Some((data, time)) = self.cache.get(symbol) {
+            if time.elapsed().as_secs() < quote_ttl() {
+                return Some(data.clone());
+            }
+        }
+
+        let fresh_since = Local::now().timestamp() - quote_ttl() as i64;
+        let db = cache_db()?;
+        let price_data = query_quote(&db, symbol, fresh_since)?;
+        self.cache.insert(symbol.to_string(), (price_data.clone(), Instant::now()));
+        Some(price_data)
+    }
+
+    /// Keep a freshly fetched quote in memory and in the cache database
+    fn store_price(&mut self, symbol: &str, price_data: &PriceData) {
+        store_cached_quote(symbol, price_data);
+        self.cache.insert(symbol.to_string(), (price_data.clone(), Instant::now()));
+    }
+
+    /// Fetch every uncached quote in `symbols` concurrently, so the `fetch_price` calls
+    /// that follow are answered from the cache; symbols another running copy is fetching are
+    /// read from the cache database once it has them
+    fn prefetch_prices(&mut self, symbols: Vec<String>) {
+        let mut missing: Vec<String> = symbols.into_iter().filter(|s| self.cached_price(s).is_none()).collect();
+        missing.sort();
+        missing.dedup();
+        let mut fetched = Vec::new();
+        let (mine, theirs) = claim_quotes(missing, |_| 0, |symbol, price_data| fetched.push((symbol, price_data)));
+        let mut store = |symbol: String, price_data: Option<PriceData>| {
+            if let Some(price_data) = price_data {
+                store_cached_quote(&symbol, &price_data);
+                fetched.push((symbol, price_data));
+            }
+        };
+        fetch_prices_concurrent(mine, self.config.fetch_concurrency, &self.config.providers, &mut store);
+        release_quote_claims();
+        let mut shared = Vec::new();
+        let unanswered = await_claimed_quotes(theirs, |symbol, price_data| shared.push((symbol, price_data)));
+        fetch_prices_concurrent(unanswered, self.config.fetch_concurrency, &self.config.providers, &mut store);
+        release_quote_claims();
+        for (symbol, price_data) in fetched.into_iter().chain(shared) {
+            self.cache.insert(symbol, (price_data, Instant::now()));
+        }
+    }
+
+    fn fetch_exchange_rate(&mut self) -> f64 {
+        if let Some(data) = self.fetch_price("USDTWD=X") {
+            data.price
+        } else {
+            32.0
+        }
+    }
+
+    /// Start an async background refresh of all stock prices
+    /// Results will be sent through the fetch_receiver channel
+    /// `live` ticks skip symbols whose exchange is closed unless `refresh_when_closed` is set
+    fn start_async_refresh(&mut self, live: bool) {
+        if self.is_fetching {
+            return; // Already fetching
+        }
+
+        self.is_fetching = true;
+        let sender = self.fetch_sender.clone();
+
+        // Collect all symbols we need to fetch: skip ones fetched within their refresh interval
+        let stocks = if self.view_combined { &self.combined_stocks } else { &self.stocks };
+        let now = chrono::Utc::now();
+        let skip_closed = live && !self.config.market.refresh_when_closed;
+        let mut symbols: Vec<String> = stocks
+            .iter()
+            .filter(|s| !s.is_priced_locally())
+            .filter(|s| {
+                !skip_closed
+                    || !s.market().is_some_and(|m| matches!(m.session(now, &self.config.market), MarketSession::Closed { .. }))
+            })
+            .filter(|s| {
+                let interval = self.refresh_interval(s);
+                interval == 0
+                    || self.cache.get(&s.symbol).is_none_or(|(_, fetched)| fetched.elapsed().as_secs() >= interval)
+            })
+            .map(|s| s.symbol.clone())
+            .collect();
+        // Pair proxies trade while the TW market is shut, so they are always fetched; the
+        // summary benchmark rides along for the alpha line
+        symbols.extend(self.pair_proxies(stocks));
+        symbols.push(self.summary_benchmark());
+
+        // Exchange rates go in the same batch, keyed by their quote symbol
+        let mut rates: HashMap<String, Option<String>> = HashMap::new();
+        rates.insert("USDTWD=X".to_string(), None);
+        for currency in self.extra_currencies() {
+            rates.insert(format!("USD{}=X", currency), Some(currency));
+        }
+        let mut quotes: Vec<String> = rates.keys().cloned().chain(symbols.iter().cloned()).collect();
+        quotes.sort();
+        quotes.dedup();
+        let concurrency = self.config.fetch_concurrency;
+        let providers = self.config.providers.clone();
+        // A quote another running copy fetched within a symbol's refresh interval (at least
+        // one live tick) is used as if fetched here
+        let tick = self.config.refresh_interval as i64;
+        let fresh_secs: HashMap<String, i64> = stocks
+            .iter()
+            .map(|s| (s.symbol.clone(), (self.refresh_interval(s) as i64).max(tick)))
+            .collect();
+
+        // Spawn background thread
+        thread::spawn(move || {
+            let deliver = |symbol: String, price_data: Option<PriceData>| {
+                match (rates.get(&symbol), &price_data) {
+                    (Some(None), Some(rate)) => {
+                        let _ = sender.send(FetchMessage::ExchangeRate(rate.price));
+                    }
+                    (Some(Some(currency)), Some(rate)) => {
+                        let _ = sender.send(FetchMessage::CurrencyRate(currency.clone(), rate.price));
+                    }
+                    _ => {}
+                }
+                if symbols.contains(&symbol) {
+                    let _ = sender.send(FetchMessage::Price(FetchResult { symbol, price_data }));
+                }
+            };
+            let (mine, theirs) = claim_quotes(
+                quotes,
+                |symbol| fresh_secs.get(symbol).copied().unwrap_or(tick),
+                |symbol, price_data| deliver(symbol, Some(price_data)),
+            );
+            let fetch = |symbols: Vec<String>| {
+                fetch_prices_concurrent(symbols, concurrency, &providers, |symbol, price_data| {
+                    if let Some(price_data) = &price_data {
+                        store_cached_quote(&symbol, price_data);
+                    }
+                    deliver(symbol, price_data);
+                });
+                release_quote_claims();
+            };
+            fetch(mine);
+            let mut shared = Vec::new();
+            let unanswered = await_claimed_quotes(theirs, |symbol, price_data| shared.push((symbol, price_data)));
+            fetch(unanswered);
+            for (symbol, price_data) in shared {
+                deliver(symbol, Some(price_data));
+            }
+
+            // Signal completion
+            let _ = sender.send(FetchMessage::BatchComplete);
+        });
+    }
+
+    /// Process any pending fetch results from background thread
+    /// Returns true if any updates were received
+    fn process_fetch_results(&mut self) -> bool {
+        let mut updated = false;
+
+        // Non-blocking receive of all pending messages
+        while let Ok(msg) = self.fetch_receiver.try_recv() {
+            match msg {
+                FetchMessage::Price(result) => {
+                    // Update price in all stock vectors
+                    if let Some(ref price_data) = result.price_data {
+                        // Live ticks flash the price cell when it moved since the last quote
+                        let previous = self.cache.get(&result.symbol).map(|(data, _)| data.price);
+                        if let Some(previous) = previous.filter(|p| self.live_mode && *p != price_data.price) {
+                            self.price_ticks.insert(result.symbol.clone(), (price_data.price > previous, Instant::now()));
+                        }
+                        // Update cache
+                        self.cache.insert(result.symbol.clone(), (price_data.clone(), Instant::now()));
+
+                        // Update all stock vectors
+                        for stock in self.stocks.iter_mut()
+                            .chain(self.tw_stocks.iter_mut())
+                            .chain(self.us_stocks.iter_mut())
+                            .chain(self.crypto_stocks.iter_mut())
+                            .chain(self.combined_stocks.iter_mut())
+                            .chain(self.combined_tw_stocks.iter_mut())
+                            .chain(self.combined_us_stocks.iter_mut())
+                            .chain(self.combined_crypto_stocks.iter_mut())
+                        {
+                            if stock.symbol == result.symbol && !stock.is_priced_locally() {
+                                stock.price_data = Some(price_data.clone());
+                            }
+                        }
+                    }
+                    updated = true;
+                }
+                FetchMessage::ExchangeRate(rate) => {
+                    self.usd_twd_rate = rate;
+                    updated = true;
+                }
+                FetchMessage::CurrencyRate(currency, rate) => {
+                    self.currency_rates.insert(currency, rate);
+                    self.apply_holding_fx();
+                    updated = true;
+                }
+                FetchMessage::BatchComplete => {
+                    self.is_fetching = false;
+                    self.last_update = Instant::now();
+                    self.sort_stocks(); // Re-sort after all prices updated
+                    self.run_hook(self.config.hooks.refresh_complete.as_deref(), "refresh_complete", Vec::new());
+                    self.apply_gap_estimates();
+                    self.update_benchmark_returns();
+                    self.record_daily_value();
+                    self.warn_currency_mismatch();
+                    updated = true;
+                }
+                FetchMessage::History(symbol, historical) => {
+                    self.history_requested.retain(|s| *s != symbol);
+                    for stock in self.stocks.iter_mut()
+                        .chain(self.tw_stocks.iter_mut())
+                        .chain(self.us_stocks.iter_mut())
+                        .chain(self.crypto_stocks.iter_mut())
+                        .chain(self.combined_stocks.iter_mut())
+                        .chain(self.combined_tw_stocks.iter_mut())
+                        .chain(self.combined_us_stocks.iter_mut())
+                        .chain(self.combined_crypto_stocks.iter_mut())
+                    {
+                        if stock.symbol == symbol && stock.historical.is_none() {
+                            stock.historical = Some(historical.clone());
+                        }
+                    }
+                    self.historical_cache.insert(symbol, historical);
+                    updated = true;
+                }
+                FetchMessage::Fundamentals(symbol, fundamentals) => {
+                    self.fundamentals_requested.retain(|s| *s != symbol);
+                    self.fundamentals_cache.insert(symbol, (Instant::now(), fundamentals));
+                    updated = true;
+                }
+                FetchMessage::Feed(load, result) => {
+                    self.add_feed(load, result);
+                    updated = true;
+                }
+                FetchMessage::Twse(reports) => {
+                    self.twse_loading = false;
+                    self.institutional_cache.extend(reports.institutional);
+                    self.margin_cache.extend(reports.margin);
+                    for key in reports.unpublished {
+                        self.twse_unpublished.insert(key, Instant::now());
+                    }
+                    updated = true;
+                }
+                FetchMessage::Backfill(done, total) => {
+                    self.backfill_progress = (done < total).then_some((done, total));
+                    if done == total {
+                        self.set_status(format!("History backfill complete ({} symbols)", total));
+                    }
+                    updated = true;
+                }
+            }
+        }
+
+        updated
+    }
+
+    /// Start the `[backfill]` job over every portfolio's quoted holdings; progress arrives as
+    /// `FetchMessage::Backfill`
+    fn start_backfill(&mut self) {
+        let config = self.config.backfill.clone();
+        if !config.enabled || Self::is_demo_mode() || self.watch_only {
+            return;
+        }
+        let mut symbols: Vec<String> = self.portfolios.iter()
+            .flat_map(|p| Self::load_stocks_from_file(&p.file_path).unwrap_or_default())
+            .filter(|s| !s.is_priced_locally())
+            .map(|s| s.symbol)
+            .collect();
+        symbols.sort();
+        symbols.dedup();
+        if symbols.is_empty() {
+            return;
+        }
+        self.backfill_progress = Some((0, symbols.len()));
+        let sender = self.fetch_sender.clone();
+        thread::spawn(move || {
+            let client = reqwest::blocking::Client::new();
+            let mut delay = config.delay_secs.max(1);
+            for (done, symbol) in symbols.iter().enumerate() {
+                let path = Self::backfill_path(symbol);
+                let fresh = fs::metadata(&path)
+                    .and_then(|m| m.modified())
+                    .is_ok_and(|t| t.elapsed().is_ok_and(|d| d.as_secs() < BACKFILL_STALE_SECS));
+                if !fresh {
+                    // A few attempts, backing off while the server rate-limits us
+                    for _ in 0..3 {
+                        let url = format!(
+                            "https://query2.finance.yahoo.com/v8/finance/chart/{}?interval=1d&range={}&events=div",
+                            symbol, config.range
+                        );
+                        let response = client.get(&url)
+                            .header("User-Agent", "Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36")
+                            .timeout(Duration::from_secs(30))
+                            .send();
+                        match response {
+                            Ok(r) if r.status() == reqwest::StatusCode::TOO_MANY_REQUESTS => {
+                                delay = (delay * 2).min(BACKFILL_MAX_DELAY_SECS);
+                                thread::sleep(Duration::from_secs(delay));
+                            }
+                            Ok(r) => {
+                                let history = r.json::<serde_json::Value>().ok().and_then(|d| HistoricalData::from_yahoo_chart(&d));
+                                if let Some(history) = history {
+                                    let mut json = history.to_cache_json();
+                                    json["range"] = serde_json::json!(config.range);
+                                    fs::create_dir_all(Self::backfill_dir()).ok();
+                                    let _ = fs::write(&path, json.to_string());
+                                }
+                                break;
+                            }
+                            Err(_) => break,
+                        }
+                    }
+                    thread::sleep(Duration::from_secs(delay));
+                }
+                if sender.send(FetchMessage::Backfill(done + 1, symbols.len())).is_err() {
+                    return; // The app has quit
+                }
+            }
+        });
+    }
+
+    fn backfill_dir() -> PathBuf {
+        dirs::home_dir().unwrap_or_default().join(".cache/stock-tui/history")
+    }
+
+    fn backfill_path(symbol: &str) -> PathBuf {
+        Self::backfill_dir().join(format!("{}.json", symbol.replace('.', "_")))
+    }
+
+    /// Daily history over `range` from the backfill store, when the stored range covers it
+    /// and the store was topped up within a day
+    fn stored_history(symbol: &str, range: &str) -> Option<HistoricalData> {
+        let path = Self::backfill_path(symbol);
+        let modified = fs::metadata(&path).ok()?.modified().ok()?;
+        if modified.elapsed().ok()?.as_secs() >= BACKFILL_STALE_SECS {
+            return None;
+        }
+        let data: serde_json::Value = serde_json::from_str(&fs::read_to_string(&path).ok()?).ok()?;
+        let stored = data["range"].as_str()?;
+        let history = HistoricalData::from_series(
+            &data["timestamps"],
+            &data["opens"],
+            &data["highs"],
+            &data["lows"],
+            &data["closes"],
+            &data["volumes"],
+        )?.with_dividends(&data["dividends"]);
+        match (range_days(stored), range_days(range)) {
+            (None, None) => Some(history),
+            (None, Some(days)) => Some(history.since(Local::now().timestamp() - days * 86_400)),
+            (Some(have), Some(days)) if have >= days => Some(history.since(Local::now().timestamp() - days * 86_400)),
+            _ => None,
+        }
+    }
+
+    fn fetch_historical(&mut self, symbol: &str) -> Option<HistoricalData> {
+        self.fetch_history(symbol, HISTORY_RANGE)
+    }
+
+    /// Fetch daily candles for an arbitrary Yahoo range ("1y", "5y", "max", ...)
+    /// The default chart range keeps the plain symbol as its cache key
+    fn fetch_history(&mut self, symbol: &str, range: &str) -> Option<HistoricalData> {
+        let cache_key = if range == HISTORY_RANGE { symbol.to_string() } else { format!("{}@{}", symbol, range) };
+
+        // Check in-memory cache first
+        if let Some(data) = self.historical_cache.get(&cache_key) {
+            if data.last_fetched.elapsed().as_secs() < history_ttl() {
+                return Some(data.clone());
+            }
+        }
+        let historical = Self::load_history(&self.config.providers, symbol, range)?;
+        self.historical_cache.insert(cache_key, historical.clone());
+        Some(historical)
+    }
+
+    /// `fetch_history` from a background thread, arriving as `FetchMessage::History` under
+    /// the same cache key
+    fn request_history(&mut self, symbol: &str, range: &str) {
+        let cache_key = if range == HISTORY_RANGE { symbol.to_string() } else { format!("{}@{}", symbol, range) };
+        let fresh = self.historical_cache.get(&cache_key).is_some_and(|h| h.last_fetched.elapsed().as_secs() < history_ttl());
+        if fresh || self.history_requested.contains(&cache_key) {
+            return;
+        }
+        self.history_requested.push(cache_key.clone());
+        let sender = self.fetch_sender.clone();
+        let providers = self.config.providers.clone();
+        let (symbol, range) = (symbol.to_string(), range.to_string());
+        thread::spawn(move || {
+            if let Some(historical) = Self::load_history(&providers, &symbol, &range) {
+                let _ = sender.send(FetchMessage::History(cache_key, historical));
+            }
+        });
+    }
+
+    /// 5-minute candles of `symbol`'s session on `date` (exchange time) and the previous
+    /// session's close. Completed days are kept in the cache database for good; Yahoo is the
+    /// only source.
+    fn load_intraday(providers: &ProvidersConfig, symbol: &str, date: chrono::NaiveDate) -> Option<(f64, HistoricalData)> {
+        let day = date.format("%Y-%m-%d").to_string();
+        if let Some(db) = cache_db() {
+            let session = db.query_row(
+                "SELECT prev_close, first_ts, last_ts FROM intraday_sessions WHERE symbol = ?1 AND day = ?2",
+                rusqlite::params![symbol, day],
+                |row| Ok((row.get::<_, f64>(0)?, row.get::<_, i64>(1)?, row.get::<_, i64>(2)?)),
+            );
+            if let Ok((prev_close, first_ts, last_ts)) = session {
+                if let Some(candles) = query_candles(&db, symbol, "5m", first_ts, last_ts) {
+                    return Some((prev_close, candles));
+                }
+            }
+        }
+        if !providers.enabled().any(|p| p == Provider::Yahoo) {
+            return None;
+        }
+
+        // A few days either side so the previous session is included across weekends
+        let midnight = |d: chrono::NaiveDate| d.and_hms_opt(0, 0, 0).map_or(0, |t| t.and_utc().timestamp());
+        let url = format!(
+            "https://query2.finance.yahoo.com/v8/finance/chart/{}?interval=5m&period1={}&period2={}",
+            symbol,
+            midnight(date - chrono::Duration::days(5)),
+            midnight(date + chrono::Duration::days(2)),
+        );
+        let data = reqwest::blocking::Client::new()
+            .get(&url)
+            .header("User-Agent", "Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36")
+            .timeout(Duration::from_secs(10))
+            .send()
+            .ok()?
+            .json::<serde_json::Value>()
+            .ok()?;
+        let all = HistoricalData::from_yahoo_chart(&data)?;
+        let offset = data["chart"]["result"][0]["meta"]["gmtoffset"].as_i64().unwrap_or(0);
+        let session_date = |t: i64| chrono::DateTime::from_timestamp(t + offset, 0).map(|d| d.date_naive());
+        let start = all.timestamps.iter().position(|&t| session_date(t) >= Some(date))?;
+        let end = all.timestamps.iter().position(|&t| session_date(t) > Some(date)).unwrap_or(all.timestamps.len());
+        if start >= end {
+            return None;
+        }
+        let mut candles = all.since(all.timestamps[start]);
+        candles.truncate(end - start);
+        let prev_close = if start > 0 { all.closes[start - 1] } else { candles.opens[0] };
+
+        if date < Local::now().date_naive() {
+            if let Some(mut db) = cache_db() {
+                let save = |tx: &rusqlite::Transaction| -> rusqlite::Result<()> {
+                    store_candles(tx, symbol, "5m", &candles)?;
+                    tx.execute(
+                        "INSERT OR REPLACE INTO intraday_sessions (symbol, day, prev_close, first_ts, last_ts) VALUES (?1, ?2, ?3, ?4, ?5)",
+                        rusqlite::params![symbol, day, prev_close, candles.timestamps[0], candles.timestamps[candles.timestamps.len() - 1]],
+                    )?;
+                    Ok(())
+                };
+                if let Ok(tx) = db.transaction() {
+                    if save(&tx).is_ok() {
+                        let _ = tx.commit();
+                    }
+                };
+            }
+        }
+        Some((prev_close, candles))
+    }
+
+    /// Replay of the current view's quoted holdings on `date`, loading their candles in parallel
+    fn load_replay(&self, date: chrono::NaiveDate) -> Option<ReplayState> {
+        let stocks = if self.view_combined { &self.combined_stocks } else { &self.stocks };
+        let quoted: Vec<&Stock> = stocks.iter().filter(|s| !s.is_priced_locally()).collect();
+        let providers = &self.config.providers;
+        let holdings: Vec<(Stock, f64, HistoricalData)> = thread::scope(|scope| {
+            let loads: Vec<_> = quoted.iter()
+                .map(|stock| scope.spawn(move || {
+                    let (prev_close, candles) = Self::load_intraday(providers, &stock.symbol, date)?;
+                    let stock = Stock { historical: None, ..(*stock).clone() };
+                    Some((stock, prev_close, candles))
+                }))
+                .collect();
+            loads.into_iter().filter_map(|load| load.join().ok().flatten()).collect()
+        });
+        (!holdings.is_empty()).then(|| ReplayState::new(date, holdings, self.usd_twd_rate))
+    }
+
+    /// Daily candles from the cache database, the backfill store or the providers, without the
+    /// in-memory cache (so background threads can use it)
+    fn load_history(providers: &ProvidersConfig, symbol: &str, range: &str) -> Option<HistoricalData> {
+        if let Some(historical) = Self::cached_history(symbol, range) {
+            return Some(historical);
+        }
+
+        // Long ranges come from the backfill store when it has them
+        if range != HISTORY_RANGE {
+            if let Some(historical) = Self::stored_history(symbol, range) {
+                return Some(historical);
+            }
+        }
+
+        // Fetch from the configured providers in failover order
+        for provider in providers.enabled().filter(|p| ProvidersConfig::serves(*p, symbol)) {
+            let url = match provider {
+                Provider::Twse => continue, // Quotes only
+                Provider::Yahoo => format!(
+                    "https://query2.finance.yahoo.com/v8/finance/chart/{}?interval=1d&range={}&events=div",
+                    symbol, range
+                ),
+                Provider::AlphaVantage => providers.alpha_vantage_history_url(symbol, range),
+            };
+            let Ok(response) = reqwest::blocking::Client::new()
+                .get(&url)
+                .header("User-Agent", "Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36")
+                .timeout(Duration::from_secs(10))
+                .send()
+            else {
+                continue;
+            };
+            let Ok(data) = response.json::<serde_json::Value>() else {
+                continue;
+            };
+            let historical = match provider {
+                Provider::Yahoo => HistoricalData::from_yahoo_chart(&data),
+                Provider::AlphaVantage => {
+                    ProvidersConfig::check_alpha_vantage_quota(&data);
+                    HistoricalData::from_alpha_vantage(&data, range)
+                }
+                Provider::Twse => None,
+            };
+            if let Some(historical) = historical {
+                Self::store_history(symbol, range, &historical);
+                return Some(historical);
+            }
+        }
+
+        None
+    }
+
+    /// Daily candles over `range` from the cache database, if that range was fetched within
+    /// `history_ttl()`. Candles stored since by other ranges' fetches are included.
+    fn cached_history(symbol: &str, range: &str) -> Option<HistoricalData> {
+        let db = cache_db()?;
+        let fresh_since = Local::now().timestamp() - history_ttl() as i64;
+        let first_ts: i64 = db
+            .query_row(
+                "SELECT first_ts FROM history_fetches WHERE symbol = ?1 AND range = ?2 AND fetched_at > ?3",
+                rusqlite::params![symbol, range, fresh_since],
+                |row| row.get(0),
+            )
+            .ok()?;
+        let mut historical = query_candles(&db, symbol, "1d", first_ts, i64::MAX)?;
+        let mut dividends = db.prepare("SELECT ts, amount FROM dividends WHERE symbol = ?1 AND ts >= ?2 ORDER BY ts").ok()?;
+        historical.dividends = dividends
+            .query_map(rusqlite::params![symbol, first_ts], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, f64>(1)?)))
+            .ok()?
+            .flatten()
+            .collect();
+        Some(historical)
+    }
+
+    /// Save fetched daily candles and dividends and record the range as fetched
+    fn store_history(symbol: &str, range: &str, historical: &HistoricalData) {
+        let Some(&first_ts) = historical.timestamps.first() else {
+            return;
+        };
+        let Some(mut db) = cache_db() else {
+            return;
+        };
+        let save = |tx: &rusqlite::Transaction| -> rusqlite::Result<()> {
+            store_candles(tx, symbol, "1d", historical)?;
+            for &(ts, amount) in &historical.dividends {
+                tx.execute(
+                    "INSERT OR REPLACE INTO dividends (symbol, ts, amount) VALUES (?1, ?2, ?3)",
+                    rusqlite::params![symbol, ts, amount],
+                )?;
+            }
+            tx.execute(
+                "INSERT OR REPLACE INTO history_fetches (symbol, range, fetched_at, first_ts) VALUES (?1, ?2, ?3, ?4)",
+                rusqlite::params![symbol, range, Local::now().timestamp(), first_ts],
+            )?;
+            Ok(())
+        };
+        if let Ok(tx) = db.transaction() {
+            if save(&tx).is_ok() {
+                let _ = tx.commit();
+            }
+        };
+    }
+
+    /// Calculate trend from historical data: compare first 5 days avg vs last 5 days avg
+    fn calculate_trend(closes: &[f64]) -> (&'static str, Color) {
+        if closes.len() < 10 {
+            return ("→", theme().dim);
+        }
+
+        let first_avg: f64 = closes.iter().take(5).sum::<f64>() / 5.0;
+        let last_avg: f64 = closes.iter().rev().take(5).sum::<f64>() / 5.0;
+        let change_pct = ((last_avg - first_avg) / first_avg) * 100.0;
+
+        if change_pct > 1.0 {
+            ("⬆", theme().gain)
+        } else if change_pct < -1.0 {
+            ("⬇", theme().loss)
+        } else {
+            ("→", theme().dim)
+        }
+    }
+
+    /// Bollinger Bands over closes: (index, middle, upper, lower) for each index with a full window
+    fn bollinger_bands(closes: &[f64], period: usize, k: f64) -> Vec<(usize, f64, f64, f64)> {
+        if period == 0 || closes.len() < period {
+            return Vec::new();
+        }
+
+        closes
+            .windows(period)
+            .enumerate()
+            .map(|(i, window)| {
+                let mean = window.iter().sum::<f64>() / period as f64;
+                let variance = window.iter().map(|c| (c - mean).powi(2)).sum::<f64>() / period as f64;
+                let band = k * variance.sqrt();
+                (i + period - 1, mean, mean + band, mean - band)
+            })
+            .collect()
+    }
+
+    /// Detect support/resistance from swing lows/highs (pivots that dominate `window` candles
+    /// on each side). Returns the nearest levels below and above `price`, closest first,
+    /// with levels within 1.5% of each other merged.
+    fn support_resistance(historical: &HistoricalData, price: f64, window: usize) -> (Vec<f64>, Vec<f64>) {
+        let (highs, lows) = (&historical.highs, &historical.lows);
+        let mut pivots: Vec<f64> = Vec::new();
+
+        for i in window..highs.len().saturating_sub(window) {
+            let range = i - window..=i + window;
+            if range.clone().all(|j| highs[j] <= highs[i]) {
+                pivots.push(highs[i]);
+            }
+            if range.clone().all(|j| lows[j] >= lows[i]) {
+                pivots.push(lows[i]);
+            }
+        }
+
+        let pick = |mut levels: Vec<f64>| -> Vec<f64> {
+            levels.sort_by(|a, b| (a - price).abs().partial_cmp(&(b - price).abs()).unwrap_or(std::cmp::Ordering::Equal));
+            let mut merged: Vec<f64> = Vec::new();
+            for level in levels {
+                if merged.iter().all(|m| ((level - m) / m).abs() > 0.015) {
+                    merged.push(level);
+                }
+                if merged.len() == MAX_LEVELS {
+                    break;
+                }
+            }
+            merged
+        };
+
+        let supports = pick(pivots.iter().cloned().filter(|&p| p < price).collect());
+        let resistances = pick(pivots.into_iter().filter(|&p| p >= price).collect());
+        (supports, resistances)
+    }
+
+    /// Custom columns read trailing dividends, loaded from a year of history per holding
+    fn needs_dividends(&self) -> bool {
+        self.custom_columns.iter().any(|c| c.expr.uses_dividends())
+    }
+
+    /// Cookie and crumb quoteSummary requires: the consent host sets the cookie,
+    /// `getcrumb` exchanges it for the crumb. Kept for the process once obtained.
+    fn yahoo_session() -> Option<(String, String)> {
+        static SESSION: Mutex<Option<(String, String)>> = Mutex::new(None);
+        let mut session = SESSION.lock().unwrap_or_else(|e| e.into_inner());
+        if session.is_none() {
+            let client = reqwest::blocking::Client::new();
+            let agent = "Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36";
+            let response = client.get("https://fc.yahoo.com").header("User-Agent", agent).timeout(Duration::from_secs(5)).send().ok()?;
+            let cookie = response.headers().get_all("set-cookie").iter()
+                .filter_map(|v| v.to_str().ok()?.split(';').next().map(str::to_string))
+                .collect::<Vec<_>>()
+                .join("; ");
+            let crumb = client
+                .get("https://query2.finance.yahoo.com/v1/test/getcrumb")
+                .header("User-Agent", agent)
+                .header("Cookie", &cookie)
+                .timeout(Duration::from_secs(5))
+                .send().ok()?
+                .error_for_status().ok()?
+                .text().ok()?;
+            *session = Some((cookie, crumb.trim().to_string())).filter(|(_, c)| !c.is_empty() && !c.contains('<'));
+        }
+        session.clone()
+    }
+
+    /// Earnings history and analyst targets, cached like history
+    fn fetch_fundamentals(&mut self, symbol: &str) -> Option<Fundamentals> {
+        if let Some((at, data)) = self.fundamentals_cache.get(symbol) {
+            if at.elapsed().as_secs() < history_ttl() {
+                return data.clone();
+            }
+        }
+        let fundamentals = Self::load_fundamentals(symbol);
+        self.fundamentals_cache.insert(symbol.to_string(), (Instant::now(), fundamentals.clone()));
+        fundamentals
+    }
+
+    /// Fundamentals for the detail view from a background thread, arriving as
+    /// `FetchMessage::Fundamentals`; nothing to do while the cached ones are fresh
+    fn request_fundamentals(&mut self, symbol: &str) {
+        let fresh = self.fundamentals_cache.get(symbol).is_some_and(|(at, _)| at.elapsed().as_secs() < history_ttl());
+        if fresh || self.fundamentals_requested.iter().any(|s| s == symbol) {
+            return;
+        }
+        self.fundamentals_requested.push(symbol.to_string());
+        let sender = self.fetch_sender.clone();
+        let symbol = symbol.to_string();
+        thread::spawn(move || {
+            let fundamentals = Self::load_fundamentals(&symbol);
+            let _ = sender.send(FetchMessage::Fundamentals(symbol, fundamentals));
+        });
+    }
+
+    /// Yahoo quoteSummary earnings history, analyst targets and calendar for `symbol`
+    fn load_fundamentals(symbol: &str) -> Option<Fundamentals> {
+        Self::yahoo_session().and_then(|(cookie, crumb)| {
+            let url = format!(
+                "https://query2.finance.yahoo.com/v10/finance/quoteSummary/{}?modules=earningsHistory,financialData,calendarEvents&crumb={}",
+                symbol, crumb
+            );
+            let data = reqwest::blocking::Client::new()
+                .get(url)
+                .header("User-Agent", "Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36")
+                .header("Cookie", cookie)
+                .timeout(Duration::from_secs(5))
+                .send().ok()?
+                .json::<serde_json::Value>().ok()?;
+            Some(Fundamentals::from_summary(data["quoteSummary"]["result"].get(0)?)).filter(|f| !f.is_empty())
+        })
+    }
+
+    /// One session of a TWSE daily report (`report` is the path after /rwd/zh/, `query` the
+    /// extra parameters): cache database first (published days never change), then TWSE
+    fn fetch_twse_report(report: &str, query: &str, date: chrono::NaiveDate) -> Option<serde_json::Value> {
+        let day = date.format("%Y-%m-%d").to_string();
+        let cached = cache_db().and_then(|db| {
+            db.query_row(
+                "SELECT data FROM twse_reports WHERE report = ?1 AND day = ?2",
+                rusqlite::params![report, day],
+                |row| row.get::<_, String>(0),
+            ).ok()
+        });
+        if let Some(data) = cached.and_then(|content| serde_json::from_str::<serde_json::Value>(&content).ok()) {
+            return Some(data);
+        }
+        let url = format!(
+            "https://www.twse.com.tw/rwd/zh/{}?date={}&{}&response=json",
+            report, date.format("%Y%m%d"), query
+        );
+        let data = reqwest::blocking::Client::new()
+            .get(url)
+            .header("User-Agent", "Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36")
+            .timeout(Duration::from_secs(10))
+            .send().ok()?
+            .json::<serde_json::Value>().ok()?;
+        if data["stat"].as_str() == Some("OK") {
+            if let Some(db) = cache_db() {
+                let _ = db.execute(
+                    "INSERT OR REPLACE INTO twse_reports (report, day, data) VALUES (?1, ?2, ?3)",
+                    rusqlite::params![report, day, data.to_string()],
+                );
+            }
+        }
+        Some(data)
+    }
+
+    /// Walk back over TW trading days from today until `sessions` published ones are known,
+    /// looking far enough back to cover weekends and holidays. Days in `known` count without
+    /// a request and days in `unpublished` are passed over; the rest go to `fetch`. Returns
+    /// what it fetched and the days it found unpublished (None from `fetch`).
+    fn twse_sessions<T>(
+        market: &MarketConfig,
+        sessions: usize,
+        known: &[chrono::NaiveDate],
+        unpublished: &[chrono::NaiveDate],
+        fetch: impl Fn(chrono::NaiveDate) -> Option<T>,
+    ) -> (Vec<(chrono::NaiveDate, T)>, Vec<chrono::NaiveDate>) {
+        let now = chrono::Utc::now();
+        let mut date = now.with_timezone(&Market::Tw.offset(now)).date_naive();
+        let (mut fetched, mut missing) = (Vec::new(), Vec::new());
+        let mut published = 0;
+        for _ in 0..sessions * 3 {
+            if published == sessions {
+                break;
+            }
+            let trading = date.weekday().num_days_from_monday() < 5 && !Market::Tw.is_holiday(date, market);
+            if trading && known.contains(&date) {
+                published += 1;
+            } else if trading && !unpublished.contains(&date) {
+                match fetch(date) {
+                    Some(report) => {
+                        fetched.push((date, report));
+                        published += 1;
+                    }
+                    None => missing.push(date),
+                }
+            }
+            date -= chrono::Duration::days(1);
+        }
+        (fetched, missing)
+    }
+
+    /// Load the last `INSTITUTIONAL_DAYS` published T86 sessions and `MARGIN_DAYS` MI_MARGN
+    /// sessions (TWSE listings only; TPEx `.TWO` and foreign symbols aren't in the reports)
+    /// from a background thread; they arrive as `FetchMessage::Twse`. A session found
+    /// unpublished isn't asked for again for `TWSE_RETRY_SECS`.
+    fn fetch_institutional(&mut self, symbol: &str) {
+        if !symbol.ends_with(".TW") || self.twse_loading {
+            return;
+        }
+        self.twse_unpublished.retain(|_, at| at.elapsed().as_secs() < TWSE_RETRY_SECS);
+        let unpublished = |report: &str| -> Vec<chrono::NaiveDate> {
+            self.twse_unpublished.keys().filter(|(r, _)| *r == report).map(|(_, date)| *date).collect()
+        };
+        let (t86_missing, margin_missing) = (unpublished(T86_REPORT), unpublished(MI_MARGN_REPORT));
+        let t86_known: Vec<chrono::NaiveDate> = self.institutional_cache.keys().copied().collect();
+        let margin_known: Vec<chrono::NaiveDate> = self.margin_cache.keys().copied().collect();
+        let market = self.config.market.clone();
+        let sender = self.fetch_sender.clone();
+        self.twse_loading = true;
+        thread::spawn(move || {
+            let (institutional, t86_unpublished) = Self::twse_sessions(&market, INSTITUTIONAL_DAYS, &t86_known, &t86_missing, |date| {
+                InstitutionalFlow::parse_t86(&Self::fetch_twse_report(T86_REPORT, "selectType=ALLBUT0999", date)?)
+            });
+            // MI_MARGN is published in the evening, ~21:00 Taipei
+            let (margin, margin_unpublished) = Self::twse_sessions(&market, MARGIN_DAYS, &margin_known, &margin_missing, |date| {
+                MarginBalance::parse_mi_margn(&Self::fetch_twse_report(MI_MARGN_REPORT, "selectType=ALL", date)?)
+            });
+            let unpublished = t86_unpublished.into_iter().map(|d| (T86_REPORT, d))
+                .chain(margin_unpublished.into_iter().map(|d| (MI_MARGN_REPORT, d)))
+                .collect();
+            let _ = sender.send(FetchMessage::Twse(TwseReports { institutional, margin, unpublished }));
+        });
+    }
+
+    /// Loaded institutional flows for a TWSE listing, latest `INSTITUTIONAL_DAYS` sessions, oldest first
+    fn institutional_flows(&self, symbol: &str) -> Vec<(chrono::NaiveDate, InstitutionalFlow)> {
+        let Some(code) = symbol.strip_suffix(".TW") else {
+            return Vec::new();
+        };
+        let mut flows: Vec<(chrono::NaiveDate, InstitutionalFlow)> = self.institutional_cache
+            .iter()
+            .filter_map(|(date, day)| day.get(code).map(|flow| (*date, *flow)))
+            .collect();
+        flows.sort_by_key(|(date, _)| std::cmp::Reverse(*date));
+        flows.truncate(INSTITUTIONAL_DAYS);
+        flows.reverse();
+        flows
+    }
+
+    /// Loaded margin / short balances for a TWSE listing, latest `MARGIN_DAYS` sessions, oldest first
+    fn margin_balances(&self, symbol: &str) -> Vec<(chrono::NaiveDate, MarginBalance)> {
+        let Some(code) = symbol.strip_suffix(".TW") else {
+            return Vec::new();
+        };
+        let mut balances: Vec<(chrono::NaiveDate, MarginBalance)> = self.margin_cache
+            .iter()
+            .filter_map(|(date, day)| day.get(code).map(|balance| (*date, *balance)))
+            .collect();
+        balances.sort_by_key(|(date, _)| std::cmp::Reverse(*date));
+        balances.truncate(MARGIN_DAYS);
+        balances.reverse();
+        balances
+    }
+
+    /// Dividends per share paid over the last 365 days
+    fn fetch_dividends_ttm(&mut self, symbol: &str) -> Option<f64> {
+        let history = self.fetch_history(symbol, "1y")?;
+        let cutoff = Local::now().timestamp() - 365 * 86_400;
+        Some(history.dividends.iter().filter(|(ts, _)| *ts >= cutoff).map(|(_, amount)| amount).sum())
+    }
+
+    /// Custom columns and the liquidity warning use history stats, so daily history is loaded for every holding
+    fn needs_history(&self) -> bool {
+        // Sparkline columns load theirs lazily (`load_sparkline_history`)
+        self.config.liquidity_fraction > 0.0 || self.custom_columns.iter().any(|c| c.expr.uses_history() && !c.sparkline)
+    }
+
+    /// Live-mode refresh interval in seconds (0 = every tick) from `refresh=` or the config default
+    fn refresh_interval(&self, stock: &Stock) -> u64 {
+        stock.refresh.as_deref()
+            .and_then(parse_refresh_interval)
+            .or_else(|| parse_refresh_interval(&self.config.default_refresh))
+            .unwrap_or(0)
+    }
+
+    /// Benchmark for a holding: its own `bench=` or the configured default for its market
+    fn benchmark_for(&self, benchmark: Option<&str>, symbol: &str) -> String {
+        match benchmark {
+            Some(b) => b.to_string(),
+            None if symbol.contains(".TW") => self.config.benchmarks.tw.clone(),
+            None => self.config.benchmarks.us.clone(),
+        }
+    }
+
+    /// Configured base currencies and holdings' `currency=` that need their own USD cross
+    /// rate (not TWD/USD)
+    fn extra_currencies(&self) -> Vec<String> {
+        let holdings = self.stocks.iter().chain(&self.combined_stocks).filter_map(|s| s.currency.as_deref());
+        let mut currencies: Vec<String> = self.config
+            .base_currencies
+            .iter()
+            .cloned()
+            .chain(holdings.map(|c| currency_unit(c).0))
+            .filter(|c| c != "TWD" && c != "USD")
+            .collect();
+        currencies.sort();
+        currencies.dedup();
+        currencies
+    }
+
+    /// Set each `currency=` holding's conversion to USD from the fetched cross rates
+    fn apply_holding_fx(&mut self) {
+        let rates = &self.currency_rates;
+        for stock in self.stocks.iter_mut()
+            .chain(self.tw_stocks.iter_mut())
+            .chain(self.us_stocks.iter_mut())
+            .chain(self.crypto_stocks.iter_mut())
+            .chain(self.combined_stocks.iter_mut())
+            .chain(self.combined_tw_stocks.iter_mut())
+            .chain(self.combined_us_stocks.iter_mut())
+            .chain(self.combined_crypto_stocks.iter_mut())
+        {
+            stock.fx_usd = stock.currency.as_deref().and_then(|c| {
+                let (major, unit) = currency_unit(c);
+                rates.get(&major).filter(|r| **r > 0.0).map(|rate| unit / rate)
+            });
+        }
+    }
+
+    /// Proxy symbols of the `[[pairs]]` whose TW holding is in `stocks`
+    fn pair_proxies(&self, stocks: &[Stock]) -> Vec<String> {
+        self.config.pairs.iter()
+            .filter(|p| stocks.iter().any(|s| s.symbol == p.symbol))
+            .map(|p| p.proxy.clone())
+            .collect()
+    }
+
+    /// Estimate paired TW holdings' opening gap from their proxies' moves while the TW
+    /// market is closed; during the session the actual `gap` applies instead
+    fn apply_gap_estimates(&mut self) {
+        let closed = matches!(Market::Tw.session(chrono::Utc::now(), &self.config.market), MarketSession::Closed { .. });
+        let (pairs, cache) = (&self.config.pairs, &self.cache);
+        for stock in self.stocks.iter_mut()
+            .chain(self.tw_stocks.iter_mut())
+            .chain(self.combined_stocks.iter_mut())
+            .chain(self.combined_tw_stocks.iter_mut())
+        {
+            stock.pre_gap = pairs.iter()
+                .find(|p| p.symbol == stock.symbol && closed)
+                .and_then(|p| cache.get(&p.proxy).map(|(data, _)| data.change_percent * p.beta));
+        }
+    }
+
+    /// Point out holdings quoted in a currency other than the one they are valued in
+    fn warn_currency_mismatch(&mut self) {
+        let stocks = if self.view_combined { &self.combined_stocks } else { &self.stocks };
+        let mismatched: Vec<String> = stocks.iter()
+            .filter_map(|s| s.currency_mismatch().map(|c| format!("{} quotes in {} (valued as {})", s.symbol, c, s.implied_currency())))
+            .collect();
+        if let Some(first) = mismatched.first() {
+            let more = if mismatched.len() > 1 { format!(" and {} more", mismatched.len() - 1) } else { String::new() };
+            self.set_status(format!("Currency mismatch: {}{}; set currency= on the holding", first, more));
+        }
+    }
+
+    /// Convert a TWD amount into a base currency via USD; None until the rate is known
+    fn convert_twd(&self, amount: f64, currency: &str) -> Option<f64> {
+        let usd = amount / self.usd_twd_rate;
+        match currency {
+            "TWD" => Some(amount),
+            "USD" => Some(usd),
+            other => self.currency_rates.get(other).map(|rate| usd * rate),
+        }
+    }
+
+    fn refresh_data(&mut self) -> Result<()> {
+        let mut currencies = self.extra_currencies();
+        let mut quotes = vec!["USDTWD=X".to_string()];
+        for portfolio in &self.portfolios {
+            let stocks = Self::load_stocks_from_file(&portfolio.file_path).unwrap_or_default();
+            quotes.extend(stocks.iter().filter(|s| !s.is_priced_locally()).map(|s| s.symbol.clone()));
+            quotes.extend(self.pair_proxies(&stocks));
+            currencies.extend(stocks.iter().filter_map(|s| s.currency.as_deref()).map(|c| currency_unit(c).0).filter(|c| c != "TWD"));
+        }
+        currencies.sort();
+        currencies.dedup();
+        quotes.extend(currencies.iter().map(|c| format!("USD{}=X", c)));
+        self.prefetch_prices(quotes);
+
+        self.usd_twd_rate = self.fetch_exchange_rate();
+        for currency in currencies {
+            if let Some(data) = self.fetch_price(&format!("USD{}=X", currency)) {
+                self.currency_rates.insert(currency, data.price);
+            }
+        }
+
+        // Load current portfolio stocks with prices
+        let (file_path, portfolio_name) = if let Some(portfolio) = self.portfolios.get(self.current_portfolio_idx) {
+            (portfolio.file_path.clone(), portfolio.name.clone())
+        } else {
+            return Ok(());
+        };
+
+        let mut stocks = Self::load_stocks_from_file(&file_path)?;
+        if !self.show_lots {
+            stocks = merge_lots(stocks);
+        }
+        let needs_history = self.needs_history();
+        let needs_dividends = self.needs_dividends();
+        for stock in &mut stocks {
+            if stock.is_priced_locally() {
+                stock.apply_manual_price();
+            } else {
+                stock.price_data = self.fetch_price(&stock.symbol);
+                if needs_history {
+                    stock.historical = self.fetch_historical(&stock.symbol);
+                }
+                if needs_dividends {
+                    stock.dividends_ttm = self.fetch_dividends_ttm(&stock.symbol);
+                }
+            }
+            stock.portfolio_name = portfolio_name.clone();
+        }
+        self.stocks = stocks;
+
+        // Split into TW, US and crypto
+        self.tw_stocks = self.stocks.iter().filter(|s| s.section() == 0).cloned().collect();
+        self.us_stocks = self.stocks.iter().filter(|s| s.section() == 1).cloned().collect();
+        self.crypto_stocks = self.stocks.iter().filter(|s| s.section() == 2).cloned().collect();
+
+        // Load combined stocks (aggregated)
+        self.load_combined_stocks()?;
+        self.update_benchmark_returns();
+        self.warn_currency_mismatch();
+
+        self.last_update = Instant::now();
+        Ok(())
+    }
+
+    fn load_combined_stocks(&mut self) -> Result<()> {
+        let mut aggregated: HashMap<String, Stock> = HashMap::new();
+        let mut portfolio_map: HashMap<String, Vec<String>> = HashMap::new();
+        let mut closed_positions = Vec::new();
+
+        for portfolio in &self.portfolios {
+            let stocks = Self::load_stocks_from_file(&portfolio.file_path)?;
+            for closed in Self::load_closed_from_file(&portfolio.file_path).unwrap_or_default() {
+                closed_positions.push((portfolio.name.clone(), closed));
+            }
+            for (line, stock) in stocks.into_iter().enumerate() {
+                // Grouped view keeps each portfolio's holding separate, the lots view every line
+                let key = if self.show_lots {
+                    format!("{}|{}|{}", portfolio.name, stock.symbol, line)
+                } else if self.group_by_portfolio {
+                    format!("{}|{}", portfolio.name, stock.symbol)
+                } else {
+                    stock.symbol.clone()
+                };
+                portfolio_map
+                    .entry(key.clone())
+                    .or_default()
+                    .push(portfolio.name.clone());
+
+                if let Some(existing) = aggregated.get_mut(&key) {
+                    existing.absorb(stock);
+                } else {
+                    aggregated.insert(key, stock);
+                }
+            }
+        }
+
+        // Fetch prices for combined stocks
+        self.combined_stocks = aggregated
+            .into_iter()
+            .map(|(key, mut stock)| {
+                let symbol = stock.symbol.clone();
+                if stock.is_priced_locally() {
+                    stock.apply_manual_price();
+                } else {
+                    stock.price_data = self.fetch_price(&symbol);
+                    if self.needs_history() {
+                        stock.historical = self.fetch_historical(&symbol);
+                    }
+                    if self.needs_dividends() {
+                        stock.dividends_ttm = self.fetch_dividends_ttm(&symbol);
+                    }
+                }
+                let portfolios = portfolio_map.get(&key).unwrap();
+                stock.portfolio_name = if portfolios.len() > 1 {
+                    portfolios.join("+")
+                } else {
+                    portfolios.first().cloned().unwrap_or_default()
+                };
+                stock
+            })
+            .collect();
+        self.combined_tw_stocks = self.combined_stocks.iter().filter(|s| s.section() == 0).cloned().collect();
+        self.combined_us_stocks = self.combined_stocks.iter().filter(|s| s.section() == 1).cloned().collect();
+        self.combined_crypto_stocks = self.combined_stocks.iter().filter(|s| s.section() == 2).cloned().collect();
+        self.closed_positions = closed_positions;
+        self.apply_holding_fx();
+        self.apply_gap_estimates();
+        self.load_sparkline_history();
+
+        self.sort_stocks();
+
+        Ok(())
+    }
+
+    /// Give holdings the daily history sparkline columns draw: fresh cached history at once,
+    /// the rest from a background thread as `FetchMessage::History`
+    fn load_sparkline_history(&mut self) {
+        if !self.custom_columns.iter().any(|c| c.sparkline) {
+            return;
+        }
+        let mut missing: Vec<String> = Vec::new();
+        for stock in self.stocks.iter_mut()
+            .chain(self.tw_stocks.iter_mut())
+            .chain(self.us_stocks.iter_mut())
+            .chain(self.crypto_stocks.iter_mut())
+            .chain(self.combined_stocks.iter_mut())
+            .chain(self.combined_tw_stocks.iter_mut())
+            .chain(self.combined_us_stocks.iter_mut())
+            .chain(self.combined_crypto_stocks.iter_mut())
+        {
+            if stock.historical.is_some() || stock.is_priced_locally() {
+                continue;
+            }
+            match self.historical_cache.get(&stock.symbol).filter(|h| h.last_fetched.elapsed().as_secs() < history_ttl()) {
+                Some(historical) => stock.historical = Some(historical.clone()),
+                None if !missing.contains(&stock.symbol) && !self.history_requested.contains(&stock.symbol) => missing.push(stock.symbol.clone()),
+                None => {}
+            }
+        }
+        if missing.is_empty() {
+            return;
+        }
+        self.history_requested.extend(missing.iter().cloned());
+        let sender = self.fetch_sender.clone();
+        let providers = self.config.providers.clone();
+        thread::spawn(move || {
+            for symbol in missing {
+                if let Some(historical) = Self::load_history(&providers, &symbol, HISTORY_RANGE) {
+                    let _ = sender.send(FetchMessage::History(symbol, historical));
+                }
+            }
+        });
+    }
+
+    fn sort_stocks(&mut self) {
+        let usd_twd = self.usd_twd_rate;
+        let fx_neutral = self.fx_neutral;
+        let custom = &self.custom_columns.clone();
+
+        let sorter = |(sort_col, sort_dir): (Option<SortColumn>, SortDirection)| move |a: &Stock, b: &Stock| -> std::cmp::Ordering {
+            let cmp = match sort_col {
+                Some(SortColumn::Symbol) => b.display.to_lowercase().cmp(&a.display.to_lowercase()), // Descending = A→Z
+                Some(SortColumn::DayGain) => {
+                    let a_val = a.day_gain_twd(usd_twd).unwrap_or(f64::NEG_INFINITY);
+                    let b_val = b.day_gain_twd(usd_twd).unwrap_or(f64::NEG_INFINITY);
+                    a_val.partial_cmp(&b_val).unwrap_or(std::cmp::Ordering::Equal)
+                }
+                Some(SortColumn::Weight) => {
+                    let a_val = a.value_twd(usd_twd).unwrap_or(0.0);
+                    let b_val = b.value_twd(usd_twd).unwrap_or(0.0);
+                    a_val.partial_cmp(&b_val).unwrap_or(std::cmp::Ordering::Equal)
+                }
+                Some(SortColumn::Custom(idx)) => match custom.get(idx) {
+                    Some(column) => {
+                        let a_val = column.expr.eval(a, usd_twd).unwrap_or(f64::NEG_INFINITY);
+                        let b_val = column.expr.eval(b, usd_twd).unwrap_or(f64::NEG_INFINITY);
+                        a_val.partial_cmp(&b_val).unwrap_or(std::cmp::Ordering::Equal)
+                    }
+                    None => std::cmp::Ordering::Equal,
+                },
+                Some(SortColumn::Price) => {
+                    let a_val = a.price_data.as_ref().map(|d| d.price).unwrap_or(0.0);
+                    let b_val = b.price_data.as_ref().map(|d| d.price).unwrap_or(0.0);
+                    a_val.partial_cmp(&b_val).unwrap_or(std::cmp::Ordering::Equal)
+                }
+                Some(SortColumn::Change) => {
+                    let a_val = a.price_data.as_ref().map(|d| d.change_percent).unwrap_or(f64::NEG_INFINITY);
+                    let b_val = b.price_data.as_ref().map(|d| d.change_percent).unwrap_or(f64::NEG_INFINITY);
+                    a_val.partial_cmp(&b_val).unwrap_or(std::cmp::Ordering::Equal)
+                }
+                Some(SortColumn::Quantity) => {
+                    a.quantity.partial_cmp(&b.quantity).unwrap_or(std::cmp::Ordering::Equal)
+                }
+                Some(SortColumn::Gain) => {
+                    let a_gain = if a.quantity > 0.0 && a.cost_basis > 0.0 {
+                        a.value_twd(usd_twd).map(|v| v - a.cost_twd(usd_twd, fx_neutral)).unwrap_or(0.0)
+                    } else { 0.0 };
+                    let b_gain = if b.quantity > 0.0 && b.cost_basis > 0.0 {
+                        b.value_twd(usd_twd).map(|v| v - b.cost_twd(usd_twd, fx_neutral)).unwrap_or(0.0)
+                    } else { 0.0 };
+                    a_gain.partial_cmp(&b_gain).unwrap_or(std::cmp::Ordering::Equal)
+                }
+                Some(SortColumn::GainPercent) => {
+                    let a_pct = if a.quantity > 0.0 && a.cost_basis > 0.0 {
+                        if let Some(ref d) = a.price_data {
+                            ((d.price - a.cost_basis) / a.cost_basis) * 100.0
+                        } else { 0.0 }
+                    } else { 0.0 };
+                    let b_pct = if b.quantity > 0.0 && b.cost_basis > 0.0 {
+                        if let Some(ref d) = b.price_data {
+                            ((d.price - b.cost_basis) / b.cost_basis) * 100.0
+                        } else { 0.0 }
+                    } else { 0.0 };
+                    a_pct.partial_cmp(&b_pct).unwrap_or(std::cmp::Ordering::Equal)
+                }
+                None => std::cmp::Ordering::Equal,
+            };
+
+            match sort_dir {
+                SortDirection::Ascending => cmp,
+                SortDirection::Descending => cmp.reverse(),
+            }
+        };
+
+        self.tw_stocks.sort_by(sorter(self.sorts[0]));
+        self.us_stocks.sort_by(sorter(self.sorts[1]));
+        self.combined_tw_stocks.sort_by(sorter(self.sorts[0]));
+        self.combined_us_stocks.sort_by(sorter(self.sorts[1]));
+        self.crypto_stocks.sort_by(sorter(self.sorts[2]));
+        self.combined_crypto_stocks.sort_by(sorter(self.sorts[2]));
+
+        // Grouped view: keep the column order within each portfolio (stable sort)
+        if self.group_by_portfolio {
+            let order = |s: &Stock| self.portfolios.iter().position(|p| p.name == s.portfolio_name).unwrap_or(usize::MAX);
+            self.combined_tw_stocks.sort_by_key(order);
+            self.combined_us_stocks.sort_by_key(order);
+            self.combined_crypto_stocks.sort_by_key(order);
+        }
+    }
+
+    /// Rows of a section's table: holdings, under portfolio headers in the grouped combined view,
+    /// with subtotal rows closing each expanded group (unless positions are hidden); the table's
+    /// own total is pinned below the rows instead (see `totals_footer`)
+    fn display_rows(&self, section: usize) -> Vec<DisplayRow> {
+        let stocks = self.section_stocks(section);
+        let grouped = self.view_combined && self.group_by_portfolio;
+        let subtotals = !self.hide_positions;
+        // While filtering, groups without a matching holding drop out entirely
+        let group_shown = |name: &str| self.filter.is_empty() || stocks.iter().any(|s| s.portfolio_name == name && self.matches_filter(s));
+        let mut rows = Vec::new();
+        for (i, stock) in stocks.iter().enumerate() {
+            let collapsed = grouped && self.collapsed_groups.contains(&stock.portfolio_name);
+            let shown = !grouped || group_shown(&stock.portfolio_name);
+            if grouped && shown && (i == 0 || stocks[i - 1].portfolio_name != stock.portfolio_name) {
+                rows.push(DisplayRow::Group(stock.portfolio_name.clone()));
+            }
+            if !collapsed && !self.hidden_symbols.contains(&stock.symbol) && self.matches_filter(stock) {
+                rows.push(DisplayRow::Stock(i));
+            }
+            let group_ends = stocks.get(i + 1).is_none_or(|next| next.portfolio_name != stock.portfolio_name);
+            if grouped && shown && subtotals && !collapsed && group_ends {
+                rows.push(DisplayRow::Subtotal(Some(stock.portfolio_name.clone())));
+            }
+        }
+        rows
+    }
+
+    /// Whether a holding passes the `/` filter: a case-insensitive fuzzy match on its symbol,
+    /// display name or description
+    fn matches_filter(&self, stock: &Stock) -> bool {
+        self.filter.is_empty()
+            || [&stock.symbol, &stock.display, &stock.name].iter().any(|text| fuzzy_score(&self.filter, text).is_some())
+    }
+
+    /// Holdings in the current view passing the filter, and the view's total
+    fn filter_counts(&self) -> (usize, usize) {
+        let stocks = if self.view_combined { &self.combined_stocks } else { &self.stocks };
+        (stocks.iter().filter(|s| self.matches_filter(s)).count(), stocks.len())
+    }
+
+    /// Group containing the selected row (its header or one of its holdings)
+    fn selected_group(&self) -> Option<String> {
+        let state = self.table_state(self.active_section);
+        let rows = self.display_rows(self.active_section);
+        let selected = state.selected()?.min(rows.len().checked_sub(1)?);
+        rows[..=selected].iter().rev().find_map(|r| match r {
+            DisplayRow::Group(name) => Some(name.clone()),
+            _ => None,
+        })
+    }
+
+    /// Collapse (`Some(true)`), expand (`Some(false)`) or toggle (`None`) the selected group,
+    /// leaving the cursor on its header
+    fn set_group_collapsed(&mut self, collapse: Option<bool>) {
+        let Some(name) = self.selected_group() else {
+            return;
+        };
+        let is_collapsed = self.collapsed_groups.contains(&name);
+        if collapse.unwrap_or(!is_collapsed) {
+            if !is_collapsed {
+                self.collapsed_groups.push(name.clone());
+            }
+        } else {
+            self.collapsed_groups.retain(|g| *g != name);
+        }
+        let header = self.display_rows(self.active_section).iter().position(|r| *r == DisplayRow::Group(name.clone()));
+        self.table_state_mut(self.active_section).select(header);
+    }
+
+    /// Select a holding by its index in the section's stock list
+    fn select_stock_row(&mut self, section: usize, stock_idx: usize) {
+        let row = self.display_rows(section).iter().position(|r| *r == DisplayRow::Stock(stock_idx));
+        if let Some(row) = row {
+            self.active_section = section;
+            self.table_state_mut(section).select(Some(row));
+        }
+    }
+
+    /// Sort column of the focused table (each table keeps its own)
+    fn sort_column(&self) -> Option<SortColumn> {
+        self.sorts[self.active_section].0
+    }
+
+    /// Sort the focused table by a column, reversing it if it's already sorted by that column
+    fn toggle_sort(&mut self, column: SortColumn) {
+        let (sort_column, sort_direction) = &mut self.sorts[self.active_section];
+        if *sort_column == Some(column) {
+            // Toggle direction
+            *sort_direction = match sort_direction {
+                SortDirection::Ascending => SortDirection::Descending,
+                SortDirection::Descending => SortDirection::Ascending,
+            };
+        } else {
+            // New column, default to descending
+            *sort_column = Some(column);
+            *sort_direction = SortDirection::Descending;
+        }
+        self.sort_stocks();
+    }
+
+    fn get_active_tw_stocks(&self) -> &[Stock] {
+        if self.view_combined {
+            &self.combined_tw_stocks
+        } else {
+            &self.tw_stocks
+        }
+    }
+
+    fn get_active_us_stocks(&self) -> &[Stock] {
+        if self.view_combined {
+            &self.combined_us_stocks
+        } else {
+            &self.us_stocks
+        }
+    }
+
+    fn get_active_crypto_stocks(&self) -> &[Stock] {
+        if self.view_combined {
+            &self.combined_crypto_stocks
+        } else {
+            &self.crypto_stocks
+        }
+    }
+
+    /// Holdings of a table section (0 = TW, 1 = US, 2 = crypto) in the current view
+    fn section_stocks(&self, section: usize) -> &[Stock] {
+        match section {
+            0 => self.get_active_tw_stocks(),
+            1 => self.get_active_us_stocks(),
+            _ => self.get_active_crypto_stocks(),
+        }
+    }
+
+    fn table_state(&self, section: usize) -> &TableState {
+        match section {
+            0 => &self.table_state_tw,
+            1 => &self.table_state_us,
+            _ => &self.table_state_crypto,
+        }
+    }
+
+    fn table_state_mut(&mut self, section: usize) -> &mut TableState {
+        match section {
+            0 => &mut self.table_state_tw,
+            1 => &mut self.table_state_us,
+            _ => &mut self.table_state_crypto,
+        }
+    }
+
+    /// Sections with a table on screen: TW and US always, crypto once there are holdings
+    fn visible_sections(&self) -> Vec<usize> {
+        let mut sections = vec![0, 1];
+        if !self.get_active_crypto_stocks().is_empty() {
+            sections.push(2);
+        }
+        sections
+    }
+
+    /// Put every table's cursor back on its first row
+    fn reset_selection(&mut self) {
+        for section in 0..3 {
+            self.table_state_mut(section).select(Some(0));
+        }
+    }
+
+    /// Whether a holding counts toward totals: hidden rows only drop out with `exclude_hidden`
+    fn in_totals(&self, stock: &Stock) -> bool {
+        !(self.config.exclude_hidden && self.hidden_symbols.contains(&stock.symbol))
+    }
+
+    /// x: hide the selected row from the tables for this session
+    fn hide_selected(&mut self) {
+        let Some(symbol) = self.get_selected_stock().map(|s| s.symbol.clone()) else {
+            return;
+        };
+        self.hidden_symbols.push(symbol.clone());
+        let len = self.display_rows(self.active_section).len();
+        let state = self.table_state_mut(self.active_section);
+        if state.selected().is_some_and(|i| i >= len) {
+            state.select(len.checked_sub(1));
+        }
+        self.set_status(format!("Hid {} ({} hidden, X to show all)", symbol, self.hidden_symbols.len()));
+    }
+
+    fn calculate_summary(&self) -> (f64, f64, f64, f64, usize, usize) {
+        let stocks = if self.view_combined {
+            &self.combined_stocks
+        } else {
+            &self.stocks
+        };
+
+        let mut total_cost = 0.0;
+        let mut total_value = 0.0;
+        let mut holdings = 0;
+
+        for stock in stocks.iter().filter(|s| self.in_totals(s)) {
+            if stock.quantity > 0.0 {
+                if let Some(value) = stock.value_twd(self.usd_twd_rate) {
+                    let cost = stock.cost_twd(self.usd_twd_rate, self.fx_neutral);
+
+                    total_cost += cost;
+                    total_value += value;
+                    holdings += 1;
+                }
+            }
+        }
+
+        let total_gain = total_value - total_cost;
+        let total_gain_percent = if total_cost > 0.0 {
+            (total_gain / total_cost) * 100.0
+        } else {
+            0.0
+        };
+
+        (total_cost, total_value, total_gain, total_gain_percent, stocks.len(), holdings)
+    }
+
+    /// Realized P/L (partial sells of open holdings plus archived positions), unrealized
+    /// P/L and their total in TWD, with the total as a percentage of open cost. USD amounts
+    /// convert at today's rate.
+    fn calculate_return_split(&self) -> (f64, f64, f64, f64) {
+        let (total_cost, _, unrealized, _, _, _) = self.calculate_summary();
+        let to_twd = |amount: f64, tw: bool| if tw { amount } else { amount * self.usd_twd_rate };
+        let stocks = if self.view_combined { &self.combined_stocks } else { &self.stocks };
+        let open: f64 = stocks.iter()
+            .filter(|s| self.in_totals(s))
+            .map(|s| to_twd(s.realized, s.is_tw()))
+            .sum();
+        let current = self.portfolios.get(self.current_portfolio_idx).map(|p| p.name.as_str());
+        let closed: f64 = self.closed_positions.iter()
+            .filter(|(portfolio, _)| self.view_combined || Some(portfolio.as_str()) == current)
+            .map(|(_, c)| to_twd(c.realized, c.symbol.contains(".TW")))
+            .sum();
+        let realized = open + closed;
+        let total = realized + unrealized;
+        let total_pct = if total_cost > 0.0 { total / total_cost * 100.0 } else { 0.0 };
+        (realized, unrealized, total, total_pct)
+    }
+
+    // Returns: (tw_value, tw_gain, tw_gain_pct, us_value_usd, us_gain_usd, us_gain_pct)
+    fn calculate_market_summary(&self) -> (f64, f64, f64, f64, f64, f64) {
+        let (tw_value, tw_gain, tw_gain_pct) = self.calculate_section_summary(0);
+        let (us_value, us_gain, us_gain_pct) = self.calculate_section_summary(1);
+        (tw_value, tw_gain, tw_gain_pct, us_value, us_gain, us_gain_pct)
+    }
+
+    /// Value, gain and gain % of one table section in its own currency (TWD for TW, USD otherwise)
+    fn calculate_section_summary(&self, section: usize) -> (f64, f64, f64) {
+        let stocks = if self.view_combined {
+            &self.combined_stocks
+        } else {
+            &self.stocks
+        };
+
+        let mut cost = 0.0;
+        let mut value = 0.0;
+        for stock in stocks.iter().filter(|s| s.section() == section && s.quantity > 0.0 && self.in_totals(s)) {
+            if let Some(stock_value) = stock.value_local() {
+                cost += stock.cost_local();
+                value += stock_value;
+            }
+        }
+
+        let gain = value - cost;
+        let gain_pct = if cost > 0.0 { (gain / cost) * 100.0 } else { 0.0 };
+        (value, gain, gain_pct)
+    }
+
+    /// Share of total TWD value per asset class, in `AssetClass::ALL` order, skipping empty classes
+    fn calculate_allocation(&self) -> Vec<(AssetClass, f64)> {
+        let stocks = if self.view_combined {
+            &self.combined_stocks
+        } else {
+            &self.stocks
+        };
+
+        let mut values = [0.0; AssetClass::ALL.len()];
+        for stock in stocks.iter().filter(|s| s.quantity > 0.0 && self.in_totals(s)) {
+            if let Some(value) = stock.value_twd(self.usd_twd_rate) {
+                let idx = AssetClass::ALL.iter().position(|c| *c == stock.asset_class()).unwrap_or(0);
+                values[idx] += value;
+            }
+        }
+
+        let total: f64 = values.iter().sum();
+        if total <= 0.0 {
+            return Vec::new();
+        }
+        AssetClass::ALL
+            .iter()
+            .zip(values)
+            .filter(|(_, value)| *value > 0.0)
+            .map(|(class, value)| (*class, value / total * 100.0))
+            .collect()
+    }
+
+    /// Total gain in TWD from USD/TWD movement since purchase across US holdings
+    /// Value per currency the holdings are denominated in, largest first: (currency, amount in
+    /// that currency, TWD at today's rates, share of the total in %)
+    fn calculate_currency_exposure(&self) -> Vec<(String, f64, f64, f64)> {
+        let stocks = if self.view_combined {
+            &self.combined_stocks
+        } else {
+            &self.stocks
+        };
+
+        let mut totals: Vec<(String, f64, f64, f64)> = Vec::new();
+        for stock in stocks.iter().filter(|s| s.quantity > 0.0 && self.in_totals(s)) {
+            let (Some(local), Some(twd)) = (stock.value_local(), stock.value_twd(self.usd_twd_rate)) else {
+                continue;
+            };
+            // `value_local` is already in USD for `currency=` holdings
+            let amount = local / stock.fx_usd.filter(|rate| *rate > 0.0 && !stock.is_tw()).unwrap_or(1.0);
+            let currency = stock.implied_currency();
+            match totals.iter_mut().find(|t| t.0 == currency) {
+                Some(total) => {
+                    total.1 += amount;
+                    total.2 += twd;
+                }
+                None => totals.push((currency, amount, twd, 0.0)),
+            }
+        }
+
+        let total: f64 = totals.iter().map(|t| t.2).sum();
+        if total <= 0.0 {
+            return Vec::new();
+        }
+        for entry in totals.iter_mut() {
+            entry.3 = entry.2 / total * 100.0;
+        }
+        totals.sort_by(|a, b| b.2.partial_cmp(&a.2).unwrap_or(std::cmp::Ordering::Equal));
+        totals
+    }
+
+    fn calculate_fx_effect(&self) -> f64 {
+        let stocks = if self.view_combined {
+            &self.combined_stocks
+        } else {
+            &self.stocks
+        };
+
+        stocks
+            .iter()
+            .filter(|s| s.quantity > 0.0 && s.price_data.is_some() && self.in_totals(s))
+            .map(|s| s.fx_effect_twd(self.usd_twd_rate))
+            .sum()
+    }
+
+    fn next_row(&mut self) {
+        let len = self.display_rows(self.active_section).len();
+
+        if len == 0 {
+            return;
+        }
+
+        let state = self.table_state_mut(self.active_section);
+
+        let i = match state.selected() {
+            Some(i) => (i + 1).min(len - 1),
+            None => 0,
+        };
+        state.select(Some(i));
+    }
+
+    fn prev_row(&mut self) {
+        let state = self.table_state_mut(self.active_section);
+
+        let i = match state.selected() {
+            Some(i) => i.saturating_sub(1),
+            None => 0,
+        };
+        state.select(Some(i));
+    }
+
+    fn get_selected_stock(&self) -> Option<&Stock> {
+        let (stocks, state) = (self.section_stocks(self.active_section), self.table_state(self.active_section));
+
+        match self.display_rows(self.active_section).get(state.selected()?) {
+            Some(DisplayRow::Stock(idx)) => stocks.get(*idx),
+            _ => None,
+        }
+    }
+
+    /// Sort menu entries: built-in columns then computed columns, as (column, label)
+    fn sort_menu_entries(&self) -> Vec<(SortColumn, String)> {
+        let mut entries: Vec<(SortColumn, String)> = SortColumn::MENU
+            .iter()
+            .map(|(column, label, key)| {
+                let label = if key.is_empty() { tr(label).to_string() } else { format!("{}  [{}]", tr(label), key) };
+                (*column, label)
+            })
+            .collect();
+        entries.extend(
+            self.custom_columns
+                .iter()
+                .enumerate()
+                .map(|(i, c)| (SortColumn::Custom(i), format!("{}  ({})", c.name, tr("computed")))),
+        );
+        entries
+    }
+
+    /// Type-ahead: select the first row in the active table whose symbol or display name
+    /// starts with the query, falling back to the first that contains it
+    fn jump_to_match(&mut self, query: &str) {
+        if query.is_empty() {
+            return;
+        }
+        let query = query.to_lowercase();
+        let stocks = self.section_stocks(self.active_section);
+        let names = |s: &Stock| [s.symbol.to_lowercase(), s.display.to_lowercase()];
+        let found = stocks.iter().position(|s| names(s).iter().any(|n| n.starts_with(&query)))
+            .or_else(|| stocks.iter().position(|s| names(s).iter().any(|n| n.contains(&query))));
+        if let Some(idx) = found {
+            self.select_stock_row(self.active_section, idx);
+        }
+    }
+
+    /// Move the selection to a symbol's row in the current view
+    fn select_symbol(&mut self, symbol: &str) {
+        for section in 0..3 {
+            if let Some(idx) = self.section_stocks(section).iter().position(|s| s.symbol == symbol) {
+                self.select_stock_row(section, idx);
+                return;
+            }
+        }
+    }
+
+    fn add_stock(&mut self, symbol: String, display: String, name: String, quantity: f64, cost_basis: f64) -> Result<()> {
+        let _lock = PortfolioLock::acquire()?;
+        if let Some(portfolio) = self.portfolios.get(self.current_portfolio_idx) {
+            let mut stocks = Self::load_stocks_from_file(&portfolio.file_path)?;
+            let symbol_is_tw = symbol.contains(".TW");
+            // Another line of a held symbol is a new lot with its own ledger trades
+            let lot = (0..).find(|n| !stocks.iter().any(|s| s.symbol == symbol && s.lot == *n)).unwrap_or_default();
+            stocks.push(Stock {
+                symbol: symbol.clone(),
+                display,
+                name,
+                quantity,
+                cost_basis,
+                price_data: None,
+                historical: None,
+                portfolio_name: portfolio.name.clone(),
+                cost_fx: if symbol_is_tw { None } else { Some(self.usd_twd_rate) },
+                realized: 0.0,
+                manual_price: None,
+                benchmark: None,
+                contract: OptionContract::parse_occ(&symbol),
+                contract_size: None,
+                asset_class: None,
+                bond: None,
+                unit: None,
+                deposit: None,
+                refresh: None,
+                alert_above: None,
+                alert_below: None,
+                tags: Vec::new(),
+                note: None,
+                target: None,
+                currency: None,
+                fx_usd: None,
+                pre_gap: None,
+                purchased: Some(Local::now().date_naive()),
+                first_buy: None,
+                dividends_ttm: None,
+                lots: 1,
+                lot,
+                ledger: quantity > 0.0,
+            });
+            if quantity > 0.0 {
+                self.append_transaction(&portfolio.name, &Transaction {
+                    date: Local::now().date_naive(),
+                    side: TradeSide::Buy,
+                    symbol,
+                    shares: quantity,
+                    price: cost_basis,
+                    fees: 0.0,
+                    lot,
+                })?;
+            }
+            self.save_stocks(&portfolio.name, &stocks)?;
+        }
+        Ok(())
+    }
+
+    /// Overwrite a holding's quantity and cost. Holdings with ledger trades derive both from
+    /// the ledger, so they are left alone and false is returned.
+    fn edit_stock(&mut self, symbol: &str, quantity: f64, cost_basis: f64) -> Result<bool> {
+        let _lock = PortfolioLock::acquire()?;
+        if let Some(portfolio) = self.portfolios.get(self.current_portfolio_idx) {
+            let closed = Self::load_closed_from_file(&portfolio.file_path)?;
+            let ledger = Self::load_ledger(&self.ledger_path(&portfolio.name));
+            let mut stocks = Self::load_stocks_from_file(&portfolio.file_path)?;
+            if let Some(stock) = stocks.iter_mut().find(|s| s.symbol == symbol) {
+                if !open_trades(&ledger, symbol, stock.lot, &closed).is_empty() {
+                    return Ok(false);
+                }
+                stock.quantity = quantity;
+                stock.cost_basis = cost_basis;
+            }
+            self.save_stocks(&portfolio.name, &stocks)?;
+        }
+        Ok(true)
+    }
+
+    /// Set or clear (None) a holding's alert thresholds in the current portfolio
+    fn set_alerts(&mut self, symbol: &str, above: Option<f64>, below: Option<f64>) -> Result<()> {
+        let _lock = PortfolioLock::acquire()?;
+        if let Some(portfolio) = self.portfolios.get(self.current_portfolio_idx) {
+            let mut stocks = Self::load_stocks_from_file(&portfolio.file_path)?;
+            if let Some(stock) = stocks.iter_mut().find(|s| s.symbol == symbol) {
+                stock.alert_above = above;
+                stock.alert_below = below;
+            }
+            self.save_stocks(&portfolio.name, &stocks)?;
+        }
+        Ok(())
+    }
+
+    /// Current view's holdings as JSON, passed to hooks on stdin
+    fn holdings_json(&self, event: &str) -> serde_json::Value {
+        let stocks = if self.view_combined { &self.combined_stocks } else { &self.stocks };
+        let holdings: Vec<serde_json::Value> = stocks
+            .iter()
+            .map(|s| {
+                serde_json::json!({
+                    "symbol": s.symbol,
+                    "display": s.display,
+                    "name": s.name,
+                    "portfolio": s.portfolio_name,
+                    "currency": s.implied_currency(),
+                    "quantity": s.quantity,
+                    "cost_basis": s.cost_basis,
+                    "price": s.price_data.as_ref().map(|d| d.price),
+                    "change": s.price_data.as_ref().map(|d| d.change),
+                    "change_percent": s.price_data.as_ref().map(|d| d.change_percent),
+                    "value_twd": s.value_twd(self.usd_twd_rate),
+                    "gain_twd": s.value_twd(self.usd_twd_rate).map(|v| v - s.cost_twd(self.usd_twd_rate, self.fx_neutral)),
+                    "day_gain_twd": s.day_gain_twd(self.usd_twd_rate),
+                })
+            })
+            .collect();
+        serde_json::json!({
+            "event": event,
+            "portfolio": self.current_view_name(),
+            "usd_twd": self.usd_twd_rate,
+            "holdings": holdings,
+        })
+    }
+
+    /// Answer pending control-socket and HTTP calls (non-blocking)
+    fn process_rpc_requests(&mut self) -> bool {
+        let Some(receiver) = &self.rpc_receiver else {
+            return false;
+        };
+        let calls: Vec<RpcRequest> = receiver.try_iter().collect();
+        let handled = !calls.is_empty();
+        for call in calls {
+            let outcome = self.handle_rpc(&call.method, &call.params);
+            let _ = call.reply.send(outcome);
+        }
+        handled
+    }
+
+    /// Run one JSON-RPC method: `refresh`, `get_summary`, `switch_portfolio` {name}
+    /// (or "ALL") and `add_stock` {symbol, quantity, cost, name?, display?, portfolio?}
+    fn handle_rpc(&mut self, method: &str, params: &serde_json::Value) -> Result<serde_json::Value, (i64, String)> {
+        let failed = |err: anyhow::Error| (-32000, err.to_string());
+        match method {
+            "refresh" => {
+                let started = !self.is_fetching;
+                if started {
+                    self.cache.clear();
+                    self.historical_cache.clear();
+                    self.start_async_refresh(false);
+                }
+                Ok(serde_json::json!({ "started": started }))
+            }
+            "get_summary" => Ok(self.summary_json()),
+            "switch_portfolio" => {
+                let name = params["name"].as_str().ok_or((-32602, "missing \"name\"".to_string()))?;
+                self.switch_to(name).map_err(failed)?;
+                Ok(serde_json::json!({ "portfolio": self.current_view_name() }))
+            }
+            "add_stock" => {
+                // Text ends up in a `|`-separated portfolio line, so separators and line
+                // breaks would shift its fields or add lines
+                let text = |key: &str| match params[key].as_str() {
+                    Some(value) if value.contains('|') || value.chars().any(char::is_control) => {
+                        Err((-32602, format!("\"{}\" may not contain '|' or control characters", key)))
+                    }
+                    value => Ok(value),
+                };
+                let number = |key: &str| {
+                    params[key].as_f64()
+                        .filter(|v| v.is_finite() && *v >= 0.0)
+                        .ok_or((-32602, format!("\"{}\" must be a non-negative number", key)))
+                };
+                let symbol = normalize_symbol(text("symbol")?.ok_or((-32602, "missing \"symbol\"".to_string()))?);
+                let display = text("display")?.map(str::to_string).unwrap_or_else(|| default_display(&symbol));
+                let name = text("name")?.unwrap_or(&symbol).to_string();
+                let quantity = number("quantity")?;
+                let cost = number("cost")?;
+                if let Some(portfolio) = params["portfolio"].as_str() {
+                    self.switch_to(portfolio).map_err(failed)?;
+                }
+                if self.view_combined {
+                    return Err((-32602, "combined view: pass \"portfolio\" or switch first".to_string()));
+                }
+                self.add_stock(symbol.clone(), display, name, quantity, cost).map_err(failed)?;
+                self.refresh_data().map_err(failed)?;
+                Ok(serde_json::json!({ "added": symbol, "portfolio": self.current_view_name() }))
+            }
+            _ => Err((-32601, format!("unknown method '{}'", method))),
+        }
+    }
+
+    /// Switch to a portfolio by name, or the combined view for "ALL"
+    fn switch_to(&mut self, name: &str) -> Result<()> {
+        if name.eq_ignore_ascii_case("ALL") {
+            self.view_combined = true;
+        } else {
+            let idx = self.portfolios.iter().position(|p| p.name == name)
+                .ok_or_else(|| anyhow::anyhow!("no portfolio named '{}'", name))?;
+            self.view_combined = false;
+            self.current_portfolio_idx = idx;
+            self.refresh_data()?;
+        }
+        self.reset_selection();
+        Ok(())
+    }
+
+    /// Holdings plus portfolio totals, written by `--daemon`
+    fn snapshot_json(&self) -> serde_json::Value {
+        let (cost, value, gain, gain_pct, _, holdings) = self.calculate_summary();
+        let (tw_value, tw_gain, tw_gain_pct, us_value, us_gain, us_gain_pct) = self.calculate_market_summary();
+        let mut snapshot = self.holdings_json("snapshot");
+        snapshot["updated"] = serde_json::json!(Local::now().to_rfc3339());
+        snapshot["summary"] = serde_json::json!({
+            "cost_twd": cost,
+            "value_twd": value,
+            "gain_twd": gain,
+            "gain_percent": gain_pct,
+            "holdings": holdings,
+            "tw": { "value_twd": tw_value, "gain_twd": tw_gain, "gain_percent": tw_gain_pct },
+            "us": { "value_usd": us_value, "gain_usd": us_gain, "gain_percent": us_gain_pct },
+        });
+        snapshot
+    }
+
+    /// `get_summary` result (also served over HTTP): the snapshot, with quantities, cost and
+    /// amounts left out while positions are hidden
+    fn summary_json(&self) -> serde_json::Value {
+        let mut snapshot = self.snapshot_json();
+        if self.hide_positions {
+            for holding in snapshot["holdings"].as_array_mut().into_iter().flatten() {
+                if let Some(fields) = holding.as_object_mut() {
+                    for key in ["quantity", "cost_basis", "value_twd", "gain_twd", "day_gain_twd"] {
+                        fields.remove(key);
+                    }
+                }
+            }
+            if let Some(fields) = snapshot.as_object_mut() {
+                fields.remove("summary");
+            }
+            snapshot["positions_hidden"] = serde_json::json!(true);
+        }
+        snapshot
+    }
+
+    /// Plain-text totals and top movers printed after leaving the TUI (`summary_on_exit`);
+    /// amounts are left out while positions are hidden
+    fn exit_summary(&self) -> String {
+        let mut lines = vec![format!(
+            "stock-tui {} — {}  (USD/TWD {:.3})",
+            self.current_view_name(),
+            Local::now().format("%Y-%m-%d %H:%M"),
+            self.usd_twd_rate
+        )];
+        if !self.hide_positions {
+            let (cost, value, gain, gain_pct, _, holdings) = self.calculate_summary();
+            let (tw_value, tw_gain, tw_gain_pct, us_value, us_gain, us_gain_pct) = self.calculate_market_summary();
+            lines.push(format!("  Total  {:>14.0} TWD  gain {:+.0} ({:+.2}%)  cost {:.0}  {} holdings", value, gain, gain_pct, cost, holdings));
+            lines.push(format!("  TW     {:>14.0} TWD  gain {:+.0} ({:+.2}%)", tw_value, tw_gain, tw_gain_pct));
+            lines.push(format!("  US     {:>14.2} USD  gain {:+.2} ({:+.2}%)", us_value, us_gain, us_gain_pct));
+            if !self.get_active_crypto_stocks().is_empty() {
+                let (crypto_value, crypto_gain, crypto_gain_pct) = self.calculate_section_summary(2);
+                lines.push(format!("  Crypto {:>14.2} USD  gain {:+.2} ({:+.2}%)", crypto_value, crypto_gain, crypto_gain_pct));
+            }
+        }
+        let stocks = if self.view_combined { &self.combined_stocks } else { &self.stocks };
+        let mut movers: Vec<(&Stock, f64)> = stocks.iter()
+            .filter_map(|s| s.price_data.as_ref().map(|d| (s, d.change_percent)))
+            .collect();
+        movers.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+        let mover = |(s, pct): &(&Stock, f64)| format!("{} {:+.2}%", s.display, pct);
+        let top: Vec<String> = movers.iter().take(3).filter(|m| m.1 > 0.0).map(mover).collect();
+        let bottom: Vec<String> = movers.iter().rev().take(3).filter(|m| m.1 < 0.0).map(mover).collect();
+        if !top.is_empty() {
+            lines.push(format!("  Up     {}", top.join(", ")));
+        }
+        if !bottom.is_empty() {
+            lines.push(format!("  Down   {}", bottom.join(", ")));
+        }
+        lines.join("\n")
+    }
+
+    /// Markdown summary of the current view's last seven days: value change, the biggest
+    /// movers, each holding against its benchmark, dividends that went ex and earnings due in
+    /// the next two weeks; amounts are left out while positions are hidden
+    fn weekly_report(&mut self) -> String {
+        let now = Local::now();
+        let today = now.date_naive();
+        let week_ago = today - chrono::Duration::days(7);
+        let since = now.timestamp() - 7 * 86400;
+        let mut lines = vec![
+            format!("# stock-tui weekly report — {}", self.current_view_name()),
+            String::new(),
+            format!("{} to {} (USD/TWD {:.3})", week_ago, today, self.usd_twd_rate),
+            String::new(),
+            "## Performance".to_string(),
+            String::new(),
+        ];
+        if self.hide_positions {
+            lines.push("Amounts hidden.".to_string());
+        } else {
+            let (cost, value, gain, gain_pct, _, holdings) = self.calculate_summary();
+            lines.push(format!("- Value: {:.0} TWD across {} holdings", value, holdings));
+            let start = self.value_history.get(&self.value_history_key())
+                .and_then(|history| history.range(..=week_ago.format("%Y-%m-%d").to_string()).next_back())
+                .map(|(_, v)| *v)
+                .filter(|v| *v > 0.0);
+            lines.push(match start {
+                Some(start) => format!("- Week: {:+.0} TWD ({:+.2}%)", value - start, (value / start - 1.0) * 100.0),
+                None => "- Week: -- (no value recorded a week ago)".to_string(),
+            });
+            lines.push(format!("- Gain over cost: {:+.0} TWD ({:+.2}%) on {:.0} TWD", gain, gain_pct, cost));
+        }
+
+        let stocks = if self.view_combined { self.combined_stocks.clone() } else { self.stocks.clone() };
+        // Move from the last close a week ago to `price`
+        let week_move = |history: &HistoricalData, price: f64| {
+            history.timestamps.iter().zip(&history.closes)
+                .take_while(|(ts, _)| **ts <= since)
+                .last()
+                .map(|(_, close)| *close)
+                .filter(|close| *close > 0.0)
+                .map(|base| (price / base - 1.0) * 100.0)
+        };
+        let mut movers = Vec::new();
+        let mut relative = Vec::new();
+        let mut dividends = Vec::new();
+        let mut earnings = Vec::new();
+        for stock in &stocks {
+            let Some(price) = stock.price_data.as_ref().map(|d| d.price) else {
+                continue;
+            };
+            if let Some(history) = self.fetch_history(&stock.symbol, "1mo") {
+                if let Some(pct) = week_move(&history, price) {
+                    movers.push((stock.display.clone(), price_decimals(stock, price), price, pct));
+                    let benchmark = self.benchmark_for(stock.benchmark.as_deref(), &stock.symbol);
+                    let benchmark_move = self.fetch_history(&benchmark, "1mo")
+                        .and_then(|h| week_move(&h, *h.closes.last()?));
+                    if let Some(benchmark_move) = benchmark_move.filter(|_| benchmark != stock.symbol) {
+                        relative.push((stock.display.clone(), pct, benchmark, benchmark_move));
+                    }
+                }
+                for (ts, amount) in history.dividends.iter().filter(|(ts, _)| *ts > since) {
+                    let date = chrono::DateTime::from_timestamp(*ts, 0).map(|d| d.with_timezone(&Local).date_naive()).unwrap_or(today);
+                    dividends.push((date, stock.display.clone(), *amount, amount * stock.quantity, stock.implied_currency()));
+                }
+            }
+            let listed = !stock.is_tw() && !stock.is_crypto() && !stock.symbol.starts_with('^') && !stock.symbol.contains('=');
+            if listed {
+                let due = self.fetch_fundamentals(&stock.symbol)
+                    .and_then(|f| f.next_earnings)
+                    .filter(|d| *d >= today && *d <= today + chrono::Duration::days(14));
+                if let Some(date) = due {
+                    earnings.push((date, stock.display.clone()));
+                }
+            }
+        }
+
+        lines.extend([String::new(), "## Top movers".to_string(), String::new()]);
+        movers.sort_by(|a, b| b.3.partial_cmp(&a.3).unwrap_or(std::cmp::Ordering::Equal));
+        if movers.len() > 6 {
+            movers.drain(3..movers.len() - 3);
+        }
+        if movers.is_empty() {
+            lines.push("No price history for the week.".to_string());
+        } else {
+            lines.push("| Holding | Price | Week |".to_string());
+            lines.push("|---|--:|--:|".to_string());
+            for (display, decimals, price, pct) in &movers {
+                lines.push(format!("| {} | {:.*} | {:+.2}% |", display, decimals, price, pct));
+            }
+        }
+
+        lines.extend([String::new(), "## Against benchmarks".to_string(), String::new()]);
+        relative.sort_by(|a, b| (b.1 - b.3).partial_cmp(&(a.1 - a.3)).unwrap_or(std::cmp::Ordering::Equal));
+        if relative.is_empty() {
+            lines.push("No benchmark history for the week.".to_string());
+        } else {
+            lines.push("| Holding | Week | Benchmark | Benchmark week | Relative |".to_string());
+            lines.push("|---|--:|---|--:|--:|".to_string());
+            for (display, pct, benchmark, benchmark_pct) in &relative {
+                lines.push(format!("| {} | {:+.2}% | {} | {:+.2}% | {:+.2} pp |", display, pct, benchmark, benchmark_pct, pct - benchmark_pct));
+            }
+        }
+
+        lines.extend([String::new(), "## Dividends (ex-date this week)".to_string(), String::new()]);
+        if dividends.is_empty() {
+            lines.push("None.".to_string());
+        } else if self.hide_positions {
+            lines.push("| Holding | Ex-date | Per share |".to_string());
+            lines.push("|---|---|--:|".to_string());
+            for (date, display, per_share, _, currency) in &dividends {
+                lines.push(format!("| {} | {} | {:.4} {} |", display, date, per_share, currency));
+            }
+        } else {
+            lines.push("| Holding | Ex-date | Per share | Amount |".to_string());
+            lines.push("|---|---|--:|--:|".to_string());
+            for (date, display, per_share, amount, currency) in &dividends {
+                lines.push(format!("| {} | {} | {:.4} | {:.2} {} |", display, date, per_share, amount, currency));
+            }
+        }
+
+        lines.extend([String::new(), "## Upcoming earnings (next 14 days)".to_string(), String::new()]);
+        earnings.sort();
+        if earnings.is_empty() {
+            lines.push("None scheduled.".to_string());
+        } else {
+            for (date, display) in &earnings {
+                lines.push(format!("- {} {}: {}", date.format("%a"), date, display));
+            }
+        }
+        lines.push(String::new());
+        lines.join("\n")
+    }
+
+    /// Write the report to `[report] path` and pipe it to `[report] command`, returning where
+    /// it went; an error for the first destination that fails
+    fn deliver_report(&self, report: &str) -> Result<Vec<String>> {
+        let config = &self.config.report;
+        let mut sent = Vec::new();
+        if let Some(path) = config.path(Local::now().date_naive()) {
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir)?;
+            }
+            fs::write(&path, report)?;
+            sent.push(path.display().to_string());
+        }
+        if !config.command.trim().is_empty() {
+            let mut child = std::process::Command::new("sh")
+                .arg("-c")
+                .arg(&config.command)
+                .env("STOCK_TUI_EVENT", "weekly_report")
+                .env("STOCK_TUI_PORTFOLIO", self.current_view_name())
+                .stdin(std::process::Stdio::piped())
+                .stdout(std::process::Stdio::null())
+                .spawn()?;
+            if let Some(mut stdin) = child.stdin.take() {
+                stdin.write_all(report.as_bytes())?;
+            }
+            let status = child.wait()?;
+            anyhow::ensure!(status.success(), "`{}` exited with {}", config.command, status);
+            sent.push(format!("`{}`", config.command));
+        }
+        anyhow::ensure!(!sent.is_empty(), "set `path` or `command` in the [report] config section");
+        Ok(sent)
+    }
+
+    /// From `--daemon`: deliver the weekly report once on `[report] weekday`, from `hour` on;
+    /// a failed delivery is retried on the next refresh
+    fn send_scheduled_report(&mut self) {
+        let Some(weekday) = self.config.report.weekday() else {
+            return;
+        };
+        let now = Local::now();
+        if now.weekday() != weekday || now.hour() < self.config.report.hour {
+            return;
+        }
+        let marker = Self::cache_db_path().with_file_name("last_report");
+        let today = now.format("%Y-%m-%d").to_string();
+        if fs::read_to_string(&marker).map(|d| d.trim() == today).unwrap_or(false) {
+            return;
+        }
+        let report = self.weekly_report();
+        match self.deliver_report(&report) {
+            Ok(_) => {
+                if let Some(dir) = marker.parent() {
+                    let _ = fs::create_dir_all(dir);
+                }
+                let _ = fs::write(&marker, &today);
+            }
+            Err(err) => eprintln!("stock-tui: weekly report: {err:?}"),
+        }
+    }
+
+    fn current_view_name(&self) -> String {
+        if self.view_combined {
+            "ALL".to_string()
+        } else {
+            self.portfolios.get(self.current_portfolio_idx).map(|p| p.name.clone()).unwrap_or_default()
+        }
+    }
+
+    /// Run the `[[commands]]` entry bound to `key` for a holding in the current view, in the
+    /// background with output discarded
+    fn run_command(&mut self, key: char, symbol: &str) {
+        let Some(command) = self.config.commands.iter().find(|c| c.key == key) else {
+            return;
+        };
+        let stocks = if self.view_combined { &self.combined_stocks } else { &self.stocks };
+        let Some(stock) = stocks.iter().find(|s| s.symbol == symbol) else {
+            return;
+        };
+        let mut process = std::process::Command::new("sh");
+        process
+            .arg("-c")
+            .arg(command.expand(stock))
+            .env("STOCK_TUI_SYMBOL", &stock.symbol)
+            .stdin(std::process::Stdio::null())
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null());
+        let message = match process.spawn() {
+            Ok(mut child) => {
+                thread::spawn(move || child.wait());
+                format!("{}: {}", command.name, stock.symbol)
+            }
+            Err(err) => format!("{} failed: {}", command.name, err),
+        };
+        self.set_status(message);
+    }
+
+    /// Run a hook command in the background; output is discarded so it can't disturb the TUI
+    fn run_hook(&self, command: Option<&str>, event: &str, env: Vec<(&str, String)>) {
+        let Some(command) = command.filter(|c| !c.trim().is_empty()) else {
+            return;
+        };
+        let (_, total_value, _, _, _, _) = self.calculate_summary();
+        let mut process = std::process::Command::new("sh");
+        process
+            .arg("-c")
+            .arg(command)
+            .env("STOCK_TUI_EVENT", event)
+            .env("STOCK_TUI_PORTFOLIO", self.current_view_name())
+            .env("STOCK_TUI_TOTAL_VALUE_TWD", format!("{:.2}", total_value))
+            .envs(env)
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null());
+        let payload = format!("{}\n", self.holdings_json(event));
+        thread::spawn(move || {
+            if let Ok(mut child) = process.spawn() {
+                if let Some(mut stdin) = child.stdin.take() {
+                    let _ = stdin.write_all(payload.as_bytes());
+                }
+                let _ = child.wait();
+            }
+        });
+    }
+
+    fn value_history_path() -> PathBuf {
+        dirs::home_dir()
+            .unwrap_or_default()
+            .join(".config/stock-tui/value_history.json")
+    }
+
+    fn cost_history_path() -> PathBuf {
+        dirs::home_dir()
+            .unwrap_or_default()
+            .join(".config/stock-tui/cost_history.json")
+    }
+
+    fn load_daily_totals(path: &Path) -> HashMap<String, BTreeMap<String, f64>> {
+        fs::read_to_string(path)
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default()
+    }
+
+    /// Value history key of the current view: the portfolio name, or `*` for the combined view
+    fn value_history_key(&self) -> String {
+        if self.view_combined {
+            "*".to_string()
+        } else {
+            self.portfolios.get(self.current_portfolio_idx).map(|p| p.name.clone()).unwrap_or_default()
+        }
+    }
+
+    /// Total (cost, value) in TWD of the current view's holdings, or only `portfolio`'s; None
+    /// while nothing is held or any holding is unpriced. Cost is at the purchase-time rate,
+    /// so it stays comparable from day to day.
+    fn daily_totals(&self, portfolio: Option<&str>) -> Option<(f64, f64)> {
+        let stocks = if self.view_combined { &self.combined_stocks } else { &self.stocks };
+        let held: Vec<&Stock> = stocks.iter()
+            .filter(|s| self.in_totals(s) && s.quantity > 0.0)
+            .filter(|s| portfolio.is_none_or(|p| s.portfolio_name == p))
+            .collect();
+        if held.is_empty() {
+            return None;
+        }
+        held.iter().try_fold((0.0, 0.0), |(cost, value), s| {
+            Some((cost + s.cost_twd(self.usd_twd_rate, false), value + s.value_twd(self.usd_twd_rate)?))
+        })
+    }
+
+    /// Record today's total value and cost of the current view after a completed refresh
+    /// (the last refresh of the day wins), and of each portfolio in the combined view,
+    /// keeping `VALUE_HISTORY_KEEP_DAYS` days. Skipped while any holding is unpriced, so a
+    /// failed quote doesn't leave a dip in the sparkline.
+    fn record_daily_value(&mut self) {
+        if self.watch_only {
+            return;
+        }
+        let Some(totals) = self.daily_totals(None) else {
+            return;
+        };
+        let mut records = vec![(self.value_history_key(), totals)];
+        if self.view_combined {
+            records.extend(self.portfolios.iter().filter_map(|p| Some((p.name.clone(), self.daily_totals(Some(&p.name))?))));
+        }
+        let today = Local::now().date_naive();
+        let date = today.format("%Y-%m-%d").to_string();
+        let oldest = (today - chrono::Duration::days(VALUE_HISTORY_KEEP_DAYS - 1)).format("%Y-%m-%d").to_string();
+        for (key, (cost, value)) in records {
+            for (history, amount) in [(&mut self.value_history, value), (&mut self.cost_history, cost)] {
+                let history = history.entry(key.clone()).or_default();
+                history.insert(date.clone(), amount);
+                history.retain(|date, _| *date >= oldest);
+            }
+        }
+
+        for (path, history) in [(Self::value_history_path(), &self.value_history), (Self::cost_history_path(), &self.cost_history)] {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent).ok();
+            }
+            if let Ok(json) = serde_json::to_string_pretty(history) {
+                let _ = write_atomic(&path, &json);
+            }
+        }
+        if self.record_positions_snapshot(today) {
+            self.run_hook(self.config.hooks.daily_snapshot.as_deref(), "daily_snapshot", Vec::new());
+        }
+    }
+
+    /// Equity curve screen for the current view, with every other recorded view a key away
+    fn equity_state(&self) -> EquityState {
+        let current = self.value_history_key();
+        let mut others: Vec<String> = self.value_history.keys().filter(|k| **k != current).cloned().collect();
+        others.sort();
+        EquityState { keys: std::iter::once(current).chain(others).collect(), selected: 0 }
+    }
+
+    fn snapshots_dir() -> PathBuf {
+        dirs::home_dir()
+            .unwrap_or_default()
+            .join(".config/stock-tui/snapshots")
+    }
+
+    /// The current view's priced holdings, as stored in a daily snapshot
+    fn positions_snapshot(&self) -> PositionsSnapshot {
+        let stocks = if self.view_combined { &self.combined_stocks } else { &self.stocks };
+        let holdings = stocks.iter()
+            .filter(|s| self.in_totals(s) && s.quantity > 0.0)
+            .filter_map(|s| Some(SnapshotHolding {
+                symbol: s.symbol.clone(),
+                display: s.display.clone(),
+                quantity: s.quantity,
+                price: s.price_data.as_ref()?.price,
+                value_twd: s.value_twd(self.usd_twd_rate)?,
+                tw: s.is_tw(),
+            }))
+            .collect();
+        PositionsSnapshot { usd_twd: self.usd_twd_rate, holdings }
+    }
+
+    /// Keep today's holdings of the current view in `snapshots/<date>.json` (one file per day,
+    /// keyed like the value history), dropping files past `VALUE_HISTORY_DAYS`. True when
+    /// this wrote the view's first snapshot of the day.
+    fn record_positions_snapshot(&self, today: chrono::NaiveDate) -> bool {
+        let dir = Self::snapshots_dir();
+        fs::create_dir_all(&dir).ok();
+        let path = dir.join(format!("{}.json", today.format("%Y-%m-%d")));
+        let mut snapshots: HashMap<String, PositionsSnapshot> = fs::read_to_string(&path)
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default();
+        let first = snapshots.insert(self.value_history_key(), self.positions_snapshot()).is_none();
+        let written = serde_json::to_string_pretty(&snapshots)
+            .is_ok_and(|json| write_atomic(&path, &json).is_ok());
+
+        let oldest = today - chrono::Duration::days(VALUE_HISTORY_DAYS - 1);
+        for entry in fs::read_dir(&dir).into_iter().flatten().flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let date = name.strip_suffix(".json").and_then(|d| chrono::NaiveDate::parse_from_str(d, "%Y-%m-%d").ok());
+            if date.is_some_and(|d| d < oldest) {
+                let _ = fs::remove_file(entry.path());
+            }
+        }
+        first && written
+    }
+
+    /// Compare the current view with its latest snapshot from before today
+    fn load_changes(&self) -> ChangesState {
+        let today = Local::now().date_naive();
+        let key = self.value_history_key();
+        let mut dates: Vec<chrono::NaiveDate> = fs::read_dir(Self::snapshots_dir())
+            .into_iter()
+            .flatten()
+            .flatten()
+            .filter_map(|e| e.file_name().to_string_lossy().strip_suffix(".json").and_then(|d| chrono::NaiveDate::parse_from_str(d, "%Y-%m-%d").ok()))
+            .filter(|d| *d < today)
+            .collect();
+        dates.sort();
+        let previous = dates.iter().rev().find_map(|date| {
+            let content = fs::read_to_string(Self::snapshots_dir().join(format!("{}.json", date.format("%Y-%m-%d")))).ok()?;
+            let mut snapshots: HashMap<String, PositionsSnapshot> = serde_json::from_str(&content).ok()?;
+            Some((*date, snapshots.remove(&key)?))
+        });
+        let current = self.positions_snapshot();
+        let mut state = ChangesState {
+            current_total: current.holdings.iter().map(|h| h.value_twd).sum(),
+            current_rate: current.usd_twd,
+            ..Default::default()
+        };
+        let Some((since, previous)) = previous else {
+            return state;
+        };
+        state.since = Some(since);
+        state.previous_total = previous.holdings.iter().map(|h| h.value_twd).sum();
+        state.previous_rate = previous.usd_twd;
+        for now in &current.holdings {
+            match previous.holdings.iter().find(|h| h.symbol == now.symbol) {
+                Some(then) => {
+                    if !now.tw && previous.usd_twd > 0.0 {
+                        state.fx_impact += then.value_twd / previous.usd_twd * (current.usd_twd - previous.usd_twd);
+                    }
+                    state.changed.push((then.clone(), now.clone()));
+                }
+                None => state.new.push(now.clone()),
+            }
+        }
+        state.closed = previous.holdings.iter()
+            .filter(|then| !current.holdings.iter().any(|h| h.symbol == then.symbol))
+            .cloned()
+            .collect();
+        state.changed.sort_by(|(a_then, a_now), (b_then, b_now)| {
+            let delta = |then: &SnapshotHolding, now: &SnapshotHolding| (now.value_twd - then.value_twd).abs();
+            delta(b_then, b_now).partial_cmp(&delta(a_then, a_now)).unwrap_or(std::cmp::Ordering::Equal)
+        });
+        state
+    }
+
+    /// Recorded daily values of the current view, oldest first
+    fn daily_values(&self) -> Vec<f64> {
+        let oldest = (Local::now().date_naive() - chrono::Duration::days(VALUE_HISTORY_DAYS - 1)).format("%Y-%m-%d").to_string();
+        self.value_history.get(&self.value_history_key())
+            .map(|h| h.range(oldest..).map(|(_, v)| *v).collect())
+            .unwrap_or_default()
+    }
+
+    /// Benchmark the summary's alpha is measured against: `[benchmarks] summary`, else the
+    /// index of whichever market holds more of the view's value
+    fn summary_benchmark(&self) -> String {
+        if !self.config.benchmarks.summary.is_empty() {
+            return self.config.benchmarks.summary.clone();
+        }
+        let (tw_value, _, _, us_value, _, _) = self.calculate_market_summary();
+        if tw_value >= us_value * self.usd_twd_rate {
+            self.config.benchmarks.tw.clone()
+        } else {
+            self.config.benchmarks.us.clone()
+        }
+    }
+
+    /// Portfolio return over today, month to date and year to date, in percent. Today's comes
+    /// from the holdings' day gains; the others from the recorded daily value before the
+    /// period began (None until one is recorded).
+    fn period_returns(&self) -> [Option<f64>; 3] {
+        let stocks = if self.view_combined { &self.combined_stocks } else { &self.stocks };
+        let (_, value, _, _, _, _) = self.calculate_summary();
+        let day_gain: f64 = stocks.iter().filter(|s| self.in_totals(s)).filter_map(|s| s.day_gain_twd(self.usd_twd_rate)).sum();
+        let day = (value - day_gain > 0.0).then(|| day_gain / (value - day_gain) * 100.0);
+        let history = self.value_history.get(&self.value_history_key());
+        let since = |start: chrono::NaiveDate| -> Option<f64> {
+            let (_, base) = history?.range(..start.format("%Y-%m-%d").to_string()).next_back()?;
+            (*base > 0.0).then(|| (value / base - 1.0) * 100.0)
+        };
+        let (month, year) = period_starts(Local::now().date_naive());
+        [day, since(month), since(year)]
+    }
+
+    /// Refresh the summary benchmark's day, month-to-date and year-to-date returns
+    fn update_benchmark_returns(&mut self) {
+        let symbol = self.summary_benchmark();
+        let Some(quote) = self.fetch_price(&symbol) else {
+            return;
+        };
+        let history = self.fetch_history(&symbol, "1y");
+        let (month, year) = period_starts(Local::now().date_naive());
+        let since = |start: chrono::NaiveDate| -> Option<f64> {
+            let history = history.as_ref()?;
+            let start = start.and_hms_opt(0, 0, 0)?.and_local_timezone(Local).single()?.timestamp();
+            let base = history.closes[..history.timestamps.partition_point(|t| *t < start)].last()?;
+            Some((quote.price / base - 1.0) * 100.0)
+        };
+        self.benchmark_returns = Some((symbol, [Some(quote.change_percent), since(month), since(year)]));
+    }
+
+    fn column_widths_path() -> PathBuf {
+        dirs::home_dir()
+            .unwrap_or_default()
+            .join(".config/stock-tui/columns.json")
+    }
+
+    fn load_column_widths() -> HashMap<String, u16> {
+        fs::read_to_string(Self::column_widths_path())
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default()
+    }
+
+    /// `[`/`]`: pick the column `<`/`>` resize, wrapping around
+    fn select_resize_column(&mut self, forward: bool) {
+        let columns = column_layout(self.view_combined, self.hide_positions, &self.custom_columns, &self.column_widths);
+        let count = columns.len();
+        self.resize_column = if forward { (self.resize_column + 1) % count } else { (self.resize_column + count - 1) % count };
+        let (name, width) = &columns[self.resize_column];
+        self.set_status(format!("Resize column: {} ({}) — < / > to adjust, = to reset", name, width));
+    }
+
+    /// `<`/`>` change the picked column's width by `delta`; `=` (None) restores its default.
+    /// Widths are saved for the next session.
+    fn resize_selected_column(&mut self, delta: Option<i16>) {
+        let columns = column_layout(self.view_combined, self.hide_positions, &self.custom_columns, &self.column_widths);
+        let Some((name, width)) = columns.get(self.resize_column.min(columns.len().saturating_sub(1))).cloned() else {
+            return;
+        };
+        match delta {
+            Some(delta) => {
+                let width = (width as i16 + delta).clamp(3, 60) as u16;
+                self.column_widths.insert(name.clone(), width);
+                self.set_status(format!("{} width {}", name, width));
+            }
+            None => {
+                self.column_widths.remove(&name);
+                self.set_status(format!("{} width reset", name));
+            }
+        }
+        let path = Self::column_widths_path();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).ok();
+        }
+        if let Ok(json) = serde_json::to_string_pretty(&self.column_widths) {
+            let _ = write_atomic(&path, &json);
+        }
+    }
+
+    fn alert_state_path() -> PathBuf {
+        dirs::home_dir()
+            .unwrap_or_default()
+            .join(".config/stock-tui/alerts.json")
+    }
+
+    fn load_alert_state() -> AlertState {
+        fs::read_to_string(Self::alert_state_path())
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default()
+    }
+
+    fn save_alert_state(&self) {
+        let path = Self::alert_state_path();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).ok();
+        }
+        if let Ok(json) = serde_json::to_string_pretty(&self.alert_state) {
+            let _ = write_atomic(&path, &json);
+        }
+    }
+
+    fn alert_key(symbol: &str, rule: &str) -> String {
+        format!("{}|{}", symbol, rule)
+    }
+
+    fn alerts_snoozed(&self) -> bool {
+        self.alert_state.snoozed_until.is_some_and(|until| Local::now().timestamp() < until)
+    }
+
+    /// Triggered alerts in the current view as (symbol, rule), excluding muted ones; empty while snoozed
+    fn active_alerts(&self) -> Vec<(String, String)> {
+        if self.alerts_snoozed() {
+            return Vec::new();
+        }
+        let stocks = if self.view_combined { &self.combined_stocks } else { &self.stocks };
+        stocks
+            .iter()
+            .filter_map(|s| s.triggered_alert().map(|rule| (s.symbol.clone(), rule)))
+            .filter(|(symbol, rule)| !self.alert_state.muted.contains(&Self::alert_key(symbol, rule)))
+            .collect()
+    }
+
+    /// Queue a popup for each alert that started triggering, drop mutes for alerts
+    /// that are no longer triggering and expired snoozes
+    fn update_alerts(&mut self) {
+        let active = self.active_alerts();
+        let stocks = if self.view_combined { &self.combined_stocks } else { &self.stocks };
+        for (symbol, rule) in &active {
+            let key = Self::alert_key(symbol, rule);
+            if !self.fired_alerts.contains(&key) {
+                let price = stocks.iter()
+                    .find(|s| &s.symbol == symbol)
+                    .and_then(|s| s.price_data.as_ref())
+                    .map(|d| d.price)
+                    .unwrap_or(0.0);
+                self.alert_popups.push(AlertPopup { symbol: symbol.clone(), rule: rule.clone(), price, time: Local::now() });
+                self.run_hook(
+                    self.config.hooks.alert_fired.as_deref(),
+                    "alert_fired",
+                    vec![
+                        ("STOCK_TUI_SYMBOL", symbol.clone()),
+                        ("STOCK_TUI_RULE", rule.clone()),
+                        ("STOCK_TUI_PRICE", price.to_string()),
+                    ],
+                );
+                self.fired_alerts.push(key);
+            }
+        }
+        self.fired_alerts.retain(|key| active.iter().any(|(symbol, rule)| Self::alert_key(symbol, rule) == *key));
+
+        let stocks = if self.view_combined { &self.combined_stocks } else { &self.stocks };
+        let before = self.alert_state.muted.len();
+        self.alert_state.muted.retain(|key| {
+            let symbol = key.split('|').next().unwrap_or_default();
+            match stocks.iter().find(|s| s.symbol == symbol) {
+                // Keep mutes for holdings not loaded in this view or not priced yet
+                Some(stock) if stock.price_data.is_some() => {
+                    stock.triggered_alert().is_some_and(|rule| Self::alert_key(symbol, &rule) == *key)
+                }
+                _ => true,
+            }
+        });
+        let mut changed = self.alert_state.muted.len() != before;
+        if self.alert_state.snoozed_until.is_some() && !self.alerts_snoozed() {
+            self.alert_state.snoozed_until = None;
+            changed = true;
+        }
+        if changed {
+            self.save_alert_state();
+        }
+    }
+
+    /// Mute the alert shown in the front popup and dismiss it
+    fn mute_popup_alert(&mut self) {
+        if !self.alert_popups.is_empty() {
+            let popup = self.alert_popups.remove(0);
+            self.alert_state.muted.push(Self::alert_key(&popup.symbol, &popup.rule));
+            self.save_alert_state();
+            self.set_status(format!("Muted {} alert ({})", popup.symbol, popup.rule));
+        }
+    }
+
+    /// Mute the selected row's alert, or the first active one
+    fn mute_alert(&mut self) {
+        let active = self.active_alerts();
+        let selected = self.get_selected_stock().map(|s| s.symbol.clone());
+        let target = active
+            .iter()
+            .find(|(symbol, _)| Some(symbol) == selected.as_ref())
+            .or_else(|| active.first());
+        if let Some((symbol, rule)) = target {
+            let message = format!("Muted {} alert ({})", symbol, rule);
+            self.alert_state.muted.push(Self::alert_key(symbol, rule));
+            self.save_alert_state();
+            self.set_status(message);
+        }
+    }
+
+    /// Snooze all alerts, stepping through the snooze durations and then back to off
+    fn cycle_snooze(&mut self) {
+        let now = Local::now();
+        let current_mins = self.alert_state.snoozed_until
+            .filter(|_| self.alerts_snoozed())
+            .map(|until| (until - now.timestamp() + 59) / 60);
+        let next = match current_mins {
+            None => SNOOZE_STEPS_MINS.first().copied(),
+            Some(mins) => SNOOZE_STEPS_MINS.iter().copied().find(|step| *step > mins),
+        };
+        match next {
+            Some(mins) => {
+                let until = now + chrono::Duration::minutes(mins);
+                self.alert_state.snoozed_until = Some(until.timestamp());
+                self.set_status(format!("Alerts snoozed until {}", until.format("%H:%M")));
+            }
+            None => {
+                self.alert_state.snoozed_until = None;
+                self.set_status("Alert snooze off");
+            }
+        }
+        self.save_alert_state();
+    }
+
+    fn etf_dir() -> PathBuf {
+        dirs::home_dir()
+            .unwrap_or_default()
+            .join(".config/stock-tui/etf")
+    }
+
+    /// User-provided top holdings for an ETF: `etf/<SYMBOL>.conf` with `SYMBOL|Weight %` lines
+    fn load_etf_holdings(symbol: &str) -> Option<Vec<(String, f64)>> {
+        let content = fs::read_to_string(Self::etf_dir().join(format!("{}.conf", symbol))).ok()?;
+        let holdings = content
+            .lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty() && !l.starts_with('#'))
+            .filter_map(|l| {
+                let (underlying, weight) = l.split_once('|')?;
+                Some((underlying.trim().to_uppercase(), weight.trim().trim_end_matches('%').parse().ok()?))
+            })
+            .collect();
+        Some(holdings)
+    }
+
+    /// Direct plus look-through exposure per underlying across the active view, largest first
+    fn calculate_exposure(&self) -> ExposureState {
+        let stocks = if self.view_combined { &self.combined_stocks } else { &self.stocks };
+        let mut rows: HashMap<String, ExposureRow> = HashMap::new();
+        let mut state = ExposureState::default();
+
+        for stock in stocks {
+            let Some(value) = stock.value_twd(self.usd_twd_rate) else {
+                continue;
+            };
+            state.total_value += value;
+
+            match Self::load_etf_holdings(&stock.symbol) {
+                Some(holdings) => {
+                    for (underlying, weight) in holdings {
+                        let row = rows.entry(underlying.clone()).or_insert_with(|| ExposureRow {
+                            symbol: underlying,
+                            direct: 0.0,
+                            via_etfs: 0.0,
+                            etfs: Vec::new(),
+                        });
+                        row.via_etfs += value * weight / 100.0;
+                        row.etfs.push(stock.display.clone());
+                    }
+                }
+                None => {
+                    let looks_like_etf = stock.name.to_uppercase().contains("ETF") || stock.symbol.starts_with("00");
+                    if looks_like_etf {
+                        state.missing.push(stock.symbol.clone());
+                    }
+                    let row = rows.entry(stock.symbol.clone()).or_insert_with(|| ExposureRow {
+                        symbol: stock.symbol.clone(),
+                        direct: 0.0,
+                        via_etfs: 0.0,
+                        etfs: Vec::new(),
+                    });
+                    row.direct += value;
+                }
+            }
+        }
+
+        state.rows = rows.into_values().collect();
+        state.rows.sort_by(|a, b| {
+            (b.direct + b.via_etfs).partial_cmp(&(a.direct + a.via_etfs)).unwrap_or(std::cmp::Ordering::Equal)
+        });
+        state
+    }
+
+    /// Priced holdings of the active view by TWD value, one entry per symbol
+    fn position_weights(&self) -> AllocationState {
+        let stocks = if self.view_combined { &self.combined_stocks } else { &self.stocks };
+        let mut state = AllocationState::default();
+        for stock in stocks.iter().filter(|s| self.in_totals(s) && s.quantity > 0.0) {
+            let Some(value) = stock.value_twd(self.usd_twd_rate) else {
+                continue;
+            };
+            state.total_value += value;
+            match state.holdings.iter_mut().find(|(display, section, _)| *display == stock.display && *section == stock.section()) {
+                Some(holding) => holding.2 += value,
+                None => state.holdings.push((stock.display.clone(), stock.section(), value)),
+            }
+        }
+        state.holdings.sort_by(|a, b| b.2.partial_cmp(&a.2).unwrap_or(std::cmp::Ordering::Equal));
+        state
+    }
+
+    fn ledger_path(&self, portfolio_name: &str) -> PathBuf {
+        self.portfolio_path(portfolio_name).with_extension("ledger")
+    }
+
+    fn append_transaction(&self, portfolio_name: &str, transaction: &Transaction) -> Result<()> {
+        let _lock = PortfolioLock::acquire()?;
+        let path = self.ledger_path(portfolio_name);
+        let is_new = !path.exists();
+        let mut file = fs::OpenOptions::new().create(true).append(true).open(&path)?;
+        if is_new {
+            writeln!(file, "# Transaction Ledger")?;
+            writeln!(file, "# Format: DATE|BUY or SELL|SYMBOL|Shares|Price|Fees[|lot=N]")?;
+        }
+        writeln!(file, "{}", transaction.to_ledger_line())?;
+        Ok(())
+    }
+
+    /// Insert a transaction ahead of a ledger's existing trades, after its header comments
+    fn prepend_transaction(path: &Path, transaction: &Transaction) -> Result<()> {
+        let _lock = PortfolioLock::acquire()?;
+        let content = fs::read_to_string(path).unwrap_or_default();
+        let header = content.lines().take_while(|l| l.starts_with('#')).count();
+        let mut lines: Vec<String> = content.lines().map(str::to_string).collect();
+        lines.insert(header, transaction.to_ledger_line());
+        write_atomic(path, &(lines.join("\n") + "\n"))?;
+        Ok(())
+    }
+
+    /// Remove the open-position trades of `symbols` from a portfolio's ledger and return them
+    fn take_ledger_entries(&self, portfolio_name: &str, symbols: &[String]) -> Result<Vec<Transaction>> {
+        let path = self.ledger_path(portfolio_name);
+        let Ok(content) = fs::read_to_string(&path) else {
+            return Ok(Vec::new());
+        };
+        let closed = Self::load_closed_from_file(&self.portfolio_path(portfolio_name))?;
+        let ledger: Vec<Transaction> = content.lines().filter_map(Transaction::parse).collect();
+        let mut lots: Vec<(&str, u32)> = ledger.iter()
+            .filter(|t| symbols.contains(&t.symbol))
+            .map(|t| (t.symbol.as_str(), t.lot))
+            .collect();
+        lots.sort();
+        lots.dedup();
+        let taken: Vec<Transaction> = lots.into_iter()
+            .flat_map(|(symbol, lot)| open_trades(&ledger, symbol, lot, &closed).into_iter().cloned())
+            .collect();
+        if taken.is_empty() {
+            return Ok(taken);
+        }
+        let kept: Vec<&str> = content.lines()
+            .filter(|line| !Transaction::parse(line).is_some_and(|t| {
+                taken.iter().any(|x| x.to_ledger_line() == t.to_ledger_line())
+            }))
+            .collect();
+        write_atomic(&path, &(kept.join("\n") + "\n"))?;
+        Ok(taken)
+    }
+
+    /// Apply a buy or sell to an existing holding and append it to the ledger.
+    /// Buys recompute the weighted-average cost (fees included); sells book realized P/L
+    /// against the average cost and archive the position once it is fully sold.
+    /// Returns the realized P/L of a sell (0 for buys).
+    fn record_trade(&mut self, trade: &Transaction) -> Result<f64> {
+        let _lock = PortfolioLock::acquire()?;
+        let Some(portfolio) = self.portfolios.get(self.current_portfolio_idx) else {
+            return Ok(0.0);
+        };
+        let mut stocks = Self::load_stocks_from_file(&portfolio.file_path)?;
+        let mut closed = Self::load_closed_from_file(&portfolio.file_path)?;
+        let mut trade = trade.clone();
+        let mut realized = 0.0;
+
+        if let Some(idx) = stocks.iter().position(|s| s.symbol == trade.symbol && s.lot == trade.lot) {
+            // A holding entered before the ledger existed gets its opening lot written first
+            let ledger_file = self.ledger_path(&portfolio.name);
+            let ledger = Self::load_ledger(&ledger_file);
+            let trades = open_trades(&ledger, &trade.symbol, trade.lot, &closed);
+            let opening = opening_lot(&stocks[idx], &trades);
+            // From here on the ledger holds the lot, unless its trades already overran the
+            // file's quantity, which then stays authoritative
+            let covered = stocks[idx].ledger || opening.is_some() || (net_shares(&trades) - stocks[idx].quantity).abs() < 1e-9;
+            if let Some(lot) = opening {
+                if trades.is_empty() {
+                    self.append_transaction(&portfolio.name, &lot)?;
+                } else {
+                    Self::prepend_transaction(&ledger_file, &lot)?;
+                }
+            }
+            let stock = &mut stocks[idx];
+            stock.ledger = covered;
+            match trade.side {
+                TradeSide::Buy => {
+                    let new_qty = stock.quantity + trade.shares;
+                    let old_cost = stock.quantity * stock.cost_basis;
+                    let new_cost = old_cost + trade.shares * trade.price + trade.fees / stock.multiplier();
+                    if !stock.is_tw() {
+                        // Blend the purchase FX rate by USD cost, the new lot at today's rate
+                        let old_fx = stock.cost_fx.unwrap_or(self.usd_twd_rate);
+                        stock.cost_fx = Some((old_cost * old_fx + (new_cost - old_cost) * self.usd_twd_rate) / new_cost.max(f64::EPSILON));
+                    }
+                    stock.cost_basis = if new_qty > 0.0 { new_cost / new_qty } else { 0.0 };
+                    stock.quantity = new_qty;
+                }
+                TradeSide::Sell => {
+                    trade.shares = trade.shares.min(stock.quantity);
+                    realized = (trade.price - stock.cost_basis) * trade.shares * stock.multiplier() - trade.fees;
+                    stock.quantity -= trade.shares;
+                    stock.realized += realized;
+
+                    if stock.quantity <= 0.0 {
+                        let stock = stocks.remove(idx);
+                        let opened = stock.purchase_date();
+                        closed.push(ClosedPosition {
+                            symbol: stock.symbol,
+                            display: stock.display,
+                            name: stock.name,
+                            quantity: trade.shares,
+                            cost_basis: stock.cost_basis,
+                            exit_price: trade.price,
+                            closed_on: trade.date,
+                            realized: stock.realized,
+                            opened,
+                            lot: Some(stock.lot),
+                        });
+                    }
+                }
+            }
+        }
+
+        self.append_transaction(&portfolio.name, &trade)?;
+        self.save_portfolio(&portfolio.name, &stocks, &closed)?;
+        Ok(realized)
+    }
+
+    fn selected_position(&self) -> Option<(usize, usize)> {
+        self.table_state(self.active_section).selected().map(|row| (self.active_section, row))
+    }
+
+    /// Space: toggle the selected row's mark and remember it as the range anchor
+    fn toggle_mark(&mut self) {
+        if let Some(symbol) = self.get_selected_stock().map(|s| s.symbol.clone()) {
+            if let Some(pos) = self.marked.iter().position(|m| *m == symbol) {
+                self.marked.remove(pos);
+            } else {
+                self.marked.push(symbol);
+            }
+            self.mark_anchor = self.selected_position();
+        }
+    }
+
+    /// V: mark every row from the anchor to the selected row in the active table
+    fn mark_range(&mut self) {
+        let Some((section, row)) = self.selected_position() else {
+            return;
+        };
+        let anchor = match self.mark_anchor {
+            Some((anchor_section, anchor_row)) if anchor_section == section => anchor_row,
+            _ => row,
+        };
+        let stocks = self.section_stocks(section);
+        let symbols: Vec<String> = self.display_rows(section)
+            .iter()
+            .skip(anchor.min(row))
+            .take(anchor.max(row) - anchor.min(row) + 1)
+            .filter_map(|r| match r {
+                DisplayRow::Stock(i) => Some(stocks[*i].symbol.clone()),
+                _ => None,
+            })
+            .collect();
+        for symbol in symbols {
+            if !self.marked.contains(&symbol) {
+                self.marked.push(symbol);
+            }
+        }
+        self.mark_anchor = Some((section, row));
+    }
+
+    /// Apply a batch operation to the marked holdings; returns a status message
+    fn apply_batch(&mut self, op: &BatchOp) -> Result<String> {
+        let _lock = PortfolioLock::acquire()?;
+        let Some(portfolio) = self.portfolios.get(self.current_portfolio_idx).cloned() else {
+            return Ok(String::new());
+        };
+        let mut stocks = Self::load_stocks_from_file(&portfolio.file_path)?;
+        let count = stocks.iter().filter(|s| self.marked.contains(&s.symbol)).count();
+        let message = match op {
+            BatchOp::Delete => {
+                self.take_ledger_entries(&portfolio.name, &self.marked)?;
+                stocks.retain(|s| !self.marked.contains(&s.symbol));
+                format!("Deleted {} holdings", count)
+            }
+            BatchOp::Tag(tag) => {
+                for stock in stocks.iter_mut().filter(|s| self.marked.contains(&s.symbol)) {
+                    if !stock.tags.contains(tag) {
+                        stock.tags.push(tag.clone());
+                    }
+                }
+                format!("Tagged {} holdings '{}'", count, tag)
+            }
+            BatchOp::Move(target_idx) => {
+                let target = self.portfolios.get(*target_idx).cloned()
+                    .filter(|t| t.name != portfolio.name)
+                    .ok_or_else(|| anyhow::anyhow!("invalid target portfolio"))?;
+                let mut target_stocks = Self::load_stocks_from_file(&target.file_path)?;
+                let (moving, staying): (Vec<Stock>, Vec<Stock>) =
+                    stocks.into_iter().partition(|s| self.marked.contains(&s.symbol));
+                let mut merged_lots: Vec<(String, u32, u32)> = Vec::new(); // Symbol, moved lot, lot it joined
+                for stock in moving {
+                    // Merge into an existing holding at the weighted average cost
+                    if let Some(existing) = target_stocks.iter_mut().find(|t| t.symbol == stock.symbol) {
+                        let quantity = existing.quantity + stock.quantity;
+                        if quantity > 0.0 {
+                            existing.cost_basis = (existing.cost_local() + stock.cost_local()) / (quantity * existing.multiplier());
+                        }
+                        existing.quantity = quantity;
+                        existing.realized += stock.realized;
+                        // Numbers on either side cover what the other's trades don't
+                        existing.ledger &= stock.ledger;
+                        merged_lots.push((stock.symbol, stock.lot, existing.lot));
+                    } else {
+                        target_stocks.push(stock);
+                    }
+                }
+                // The moved holdings' trades follow them to the target ledger
+                for mut trade in self.take_ledger_entries(&portfolio.name, &self.marked)? {
+                    if let Some((_, _, lot)) = merged_lots.iter().find(|(s, l, _)| *s == trade.symbol && *l == trade.lot) {
+                        trade.lot = *lot;
+                    }
+                    self.append_transaction(&target.name, &trade)?;
+                }
+                self.save_stocks(&target.name, &target_stocks)?;
+                stocks = staying;
+                format!("Moved {} holdings to {}", count, target.name)
+            }
+        };
+        self.save_stocks(&portfolio.name, &stocks)?;
+        self.marked.clear();
+        self.mark_anchor = None;
+        Ok(message)
+    }
+
+    /// Remove a holding; with `archive` it is closed out at the last quote into the archive section
+    fn delete_stock(&mut self, symbol: &str, archive: bool) -> Result<()> {
+        let _lock = PortfolioLock::acquire()?;
+        if let Some(portfolio) = self.portfolios.get(self.current_portfolio_idx).cloned() {
+            let mut stocks = Self::load_stocks_from_file(&portfolio.file_path)?;
+            let mut closed = Self::load_closed_from_file(&portfolio.file_path)?;
+            if archive {
+                // Every line of the symbol goes, so the archive line closes all its lots
+                let lines = stocks.iter().filter(|s| s.symbol == symbol).cloned().collect();
+                if let Some(stock) = merge_lots(lines).first() {
+                    let exit_price = self.stocks.iter()
+                        .find(|s| s.symbol == symbol)
+                        .and_then(|s| s.price_data.as_ref())
+                        .map(|d| d.price)
+                        .unwrap_or(stock.cost_basis);
+                    closed.push(ClosedPosition {
+                        symbol: stock.symbol.clone(),
+                        display: stock.display.clone(),
+                        name: stock.name.clone(),
+                        quantity: stock.quantity,
+                        cost_basis: stock.cost_basis,
+                        exit_price,
+                        closed_on: Local::now().date_naive(),
+                        realized: stock.realized + (exit_price - stock.cost_basis) * stock.quantity * stock.multiplier(),
+                        opened: stock.purchase_date(),
+                        lot: None,
+                    });
+                }
+            }
+            stocks.retain(|s| s.symbol != symbol);
+            if !archive {
+                self.take_ledger_entries(&portfolio.name, &[symbol.to_string()])?;
+            }
+            self.save_portfolio(&portfolio.name, &stocks, &closed)?;
+        }
+        Ok(())
+    }
+
+    /// Archived positions of the current portfolio, or of every portfolio in the combined view
+    fn load_archive(&self) -> ArchiveState {
+        let portfolios: Vec<&Portfolio> = if self.view_combined {
+            self.portfolios.iter().collect()
+        } else {
+            self.portfolios.get(self.current_portfolio_idx).into_iter().collect()
+        };
+        let mut rows = Vec::new();
+        for portfolio in portfolios {
+            for closed in Self::load_closed_from_file(&portfolio.file_path).unwrap_or_default() {
+                rows.push((portfolio.name.clone(), closed));
+            }
+        }
+        rows.sort_by_key(|(_, c)| std::cmp::Reverse(c.closed_on));
+        ArchiveState { rows, ..Default::default() }
+    }
+
+    /// Headlines from every `[[feeds]]` entry, newest first; fetched at most every
+    /// `NEWS_CACHE_SECS` unless `refetch`. Filters cover all holdings in the current view.
+    fn load_news(&mut self, refetch: bool) -> NewsState {
+        let stale = self.news_cache.as_ref().is_none_or(|(at, _, _)| at.elapsed().as_secs() >= NEWS_CACHE_SECS);
+        if refetch || stale {
+            // Each feed loads on its own thread and is shown as it arrives (`add_feed`), so a
+            // slow one holds up nothing else; results of an earlier load are dropped
+            let load = self.news_load.0 + 1;
+            self.news_load = (load, self.config.feeds.len());
+            self.news_cache = Some((Instant::now(), Vec::new(), Vec::new()));
+            for feed in self.config.feeds.clone() {
+                let sender = self.fetch_sender.clone();
+                thread::spawn(move || {
+                    let result = fetch_feed(&feed).map_err(|err| format!("{}: {}", feed.name, err));
+                    let _ = sender.send(FetchMessage::Feed(load, result));
+                });
+            }
+        }
+        let (items, errors) = self.news_cache.as_ref().map(|(_, i, e)| (i.clone(), e.clone())).unwrap_or_default();
+
+        let stocks = if self.view_combined { &self.combined_stocks } else { &self.stocks };
+        let holding_filters: Vec<(String, Vec<String>)> = stocks
+            .iter()
+            .map(|s| {
+                let code = s.symbol.trim_end_matches(".TWO").trim_end_matches(".TW");
+                let mut keywords = vec![s.display.to_lowercase(), code.to_lowercase()];
+                if s.name != s.symbol {
+                    keywords.push(s.name.to_lowercase());
+                }
+                keywords.retain(|k| k.chars().count() >= 2);
+                keywords.dedup();
+                (s.display.clone(), keywords)
+            })
+            .collect();
+        let mut filters = vec![
+            ("All".to_string(), Vec::new()),
+            ("Holdings".to_string(), holding_filters.iter().flat_map(|(_, k)| k.clone()).collect()),
+        ];
+        filters.extend(holding_filters);
+        NewsState { items, errors, pending: self.news_load.1, filters, ..Default::default() }
+    }
+
+    /// Merge one feed of the latest load into the cache and the open news screen
+    fn add_feed(&mut self, load: u64, result: Result<Vec<NewsItem>, String>) {
+        if load != self.news_load.0 {
+            return;
+        }
+        self.news_load.1 = self.news_load.1.saturating_sub(1);
+        let Some((_, items, errors)) = self.news_cache.as_mut() else {
+            return;
+        };
+        match result {
+            Ok(feed_items) => {
+                items.extend(feed_items);
+                items.sort_by_key(|item| std::cmp::Reverse(item.published));
+            }
+            Err(err) => errors.push(err),
+        }
+        if let InputMode::News(state) = &mut self.input_mode {
+            state.items = items.clone();
+            state.errors = errors.clone();
+            state.pending = self.news_load.1;
+            state.selected = state.selected.min(state.visible().len().saturating_sub(1));
+        }
+    }
+
+    /// Write the cached candles for a symbol to `<SYMBOL>_<range>.csv` in the working directory
+    fn export_chart_csv(&self, symbol: &str) -> Result<PathBuf> {
+        let historical = self
+            .historical_cache
+            .get(symbol)
+            .ok_or_else(|| anyhow::anyhow!("no chart data for {}", symbol))?;
+        let path = std::env::current_dir()?.join(format!("{}_{}.csv", symbol.replace('.', "_"), HISTORY_RANGE));
+        historical.write_csv(&path)?;
+        Ok(path)
+    }
+
+    /// Starting points offered by the new portfolio dialog
+    fn portfolio_sources(&self) -> Vec<PortfolioSource> {
+        std::iter::once(PortfolioSource::Empty)
+            .chain(self.portfolios.iter().map(|p| PortfolioSource::Clone(p.name.clone())))
+            .chain((0..PORTFOLIO_TEMPLATES.len()).map(PortfolioSource::Template))
+            .collect()
+    }
+
+    /// Write a new portfolio file and return how many watch entries it starts with
+    fn create_portfolio(&mut self, name: &str, source: &PortfolioSource) -> Result<usize> {
+        let _lock = PortfolioLock::acquire()?;
+        let path = Self::portfolios_dir().join(format!("{}.conf", name));
+        if path.exists() {
+            anyhow::bail!("portfolio '{}' already exists", name);
+        }
+        let lines: Vec<String> = match source {
+            PortfolioSource::Empty => Vec::new(),
+            PortfolioSource::Clone(from) => {
+                let content = fs::read_to_string(self.portfolio_path(from))?;
+                let mut seen: Vec<String> = Vec::new();
+                content.lines()
+                    .map(str::trim)
+                    .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                    .filter_map(|line| {
+                        let parts: Vec<&str> = line.split('|').map(str::trim).collect();
+                        // Lots of one symbol become a single entry
+                        if parts.len() < 3 || seen.iter().any(|s| s == parts[0]) {
+                            return None;
+                        }
+                        seen.push(parts[0].to_string());
+                        let extras = parts.iter().skip(5).filter(|extra| {
+                            !extra.split_once('=').is_some_and(|(key, _)| CLONE_DROPPED_FIELDS.contains(&key.trim()))
+                        });
+                        let fields: Vec<&str> = parts[..3].iter().copied().chain(["0", "0"]).chain(extras.copied()).collect();
+                        Some(fields.join("|"))
+                    })
+                    .collect()
+            }
+            PortfolioSource::Template(idx) => PORTFOLIO_TEMPLATES[*idx].1.iter()
+                .map(|(symbol, display, description)| format!("{}|{}|{}|0|0", symbol, display, description))
+                .collect(),
+        };
+        let mut content = "# Stock Portfolio Configuration\n# Format: SYMBOL|Display Name|Description|Quantity|Cost Basis\n".to_string();
+        for line in &lines {
+            content.push_str(line);
+            content.push('\n');
+        }
+        write_atomic(&path, &content)?;
+        self.load_portfolios()?;
+        Ok(lines.len())
+    }
+}
+
+/// Why a portfolio line would load wrong: missing fields read as 0 (or drop the line),
+/// and a quantity or cost that isn't a number reads as 0
+fn line_problem(line: &str) -> Option<String> {
+    let parts: Vec<&str> = line.split('|').map(str::trim).collect();
+    if parts[0].is_empty() {
+        return Some("empty symbol".to_string());
+    }
+    match parts.len() {
+        1 | 2 => return Some("missing name, quantity and cost (line is skipped)".to_string()),
+        3 => return Some("missing quantity and cost (read as 0)".to_string()),
+        4 => return Some("missing cost (read as 0)".to_string()),
+        _ => {}
+    }
+    if parts[3] == "-" && parts[4] == "-" {
+        return None; // Kept in the ledger
+    }
+    for (field, value) in [("quantity", parts[3]), ("cost", parts[4])] {
+        if value.is_empty() {
+            return Some(format!("empty {} (read as 0)", field));
+        }
+        if value.parse::<f64>().is_err() {
+            return Some(format!("{} '{}' is not a number", field, value));
+        }
+    }
+    None
+}
+
+/// A legacy line padded to the full format: display and name default from the symbol,
+/// quantity and cost to 0
+fn complete_line(line: &str) -> String {
+    let mut parts: Vec<String> = line.split('|').map(|p| p.trim().to_string()).collect();
+    let display = default_display(&parts[0]);
+    for (idx, fill) in [display.clone(), display, "0".to_string(), "0".to_string()].into_iter().enumerate() {
+        match parts.get_mut(idx + 1) {
+            Some(part) if part.is_empty() => *part = fill,
+            Some(_) => {}
+            None => parts.push(fill),
+        }
+    }
+    parts.join("|")
+}
+
+/// One row per symbol, in file order, with repeated lines folded into the first
+fn merge_lots(stocks: Vec<Stock>) -> Vec<Stock> {
+    let mut merged: Vec<Stock> = Vec::with_capacity(stocks.len());
+    for stock in stocks {
+        match merged.iter_mut().find(|s| s.symbol == stock.symbol) {
+            Some(existing) => existing.absorb(stock),
+            None => merged.push(stock),
+        }
+    }
+    merged
+}
+
+/// Upper-case a typed symbol; bare 4–6 digit codes are Taiwan listings
+fn normalize_symbol(input: &str) -> String {
+    let symbol = input.trim().to_uppercase();
+    if symbol.chars().all(|c| c.is_ascii_digit()) && symbol.len() >= 4 && symbol.len() <= 6 {
+        format!("{}.TW", symbol)
+    } else {
+        symbol
+    }
+}
+
+/// Table label when none is given: the option contract label, or the symbol without `.TW`/`-USD`
+fn default_display(symbol: &str) -> String {
+    OptionContract::parse_occ(symbol)
+        .map(|c| c.label())
+        .unwrap_or_else(|| symbol.replace(".TW", "").replace("-USD", ""))
+}
+
+/// Turns a provider's quote response into a price
+type QuoteParser = fn(&serde_json::Value) -> Option<PriceData>;
+
+/// Quote requests for `symbol` from one per-symbol provider, each with the parser for its response
+fn quote_requests(symbol: &str, provider: Provider, providers: &ProvidersConfig) -> Vec<(String, QuoteParser)> {
+    // Use chart API (v7 quote API is restricted by Yahoo)
+    let from_chart: QuoteParser = |data| PriceData::from_chart(data["chart"]["result"].get(0)?);
+    match provider {
+        Provider::Twse => Vec::new(), // Batched by `fetch_twse_quotes`
+        Provider::Yahoo => vec![
+            (format!("https://query2.finance.yahoo.com/v8/finance/chart/{}", symbol), from_chart),
+            (format!("https://query1.finance.yahoo.com/v8/finance/chart/{}", symbol), from_chart),
+        ],
+        // Checked again here: the quota may have run out while this request waited its turn
+        Provider::AlphaVantage if !ProvidersConfig::serves(provider, symbol) => Vec::new(),
+        Provider::AlphaVantage => {
+            let parse: QuoteParser = |data| {
+                ProvidersConfig::check_alpha_vantage_quota(data);
+                PriceData::from_alpha_vantage(data)
+            };
+            vec![(providers.alpha_vantage_quote_url(symbol), parse)]
+        }
+    }
+}
+
+/// Standalone blocking price fetch; quotes themselves are not cached, always fetches fresh data
+fn fetch_price_blocking(symbol: &str, providers: &ProvidersConfig) -> Option<PriceData> {
+    let mut fetched = None;
+    fetch_prices_concurrent(vec![symbol.to_string()], 1, providers, |_, price_data| fetched = price_data);
+    fetched
+}
+
+/// Fetch `symbol`'s quote from one per-symbol provider
+async fn fetch_price_async(client: &reqwest::Client, symbol: &str, provider: Provider, providers: &ProvidersConfig) -> Option<PriceData> {
+    for (url, parse) in quote_requests(symbol, provider, providers) {
+        let Ok(response) = client
+            .get(url)
+            .header("User-Agent", "Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36")
+            .timeout(Duration::from_secs(5))
+            .send()
+            .await
+        else {
+            continue;
+        };
+        if let Some(price_data) = response.json::<serde_json::Value>().await.ok().as_ref().and_then(parse) {
+            return Some(price_data);
+        }
+    }
+
+    None
+}
+
+/// Most channels put in one TWSE MIS request
+const TWSE_BATCH: usize = 100;
+
+/// 52-week high and low, and the day they were looked up; None when nothing had them that day
+type YearRange = (chrono::NaiveDate, Option<(f64, f64)>);
+
+/// 52-week ranges of Taiwan symbols, which MIS quotes lack
+static YEAR_RANGES: Mutex<BTreeMap<String, YearRange>> = Mutex::new(BTreeMap::new());
+
+/// High and low of `symbol`'s cached daily candles over the past year, when they cover it
+fn candle_year_range(symbol: &str) -> Option<(f64, f64)> {
+    let year_ago = chrono::Utc::now().timestamp() - 365 * 86400;
+    let db = cache_db()?;
+    let (first, high, low) = db
+        .query_row(
+            "SELECT MIN(ts), MAX(high), MIN(low) FROM candles WHERE symbol = ?1 AND interval = '1d' AND ts >= ?2",
+            rusqlite::params![symbol, year_ago],
+            |row| Ok((row.get::<_, Option<i64>>(0)?, row.get::<_, Option<f64>>(1)?, row.get::<_, Option<f64>>(2)?)),
+        )
+        .ok()?;
+    // A shorter history would narrow the range
+    (first? <= year_ago + 14 * 86400).then_some((high?, low?))
+}
+
+/// Taiwan quotes for `symbols` from TWSE MIS, in one request per `TWSE_BATCH` channels, with the
+/// 52-week range from a year of cached daily candles or, failing that, Yahoo's quote
+async fn fetch_twse_quotes(client: &reqwest::Client, symbols: &[String]) -> Vec<(String, PriceData)> {
+    let channels: HashMap<String, &String> =
+        symbols.iter().filter_map(|s| Some((ProvidersConfig::twse_channel(s)?, s))).collect();
+    let keys: Vec<String> = channels.keys().cloned().collect();
+    let mut quotes = Vec::new();
+    for batch in keys.chunks(TWSE_BATCH) {
+        let Ok(response) = client
+            .get(ProvidersConfig::twse_quote_url(batch))
+            .header("User-Agent", "Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36")
+            .timeout(Duration::from_secs(5))
+            .send()
+            .await
+        else {
+            continue;
+        };
+        let Ok(data) = response.json::<serde_json::Value>().await else {
+            continue;
+        };
+        for info in data["msgArray"].as_array().into_iter().flatten() {
+            let channel = format!("{}_{}", info["ex"].as_str().unwrap_or(""), info["ch"].as_str().unwrap_or(""));
+            if let (Some(symbol), Some(price_data)) = (channels.get(&channel), PriceData::from_twse_mis(info)) {
+                quotes.push(((*symbol).clone(), price_data));
+            }
+        }
+    }
+
+    let today = Local::now().date_naive();
+    let mut lookups = tokio::task::JoinSet::new();
+    for (symbol, _) in &quotes {
+        let known = YEAR_RANGES.lock().unwrap_or_else(|e| e.into_inner()).get(symbol).filter(|(day, _)| *day == today).is_some();
+        if known {
+            continue;
+        }
+        if let Some(range) = candle_year_range(symbol) {
+            YEAR_RANGES.lock().unwrap_or_else(|e| e.into_inner()).insert(symbol.clone(), (today, Some(range)));
+            continue;
+        }
+        let client = client.clone();
+        let symbol = symbol.clone();
+        lookups.spawn(async move {
+            let quote = fetch_price_async(&client, &symbol, Provider::Yahoo, &ProvidersConfig::default()).await;
+            let range = quote.and_then(|q| Some((q.year_high?, q.year_low?)));
+            YEAR_RANGES.lock().unwrap_or_else(|e| e.into_inner()).insert(symbol, (today, range));
+        });
+    }
+    while lookups.join_next().await.is_some() {}
+
+    let ranges = YEAR_RANGES.lock().unwrap_or_else(|e| e.into_inner());
+    for (symbol, price_data) in &mut quotes {
+        if let Some((_, Some((high, low)))) = ranges.get(symbol) {
+            // Today's trading may have set a new high or low
+            price_data.year_high = Some(high.max(price_data.day_high.unwrap_or(price_data.price)).max(price_data.price));
+            price_data.year_low = Some(low.min(price_data.day_low.unwrap_or(price_data.price)).min(price_data.price));
+        }
+    }
+    quotes
+}
+
+/// Fetch quotes on a private tokio runtime with at most `concurrency` requests in flight.
+/// Providers are tried in order, each for the symbols the ones before it didn't answer;
+/// TWSE is asked for all its symbols at once. `on_result` runs on the calling thread as
+/// each quote completes, in completion order.
+fn fetch_prices_concurrent(
+    symbols: Vec<String>,
+    concurrency: usize,
+    providers: &ProvidersConfig,
+    mut on_result: impl FnMut(String, Option<PriceData>),
+) {
+    if symbols.is_empty() {
+        return;
+    }
+    let Ok(runtime) = tokio::runtime::Builder::new_current_thread().enable_all().build() else {
+        return;
+    };
+    runtime.block_on(async {
+        let client = reqwest::Client::new();
+        let limit = std::sync::Arc::new(tokio::sync::Semaphore::new(concurrency.max(1)));
+        let mut remaining = symbols;
+        for provider in providers.enabled() {
+            if remaining.is_empty() {
+                break;
+            }
+            if provider == Provider::Twse {
+                for (symbol, price_data) in fetch_twse_quotes(&client, &remaining).await {
+                    remaining.retain(|s| *s != symbol);
+                    on_result(symbol, Some(price_data));
+                }
+                continue;
+            }
+            let mut tasks = tokio::task::JoinSet::new();
+            for symbol in remaining.iter().filter(|s| ProvidersConfig::serves(provider, s)).cloned() {
+                let client = client.clone();
+                let limit = limit.clone();
+                let providers = providers.clone();
+                tasks.spawn(async move {
+                    let _permit = limit.acquire_owned().await;
+                    let price_data = fetch_price_async(&client, &symbol, provider, &providers).await;
+                    (symbol, price_data)
+                });
+            }
+            while let Some(joined) = tasks.join_next().await {
+                if let Ok((symbol, Some(price_data))) = joined {
+                    remaining.retain(|s| *s != symbol);
+                    on_result(symbol, Some(price_data));
+                }
+            }
+        }
+        for symbol in remaining {
+            on_result(symbol, None);
+        }
+    });
+}
+
+/// A control-socket call waiting for the main loop; the response goes back on `reply`
+struct RpcRequest {
+    method: String,
+    params: serde_json::Value,
+    reply: Sender<Result<serde_json::Value, (i64, String)>>,
+}
+
+/// Listen on a Unix socket for newline-delimited JSON-RPC 2.0 requests. Connections are
+/// served on their own threads; each call is handed to the main loop and answered in order.
+fn start_control_socket(path: &Path, sender: Sender<RpcRequest>) -> Result<()> {
+    use std::os::unix::net::{UnixListener, UnixStream};
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    // A socket left behind by a previous run refuses connections; one that answers belongs
+    // to another running copy and is left alone
+    match UnixStream::connect(path) {
+        Ok(_) => anyhow::bail!("{} is in use by another stock-tui", path.display()),
+        Err(err) if err.kind() == io::ErrorKind::ConnectionRefused => fs::remove_file(path)?,
+        Err(_) => {}
+    }
+    let listener = UnixListener::bind(path)?;
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let sender = sender.clone();
+            thread::spawn(move || {
+                let Ok(mut writer) = stream.try_clone() else {
+                    return;
+                };
+                for line in BufReader::new(stream).lines().map_while(|l| l.ok()) {
+                    if line.trim().is_empty() {
+                        continue;
+                    }
+                    let (id, outcome) = match serde_json::from_str::<serde_json::Value>(&line) {
+                        Ok(request) => {
+                            let (reply, response) = mpsc::channel();
+                            let call = RpcRequest {
+                                method: request["method"].as_str().unwrap_or_default().to_string(),
+                                params: request["params"].clone(),
+                                reply,
+                            };
+                            if sender.send(call).is_err() {
+                                return; // TUI has exited
+                            }
+                            (request["id"].clone(), response.recv().unwrap_or_else(|_| Err((-32603, "internal error".to_string()))))
+                        }
+                        Err(err) => (serde_json::Value::Null, Err((-32700, format!("parse error: {}", err)))),
+                    };
+                    let response = match outcome {
+                        Ok(result) => serde_json::json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+                        Err((code, message)) => serde_json::json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } }),
+                    };
+                    if writeln!(writer, "{}", response).is_err() {
+                        return;
+                    }
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+/// How long the status page waits on a client's request or for its reply to go out
+const HTTP_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Serve `GET /portfolio.json` (the `get_summary` result) and a small auto-refreshing HTML
+/// page at `/`. Read-only: every request asks the main loop for a fresh summary.
+fn start_http_server(config: &HttpConfig, sender: Sender<RpcRequest>) -> Result<()> {
+    let listener = std::net::TcpListener::bind((config.bind.as_str(), config.port))?;
+    thread::spawn(move || {
+        for mut stream in listener.incoming().flatten() {
+            // Connections are served one at a time, so a client that stalls mid-request
+            // must not hold up the next one
+            if stream.set_read_timeout(Some(HTTP_TIMEOUT)).is_err() || stream.set_write_timeout(Some(HTTP_TIMEOUT)).is_err() {
+                continue;
+            }
+            // Read the request line, then drain the headers so closing doesn't reset the connection
+            let mut reader = BufReader::new(&stream);
+            let mut request_line = String::new();
+            if reader.read_line(&mut request_line).is_err() {
+                continue;
+            }
+            let mut header = String::new();
+            while reader.read_line(&mut header).is_ok_and(|n| n > 0) && !header.trim().is_empty() {
+                header.clear();
+            }
+            let path = request_line.split_whitespace().nth(1).unwrap_or("/");
+            let summary = || -> Option<serde_json::Value> {
+                let (reply, response) = mpsc::channel();
+                sender.send(RpcRequest { method: "get_summary".to_string(), params: serde_json::Value::Null, reply }).ok()?;
+                response.recv().ok()?.ok()
+            };
+            let (status, content_type, body) = match path {
+                "/portfolio.json" => match summary() {
+                    Some(json) => ("200 OK", "application/json", json.to_string()),
+                    None => ("503 Service Unavailable", "text/plain", "unavailable".to_string()),
+                },
+                "/" | "/index.html" => match summary() {
+                    Some(json) => ("200 OK", "text/html; charset=utf-8", summary_html(&json)),
+                    None => ("503 Service Unavailable", "text/plain", "unavailable".to_string()),
+                },
+                _ => ("404 Not Found", "text/plain", "not found".to_string()),
+            };
+            let _ = write!(
+                stream,
+                "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n{}",
+                status,
+                content_type,
+                body.len(),
+                body
+            );
+        }
+    });
+    Ok(())
+}
+
+/// Phone-friendly page for the HTTP endpoint; reloads every 30 seconds. Totals and the value
+/// column are left out of a snapshot with `positions_hidden`.
+fn summary_html(snapshot: &serde_json::Value) -> String {
+    let escape = |s: &str| s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;");
+    let number = |v: &serde_json::Value, decimals: usize| v.as_f64().map(|n| format!("{:.*}", decimals, n)).unwrap_or_else(|| "--".to_string());
+    let hidden = snapshot["positions_hidden"].as_bool().unwrap_or(false);
+    let summary = &snapshot["summary"];
+    let gain = summary["gain_twd"].as_f64().unwrap_or(0.0);
+    let mut rows = String::new();
+    for holding in snapshot["holdings"].as_array().into_iter().flatten() {
+        let change = holding["change_percent"].as_f64().unwrap_or(0.0);
+        rows.push_str(&format!(
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td class=\"{}\">{:+.2}%</td>{}</tr>",
+            escape(holding["symbol"].as_str().unwrap_or_default()),
+            escape(holding["name"].as_str().unwrap_or_default()),
+            number(&holding["price"], holding["symbol"].as_str().zip(holding["price"].as_f64())
+                .and_then(|(symbol, price)| instrument_decimals(symbol, price)).unwrap_or(2)),
+            if change >= 0.0 { "up" } else { "down" },
+            change,
+            if hidden { String::new() } else { format!("<td>{}</td>", number(&holding["value_twd"], 0)) },
+        ));
+    }
+    let totals = if hidden {
+        "Positions hidden".to_string()
+    } else {
+        format!(
+            "Value {} TWD · Gain <span class=\"{}\">{} ({:+.2}%)</span>",
+            number(&summary["value_twd"], 0),
+            if gain >= 0.0 { "up" } else { "down" },
+            number(&summary["gain_twd"], 0),
+            summary["gain_percent"].as_f64().unwrap_or(0.0),
+        )
+    };
+    format!(
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><meta name=\"viewport\" content=\"width=device-width\">\
+         <meta http-equiv=\"refresh\" content=\"30\"><title>stock-tui</title><style>\
+         body{{font-family:monospace;margin:1em}}td{{padding:2px 8px;text-align:right}}td:nth-child(-n+2){{text-align:left}}\
+         .up{{color:#2a2}}.down{{color:#d33}}</style></head><body>\
+         <h3>{} — {}</h3><p>{} · USD/TWD {}</p>\
+         <table><tr><th>Symbol</th><th>Name</th><th>Price</th><th>Change</th>{}</tr>{}</table></body></html>",
+        escape(snapshot["portfolio"].as_str().unwrap_or_default()),
+        escape(snapshot["updated"].as_str().unwrap_or_default()),
+        totals,
+        number(&snapshot["usd_twd"], 3),
+        if hidden { "" } else { "<th>Value (TWD)</th>" },
+        rows
+    )
+}
+
+/// `stock-tui doctor`: check config, portfolio files, cache, network and clock, printing a
+/// fix for each problem. Exits non-zero if any check fails.
+fn run_doctor() -> Result<()> {
+    let mut failures = 0;
+    let mut report = |ok: Option<bool>, what: String, hint: &str| {
+        let tag = match ok {
+            Some(true) => "  ok  ",
+            Some(false) => "  FAIL",
+            None => "  warn",
+        };
+        println!("{} {}", tag, what);
+        if ok != Some(true) && !hint.is_empty() {
+            println!("         → {}", hint);
+        }
+        if ok == Some(false) {
+            failures += 1;
+        }
+    };
+
+    println!("stock-tui doctor\n");
+    // Config
+    let path = Config::path();
+    match Config::load() {
+        Ok(mut config) if path.exists() => {
+            report(Some(true), format!("config {}", path.display()), "");
+            if let Some(format) = config.time.sanitize() {
+                report(None, format!("invalid time format '{}'", format), "fix [time] formats (strftime syntax)");
+            }
+            let year = Local::now().year();
+            for setting in config.market.missing_calendars(year) {
+                report(None, format!("no {} holidays for [market] {}", year, setting), "list the exchange's closures for the year there (YYYY-MM-DD)");
+            }
+            let shadowed = config.shadowed_command_keys();
+            if !shadowed.is_empty() {
+                report(None, format!("[[commands]] keys '{}' are taken by built-in keys", shadowed), "give those commands unused keys, such as o, u, w, J, Q, Y or Z");
+            }
+            if let Err(err) = Theme::from_config(&config.theme) {
+                report(None, err, "fix [theme] (colors are names like \"green\", \"#859900\" or a palette index)");
+            }
+            if let Err(value) = config.cache.ttls() {
+                report(None, format!("invalid [cache] lifetime '{}'", value), "use an interval such as \"60s\", \"5m\" or \"6h\"");
+            }
+            let shorthand = config.computed.iter().map(|line| {
+                let expr = line.split_once('=').map_or(line.as_str(), |(_, e)| e);
+                (line.clone(), expr.trim().to_string())
+            });
+            for (name, expr) in config.columns.iter().map(|c| (c.name.clone(), c.expr.clone())).chain(shorthand) {
+                if expr != "range" {
+                    if let Err(err) = Expr::parse(&expr) {
+                        report(Some(false), format!("column '{}': {}", name, err), "fix the expression in [[columns]] or computed");
+                    }
+                }
+            }
+        }
+        Ok(_) => report(Some(true), format!("no config at {} (using defaults)", path.display()), ""),
+        Err(err) => report(Some(false), format!("config: {}", err), "fix the TOML syntax; the app falls back to defaults"),
+    }
+
+    // Portfolio files: lines the loader would silently skip
+    let dir = App::portfolios_dir();
+    let files: Vec<PathBuf> = fs::read_dir(&dir)
+        .map(|entries| entries.filter_map(|e| e.ok()).map(|e| e.path()).filter(|p| p.extension().is_some_and(|x| x == "conf")).collect())
+        .unwrap_or_default();
+    if files.is_empty() {
+        report(None, format!("no portfolios in {}", dir.display()), "a main.conf is created on first run; add holdings with `a`");
+    }
+    for file in &files {
+        let content = fs::read_to_string(file).unwrap_or_default();
+        let mut holdings = 0;
+        for (n, line) in content.lines().enumerate().map(|(n, l)| (n + 1, l.trim())) {
+            if line.is_empty() || line.starts_with('#') || line.starts_with('~') {
+                continue;
+            }
+            if let Some(problem) = line_problem(line) {
+                report(None, format!("{}:{}: {} in '{}'", file.display(), n, problem, line), "expected SYMBOL|Display|Name|Quantity|Cost; the app offers fixes on launch");
+            }
+            if line.split('|').count() >= 3 {
+                holdings += 1;
+            }
+        }
+        report(Some(true), format!("portfolio {} ({} holdings)", file.display(), holdings), "");
+    }
+
+    // Cache directory
+    let cache = App::cache_dir();
+    let probe = cache.join(".doctor");
+    match fs::create_dir_all(&cache).and_then(|_| fs::write(&probe, b"ok")) {
+        Ok(()) => {
+            let _ = fs::remove_file(&probe);
+            report(Some(true), format!("cache {} is writable", cache.display()), "");
+        }
+        Err(err) => report(Some(false), format!("cache {}: {}", cache.display(), err), "make the directory writable; prices cannot be cached"),
+    }
+    let db_path = App::cache_db_path();
+    let counts = open_cache_db(&db_path).and_then(|db| {
+        db.query_row(
+            "SELECT (SELECT COUNT(*) FROM quotes), (SELECT COUNT(DISTINCT symbol) FROM candles)",
+            rusqlite::params![],
+            |row| Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)?)),
+        )
+    });
+    match counts {
+        Ok((quotes, symbols)) => report(
+            Some(true),
+            format!("cache database {} ({} quotes, history for {} symbols)", db_path.display(), quotes, symbols),
+            "",
+        ),
+        Err(err) => report(
+            Some(false),
+            format!("cache database {}: {}", db_path.display(), err),
+            "delete the file to start a fresh cache; quotes and history are only kept in memory",
+        ),
+    }
+
+    // Network: each quote host, a TW listing and the FX rate
+    let client = reqwest::blocking::Client::new();
+    let mut server_date = None;
+    for (host, symbol) in [("query1", "AAPL"), ("query2", "AAPL"), ("query2", "2330.TW"), ("query2", "USDTWD=X")] {
+        let url = format!("https://{}.finance.yahoo.com/v8/finance/chart/{}", host, symbol);
+        let started = Instant::now();
+        let response = client
+            .get(&url)
+            .header("User-Agent", "Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36")
+            .timeout(Duration::from_secs(5))
+            .send();
+        let what = format!("{}.finance.yahoo.com {}", host, symbol);
+        match response {
+            Ok(response) if response.status().is_success() => {
+                server_date = server_date.or_else(|| {
+                    let date = response.headers().get("date")?.to_str().ok()?;
+                    chrono::DateTime::parse_from_rfc2822(date).ok()
+                });
+                let quoted = response.json::<serde_json::Value>().ok()
+                    .and_then(|data| PriceData::from_chart(data["chart"]["result"].get(0)?));
+                match quoted {
+                    Some(data) => report(Some(true), format!("{}: {} ({} ms)", what, data.price, started.elapsed().as_millis()), ""),
+                    None => report(Some(false), format!("{}: response has no price", what), "Yahoo changed its format or the symbol is delisted"),
+                }
+            }
+            Ok(response) => report(
+                Some(false),
+                format!("{}: HTTP {}", what, response.status()),
+                "429 means rate limited (wait a few minutes); 401/403 can mean a blocked network or VPN",
+            ),
+            Err(err) => report(Some(false), format!("{}: {}", what, err), "check the internet connection, proxy (HTTPS_PROXY) and DNS"),
+        }
+    }
+
+    // Clock and timezone
+    let now = Local::now();
+    report(Some(true), format!("local time {} (UTC{})", now.format("%Y-%m-%d %H:%M:%S"), now.format("%:z")), "");
+    if let Some(server) = server_date {
+        let skew = (now.timestamp() - server.timestamp()).abs();
+        if skew > 60 {
+            report(Some(false), format!("clock is {}s off the quote server", skew), "enable NTP sync; market hours and caching depend on the clock");
+        } else {
+            report(Some(true), format!("clock within {}s of the quote server", skew), "");
+        }
+    }
+    // An unknown TZ name silently falls back to UTC
+    if let Ok(tz) = std::env::var("TZ") {
+        let utc_like = ["", "UTC", "GMT", "Etc/UTC", "Etc/GMT", "Europe/London", "UTC0"].contains(&tz.trim_start_matches(':'));
+        if now.offset().local_minus_utc() == 0 && !utc_like {
+            report(None, format!("TZ={} resolves to UTC", tz), "check the zone name (e.g. Asia/Taipei) and that tzdata is installed");
+        }
+    }
+
+    println!();
+    if failures > 0 {
+        println!("{} check(s) failed", failures);
+        std::process::exit(1);
+    }
+    println!("All checks passed");
+    Ok(())
+}
+
+/// `stock-tui quote AAPL 2330`: one line per symbol on stdout, through the same providers
+/// and price cache as the TUI. Exits non-zero when any symbol has no quote.
+fn run_quote(symbols: &[String], json: bool) -> Result<()> {
+    let (mut app, config_error) = App::unloaded();
+    if let Some(err) = config_error {
+        eprintln!("stock-tui: {}", err);
+    }
+    let symbols: Vec<String> = symbols.iter().map(|s| normalize_symbol(s)).collect();
+    app.prefetch_prices(symbols.clone());
+
+    let mut missing = 0;
+    let mut quotes = Vec::new();
+    for symbol in &symbols {
+        let Some(data) = app.fetch_price(symbol) else {
+            eprintln!("stock-tui: no quote for {}", symbol);
+            missing += 1;
+            continue;
+        };
+        let currency = data.currency.clone().unwrap_or_else(|| if symbol.contains(".TW") { "TWD" } else { "USD" }.to_string());
+        if json {
+            quotes.push(serde_json::json!({
+                "symbol": symbol,
+                "price": data.price,
+                "change": data.change,
+                "change_percent": data.change_percent,
+                "currency": currency,
+            }));
+        } else {
+            let decimals = instrument_decimals(symbol, data.price).unwrap_or(2);
+            println!(
+                "{:<12} {:>12.*} {:>+10.*} {:>+8.2}%  {}",
+                symbol, decimals, data.price, decimals, data.change, data.change_percent, currency
+            );
+        }
+    }
+    if json {
+        println!("{}", serde_json::to_string_pretty(&quotes)?);
+    }
+    if missing > 0 {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// `report`: the weekly summary for one view (the combined one by default), printed, written
+/// to `output`, or delivered as `[report]` configures
+fn run_report(portfolio: &str, output: Option<&String>, send: bool) -> Result<()> {
+    let mut app = App::new(Some(portfolio))?;
+    let report = app.weekly_report();
+    if send {
+        for destination in app.deliver_report(&report)? {
+            eprintln!("stock-tui: report sent to {}", destination);
+        }
+    } else if let Some(path) = output {
+        fs::write(path, report)?;
+    } else {
+        print!("{}", report);
+    }
+    Ok(())
+}
+
+/// `--daemon`: no TUI; refresh all portfolios on `[daemon] interval` and rewrite the JSON
+/// snapshot each time (atomically, so readers never see a partial write), sending the
+/// weekly report when `[report]` schedules one
+fn run_daemon() -> Result<()> {
+    let mut app = App::new(None)?;
+    app.view_combined = true;
+    let path = app.config.daemon.path();
+    let interval = Duration::from_secs(app.config.daemon.interval_secs());
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    loop {
+        if let Err(err) = write_atomic(&path, &serde_json::to_string_pretty(&app.snapshot_json())?) {
+            eprintln!("stock-tui: writing {}: {}", path.display(), err);
+        }
+        app.send_scheduled_report();
+        thread::sleep(interval);
+        app.cache.clear();
+        app.historical_cache.clear();
+        if let Err(err) = app.refresh_data() {
+            eprintln!("stock-tui: refresh failed: {err:?}");
+        }
+    }
+}
+
+/// Command-line flags and the `doctor`/`bench`/`quote`/`report` subcommands
+fn cli() -> Command {
+    Command::new("stock-tui")
+        .version(env!("CARGO_PKG_VERSION"))
+        .about("Terminal stock portfolio tracker with real-time prices from Yahoo Finance")
+        .arg(Arg::new("symbols")
+            .value_name("SYMBOL")
+            .num_args(1..)
+            .conflicts_with("portfolio")
+            .help("Watch just these symbols in a temporary, unsaved session"))
+        .arg(Arg::new("portfolio").long("portfolio").value_name("NAME").help("Open this portfolio at startup (ALL for the combined view)"))
+        .arg(Arg::new("live").long("live").action(ArgAction::SetTrue).help("Start in live mode (auto-refresh)"))
+        .arg(Arg::new("hide-positions").long("hide-positions").action(ArgAction::SetTrue).help("Start with quantities, cost and gains hidden"))
+        .arg(Arg::new("daemon").long("daemon").action(ArgAction::SetTrue).help("Run without the TUI, writing a JSON snapshot on a schedule"))
+        .arg(Arg::new("summary-json").long("summary-json").action(ArgAction::SetTrue).help("Print the startup view's positions, prices and totals as JSON and exit"))
+        .subcommand(Command::new("doctor").about("Check config, cache and connectivity"))
+        .subcommand(Command::new("quote")
+            .about("Print price and change for symbols without starting the TUI")
+            .arg(Arg::new("symbols").value_name("SYMBOL").num_args(1..).required(true).help("Symbols to quote (bare 4-6 digit codes are Taiwan listings)"))
+            .arg(Arg::new("json").long("json").action(ArgAction::SetTrue).help("Print a JSON array instead of aligned text")))
+        .subcommand(Command::new("report")
+            .about("Print the weekly summary report as Markdown, or send it as [report] configures")
+            .arg(Arg::new("portfolio").long("portfolio").value_name("NAME").help("Portfolio to report on (default: ALL, the combined view)"))
+            .arg(Arg::new("output").long("output").short('o').value_name("FILE").conflicts_with("send").help("Write the report to FILE instead of stdout"))
+            .arg(Arg::new("send").long("send").action(ArgAction::SetTrue).help("Write to [report] path and pipe to [report] command")))
+}
+
+fn main() -> Result<()> {
+    let matches = cli().get_matches();
+    match matches.subcommand() {
+        Some(("doctor", _)) => return run_doctor(),
+        Some(("quote", quote)) => {
+            let symbols: Vec<String> = quote.get_many::<String>("symbols").into_iter().flatten().cloned().collect();
+            return run_quote(&symbols, quote.get_flag("json"));
+        }
+        Some(("report", report)) => {
+            let portfolio = report.get_one::<String>("portfolio").map(String::as_str).unwrap_or("ALL");
+            return run_report(portfolio, report.get_one::<String>("output"), report.get_flag("send"));
+        }
+        _ => {}
+    }
+    if matches.get_flag("daemon") {
+        return run_daemon();
+    }
+    // Load before taking over the terminal so an unknown --portfolio prints a plain error
+    let mut app = match matches.get_many::<String>("symbols") {
+        Some(symbols) => App::new_watch(&symbols.cloned().collect::<Vec<_>>())?,
+        None => App::new(matches.get_one::<String>("portfolio").map(String::as_str))?,
+    };
+    app.live_mode = matches.get_flag("live");
+    app.hide_positions |= matches.get_flag("hide-positions");
+    if matches.get_flag("summary-json") {
+        println!("{}", serde_json::to_string_pretty(&app.snapshot_json())?);
+        app.end_watch();
+        return Ok(());
+    }
+
+    enable_raw_mode()?;
+    let mut stdout = io::stdout();
+    execute!(stdout, EnterAlternateScreen, EnableMouseCapture)?;
+    let backend = CrosstermBackend::new(stdout);
+    let mut terminal = Terminal::new(backend)?;
+
+    // The control socket and HTTP endpoint both hand requests to the main loop
+    let (rpc_sender, rpc_receiver) = mpsc::channel();
+    let mut socket = (!app.config.control_socket.is_empty()).then(|| expand_home(&app.config.control_socket));
+    if let Some(path) = &socket {
+        if let Err(err) = start_control_socket(path, rpc_sender.clone()) {
+            app.set_status(format!("Control socket {}: {}", path.display(), err));
+            socket = None;
+        }
+    }
+    let http = app.config.http.clone();
+    if http.port != 0 {
+        if let Err(err) = start_http_server(&http, rpc_sender.clone()) {
+            app.set_status(format!("HTTP {}:{}: {}", http.bind, http.port, err));
+        }
+    }
+    app.rpc_receiver = Some(rpc_receiver);
+    app.start_backfill();
+    let res = run_app(&mut terminal, &mut app);
+    if let Some(path) = socket {
+        let _ = fs::remove_file(path);
+    }
+    app.end_watch();
+
+    disable_raw_mode()?;
+    execute!(
+        terminal.backend_mut(),
+        LeaveAlternateScreen,
+        DisableMouseCapture
+    )?;
+    terminal.show_cursor()?;
+
+    if let Err(err) = res {
+        eprintln!("Error: {err:?}");
+    } else if app.config.summary_on_exit {
+        println!("{}", app.exit_summary());
+    }
+
+    Ok(())
+}
+
+enum Action {
+    None,
+    Quit,
+    AddStock(String, String, String, f64, f64),
+    EditStock(String, f64, f64),
+    SetAlerts(String, Option<f64>, Option<f64>),
+    Trade(Transaction),
+    DeleteStock(String, bool), // Symbol, move to the archive section instead of dropping it
+    Batch(BatchOp),
+    CreatePortfolio(String, PortfolioSource),
+    FixLine(LineIssue, Option<String>), // Replacement line, None to delete it
+    StartReplay(chrono::NaiveDate),
+    Refresh,
+    SwitchPortfolio(usize),
+    Sort(SortColumn),
+    ToggleGroup,
+    ToggleLots,
+    SelectTwRow(usize),
+    SelectUsRow(usize),
+    SelectCryptoRow(usize),
+    ViewCombined,
+    OpenDetail,
+    ExportChart(String),
+    RunCommand(char, String), // Configured command key, symbol
+    RunDca(String, f64, u32, bool),
+}
+
+const LIVE_REFRESH_INTERVAL_SECS: u64 = 5;
+const PRICE_FLASH: Duration = Duration::from_secs(1); // How long a live price move stays highlighted
+const STATUS_MESSAGE_SECS: u64 = 5;
+const SNOOZE_STEPS_MINS: [i64; 4] = [15, 60, 4 * 60, 24 * 60]; // `z` cycles through these, then off
+
+fn run_app<B: ratatui::backend::Backend>(terminal: &mut Terminal<B>, app: &mut App) -> Result<()> {
+    loop {
+        // Process any pending fetch results from background thread (non-blocking)
+        let fetched = app.process_fetch_results();
+        if app.process_rpc_requests() || fetched || app.expire_price_ticks() {
+            app.invalidate_rows();
+        }
+        app.update_alerts();
+        if let InputMode::Replay(replay) = &mut app.input_mode {
+            replay.tick();
+        }
+
+        let frame = terminal.draw(|f| ui(f, app))?;
+        // Note: clickable_regions are updated during ui() rendering
+        if app.snapshot_requested {
+            app.snapshot_requested = false;
+            match write_screen_snapshot(frame.buffer) {
+                Ok(path) => app.set_status(format!("Saved screen to {} (+ .ans)", path.display())),
+                Err(err) => app.set_status(format!("Screen snapshot failed: {}", err)),
+            }
+        }
+
+        // Live mode: start async refresh every `refresh_interval` seconds (non-blocking)
+        if app.live_mode
+            && !app.is_fetching
+            && matches!(app.input_mode, InputMode::Normal)
+            && app.last_live_refresh.elapsed().as_secs() >= app.config.refresh_interval
+        {
+            app.last_live_refresh = Instant::now();
+            app.start_async_refresh(true);
+            app.invalidate_rows();
+        }
+
+        if event::poll(Duration::from_millis(100))? {
+            let event = event::read()?;
+            // Cursor movement, section switches, scrolling and resizes leave the rows as they
+            // are; the cached tables are keyed on area, active section and expanded row instead
+            let navigates = matches!(app.input_mode, InputMode::Normal) && app.alert_popups.is_empty() && match &event {
+                Event::Key(key) => key.kind != KeyEventKind::Press || matches!(key.code, KeyCode::Up | KeyCode::Down | KeyCode::Char('j') | KeyCode::Char('k') | KeyCode::Tab),
+                Event::Mouse(mouse) => !matches!(mouse.kind, MouseEventKind::Down(MouseButton::Left)),
+                Event::Resize(_, _) => true,
+                _ => false,
+            };
+
+            let action = match event {
+                Event::Key(key) if key.kind == KeyEventKind::Press
+                    && key.modifiers.contains(KeyModifiers::CONTROL)
+                    && key.code == KeyCode::Char('p') =>
+                {
+                    if matches!(app.input_mode, InputMode::Normal) {
+                        app.input_mode = InputMode::Palette(PaletteState::default());
+                    }
+                    Action::None
+                }
+                Event::Key(key) if key.kind == KeyEventKind::Press => {
+                    handle_input(app, key.code)
+                }
+                Event::Mouse(mouse) => {
+                    handle_mouse(app, mouse.kind, mouse.column, mouse.row)
+                }
+                // Redraw at the new size right away; `ui` lays everything out and records the
+                // click regions again (or shows the too-small screen)
+                Event::Resize(_, _) => {
+                    terminal.autoresize()?;
+                    Action::None
+                }
+                _ => Action::None,
+            };
+            if !navigates && !matches!(action, Action::SelectTwRow(_) | Action::SelectUsRow(_) | Action::SelectCryptoRow(_)) {
+                app.invalidate_rows();
+            }
+
+            match action {
+                    Action::Quit => return Ok(()),
+                    Action::AddStock(symbol, display, name, qty, cost) => {
+                        app.add_stock(symbol, display, name, qty, cost)?;
+                        app.refresh_data()?;
+                        app.input_mode = InputMode::Normal;
+                    }
+                    Action::EditStock(symbol, qty, cost) => {
+                        if !app.edit_stock(&symbol, qty, cost)? {
+                            app.set_status(format!("{} is tracked by the ledger: record trades with b/s instead", symbol));
+                        }
+                        app.refresh_data()?;
+                        app.input_mode = InputMode::Normal;
+                    }
+                    Action::StartReplay(date) => match app.load_replay(date) {
+                        Some(replay) => app.input_mode = InputMode::Replay(replay),
+                        None => {
+                            app.input_mode = InputMode::Normal;
+                            app.set_status(format!("No intraday candles for {} (Yahoo keeps about 60 days)", date));
+                        }
+                    },
+                    Action::FixLine(issue, fixed) => {
+                        let selected = match &app.input_mode {
+                            InputMode::Reconcile(state) => state.selected,
+                            _ => 0,
+                        };
+                        match app.rewrite_line(&issue, fixed.as_deref()) {
+                            Ok(()) => app.set_status(match fixed {
+                                Some(_) => format!("Fixed line {} of {}", issue.line + 1, issue.portfolio),
+                                None => format!("Removed line {} of {}", issue.line + 1, issue.portfolio),
+                            }),
+                            Err(err) => app.set_status(format!("Fix failed: {}", err)),
+                        }
+                        app.refresh_data()?;
+                        let issues = app.find_line_issues();
+                        app.input_mode = if issues.is_empty() {
+                            InputMode::Normal
+                        } else {
+                            let selected = selected.min(issues.len() - 1);
+                            InputMode::Reconcile(ReconcileState { issues, selected, editing: None })
+                        };
+                    }
+                    Action::SetAlerts(symbol, above, below) => {
+                        app.set_alerts(&symbol, above, below)?;
+                        app.refresh_data()?;
+                        app.input_mode = InputMode::Normal;
+                    }
+                    Action::Trade(trade) => {
+                        let realized = app.record_trade(&trade)?;
+                        app.refresh_data()?;
+                        app.input_mode = InputMode::Normal;
+                        app.set_status(match trade.side {
+                            TradeSide::Buy => format!("Bought {} {} @ {}", trade.shares, trade.symbol, trade.price),
+                            TradeSide::Sell => format!("Sold {} {} @ {}, realized {:+.2}", trade.shares, trade.symbol, trade.price, realized),
+                        });
+                    }
+                    Action::DeleteStock(symbol, archive) => {
+                        app.delete_stock(&symbol, archive)?;
+                        if archive {
+                            app.set_status(format!("Archived {} (O to view closed positions)", symbol));
+                        }
+                        app.refresh_data()?;
+                        app.input_mode = InputMode::Normal;
+                    }
+                    Action::Batch(op) => {
+                        app.input_mode = InputMode::Normal;
+                        match app.apply_batch(&op) {
+                            Ok(message) => app.set_status(message),
+                            Err(err) => app.set_status(format!("Batch failed: {}", err)),
+                        }
+                        app.refresh_data()?;
+                    }
+                    Action::CreatePortfolio(name, source) => {
+                        app.input_mode = InputMode::Normal;
+                        if app.watch_only {
+                            app.set_status("Watch session: portfolios are left untouched");
+                        } else {
+                            match app.create_portfolio(&name, &source) {
+                                Ok(symbols) => {
+                                    if let Some(idx) = app.portfolios.iter().position(|p| p.name == name) {
+                                        app.view_combined = false;
+                                        app.current_portfolio_idx = idx;
+                                        app.refresh_data()?;
+                                        app.reset_selection();
+                                    }
+                                    app.set_status(format!("Created portfolio {} with {} symbols", name, symbols));
+                                }
+                                Err(err) => app.set_status(format!("Could not create portfolio: {}", err)),
+                            }
+                        }
+                    }
+                    Action::Refresh => {
+                        if !app.is_fetching {
+                            app.cache.clear();
+                            app.historical_cache.clear();
+                            app.start_async_refresh(false);
+                        }
+                    }
+                    Action::SwitchPortfolio(idx) => {
+                        app.view_combined = false;
+                        app.current_portfolio_idx = idx;
+                        app.refresh_data()?;
+                        app.reset_selection();
+                    }
+                    Action::Sort(column) => {
+                        app.toggle_sort(column);
+                    }
+                    Action::SelectTwRow(idx) => {
+                        app.active_section = 0;
+                        app.table_state_tw.select(Some(idx));
+                    }
+                    Action::SelectUsRow(idx) => {
+                        app.active_section = 1;
+                        app.table_state_us.select(Some(idx));
+                    }
+                    Action::SelectCryptoRow(idx) => {
+                        app.active_section = 2;
+                        app.table_state_crypto.select(Some(idx));
+                    }
+                    Action::ToggleGroup => {
+                        app.group_by_portfolio = !app.group_by_portfolio;
+                        app.load_combined_stocks()?;
+                        if app.view_combined {
+                            app.reset_selection();
+                            app.set_status(if app.group_by_portfolio { "Grouped by portfolio" } else { "Holdings aggregated across portfolios" });
+                        }
+                    }
+                    Action::ToggleLots => {
+                        app.show_lots = !app.show_lots;
+                        app.refresh_data()?;
+                        app.reset_selection();
+                        app.set_status(if app.show_lots { "Showing individual lots" } else { "Lots aggregated by symbol" });
+                    }
+                    Action::ViewCombined => {
+                        app.view_combined = true;
+                        app.reset_selection();
+                    }
+                    Action::OpenDetail => {
+                        if let Some(stock) = app.get_selected_stock() {
+                            let symbol = stock.symbol.clone();
+                            let stock_benchmark = stock.benchmark.clone();
+                            let has_fundamentals = stock.asset_class() == AssetClass::Equity && !stock.is_priced_locally();
+                            let historical = app.fetch_historical(&symbol);
+                            if has_fundamentals {
+                                app.request_fundamentals(&symbol);
+                                app.request_history(&symbol, DIVIDEND_HISTORY_RANGE);
+                                app.fetch_institutional(&symbol);
+                            }
+                            // Benchmark history for the relative-performance line
+                            let benchmark = app.benchmark_for(stock_benchmark.as_deref(), &symbol);
+                            app.fetch_historical(&benchmark);
+                            // Update historical data in all vectors
+                            for s in app.stocks.iter_mut().chain(app.tw_stocks.iter_mut())
+                                .chain(app.us_stocks.iter_mut()).chain(app.crypto_stocks.iter_mut()).chain(app.combined_stocks.iter_mut())
+                                .chain(app.combined_tw_stocks.iter_mut()).chain(app.combined_us_stocks.iter_mut())
+                                .chain(app.combined_crypto_stocks.iter_mut())
+                            {
+                                if s.symbol == symbol {
+                                    s.historical = historical.clone();
+                                }
+                            }
+                            app.input_mode = InputMode::DetailView(symbol);
+                        }
+                    }
+                    Action::RunCommand(key, symbol) => {
+                        app.run_command(key, &symbol);
+                    }
+                    Action::ExportChart(symbol) => {
+                        if !app.historical_cache.contains_key(&symbol) {
+                            app.fetch_historical(&symbol);
+                        }
+                        match app.export_chart_csv(&symbol) {
+                            Ok(path) => app.set_status(format!("Exported {}", path.display())),
+                            Err(err) => app.set_status(format!("Export failed: {}", err)),
+                        }
+                    }
+                    Action::RunDca(symbol, amount, years, drip) => {
+                        let range = match years {
+                            0..=1 => "1y",
+                            2 => "2y",
+                            3..=5 => "5y",
+                            6..=10 => "10y",
+                            _ => "max",
+                        };
+                        let since = Local::now().timestamp() - years as i64 * 365 * 24 * 60 * 60;
+                        let report = app
+                            .fetch_history(&symbol, range)
+                            .and_then(|h| DcaReport::simulate(&symbol, &h, amount, years, since, drip))
+                            .map(|mut report| {
+                                let currency = if symbol.contains(".TW") { "TWD" } else { "USD" };
+                                report.expected_return = app.config.assumptions.expected_return(currency);
+                                report
+                            });
+                        match report {
+                            Some(report) => app.input_mode = InputMode::DcaReport(report),
+                            None => {
+                                app.input_mode = InputMode::Normal;
+                                app.set_status(format!("No history available for {}", symbol));
+                            }
+                        }
+                    }
+                    Action::None => {}
+                }
+        }
+    }
+}
+
+fn handle_input(app: &mut App, key: KeyCode) -> Action {
+    // Alert popups sit on top of the normal view until dismissed
+    if matches!(app.input_mode, InputMode::Normal) && !app.alert_popups.is_empty() {
+        match key {
+            KeyCode::Esc | KeyCode::Enter | KeyCode::Char(' ') => {
+                app.alert_popups.remove(0);
+            }
+            KeyCode::Char('m') => app.mute_popup_alert(),
+            KeyCode::Char('z') => {
+                app.alert_popups.clear();
+                app.cycle_snooze();
+            }
+            _ => {}
+        }
+        return Action::None;
+    }
+    match &mut app.input_mode {
+        InputMode::Normal => match key {
+            KeyCode::Char('q') => Action::Quit,
+            KeyCode::Char('0') | KeyCode::Char('`') => {
+                app.view_combined = true;
+                app.reset_selection();
+                Action::None
+            }
+            KeyCode::Char(c) if c.is_ascii_digit() => {
+                let idx = c.to_digit(10).unwrap() as usize - 1;
+                if idx < app.portfolios.len() {
+                    Action::SwitchPortfolio(idx)
+                } else {
+                    Action::None
+                }
+            }
+            KeyCode::Tab => {
+                let sections = app.visible_sections();
+                let at = sections.iter().position(|s| *s == app.active_section).unwrap_or(0);
+                app.active_section = sections[(at + 1) % sections.len()];
+                Action::None
+            }
+            KeyCode::Down | KeyCode::Char('j') => {
+                app.next_row();
+                Action::None
+            }
+            KeyCode::Up | KeyCode::Char('k') => {
+                app.prev_row();
+                Action::None
+            }
+            // Grouped combined view: arrows collapse/expand the group under the cursor
+            KeyCode::Right | KeyCode::Char('l') if app.view_combined && app.group_by_portfolio => {
+                app.set_group_collapsed(Some(false));
+                Action::None
+            }
+            KeyCode::Left | KeyCode::Char('h') if app.view_combined && app.group_by_portfolio => {
+                app.set_group_collapsed(Some(true));
+                Action::None
+            }
+            KeyCode::Right | KeyCode::Char('l') => {
+                if !app.view_combined && app.portfolios.len() > 1 {
+                    let idx = (app.current_portfolio_idx + 1) % app.portfolios.len();
+                    Action::SwitchPortfolio(idx)
+                } else {
+                    Action::None
+                }
+            }
+            KeyCode::Left | KeyCode::Char('h') => {
+                if !app.view_combined && app.portfolios.len() > 1 {
+                    let idx = if app.current_portfolio_idx == 0 {
+                        app.portfolios.len() - 1
+                    } else {
+                        app.current_portfolio_idx - 1
+                    };
+                    Action::SwitchPortfolio(idx)
+                } else {
+                    Action::None
+                }
+            }
+            KeyCode::Char('r') => Action::Refresh,
+            KeyCode::Char('a') if !app.view_combined => {
+                app.input_mode = InputMode::AddStock(AddStockState::default());
+                Action::None
+            }
+            KeyCode::Char('e') if !app.view_combined => {
+                if let Some(stock) = app.get_selected_stock() {
+                    let lots: usize = app.stocks.iter().filter(|s| s.symbol == stock.symbol).map(|s| s.lots).sum();
+                    if lots > 1 {
+                        let symbol = stock.symbol.clone();
+                        app.set_status(format!("{} is held in {} lots; edit them in the portfolio file", symbol, lots));
+                        return Action::None;
+                    }
+                    app.input_mode = InputMode::EditStock(EditStockState {
+                        symbol: stock.symbol.clone(),
+                        quantity: stock.quantity.to_string(),
+                        cost_basis: stock.cost_basis.to_string(),
+                        step: 0,
+                    });
+                }
+                Action::None
+            }
+            KeyCode::Char('A') if !app.view_combined => {
+                if let Some(stock) = app.get_selected_stock() {
+                    let format_level = |level: Option<f64>| level.map(|l| l.to_string()).unwrap_or_default();
+                    app.input_mode = InputMode::AlertEdit(AlertEditState {
+                        symbol: stock.symbol.clone(),
+                        above: format_level(stock.alert_above),
+                        below: format_level(stock.alert_below),
+                        step: 0,
+                    });
+                }
+                Action::None
+            }
+            KeyCode::Char('b') if !app.view_combined => {
+                if let Some(stock) = app.get_selected_stock() {
+                    app.input_mode = InputMode::Trade(TradeState::new(TradeSide::Buy, stock));
+                }
+                Action::None
+            }
+            KeyCode::Char('s') if !app.view_combined => {
+                if let Some(stock) = app.get_selected_stock() {
+                    app.input_mode = InputMode::Trade(TradeState::new(TradeSide::Sell, stock));
+                }
+                Action::None
+            }
+            KeyCode::Char('f') => {
+                app.input_mode = InputMode::Find(String::new());
+                Action::None
+            }
+            KeyCode::Char('/') => {
+                app.input_mode = InputMode::Filter;
+                Action::None
+            }
+            KeyCode::Char('x') => {
+                app.hide_selected();
+                Action::None
+            }
+            KeyCode::Char('i') => {
+                app.expand_selected = !app.expand_selected;
+                Action::None
+            }
+            KeyCode::Char('[') | KeyCode::Char(']') => {
+                app.select_resize_column(key == KeyCode::Char(']'));
+                Action::None
+            }
+            KeyCode::Char('<') | KeyCode::Char('>') => {
+                app.resize_selected_column(Some(if key == KeyCode::Char('>') { 1 } else { -1 }));
+                Action::None
+            }
+            KeyCode::Char('=') => {
+                app.resize_selected_column(None);
+                Action::None
+            }
+            KeyCode::Char('X') => {
+                if !app.hidden_symbols.is_empty() {
+                    app.set_status(format!("Showing {} hidden rows", app.hidden_symbols.len()));
+                    app.hidden_symbols.clear();
+                }
+                Action::None
+            }
+            // Combined view: group rows by portfolio instead of aggregating
+            KeyCode::Char('P') => Action::ToggleGroup,
+            // Repeated lines of a symbol as separate lots or one weighted row
+            KeyCode::Char('K') => Action::ToggleLots,
+            KeyCode::Char('W') => {
+                app.snapshot_requested = true;
+                Action::None
+            }
+            KeyCode::Char('R') if app.custom_columns.iter().any(|c| c.gauge) => {
+                app.range_year = !app.range_year;
+                let year = app.range_year;
+                app.custom_columns.iter_mut().for_each(|c| c.toggle_range(year));
+                app.set_status(if year { "Range gauge: 52-week" } else { "Range gauge: today" });
+                Action::None
+            }
+            // Multi-select for batch delete / move / tag (current portfolio only)
+            KeyCode::Char(' ') if !app.view_combined => {
+                app.toggle_mark();
+                Action::None
+            }
+            KeyCode::Char('V') if !app.view_combined => {
+                app.mark_range();
+                Action::None
+            }
+            KeyCode::Char('v') => {
+                app.input_mode = InputMode::Equity(app.equity_state());
+                Action::None
+            }
+            KeyCode::Esc if !app.marked.is_empty() => {
+                app.marked.clear();
+                app.mark_anchor = None;
+                Action::None
+            }
+            KeyCode::Esc if !app.filter.is_empty() => {
+                app.filter.clear();
+                app.reset_selection();
+                Action::None
+            }
+            KeyCode::Char('d') if !app.view_combined && !app.marked.is_empty() => {
+                app.input_mode = InputMode::BatchDelete;
+                Action::None
+            }
+            KeyCode::Char('M') if !app.view_combined && !app.marked.is_empty() => {
+                app.input_mode = InputMode::BatchMove(String::new());
+                Action::None
+            }
+            KeyCode::Char('t') if !app.view_combined && !app.marked.is_empty() => {
+                app.input_mode = InputMode::BatchTag(String::new());
+                Action::None
+            }
+            KeyCode::Char('d') if !app.view_combined => {
+                if let Some(stock) = app.get_selected_stock() {
+                    app.input_mode = InputMode::DeleteConfirm(stock.symbol.clone());
+                }
+                Action::None
+            }
+            KeyCode::Char('n') => {
+                app.input_mode = InputMode::NewPortfolio(NewPortfolioState { sources: app.portfolio_sources(), ..Default::default() });
+                Action::None
+            }
+            // ETF look-through exposure
+            KeyCode::Char('E') => {
+                app.input_mode = InputMode::Exposure(app.calculate_exposure());
+                Action::None
+            }
+            // Position weights by symbol and market
+            KeyCode::Char('B') => {
+                app.input_mode = InputMode::Allocation(app.position_weights());
+                Action::None
+            }
+            // Headlines from configured RSS/Atom feeds
+            KeyCode::Char('N') => {
+                app.input_mode = InputMode::News(app.load_news(false));
+                Action::None
+            }
+            // What changed since the previous day's snapshot
+            KeyCode::Char('U') => {
+                app.input_mode = InputMode::Changes(app.load_changes());
+                Action::None
+            }
+            // Archived (sold or archived-on-delete) positions
+            KeyCode::Char('O') => {
+                app.input_mode = InputMode::Archive(app.load_archive());
+                Action::None
+            }
+            // Intraday replay of the last complete session
+            KeyCode::Char('I') => {
+                // Default to the last weekday before today, the latest complete session
+                let mut date = Local::now().date_naive() - chrono::Duration::days(1);
+                while date.weekday().number_from_monday() > 5 {
+                    date -= chrono::Duration::days(1);
+                }
+                app.input_mode = InputMode::ReplaySetup(date.format("%Y-%m-%d").to_string());
+                Action::None
+            }
+            // DCA simulator, pre-filled with the selected symbol
+            KeyCode::Char('D') => {
+                let symbol = app.get_selected_stock().map(|s| s.symbol.clone()).unwrap_or_default();
+                app.input_mode = InputMode::DcaSetup(DcaState {
+                    step: if symbol.is_empty() { 0 } else { 1 },
+                    symbol,
+                    amount: String::new(),
+                    years: "5".to_string(),
+                    drip: app.reinvest_dividends,
+                });
+                Action::None
+            }
+            // Sorting keys: F1/p=Price, F2/c=Change, F3/y=Qty, F4/g=Gain, F5/G=Gain%
+            KeyCode::F(1) | KeyCode::Char('p') => Action::Sort(SortColumn::Price),
+            KeyCode::F(2) | KeyCode::Char('c') => Action::Sort(SortColumn::Change),
+            KeyCode::F(3) | KeyCode::Char('y') => Action::Sort(SortColumn::Quantity),
+            KeyCode::F(4) | KeyCode::Char('g') => Action::Sort(SortColumn::Gain),
+            KeyCode::F(5) | KeyCode::Char('G') => Action::Sort(SortColumn::GainPercent),
+            KeyCode::Char('S') => {
+                let current = app.sort_menu_entries().iter().position(|(c, _)| Some(*c) == app.sort_column()).unwrap_or(0);
+                app.input_mode = InputMode::SortMenu(current);
+                Action::None
+            }
+            // Cycle sorting through computed columns
+            KeyCode::Char('C') if !app.custom_columns.is_empty() => {
+                let next = match app.sort_column() {
+                    Some(SortColumn::Custom(idx)) => (idx + 1) % app.custom_columns.len(),
+                    _ => 0,
+                };
+                Action::Sort(SortColumn::Custom(next))
+            }
+            // Alert banner: mute one alert, snooze all
+            KeyCode::Char('m') => {
+                app.mute_alert();
+                Action::None
+            }
+            KeyCode::Char('z') => {
+                app.cycle_snooze();
+                Action::None
+            }
+            // Toggle hide positions for privacy
+            KeyCode::Char('H') => {
+                app.hide_positions = !app.hide_positions;
+                Action::None
+            }
+            // Toggle live mode (auto-refresh every 5 seconds)
+            KeyCode::Char('L') => {
+                app.live_mode = !app.live_mode;
+                if app.live_mode {
+                    app.last_live_refresh = Instant::now();
+                }
+                Action::None
+            }
+            // Toggle FX-neutral gains (US gains in USD, ignoring currency movement)
+            KeyCode::Char('F') => {
+                app.fx_neutral = !app.fx_neutral;
+                app.sort_stocks();
+                Action::None
+            }
+            // Toggle between gain amount and percentage in table titles
+            KeyCode::Char('T') => {
+                app.show_gain_amount = !app.show_gain_amount;
+                Action::None
+            }
+            // Enter to view stock detail - fetch historical on demand
+            KeyCode::Enter if app.get_selected_stock().is_none() && app.selected_group().is_some() => {
+                app.set_group_collapsed(None);
+                Action::None
+            }
+            KeyCode::Enter => Action::OpenDetail,
+            // Configured `[[commands]]` keys; built-in keys take precedence
+            KeyCode::Char(c) if app.config.commands.iter().any(|cmd| cmd.key == c) => match app.get_selected_stock() {
+                Some(stock) => Action::RunCommand(c, stock.symbol.clone()),
+                None => Action::None,
+            },
+            _ => Action::None,
+        },
+        InputMode::DetailView(symbol) => match key {
+            KeyCode::Esc | KeyCode::Enter | KeyCode::Char('q') => {
+                app.input_mode = InputMode::Normal;
+                Action::None
+            }
+            KeyCode::Char('x') => Action::ExportChart(symbol.clone()),
+            KeyCode::Char('b') => {
+                app.show_bollinger = !app.show_bollinger;
+                Action::None
+            }
+            KeyCode::Char('v') => {
+                app.show_levels = !app.show_levels;
+                Action::None
+            }
+            KeyCode::Char('d') => {
+                app.reinvest_dividends = !app.reinvest_dividends;
+                Action::None
+            }
+            KeyCode::Char(c) if app.config.commands.iter().any(|cmd| cmd.key == c) => Action::RunCommand(c, symbol.clone()),
+            _ => Action::None,
+        },
+        InputMode::AddStock(state) => match key {
+            KeyCode::Esc => {
+                app.input_mode = InputMode::Normal;
+                Action::None
+            }
+            KeyCode::Enter => {
+                if state.step < 4 {
+                    state.step += 1;
+                    Action::None
+                } else {
+                    let symbol = normalize_symbol(&state.symbol);
+                    let display = if state.display.is_empty() {
+                        default_display(&symbol)
+                    } else {
+                        state.display.clone()
+                    };
+                    let name = if state.name.is_empty() {
+                        symbol.clone()
+                    } else {
+                        state.name.clone()
+                    };
+                    let quantity: f64 = state.quantity.parse().unwrap_or(0.0);
+                    let cost_basis: f64 = state.cost_basis.parse().unwrap_or(0.0);
+                    Action::AddStock(symbol, display, name, quantity, cost_basis)
+                }
+            }
+            KeyCode::Backspace => {
+                let field = match state.step {
+                    0 => &mut state.symbol,
+                    1 => &mut state.display,
+                    2 => &mut state.name,
+                    3 => &mut state.quantity,
+                    _ => &mut state.cost_basis,
+                };
+                field.pop();
+                Action::None
+            }
+            KeyCode::Char(c) => {
+                let field = match state.step {
+                    0 => &mut state.symbol,
+                    1 => &mut state.display,
+                    2 => &mut state.name,
+                    3 => &mut state.quantity,
+                    _ => &mut state.cost_basis,
+                };
+                field.push(c);
+                Action::None
+            }
+            _ => Action::None,
+        },
+        InputMode::EditStock(state) => match key {
+            KeyCode::Esc => {
+                app.input_mode = InputMode::Normal;
+                Action::None
+            }
+            KeyCode::Tab => {
+                state.step = (state.step + 1) % 2;
+                Action::None
+            }
+            KeyCode::Enter => {
+                let symbol = state.symbol.clone();
+                let quantity: f64 = state.quantity.parse().unwrap_or(0.0);
+                let cost_basis: f64 = state.cost_basis.parse().unwrap_or(0.0);
+                Action::EditStock(symbol, quantity, cost_basis)
+            }
+            KeyCode::Backspace => {
+                let field = match state.step {
+                    0 => &mut state.quantity,
+                    _ => &mut state.cost_basis,
+                };
+                field.pop();
+                Action::None
+            }
+            KeyCode::Char(c) if c.is_ascii_digit() || c == '.' => {
+                let field = match state.step {
+                    0 => &mut state.quantity,
+                    _ => &mut state.cost_basis,
+                };
+                field.push(c);
+                Action::None
+            }
+            _ => Action::None,
+        },
+        InputMode::SortMenu(selected) => match key {
+            KeyCode::Esc | KeyCode::Char('q') | KeyCode::Char('S') => {
+                app.input_mode = InputMode::Normal;
+                Action::None
+            }
+            KeyCode::Down | KeyCode::Char('j') => {
+                *selected = (*selected + 1).min(SortColumn::MENU.len() + app.custom_columns.len() - 1);
+                Action::None
+            }
+            KeyCode::Up | KeyCode::Char('k') => {
+                *selected = selected.saturating_sub(1);
+                Action::None
+            }
+            KeyCode::Enter => {
+                let selected = *selected;
+                let entries = app.sort_menu_entries();
+                let column = entries.get(selected.min(entries.len() - 1)).map(|(c, _)| *c);
+                app.input_mode = InputMode::Normal;
+                column.map(Action::Sort).unwrap_or(Action::None)
+            }
+            _ => Action::None,
+        },
+        InputMode::Palette(state) => match key {
+            KeyCode::Esc => {
+                app.input_mode = InputMode::Normal;
+                Action::None
+            }
+            KeyCode::Down => {
+                state.selected += 1;
+                Action::None
+            }
+            KeyCode::Up => {
+                state.selected = state.selected.saturating_sub(1);
+                Action::None
+            }
+            KeyCode::Backspace => {
+                state.query.pop();
+                state.selected = 0;
+                Action::None
+            }
+            KeyCode::Char(c) => {
+                state.query.push(c);
+                state.selected = 0;
+                Action::None
+            }
+            KeyCode::Enter => {
+                let query = state.query.clone();
+                let selected = state.selected;
+                let matches = palette_matches(app, &query);
+                let command = matches.get(selected.min(matches.len().saturating_sub(1))).map(|(_, c)| c.clone());
+                app.input_mode = InputMode::Normal;
+                match command {
+                    Some(PaletteCommand::Key(key)) => handle_input(app, key),
+                    Some(PaletteCommand::SwitchPortfolio(idx)) => Action::SwitchPortfolio(idx),
+                    Some(PaletteCommand::Sort(column)) => Action::Sort(column),
+                    Some(PaletteCommand::Detail(symbol)) => {
+                        app.select_symbol(&symbol);
+                        Action::OpenDetail
+                    }
+                    Some(PaletteCommand::Export(symbol)) => Action::ExportChart(symbol),
+                    None => Action::None,
+                }
+            }
+            _ => Action::None,
+        },
+        InputMode::AlertEdit(state) => match key {
+            KeyCode::Esc => {
+                app.input_mode = InputMode::Normal;
+                Action::None
+            }
+            KeyCode::Tab => {
+                state.step = (state.step + 1) % 2;
+                Action::None
+            }
+            KeyCode::Enter => {
+                let above = state.above.parse().ok();
+                let below = state.below.parse().ok();
+                Action::SetAlerts(state.symbol.clone(), above, below)
+            }
+            KeyCode::Backspace => {
+                state.field_mut().pop();
+                Action::None
+            }
+            KeyCode::Char(c) if c.is_ascii_digit() || c == '.' => {
+                state.field_mut().push(c);
+                Action::None
+            }
+            _ => Action::None,
+        },
+        InputMode::DcaSetup(state) => match key {
+            KeyCode::Esc => {
+                app.input_mode = InputMode::Normal;
+                Action::None
+            }
+            KeyCode::Tab => {
+                state.step = (state.step + 1) % 4;
+                Action::None
+            }
+            KeyCode::Enter => {
+                if state.step < 3 {
+                    state.step += 1;
+                    Action::None
+                } else {
+                    let symbol = state.symbol.trim().to_uppercase();
+                    let amount: f64 = state.amount.parse().unwrap_or(0.0);
+                    let years: u32 = state.years.parse().unwrap_or(0);
+                    if symbol.is_empty() || amount <= 0.0 || years == 0 {
+                        Action::None
+                    } else {
+                        Action::RunDca(symbol, amount, years, state.drip)
+                    }
+                }
+            }
+            KeyCode::Backspace => {
+                match state.step {
+                    0 => { state.symbol.pop(); }
+                    1 => { state.amount.pop(); }
+                    2 => { state.years.pop(); }
+                    _ => {}
+                }
+                Action::None
+            }
+            KeyCode::Char(c) => {
+                match state.step {
+                    0 => state.symbol.push(c),
+                    1 if c.is_ascii_digit() || c == '.' => state.amount.push(c),
+                    2 if c.is_ascii_digit() => state.years.push(c),
+                    3 if c == ' ' || c == 'y' || c == 'n' => state.drip = c == 'y' || (c == ' ' && !state.drip),
+                    _ => {}
+                }
+                Action::None
+            }
+            _ => Action::None,
+        },
+        InputMode::Exposure(state) => match key {
+            KeyCode::Esc | KeyCode::Enter | KeyCode::Char('q') => {
+                app.input_mode = InputMode::Normal;
+                Action::None
+            }
+            KeyCode::Down | KeyCode::Char('j') => {
+                state.scroll = (state.scroll + 1).min(state.rows.len().saturating_sub(1));
+                Action::None
+            }
+            KeyCode::Up | KeyCode::Char('k') => {
+                state.scroll = state.scroll.saturating_sub(1);
+                Action::None
+            }
+            _ => Action::None,
+        },
+        InputMode::Allocation(state) => {
+            match key {
+                KeyCode::Esc | KeyCode::Enter | KeyCode::Char('q') | KeyCode::Char('B') => app.input_mode = InputMode::Normal,
+                KeyCode::Char('t') => state.treemap = !state.treemap,
+                KeyCode::Down | KeyCode::Char('j') => state.scroll = (state.scroll + 1).min(state.holdings.len().saturating_sub(1)),
+                KeyCode::Up | KeyCode::Char('k') => state.scroll = state.scroll.saturating_sub(1),
+                _ => {}
+            }
+            Action::None
+        }
+        InputMode::News(state) => match key {
+            KeyCode::Esc | KeyCode::Char('q') | KeyCode::Char('N') => {
+                app.input_mode = InputMode::Normal;
+                Action::None
+            }
+            KeyCode::Down | KeyCode::Char('j') => {
+                state.selected = (state.selected + 1).min(state.visible().len().saturating_sub(1));
+                Action::None
+            }
+            KeyCode::Up | KeyCode::Char('k') => {
+                state.selected = state.selected.saturating_sub(1);
+                Action::None
+            }
+            KeyCode::Char('f') => {
+                state.cycle_filter();
+                Action::None
+            }
+            KeyCode::Char('r') => {
+                let filter = state.filter;
+                let mut refreshed = app.load_news(true);
+                refreshed.filter = filter.min(refreshed.filters.len().saturating_sub(1));
+                app.input_mode = InputMode::News(refreshed);
+                Action::None
+            }
+            _ => Action::None,
+        },
+        InputMode::Archive(state) => match key {
+            KeyCode::Esc | KeyCode::Enter | KeyCode::Char('q') | KeyCode::Char('O') => {
+                app.input_mode = InputMode::Normal;
+                Action::None
+            }
+            KeyCode::Down | KeyCode::Char('j') => {
+                state.scroll = (state.scroll + 1).min(state.rows.len().saturating_sub(1));
+                Action::None
+            }
+            KeyCode::Up | KeyCode::Char('k') => {
+                state.scroll = state.scroll.saturating_sub(1);
+                Action::None
+            }
+            KeyCode::Char('s') => {
+                state.sort_by(ArchiveSort::Symbol);
+                Action::None
+            }
+            KeyCode::Char('c') => {
+                state.sort_by(ArchiveSort::Closed);
+                Action::None
+            }
+            KeyCode::Char('h') => {
+                state.sort_by(ArchiveSort::Held);
+                Action::None
+            }
+            KeyCode::Char('r') => {
+                state.sort_by(ArchiveSort::Realized);
+                Action::None
+            }
+            _ => Action::None,
+        },
+        InputMode::ReplaySetup(date) => match key {
+            KeyCode::Esc => {
+                app.input_mode = InputMode::Normal;
+                Action::None
+            }
+            KeyCode::Enter => match chrono::NaiveDate::parse_from_str(date.trim(), "%Y-%m-%d") {
+                Ok(date) => Action::StartReplay(date),
+                Err(_) => {
+                    app.set_status("Enter the date as YYYY-MM-DD");
+                    Action::None
+                }
+            },
+            KeyCode::Backspace => {
+                date.pop();
+                Action::None
+            }
+            KeyCode::Char(c) if c.is_ascii_digit() || c == '-' => {
+                date.push(c);
+                Action::None
+            }
+            _ => Action::None,
+        },
+        InputMode::Replay(state) => {
+            match key {
+                KeyCode::Esc | KeyCode::Char('q') | KeyCode::Char('I') => app.input_mode = InputMode::Normal,
+                KeyCode::Char(' ') => {
+                    // Playing again from the end starts over
+                    if !state.playing && state.cursor + 1 >= state.times.len() {
+                        state.cursor = 0;
+                    }
+                    state.playing = !state.playing;
+                }
+                KeyCode::Right | KeyCode::Char('l') => {
+                    state.playing = false;
+                    state.cursor = (state.cursor + 1).min(state.times.len() - 1);
+                }
+                KeyCode::Left | KeyCode::Char('h') => {
+                    state.playing = false;
+                    state.cursor = state.cursor.saturating_sub(1);
+                }
+                KeyCode::Home | KeyCode::Char('0') => state.cursor = 0,
+                KeyCode::End => state.cursor = state.times.len() - 1,
+                KeyCode::Char('+') | KeyCode::Char('=') => state.speed = (state.speed + 1).min(REPLAY_SPEEDS.len() - 1),
+                KeyCode::Char('-') => state.speed = state.speed.saturating_sub(1),
+                _ => {}
+            }
+            Action::None
+        }
+        InputMode::Reconcile(state) => {
+            let Some(issue) = state.issues.get(state.selected).cloned() else {
+                app.input_mode = InputMode::Normal;
+                return Action::None;
+            };
+            if let Some(text) = state.editing.as_mut() {
+                return match key {
+                    KeyCode::Esc => {
+                        state.editing = None;
+                        Action::None
+                    }
+                    KeyCode::Enter => match line_problem(text.trim()) {
+                        Some(problem) => {
+                            app.set_status(format!("Still malformed: {}", problem));
+                            Action::None
+                        }
+                        None => Action::FixLine(issue, Some(text.trim().to_string())),
+                    },
+                    KeyCode::Backspace => {
+                        text.pop();
+                        Action::None
+                    }
+                    KeyCode::Char(c) => {
+                        text.push(c);
+                        Action::None
+                    }
+                    _ => Action::None,
+                };
+            }
+            match key {
+                KeyCode::Esc | KeyCode::Char('q') => {
+                    app.input_mode = InputMode::Normal;
+                    Action::None
+                }
+                KeyCode::Down | KeyCode::Char('j') => {
+                    state.selected = (state.selected + 1).min(state.issues.len() - 1);
+                    Action::None
+                }
+                KeyCode::Up | KeyCode::Char('k') => {
+                    state.selected = state.selected.saturating_sub(1);
+                    Action::None
+                }
+                KeyCode::Enter | KeyCode::Char('e') => {
+                    state.editing = Some(issue.text.clone());
+                    Action::None
+                }
+                KeyCode::Char('c') => Action::FixLine(issue.clone(), Some(complete_line(&issue.text))),
+                KeyCode::Char('d') => Action::FixLine(issue, None),
+                _ => Action::None,
+            }
+        }
+        InputMode::Equity(state) => {
+            match key {
+                KeyCode::Esc | KeyCode::Enter | KeyCode::Char('q') | KeyCode::Char('v') => app.input_mode = InputMode::Normal,
+                KeyCode::Right | KeyCode::Char('l') | KeyCode::Tab => state.selected = (state.selected + 1) % state.keys.len(),
+                KeyCode::Left | KeyCode::Char('h') | KeyCode::BackTab => {
+                    state.selected = (state.selected + state.keys.len() - 1) % state.keys.len();
+                }
+                _ => {}
+            }
+            Action::None
+        }
+        InputMode::Changes(state) => match key {
+            KeyCode::Esc | KeyCode::Enter | KeyCode::Char('q') | KeyCode::Char('U') => {
+                app.input_mode = InputMode::Normal;
+                Action::None
+            }
+            KeyCode::Down | KeyCode::Char('j') => {
+                state.scroll = (state.scroll + 1).min(state.changed.len().saturating_sub(1));
+                Action::None
+            }
+            KeyCode::Up | KeyCode::Char('k') => {
+                state.scroll = state.scroll.saturating_sub(1);
+                Action::None
+            }
+            _ => Action::None,
+        },
+        InputMode::DcaReport(_) => match key {
+            KeyCode::Esc | KeyCode::Enter | KeyCode::Char('q') => {
+                app.input_mode = InputMode::Normal;
+                Action::None
+            }
+            _ => Action::None,
+        },
+        InputMode::Trade(state) => match key {
+            KeyCode::Esc => {
+                app.input_mode = InputMode::Normal;
+                Action::None
+            }
+            KeyCode::Tab => {
+                state.step = (state.step + 1) % 4;
+                Action::None
+            }
+            KeyCode::Enter => {
+                if state.step < 3 {
+                    state.step += 1;
+                    Action::None
+                } else {
+                    state.to_transaction().map(Action::Trade).unwrap_or(Action::None)
+                }
+            }
+            KeyCode::Backspace => {
+                state.field_mut().pop();
+                Action::None
+            }
+            KeyCode::Char(c) if c.is_ascii_digit() || c == '.' || (c == '-' && state.step == 3) => {
+                state.field_mut().push(c);
+                Action::None
+            }
+            _ => Action::None,
+        },
+        InputMode::DeleteConfirm(symbol) => match key {
+            KeyCode::Char('y') | KeyCode::Char('Y') => {
+                Action::DeleteStock(symbol.clone(), false)
+            }
+            KeyCode::Char('a') | KeyCode::Char('A') => {
+                Action::DeleteStock(symbol.clone(), true)
+            }
+            _ => {
+                app.input_mode = InputMode::Normal;
+                Action::None
+            }
+        },
+        InputMode::Find(query) => match key {
+            KeyCode::Esc | KeyCode::Enter => {
+                app.input_mode = InputMode::Normal;
+                Action::None
+            }
+            KeyCode::Backspace => {
+                query.pop();
+                let query = query.clone();
+                app.jump_to_match(&query);
+                Action::None
+            }
+            KeyCode::Char(c) => {
+                query.push(c);
+                let query = query.clone();
+                app.jump_to_match(&query);
+                Action::None
+            }
+            _ => Action::None,
+        },
+        InputMode::Filter => match key {
+            KeyCode::Enter => {
+                app.input_mode = InputMode::Normal;
+                Action::None
+            }
+            KeyCode::Esc => {
+                app.filter.clear();
+                app.reset_selection();
+                app.input_mode = InputMode::Normal;
+                Action::None
+            }
+            KeyCode::Backspace => {
+                app.filter.pop();
+                app.reset_selection();
+                Action::None
+            }
+            KeyCode::Char(c) => {
+                app.filter.push(c);
+                app.reset_selection();
+                Action::None
+            }
+            _ => Action::None,
+        },
+        InputMode::BatchDelete => match key {
+            KeyCode::Char('y') | KeyCode::Char('Y') => Action::Batch(BatchOp::Delete),
+            _ => {
+                app.input_mode = InputMode::Normal;
+                Action::None
+            }
+        },
+        InputMode::BatchMove(input) | InputMode::BatchTag(input) => match key {
+            KeyCode::Esc => {
+                app.input_mode = InputMode::Normal;
+                Action::None
+            }
+            KeyCode::Backspace => {
+                input.pop();
+                Action::None
+            }
+            KeyCode::Char(c) if c != '|' && c != ',' => {
+                input.push(c);
+                Action::None
+            }
+            KeyCode::Enter => {
+                let input = input.trim().to_string();
+                if input.is_empty() {
+                    return Action::None;
+                }
+                if matches!(app.input_mode, InputMode::BatchTag(_)) {
+                    return Action::Batch(BatchOp::Tag(input));
+                }
+                // Portfolio number (as on the tabs) or name
+                let target = input.parse::<usize>().ok()
+                    .and_then(|n| n.checked_sub(1))
+                    .filter(|idx| *idx < app.portfolios.len())
+                    .or_else(|| app.portfolios.iter().position(|p| p.name.eq_ignore_ascii_case(&input)));
+                match target {
+                    Some(idx) => Action::Batch(BatchOp::Move(idx)),
+                    None => {
+                        app.set_status(format!("No portfolio '{}'", input));
+                        Action::None
+                    }
+                }
+            }
+            _ => Action::None,
+        },
+        InputMode::NewPortfolio(state) => match key {
+            KeyCode::Esc => {
+                app.input_mode = InputMode::Normal;
+                Action::None
+            }
+            KeyCode::Enter => {
+                match state.sources.get(state.selected) {
+                    Some(source) if !state.name.is_empty() => Action::CreatePortfolio(state.name.clone(), source.clone()),
+                    _ => Action::None,
+                }
+            }
+            KeyCode::Up => {
+                state.selected = state.selected.saturating_sub(1);
+                Action::None
+            }
+            KeyCode::Down => {
+                state.selected = (state.selected + 1).min(state.sources.len().saturating_sub(1));
+                Action::None
+            }
+            KeyCode::Backspace => {
+                state.name.pop();
+                Action::None
+            }
+            KeyCode::Char(c) if c.is_alphanumeric() || c == '_' => {
+                state.name.push(c.to_ascii_lowercase());
+                Action::None
+            }
+            _ => Action::None,
+        },
+    }
+}
+
+/// Check if a point (x, y) is inside a Rect
+fn point_in_rect(x: u16, y: u16, rect: Rect) -> bool {
+    x >= rect.x && x < rect.x + rect.width && y >= rect.y && y < rect.y + rect.height
+}
+
+fn handle_mouse(app: &mut App, kind: MouseEventKind, x: u16, y: u16) -> Action {
+    // Only handle left clicks
+    let is_click = matches!(kind, MouseEventKind::Down(MouseButton::Left));
+
+    if !is_click {
+        return Action::None;
+    }
+
+    // In detail view or DCA report, any click closes it
+    if matches!(app.input_mode, InputMode::DetailView(_) | InputMode::DcaReport(_) | InputMode::Exposure(_) | InputMode::Allocation(_) | InputMode::Archive(_) | InputMode::Changes(_) | InputMode::Equity(_) | InputMode::News(_) | InputMode::Reconcile(_) | InputMode::Replay(_)) {
+        app.input_mode = InputMode::Normal;
+        return Action::None;
+    }
+
+    // Only handle mouse in Normal mode
+    if !matches!(app.input_mode, InputMode::Normal) {
+        return Action::None;
+    }
+
+    let regions = &app.clickable_regions;
+
+    // Check portfolio tabs
+    for (rect, idx) in &regions.portfolio_tabs {
+        if point_in_rect(x, y, *rect) {
+            if *idx == 0 {
+                return Action::ViewCombined;
+            } else {
+                return Action::SwitchPortfolio(*idx - 1);
+            }
+        }
+    }
+
+    // Check TW stock table rows
+    // Click on already-selected row opens detail view
+    for (rect, row_idx) in &regions.tw_rows {
+        if point_in_rect(x, y, *rect) {
+            let currently_selected = app.table_state_tw.selected() == Some(*row_idx) && app.active_section == 0;
+            if currently_selected {
+                return Action::OpenDetail;
+            }
+            return Action::SelectTwRow(*row_idx);
+        }
+    }
+
+    // Check US stock table rows
+    for (rect, row_idx) in &regions.us_rows {
         if point_in_rect(x, y, *rect) {
             let currently_selected = app.table_state_us.selected() == Some(*row_idx) && app.active_section == 1;
             if currently_selected {
                 return Action::OpenDetail;
             }
-            return Action::SelectUsRow(*row_idx);
-        }
-    }
+            return Action::SelectUsRow(*row_idx);
+        }
+    }
+
+    // Check crypto rows
+    for (rect, row_idx) in &regions.crypto_rows {
+        if point_in_rect(x, y, *rect) {
+            let currently_selected = app.table_state_crypto.selected() == Some(*row_idx) && app.active_section == 2;
+            if currently_selected {
+                return Action::OpenDetail;
+            }
+            return Action::SelectCryptoRow(*row_idx);
+        }
+    }
+
+    // Footer items act like their key
+    if let Some((_, key)) = regions.footer_buttons.iter().find(|(rect, _)| point_in_rect(x, y, *rect)) {
+        return handle_input(app, *key);
+    }
+
+    // Click on table area but not on a row - activate that section
+    if point_in_rect(x, y, regions.tw_table) {
+        app.active_section = 0;
+    } else if point_in_rect(x, y, regions.us_table) {
+        app.active_section = 1;
+    } else if point_in_rect(x, y, regions.crypto_table) {
+        app.active_section = 2;
+    }
+
+    Action::None
+}
+
+/// Smallest terminal the layout fits: tabs, two tables showing a couple of rows, summary and footer
+const MIN_WIDTH: u16 = 80;
+const MIN_HEIGHT: u16 = 27;
+
+fn ui(f: &mut Frame, app: &mut App) {
+    // Clear clickable regions before each render
+    app.clickable_regions = ClickableRegions::default();
+    if f.area().width < MIN_WIDTH || f.area().height < MIN_HEIGHT {
+        render_too_small(f, f.area());
+        return;
+    }
+
+    let alerts = app.active_alerts();
+    let show_banner = !alerts.is_empty() || app.alerts_snoozed();
+
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Length(3),  // Tabs
+            Constraint::Min(10),    // Main content
+            Constraint::Length(12), // Summary
+            Constraint::Length(if show_banner { 1 } else { 0 }), // Alert banner
+            Constraint::Length(2),  // Footer
+        ])
+        .split(f.area());
+
+    render_tabs(f, app, chunks[0]);
+    render_stock_tables(f, app, chunks[1]);
+    render_summary(f, app, chunks[2]);
+    if show_banner {
+        render_alert_banner(f, app, &alerts, chunks[3]);
+    }
+    render_footer(f, app, chunks[4]);
+
+    // Render dialogs
+    match &app.input_mode {
+        InputMode::AddStock(state) => render_add_dialog(f, state),
+        InputMode::EditStock(state) => render_edit_dialog(f, state),
+        InputMode::AlertEdit(state) => render_alert_dialog(f, state),
+        InputMode::Palette(state) => render_palette(f, app, state),
+        InputMode::SortMenu(selected) => render_sort_menu(f, app, *selected),
+        InputMode::Find(_) | InputMode::Filter => {}
+        InputMode::BatchDelete => render_delete_dialog(f, &format!("{} {}", app.marked.len(), tr("marked holdings"))),
+        InputMode::BatchMove(input) => render_prompt_dialog(f, tr(" Move Holdings "), &format!("{} ({}):", tr("Move marked holdings to portfolio (number or name)"), app.marked.len()), input),
+        InputMode::BatchTag(input) => render_prompt_dialog(f, tr(" Tag Holdings "), &format!("{} ({}):", tr("Tag for marked holdings"), app.marked.len()), input),
+        InputMode::DeleteConfirm(symbol) => render_delete_dialog(f, symbol),
+        InputMode::NewPortfolio(state) => render_new_portfolio_dialog(f, state),
+        InputMode::DetailView(symbol) => render_detail_view(f, app, symbol),
+        InputMode::DcaSetup(state) => render_dca_dialog(f, state),
+        InputMode::Trade(state) => render_trade_dialog(f, app, state),
+        InputMode::Exposure(state) => render_exposure_view(f, state),
+        InputMode::Allocation(state) => render_allocation_view(f, state, app.hide_positions),
+        InputMode::Archive(state) => render_archive_view(f, state, app.view_combined, &app.config.time),
+        InputMode::Changes(state) => render_changes_view(f, state, &app.config.time),
+        InputMode::Equity(state) => render_equity_view(f, app, state),
+        InputMode::Reconcile(state) => render_reconcile_view(f, state),
+        InputMode::ReplaySetup(date) => render_replay_setup_dialog(f, date),
+        InputMode::Replay(state) => render_replay_view(f, state, app.hide_positions),
+        InputMode::News(state) => render_news_view(f, state, &app.config),
+        InputMode::DcaReport(report) => render_dca_report(f, report),
+        InputMode::Normal => {
+            if let Some(popup) = app.alert_popups.first() {
+                render_alert_popup(f, popup, app.alert_popups.len());
+            }
+        }
+    }
+}
+
+fn render_tabs(f: &mut Frame, app: &mut App, area: Rect) {
+    let mut titles: Vec<Line> = vec![
+        if app.view_combined {
+            Line::from(" `/0:ALL ").magenta().bold()
+        } else {
+            Line::from(" `/0:ALL ").dark_gray()
+        }
+    ];
+
+    // Track tab widths for click detection
+    let mut tab_widths: Vec<usize> = vec![9]; // " `/0:ALL " = 9 chars
+
+    for (i, p) in app.portfolios.iter().enumerate() {
+        let title = format!(" {}:{} ", i + 1, p.name);
+        tab_widths.push(title.width());
+        if !app.view_combined && i == app.current_portfolio_idx {
+            titles.push(Line::from(title).cyan().bold());
+        } else {
+            titles.push(Line::from(title).dark_gray());
+        }
+    }
+
+    // Calculate clickable regions for tabs (inside the border)
+    let inner_x = area.x + 1; // Account for left border
+    let tab_y = area.y + 1;   // Account for top border
+    let mut current_x = inner_x;
+
+    for (i, width) in tab_widths.iter().enumerate() {
+        let tab_rect = Rect::new(current_x, tab_y, *width as u16, 1);
+        app.clickable_regions.portfolio_tabs.push((tab_rect, i));
+        current_x += *width as u16 + 1; // +1 for divider "|"
+    }
+
+    let tabs = Tabs::new(titles)
+        .block(Block::default().borders(Borders::ALL).title(tr(" Portfolios ")))
+        .divider("|");
+
+    f.render_widget(tabs, area);
+}
+
+/// Drawn instead of the layout (leaving nothing clickable) while the terminal is below the minimum size
+fn render_too_small(f: &mut Frame, area: Rect) {
+    let dimension = |label: &'static str, have: u16, need: u16| Line::from(vec![
+        Span::raw(format!("{} ", tr(label))),
+        Span::styled(have.to_string(), Style::default().fg(if have < need { theme().loss } else { theme().gain })),
+        Span::raw(format!(" / {}", need)),
+    ]);
+    let lines = vec![
+        Line::from(tr("Terminal too small")).style(Style::default().fg(theme().header).add_modifier(Modifier::BOLD)),
+        Line::from(""),
+        dimension("Width", area.width, MIN_WIDTH),
+        dimension("Height", area.height, MIN_HEIGHT),
+        Line::from(""),
+        Line::from(tr("Enlarge the window to continue")).style(Style::default().fg(theme().muted)),
+    ];
+    let top = area.height.saturating_sub(lines.len() as u16) / 2;
+    let body = Rect { y: area.y + top, height: area.height - top, ..area };
+    f.render_widget(Paragraph::new(lines).alignment(Alignment::Center), body);
+}
+
+fn render_stock_tables(f: &mut Frame, app: &mut App, area: Rect) {
+    // The crypto table only takes space once there are crypto holdings
+    let sections = app.visible_sections();
+    if !sections.contains(&app.active_section) {
+        app.active_section = 0;
+        app.invalidate_rows();
+    }
+    let constraints: &[Constraint] = if sections.len() == 3 {
+        &[Constraint::Percentage(40), Constraint::Percentage(35), Constraint::Percentage(25)]
+    } else {
+        &[Constraint::Percentage(50), Constraint::Percentage(50)]
+    };
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints(constraints)
+        .split(area);
+
+    // Tables are rebuilt only when something changed since they were cached, or when the
+    // selection moved on or off an expanded row
+    for (n, &section) in sections.iter().enumerate() {
+        let stale = app.row_cache.get(&section).is_none_or(|cache| {
+            cache.generation != app.render_generation
+                || cache.area != chunks[n]
+                || cache.active != (app.active_section == section)
+                || cache.expanded != expanded_row(app, section, &cache.display)
+        });
+        if stale {
+            let table = build_section_table(app, section, chunks[n]);
+            app.row_cache.insert(section, table);
+        }
+    }
+
+    // Record table and row areas for click detection (rows start after border + header)
+    for (n, &section) in sections.iter().enumerate() {
+        let table = chunks[n];
+        let (rows_area, footer) = totals_footer(app, section, table);
+        let bottom = if footer.is_some() { rows_area.y + rows_area.height } else { rows_area.y + rows_area.height - 1 };
+        let cache = &app.row_cache[&section];
+        let extra = |i: usize| if cache.expanded.is_some_and(|(row, _)| i > row) { EXPANDED_ROW_LINES } else { 0 };
+        let mut rows = Vec::new();
+        for i in 0..cache.display.len() {
+            let row_y = table.y + 2 + i as u16 + extra(i);
+            if row_y < bottom { // Don't exceed table bounds or run under the pinned totals
+                rows.push((Rect::new(table.x + 1, row_y, table.width.saturating_sub(2), 1), i));
+            }
+        }
+        let regions = &mut app.clickable_regions;
+        match section {
+            0 => (regions.tw_table, regions.tw_rows) = (table, rows),
+            1 => (regions.us_table, regions.us_rows) = (table, rows),
+            _ => (regions.crypto_table, regions.crypto_rows) = (table, rows),
+        }
+    }
+
+    for (n, &section) in sections.iter().enumerate() {
+        let (area, footer) = totals_footer(app, section, chunks[n]);
+        let Some(cache) = app.row_cache.remove(&section) else {
+            continue;
+        };
+        f.render_stateful_widget(&cache.table, area, app.table_state_mut(section));
+        if let Some(footer) = footer {
+            f.render_stateful_widget(&cache.totals, footer, &mut TableState::default());
+        }
+        if let Some((row, i)) = cache.expanded {
+            let offset = app.table_state(section).offset();
+            render_expanded_row(f, app, &app.section_stocks(section)[i], area, row.saturating_sub(offset));
+        }
+        app.row_cache.insert(section, cache);
+    }
+}
+
+/// A section's table as last built, kept across frames so idle redraws don't rebuild
+/// every row of a large portfolio
+struct SectionTable {
+    generation: u64, // `App::render_generation` it was built at
+    area: Rect,
+    active: bool, // Drawn with the active section's border
+    display: Vec<DisplayRow>,
+    expanded: Option<(usize, usize)>, // (display row, stock index) expanded in place with `i`
+    table: Table<'static>,
+    totals: Table<'static>, // Pinned totals row, drawn when `totals_footer` leaves room
+}
+
+/// Build a section's table (header, rows and pinned totals) for the given area
+fn build_section_table(app: &App, section: usize, area: Rect) -> SectionTable {
+    let display = app.display_rows(section);
+    let expanded = expanded_row(app, section, &display);
+
+    // Sort indicator
+    let (sort_column, sort_direction) = app.sorts[section];
+    let sort_arrow = match sort_direction {
+        SortDirection::Ascending => "▲",
+        SortDirection::Descending => "▼",
+    };
+
+    let header_col = |name: &str, col: Option<SortColumn>| -> String {
+        if sort_column == col {
+            format!("{}{}", name, sort_arrow)
+        } else {
+            name.to_string()
+        }
+    };
+
+    let header_style = Style::default().fg(theme().header).add_modifier(Modifier::BOLD);
+
+    // Build header based on hide_positions state
+    let header = if app.hide_positions {
+        let mut cols = vec![
+            tr("Symbol").to_string(),
+            tr("Name").to_string(),
+            header_col(tr("Price"), Some(SortColumn::Price)),
+            header_col(tr("Change"), Some(SortColumn::Change)),
+        ];
+        if app.view_combined {
+            cols.push(tr("Portfolio").to_string());
+        }
+        Row::new(cols).style(header_style).height(1)
+    } else {
+        let mut cols = vec![
+            tr("Symbol").to_string(),
+            tr("Name").to_string(),
+            header_col(tr("Price"), Some(SortColumn::Price)),
+            header_col(tr("Change"), Some(SortColumn::Change)),
+            header_col(tr("Qty"), Some(SortColumn::Quantity)),
+            tr("Cost").to_string(),
+            header_col(tr("Gain"), Some(SortColumn::Gain)),
+            header_col(tr("Gain %"), Some(SortColumn::GainPercent)),
+        ];
+        cols.extend(app.custom_columns.iter().enumerate().map(|(i, c)| header_col(&c.name, Some(SortColumn::Custom(i)))));
+        if app.view_combined {
+            cols.push(tr("Portfolio").to_string());
+        }
+        Row::new(cols)
+            .style(header_style)
+            .height(1)
+    };
+
+    // Section titles with market totals: TWD for Taiwan, USD for US and crypto
+    let title = |section: usize| -> Line<'static> {
+        let base = tr(match (section, app.view_combined, app.fx_neutral) {
+            (0, true, _) => "Taiwan Stocks (All)",
+            (0, false, _) => "Taiwan Stocks",
+            (1, true, true) => "US Stocks (All, FX-neutral USD gains)",
+            (1, true, false) => "US Stocks (All)",
+            (1, false, true) => "US Stocks (FX-neutral USD gains)",
+            (1, false, false) => "US Stocks",
+            (_, true, _) => "Crypto (All)",
+            (_, false, _) => "Crypto",
+        });
+        if app.hide_positions {
+            return Line::from(base);
+        }
+        let (value, gain, gain_pct) = app.calculate_section_summary(section);
+        let (currency, decimals) = if section == 0 { ("TWD", 0) } else { ("USD", 2) };
+        let gain_display = if app.show_gain_amount {
+            format!("{:+.*} {}", decimals, gain, currency)
+        } else {
+            format!("{:+.2}%", gain_pct)
+        };
+        Line::from(vec![
+            Span::raw(format!("{} ", base)),
+            Span::styled(format!("{:.*} {} ", decimals, value, currency), Style::default().fg(Color::White)),
+            Span::styled(gain_display, Style::default().fg(if gain >= 0.0 { theme().gain } else { theme().loss })),
+        ])
+    };
+    // Names are cut to the (possibly resized) Name column, leaving a little gap
+    let name_width = column_layout(app.view_combined, app.hide_positions, &app.custom_columns, &app.column_widths)
+        .iter()
+        .find(|(name, _)| name == "Name")
+        .map_or(10, |(_, width)| *width as usize)
+        .saturating_sub(2);
+    // Rows marked for batch operations get a navy background
+    let to_row = |s: &Stock| {
+        let tick = app.price_ticks.get(&s.symbol).map(|(up, _)| *up);
+        let row = stock_to_row(s, tick, app.usd_twd_rate, app.view_combined, app.hide_positions, app.fx_neutral, &app.custom_columns, &app.config, name_width);
+        if app.marked.contains(&s.symbol) { row.style(Style::default().bg(theme().marked)) } else { row }
+    };
+    // Group headers summarise the holdings listed under them
+    let build_rows = |stocks: &[Stock], display: &[DisplayRow], expanded: Option<usize>| -> Vec<Row<'static>> {
+        display.iter().enumerate().map(|(row, r)| match r {
+            DisplayRow::Stock(i) if expanded == Some(row) => to_row(&stocks[*i]).height(1 + EXPANDED_ROW_LINES),
+            DisplayRow::Stock(i) => to_row(&stocks[*i]),
+            DisplayRow::Group(name) => {
+                let members: Vec<&Stock> = stocks.iter().filter(|s| &s.portfolio_name == name && app.in_totals(s)).collect();
+                group_to_row(name, &members, app.collapsed_groups.contains(name), app.hide_positions, app.usd_twd_rate, app.fx_neutral, app.custom_columns.len(), &app.config.gradient)
+            }
+            DisplayRow::Subtotal(group) => {
+                let members: Vec<&Stock> = stocks.iter()
+                    .filter(|s| group.as_ref().is_none_or(|g| *g == s.portfolio_name) && app.in_totals(s))
+                    .collect();
+                let mut cells = vec![
+                    Cell::from(format!("Σ {}", group.as_deref().unwrap_or(tr("Total")))).style(Style::default().fg(theme().header).add_modifier(Modifier::BOLD)),
+                    Cell::from(format!("{} {}", members.len(), tr("holdings"))).style(Style::default().fg(theme().muted)),
+                ];
+                cells.extend(subtotal_cells(&members, app.usd_twd_rate, app.fx_neutral, app.custom_columns.len(), &app.config.gradient));
+                Row::new(cells)
+            }
+        }).collect()
+    };
+    let stocks = app.section_stocks(section);
+    let rows = build_rows(stocks, &display, expanded.map(|(row, _)| row));
+    let widths = get_widths(app.view_combined, app.hide_positions, &app.custom_columns, &app.column_widths);
+    let border_style = if app.active_section == section { Style::default().fg(theme().border) } else { Style::default() };
+    // With pinned totals the table leaves its bottom border to the footer
+    let borders = if totals_footer(app, section, area).1.is_some() { Borders::TOP | Borders::LEFT | Borders::RIGHT } else { Borders::ALL };
+    let table = Table::new(rows, widths.clone())
+        .header(header)
+        .block(Block::default().borders(borders).title(title(section)).border_style(border_style))
+        .row_highlight_style(Style::default().bg(theme().highlight));
+    let totals = Table::new(build_rows(stocks, &[DisplayRow::Subtotal(None)], None), widths)
+        .block(Block::default().borders(Borders::LEFT | Borders::RIGHT | Borders::BOTTOM).border_style(border_style))
+        .style(Style::default().add_modifier(Modifier::BOLD));
+    SectionTable { generation: app.render_generation, area, active: app.active_section == section, display, expanded, table, totals }
+}
+
+/// Display row expanded in place with `i` and its stock index, in the active section only
+fn expanded_row(app: &App, section: usize, display: &[DisplayRow]) -> Option<(usize, usize)> {
+    app.table_state(section).selected()
+        .filter(|_| app.expand_selected && app.active_section == section)
+        .and_then(|row| match display.get(row) {
+            Some(DisplayRow::Stock(i)) => Some((row, *i)),
+            _ => None,
+        })
+}
+
+/// Split a section's table area into the scrolling rows and, when positions are shown, a
+/// footer pinning the table's totals row above the bottom border whatever the scroll position
+fn totals_footer(app: &App, section: usize, area: Rect) -> (Rect, Option<Rect>) {
+    if app.hide_positions || app.section_stocks(section).is_empty() || area.height < 5 {
+        return (area, None);
+    }
+    let rows = Rect { height: area.height - 2, ..area };
+    (rows, Some(Rect { y: area.y + area.height - 2, height: 2, ..area }))
+}
+
+/// Extra lines drawn under the selected row while it is expanded with `i`
+const EXPANDED_ROW_LINES: u16 = 2;
+
+/// Fill the blank lines of an expanded row (`visible_row` rows below the table's first
+/// row) with cost, day gain, 52-week range and the holding's note
+fn render_expanded_row(f: &mut Frame, app: &App, stock: &Stock, table: Rect, visible_row: usize) {
+    let y = table.y + 2 + visible_row as u16 + 1; // border + header, then the row's own line
+    if y + EXPANDED_ROW_LINES >= table.y + table.height {
+        return; // Scrolled to the bottom edge; the table clips the row anyway
+    }
+    let dim = Style::default().fg(theme().dim);
+    let currency = if stock.is_tw() { "TWD" } else { "USD" };
+    let mut first = vec![Span::raw("  ")];
+    if !app.hide_positions {
+        first.push(Span::styled(format!("Cost {:.2} {} (avg {:.2})", stock.cost_local(), currency, stock.cost_basis), dim));
+        if let Some(day) = stock.price_data.as_ref().map(|d| d.change * stock.quantity * stock.multiplier()) {
+            let color = if day >= 0.0 { theme().gain } else { theme().loss };
+            first.push(Span::styled("  ·  Day ", dim));
+            first.push(Span::styled(format!("{:+.2} {}", day, currency), Style::default().fg(color)));
+        }
+    }
+    if let Some(days) = stock.held_days() {
+        first.push(Span::styled(format!("  ·  Held {}d", days), dim));
+    }
+    if stock.lots > 1 {
+        first.push(Span::styled(format!("  ·  {} lots", stock.lots), dim));
+    }
+    let mut second = vec![Span::raw("  ")];
+    match stock.price_data.as_ref().filter(|d| d.year_high.is_some() && d.year_low.is_some()) {
+        Some(data) => {
+            let pos = data.year_position().unwrap_or(0.5) * 100.0;
+            second.push(Span::styled(format!("52w {:.2} ", data.year_low.unwrap_or(0.0)), dim));
+            second.push(Span::styled(gauge_bar(pos), Style::default().fg(theme().border)));
+            second.push(Span::styled(format!(" {:.2} ({:.0}%)", data.year_high.unwrap_or(0.0), pos), dim));
+        }
+        None => second.push(Span::styled("52w --", dim)),
+    }
+    if let Some(target) = stock.target {
+        let progress = stock.price_data.as_ref().map_or("--".to_string(), |d| format!("{:.0}%", d.price / target * 100.0));
+        second.push(Span::styled(format!("  ·  Target {:.2} ({})", target, progress), dim));
+    }
+    if let Some(note) = &stock.note {
+        second.push(Span::styled(format!("  ·  {}", note), Style::default().fg(theme().header)));
+    }
+    let area = Rect::new(table.x + 1, y, table.width.saturating_sub(2), EXPANDED_ROW_LINES);
+    f.render_widget(Paragraph::new(vec![Line::from(first), Line::from(second)]).style(Style::default().bg(theme().highlight)), area);
+}
+
+/// Table columns for the current view with their widths: the built-in default unless
+/// resized with `<`/`>` (`overrides` is keyed by column name)
+fn column_layout(combined: bool, hide_positions: bool, custom: &[CustomColumn], overrides: &HashMap<String, u16>) -> Vec<(String, u16)> {
+    let mut columns: Vec<(String, u16)> = if hide_positions {
+        vec![
+            ("Symbol".to_string(), 10),
+            ("Name".to_string(), 16),
+            ("Price".to_string(), 12),
+            ("Change".to_string(), 10),
+        ]
+    } else {
+        let mut columns = vec![
+            ("Symbol".to_string(), 8),
+            ("Name".to_string(), if combined { 10 } else { 12 }),
+            ("Price".to_string(), 10),
+            ("Change".to_string(), 9),
+            ("Qty".to_string(), 8),
+            ("Cost".to_string(), 8),
+            ("Gain".to_string(), 12),
+            ("Gain %".to_string(), 8),
+        ];
+        columns.extend(custom.iter().map(|c| {
+            let min = match (c.gauge, c.percent) {
+                _ if c.sparkline => SPARKLINE_DAYS + 7, // Closes, space and up to "+99.9%"
+                (true, true) => GAUGE_WIDTH + 5, // Bar, space and up to "999%"
+                (true, false) => GAUGE_WIDTH,
+                _ => 8,
+            };
+            (c.name.clone(), c.name.width().max(min) as u16 + 1)
+        }));
+        columns
+    };
+    if combined {
+        columns.push(("Portfolio".to_string(), if hide_positions { 12 } else { 10 }));
+    }
+    for (name, width) in columns.iter_mut() {
+        if let Some(&resized) = overrides.get(name.as_str()) {
+            *width = resized;
+        }
+    }
+    columns
+}
+
+fn get_widths(combined: bool, hide_positions: bool, custom: &[CustomColumn], overrides: &HashMap<String, u16>) -> Vec<Constraint> {
+    column_layout(combined, hide_positions, custom, overrides)
+        .into_iter()
+        .map(|(_, width)| Constraint::Length(width))
+        .collect()
+}
+
+/// TWSE/TPEx tick size at a price level; ETFs (codes starting `00`) tick finer
+fn tw_tick_size(symbol: &str, price: f64) -> f64 {
+    if symbol.starts_with("00") {
+        return if price < 50.0 { 0.01 } else { 0.05 };
+    }
+    match price {
+        p if p < 10.0 => 0.01,
+        p if p < 50.0 => 0.05,
+        p if p < 100.0 => 0.1,
+        p if p < 500.0 => 0.5,
+        p if p < 1000.0 => 1.0,
+        _ => 5.0,
+    }
+}
+
+/// The tick a TW price should be a multiple of, when it isn't one
+fn off_tick(symbol: &str, price: f64) -> Option<f64> {
+    if !symbol.contains(".TW") || price <= 0.0 {
+        return None;
+    }
+    let tick = tw_tick_size(symbol, price);
+    let steps = price / tick;
+    ((steps - steps.round()).abs() > 1e-6).then_some(tick)
+}
+
+/// Truncate to at most `width` terminal columns, counting CJK characters as two;
+/// a cut string ends in `…`
+fn fit_width(text: &str, width: usize) -> String {
+    if text.width() <= width {
+        return text.to_string();
+    }
+    let mut out = String::new();
+    let mut used = 0;
+    for c in text.chars() {
+        let w = c.width().unwrap_or(0);
+        if used + w + 1 > width {
+            break;
+        }
+        out.push(c);
+        used += w;
+    }
+    out.push('…');
+    out
+}
+
+/// SGR parameters selecting `color` as foreground or background; `None` for the terminal default
+fn ansi_color(color: Color, fg: bool) -> Option<String> {
+    let base = if fg { 30 } else { 40 };
+    let named = |n: u8| Some((base + n).to_string());
+    let bright = |n: u8| Some((base + 60 + n).to_string());
+    match color {
+        Color::Reset => None,
+        Color::Black => named(0),
+        Color::Red => named(1),
+        Color::Green => named(2),
+        Color::Yellow => named(3),
+        Color::Blue => named(4),
+        Color::Magenta => named(5),
+        Color::Cyan => named(6),
+        Color::Gray => named(7),
+        Color::DarkGray => bright(0),
+        Color::LightRed => bright(1),
+        Color::LightGreen => bright(2),
+        Color::LightYellow => bright(3),
+        Color::LightBlue => bright(4),
+        Color::LightMagenta => bright(5),
+        Color::LightCyan => bright(6),
+        Color::White => bright(7),
+        Color::Rgb(r, g, b) => Some(format!("{};2;{};{};{}", base + 8, r, g, b)),
+        Color::Indexed(i) => Some(format!("{};5;{}", base + 8, i)),
+    }
+}
+
+/// Rendered frame as text, one line per row with trailing blanks trimmed; `ansi` keeps
+/// colors and bold/underline as escape sequences
+fn buffer_to_text(buffer: &Buffer, ansi: bool) -> String {
+    let area = buffer.area;
+    let mut out = String::new();
+    for y in area.top()..area.bottom() {
+        let mut line = String::new();
+        let mut style = None;
+        let mut to_skip = 0;
+        for x in area.left()..area.right() {
+            let cell = &buffer[(x, y)];
+            // The cells under a wide (CJK) character are placeholders
+            if to_skip > 0 {
+                to_skip -= 1;
+                continue;
+            }
+            to_skip = cell.symbol().width().saturating_sub(1);
+            if ansi && style != Some((cell.fg, cell.bg, cell.modifier)) {
+                style = Some((cell.fg, cell.bg, cell.modifier));
+                let mut params = vec!["0".to_string()];
+                for (modifier, code) in [(Modifier::BOLD, "1"), (Modifier::DIM, "2"), (Modifier::ITALIC, "3"), (Modifier::UNDERLINED, "4"), (Modifier::REVERSED, "7")] {
+                    if cell.modifier.contains(modifier) {
+                        params.push(code.to_string());
+                    }
+                }
+                params.extend(ansi_color(cell.fg, true));
+                params.extend(ansi_color(cell.bg, false));
+                line.push_str(&format!("\x1b[{}m", params.join(";")));
+            }
+            line.push_str(cell.symbol());
+        }
+        if ansi {
+            line.push_str("\x1b[0m");
+        } else {
+            line.truncate(line.trim_end().len());
+        }
+        out.push_str(&line);
+        out.push('\n');
+    }
+    out
+}
+
+/// Write the frame as `stock-tui_<timestamp>.txt` (plain) and `.ans` (with colors)
+/// in the working directory; returns the plain-text path
+fn write_screen_snapshot(buffer: &Buffer) -> Result<PathBuf> {
+    let path = std::env::current_dir()?.join(format!("stock-tui_{}.txt", Local::now().format("%Y%m%d_%H%M%S")));
+    fs::write(&path, buffer_to_text(buffer, false))?;
+    fs::write(path.with_extension("ans"), buffer_to_text(buffer, true))?;
+    Ok(path)
+}
+
+/// Decimals for quotes that aren't share prices: indices in whole points (TAIEX 22345)
+/// unless small (VIX 14.52), FX pairs to the pip (USD/TWD 32.415, EUR/USD 1.0842)
+fn instrument_decimals(symbol: &str, price: f64) -> Option<usize> {
+    let fx_pair = symbol.strip_suffix("=X")
+        .is_some_and(|pair| pair.len() == 6 && !METAL_PREFIXES.iter().any(|p| pair.starts_with(p)));
+    if symbol.starts_with('^') {
+        Some(if price >= 1000.0 { 0 } else { 2 })
+    } else if fx_pair {
+        Some(if price < 10.0 { 4 } else { 3 })
+    } else {
+        None
+    }
+}
+
+fn price_decimals(stock: &Stock, price: f64) -> usize {
+    if let Some(decimals) = instrument_decimals(&stock.symbol, price) {
+        decimals
+    } else if stock.deposit.is_some() {
+        4
+    } else if stock.is_future() && price < 10.0 {
+        3
+    } else if stock.is_tw() && stock.asset_class() == AssetClass::Equity {
+        // Show as many decimals as the tick has: 0.01/0.05 → 2, 0.1/0.5 → 1, 1/5 → 0
+        match tw_tick_size(&stock.symbol, price) {
+            t if t < 0.1 => 2,
+            t if t < 1.0 => 1,
+            _ => 0,
+        }
+    } else {
+        2
+    }
+}
+
+/// Price cell, flashed with the move's color and a ▲/▼ marker while `tick` is set
+fn price_cell(price: String, color: Color, tick: Option<bool>) -> Cell<'static> {
+    match tick {
+        Some(up) => {
+            let (marker, bg) = if up { ("▲", theme().gain) } else { ("▼", theme().loss) };
+            Cell::from(Line::from(format!("{}{}", marker, price)).alignment(Alignment::Right))
+                .style(Style::default().fg(Color::Black).bg(bg).add_modifier(Modifier::BOLD))
+        }
+        None => Cell::from(Line::from(price).alignment(Alignment::Right)).style(Style::default().fg(color)),
+    }
+}
+
+/// `tick` is the direction of a price move still being flashed in live mode
+#[allow(clippy::too_many_arguments)]
+fn stock_to_row(stock: &Stock, tick: Option<bool>, usd_twd_rate: f64, show_portfolio: bool, hide_positions: bool, fx_neutral: bool, custom: &[CustomColumn], config: &Config, name_width: usize) -> Row<'static> {
+    let gradient = &config.gradient;
+    let (price, change_pct) = stock.price_data.as_ref()
+        .map(|d| (d.price, d.change_percent))
+        .unwrap_or((0.0, 0.0));
+
+    let arrow = if change_pct >= 0.0 { "↑" } else { "↓" };
+    let color = gradient.color(change_pct, gradient.full);
+
+    // Triggered alerts take precedence; options warn as expiry approaches; thin volume is flagged ◔
+    // and a quote currency the holding isn't valued in ¤
+    let illiquid = config.liquidity_fraction > 0.0
+        && stock.volume_fraction().is_some_and(|f| f > config.liquidity_fraction);
+    let symbol_cell = match &stock.contract {
+        _ if stock.triggered_alert().is_some() => {
+            Cell::from(format!("⚑ {}", stock.display)).style(Style::default().fg(theme().alert).add_modifier(Modifier::BOLD))
+        }
+        _ if illiquid => {
+            Cell::from(format!("◔ {}", stock.display)).style(Style::default().fg(Color::LightRed))
+        }
+        _ if stock.currency_mismatch().is_some() => {
+            Cell::from(format!("¤ {}", stock.display)).style(Style::default().fg(theme().header))
+        }
+        Some(contract) if contract.days_to_expiry() < 0 => {
+            Cell::from(format!("{} EXP", stock.display)).style(Style::default().fg(theme().loss))
+        }
+        Some(contract) if contract.days_to_expiry() <= OPTION_EXPIRY_WARN_DAYS => {
+            Cell::from(format!("{} ⚠{}d", stock.display, contract.days_to_expiry())).style(Style::default().fg(theme().header))
+        }
+        _ => Cell::from(stock.display.clone()),
+    };
+
+    let mut cells = vec![
+        symbol_cell,
+        Cell::from(fit_width(&stock.name, name_width)),
+        // Low-priced commodities (copper, natural gas) need the extra digit, deposits show accrual
+        price_cell(format!("{:.*}", price_decimals(stock, price), price), color, tick),
+        Cell::from(Line::from(format!("{}{:.1}%", arrow, change_pct)).alignment(Alignment::Right)).style(Style::default().fg(color)),
+    ];
+
+    // Only show position columns if not hidden
+    if !hide_positions {
+        let (gain, cost_value) = display_gain(stock, price, usd_twd_rate, fx_neutral);
+        let gain_pct = if cost_value > 0.0 { (gain / cost_value) * 100.0 } else { 0.0 };
+
+        let gain_color = gradient.color(gain_pct, gradient.gain_full);
+        let gain_str = format!("{:+.0}", gain);
+        let gain_pct_str = format!("{:+.1}%", gain_pct);
+
+        cells.push(Cell::from(Line::from(format_quantity(stock.quantity)).alignment(Alignment::Right)));
+        cells.push(Cell::from(Line::from(format!("{:.1}", stock.cost_basis)).alignment(Alignment::Right)));
+        cells.push(Cell::from(Line::from(gain_str).alignment(Alignment::Right)).style(Style::default().fg(gain_color)));
+        cells.push(Cell::from(Line::from(gain_pct_str).alignment(Alignment::Right)).style(Style::default().fg(gain_color)));
+
+        for column in custom {
+            let value = column.expr.eval(stock, usd_twd_rate);
+            if column.gauge {
+                let bar = match value {
+                    Some(v) if column.percent => format!("{} {:.*}%", gauge_bar(v), column.decimals, v),
+                    Some(v) => gauge_bar(v),
+                    None => "--".to_string(),
+                };
+                // A percent gauge turns green once it reaches 100% (a target hit)
+                let color = if column.percent && value.is_some_and(|v| v >= 100.0) { theme().gain } else { theme().border };
+                cells.push(Cell::from(bar).style(Style::default().fg(color)));
+                continue;
+            }
+            if column.sparkline {
+                // `--` until the background history arrives
+                let closes = stock.historical.as_ref().map_or(&[][..], |h| &h.closes[h.closes.len().saturating_sub(SPARKLINE_DAYS)..]);
+                let text = match value {
+                    Some(v) => format!("{} {:+.*}%", sparkline(closes), column.decimals, v),
+                    None => "--".to_string(),
+                };
+                let color = if value.unwrap_or(0.0) >= 0.0 { theme().gain } else { theme().loss };
+                cells.push(Cell::from(Line::from(text).alignment(Alignment::Right)).style(Style::default().fg(color)));
+                continue;
+            }
+            let text = value
+                .map(|v| format!("{:.*}", column.decimals, v))
+                .unwrap_or_else(|| "--".to_string());
+            cells.push(Cell::from(Line::from(text).alignment(Alignment::Right)));
+        }
+    }
+
+    if show_portfolio {
+        cells.push(Cell::from(stock.portfolio_name.clone()).style(Style::default().fg(theme().muted)));
+    }
+
+    Row::new(cells)
+}
+
+/// Whole quantities without decimals; fractional ones (crypto, fractional shares) with up to 4
+fn format_quantity(quantity: f64) -> String {
+    if quantity.fract().abs() < 1e-9 {
+        format!("{:.0}", quantity)
+    } else {
+        let text = format!("{:.4}", quantity);
+        text.trim_end_matches('0').trim_end_matches('.').to_string()
+    }
+}
+
+/// Gain and the cost it is measured against, in the currency the Gain column shows:
+/// TWD for TW, USD for US in the FX-neutral view, otherwise TWD including currency movement
+fn display_gain(stock: &Stock, price: f64, usd_twd_rate: f64, fx_neutral: bool) -> (f64, f64) {
+    if stock.quantity <= 0.0 || stock.cost_basis <= 0.0 {
+        return (0.0, 0.0);
+    }
+    let current_value = stock.quantity * price * stock.multiplier();
+    if stock.is_tw() || fx_neutral {
+        let cost_value = stock.cost_local();
+        (current_value - cost_value, cost_value)
+    } else {
+        let cost_value = stock.cost_twd(usd_twd_rate, false);
+        (current_value * usd_twd_rate - cost_value, cost_value)
+    }
+}
+
+/// Position columns of a subtotal row: value, day gain, total cost, gain and gain %
+fn subtotal_cells(stocks: &[&Stock], usd_twd_rate: f64, fx_neutral: bool, custom_count: usize, gradient: &GradientConfig) -> Vec<Cell<'static>> {
+    let priced: Vec<(&Stock, &PriceData)> = stocks.iter().filter_map(|s| s.price_data.as_ref().map(|d| (*s, d))).collect();
+    let value: f64 = priced.iter().map(|(s, d)| s.quantity * d.price * s.multiplier()).sum();
+    let cost: f64 = priced.iter().map(|(s, _)| s.cost_local()).sum();
+    let day: f64 = priced.iter().map(|(s, d)| s.quantity * d.change * s.multiplier()).sum();
+    let (gain, gain_cost) = priced.iter()
+        .map(|(s, d)| display_gain(s, d.price, usd_twd_rate, fx_neutral))
+        .fold((0.0, 0.0), |(g, c), (sg, sc)| (g + sg, c + sc));
+    let day_pct = if value - day > 0.0 { day / (value - day) * 100.0 } else { 0.0 };
+    let gain_pct = if gain_cost > 0.0 { gain / gain_cost * 100.0 } else { 0.0 };
+    let day_color = gradient.color(day_pct, gradient.full);
+    let gain_color = gradient.color(gain_pct, gradient.gain_full);
+    let bold = Style::default().add_modifier(Modifier::BOLD);
+
+    let mut cells = vec![
+        Cell::from(Line::from(format!("{:.0}", value)).alignment(Alignment::Right)).style(bold),
+        Cell::from(Line::from(format!("{:+.0}", day)).alignment(Alignment::Right)).style(bold.fg(day_color)),
+        Cell::from(""),
+        Cell::from(Line::from(format!("{:.0}", cost)).alignment(Alignment::Right)).style(bold),
+        Cell::from(Line::from(format!("{:+.0}", gain)).alignment(Alignment::Right)).style(bold.fg(gain_color)),
+        Cell::from(Line::from(format!("{:+.1}%", gain_pct)).alignment(Alignment::Right)).style(bold.fg(gain_color)),
+    ];
+    cells.extend((0..custom_count).map(|_| Cell::from("")));
+    cells
+}
+
+/// Portfolio header row for the grouped combined view; a collapsed group (▸) carries its subtotals
+#[allow(clippy::too_many_arguments)]
+fn group_to_row(name: &str, stocks: &[&Stock], collapsed: bool, hide_positions: bool, usd_twd_rate: f64, fx_neutral: bool, custom_count: usize, gradient: &GradientConfig) -> Row<'static> {
+    let mut cells = vec![
+        Cell::from(format!("{} {}", if collapsed { "▸" } else { "▾" }, name)).style(Style::default().fg(theme().border).add_modifier(Modifier::BOLD)),
+        Cell::from(format!("{} {}", stocks.len(), tr("holdings"))).style(Style::default().fg(theme().muted)),
+    ];
+
+    if collapsed && !hide_positions {
+        cells.extend(subtotal_cells(stocks, usd_twd_rate, fx_neutral, custom_count, gradient));
+    }
+
+    Row::new(cells)
+}
+
+fn render_summary(f: &mut Frame, app: &App, area: Rect) {
+    let title = tr(if app.view_combined {
+        " Combined Summary (All Portfolios) "
+    } else {
+        " Summary "
+    });
+
+    let time_config = &app.config.time;
+    let mut time_str = Local::now().format(&time_config.time_format).to_string();
+    for zone in &time_config.clocks {
+        let now = chrono::Utc::now();
+        if let Some((label, offset)) = zone_offset(zone, now) {
+            time_str.push_str(&format!("  {} {}", label, now.with_timezone(&offset).format(&time_config.time_format)));
+        }
+    }
+
+    // Status indicator: refreshing, live mode countdown, or nothing
+    let status_indicator = if app.is_fetching {
+        format!("  |  {}", tr("Refreshing..."))
+    } else if app.live_mode {
+        let elapsed = app.last_live_refresh.elapsed().as_secs();
+        let remaining = app.config.refresh_interval.saturating_sub(elapsed);
+        format!("  |  LIVE ({}s)", remaining)
+    } else if let Some((done, total)) = app.backfill_progress {
+        format!("  |  Backfill {}/{}", done, total)
+    } else {
+        String::new()
+    };
+
+    let status_color = if app.is_fetching { theme().header } else { theme().gain };
+
+    let mut header_spans = vec![
+        Span::styled(format!("{}: {}  |  USD/TWD: {:.3}", tr("Updated"), time_str, app.usd_twd_rate), Style::default().fg(theme().muted)),
+        Span::styled(status_indicator, Style::default().fg(status_color).add_modifier(Modifier::BOLD)),
+    ];
+
+    // Exchange open/closed indicators from the session calendars
+    let now = chrono::Utc::now();
+    for market in [Market::Tw, Market::Us] {
+        let (text, color) = match market.session(now, &app.config.market) {
+            MarketSession::Open { closes } => (format!("  {} ● closes in {}", market.label(), countdown(closes - now)), theme().gain),
+            MarketSession::Closed { opens, holiday: true } => {
+                (format!("  {} ○ holiday, opens in {}", market.label(), countdown(opens - now)), theme().header)
+            }
+            MarketSession::Closed { opens, .. } => (format!("  {} ○ opens in {}", market.label(), countdown(opens - now)), theme().muted),
+        };
+        header_spans.push(Span::styled(text, Style::default().fg(color)));
+    }
+
+    let text = if app.hide_positions {
+        // Show minimal info when positions are hidden
+        vec![
+            Line::from(header_spans),
+            Line::from(""),
+            Line::from(vec![
+                Span::styled(tr("  Positions hidden (press H to show)"), Style::default().fg(theme().header)),
+            ]),
+        ]
+    } else {
+        let (total_cost, total_value, total_gain, total_gain_percent, stock_count, holdings) = app.calculate_summary();
+        let gain_color = if total_gain >= 0.0 { theme().gain } else { theme().loss };
+
+        // One column per configured base currency
+        let in_base_currencies = |twd: f64| -> String {
+            app.config
+                .base_currencies
+                .iter()
+                .map(|currency| match app.convert_twd(twd, currency) {
+                    Some(amount) => format!("{:>15.2} {}", amount, currency),
+                    None => format!("{:>15} {}", "--", currency),
+                })
+                .collect::<Vec<_>>()
+                .join("  ")
+        };
+
+        vec![
+            Line::from(header_spans),
+            Line::from(""),
+            Line::from(format!("{}{}", tr("  Total Cost:   "), in_base_currencies(total_cost))),
+            Line::from({
+                let mut spans = vec![Span::raw(format!("{}{}", tr("  Total Value:  "), in_base_currencies(total_value)))];
+                // Daily totals of this view over the last 30 days, once there are two to compare
+                let values = app.daily_values();
+                if let (Some(first), true) = (values.first(), values.len() >= 2) {
+                    let change = if *first > 0.0 { (total_value / first - 1.0) * 100.0 } else { 0.0 };
+                    let color = if change >= 0.0 { theme().gain } else { theme().loss };
+                    spans.push(Span::styled(format!("   {}d ", values.len()), Style::default().fg(theme().muted)));
+                    spans.push(Span::styled(sparkline(&values), Style::default().fg(theme().border)));
+                    spans.push(Span::styled(format!(" {:+.2}%", change), Style::default().fg(color)));
+                }
+                spans
+            }),
+            Line::from(vec![
+                Span::raw(tr("  Total Gain:   ")),
+                Span::styled(format!("{} ({:+.2}%)", in_base_currencies(total_gain), total_gain_percent), Style::default().fg(gain_color)),
+            ]),
+            Line::from({
+                let (realized, unrealized, total, total_pct) = app.calculate_return_split();
+                let colored = |amount: f64| Style::default().fg(if amount >= 0.0 { theme().gain } else { theme().loss });
+                vec![
+                    Span::raw(tr("  Realized:     ")),
+                    Span::styled(format!("{:+.0} TWD", realized), colored(realized)),
+                    Span::raw(format!("  |  {}: ", tr("Unrealized"))),
+                    Span::styled(format!("{:+.0} TWD", unrealized), colored(unrealized)),
+                    Span::raw(format!("  |  {}: ", tr("Total return"))),
+                    Span::styled(format!("{:+.0} TWD ({:+.2}%)", total, total_pct), colored(total).add_modifier(Modifier::BOLD)),
+                ]
+            }),
+            Line::from({
+                // Period returns less the benchmark's, once its quote has loaded
+                let mut spans = vec![Span::raw(tr("  Alpha:        "))];
+                match &app.benchmark_returns {
+                    Some((symbol, benchmark)) => {
+                        spans.push(Span::styled(format!("vs {}  ", symbol), Style::default().fg(theme().muted)));
+                        let portfolio = app.period_returns();
+                        for (i, label) in ["Day", "MTD", "YTD"].iter().enumerate() {
+                            if i > 0 {
+                                spans.push(Span::raw("  |  "));
+                            }
+                            spans.push(Span::raw(format!("{} ", label)));
+                            match (portfolio[i], benchmark[i]) {
+                                (Some(own), Some(index)) => {
+                                    let alpha = own - index;
+                                    spans.push(Span::styled(format!("{:+.2}%", alpha), Style::default().fg(if alpha >= 0.0 { theme().gain } else { theme().loss })));
+                                    spans.push(Span::styled(format!(" ({:+.2} vs {:+.2})", own, index), Style::default().fg(theme().muted)));
+                                }
+                                _ => spans.push(Span::styled("--", Style::default().fg(theme().muted))),
+                            }
+                        }
+                    }
+                    None => spans.push(Span::styled("--", Style::default().fg(theme().muted))),
+                }
+                spans
+            }),
+            Line::from({
+                let mut counts = if app.fx_neutral {
+                    format!("  {}: {}  |  {}: {}  |  FX-neutral (excl. FX effect {:+.0} TWD)", tr("Stocks"), stock_count, tr("Holdings"), holdings, app.calculate_fx_effect())
+                } else {
+                    format!("  {}: {}  |  {}: {}  |  {}: {:+.0} TWD", tr("Stocks"), stock_count, tr("Holdings"), holdings, tr("FX effect"), app.calculate_fx_effect())
+                };
+                // Crypto subtotal, in USD and converted to TWD
+                if !app.get_active_crypto_stocks().is_empty() {
+                    let (value, _, gain_pct) = app.calculate_section_summary(2);
+                    counts.push_str(&format!(
+                        "  |  {}: {:.2} USD ({:.0} TWD) {:+.2}%",
+                        tr("Crypto"), value, value * app.usd_twd_rate, gain_pct
+                    ));
+                }
+                counts
+            }),
+            Line::from(format!(
+                "{}{}",
+                tr("  Allocation:   "),
+                app.calculate_allocation()
+                    .iter()
+                    .map(|(class, pct)| format!("{} {:.1}%", class.label(), pct))
+                    .collect::<Vec<_>>()
+                    .join("  ")
+            )),
+            Line::from({
+                // Everything outside TWD is converted through USD/TWD, so a 1% move in the
+                // rate moves the TWD value by 1% of the foreign share
+                let exposure = app.calculate_currency_exposure();
+                let mut spans = vec![Span::raw(tr("  Currency:     "))];
+                for (currency, amount, _, pct) in &exposure {
+                    spans.push(Span::raw(format!("{} {:.1}%", currency, pct)));
+                    if currency != "TWD" {
+                        spans.push(Span::styled(format!(" ({:.0})", amount), Style::default().fg(theme().muted)));
+                    }
+                    spans.push(Span::raw("  "));
+                }
+                let foreign: f64 = exposure.iter().filter(|e| e.0 != "TWD").map(|e| e.2).sum();
+                if foreign > 0.0 {
+                    spans.push(Span::styled(
+                        format!("|  USD/TWD ±1% → ±{:.0} TWD", foreign / 100.0),
+                        Style::default().fg(theme().muted),
+                    ));
+                }
+                spans
+            }),
+        ]
+    };
+
+    let paragraph = Paragraph::new(text)
+        .block(Block::default().borders(Borders::ALL).title(title)
+            .title_style(if app.view_combined { Style::default().fg(theme().alert).bold() } else { Style::default() }));
+
+    f.render_widget(paragraph, area);
+}
+
+/// Compact time-until string: "2d 3h", "9h 12m", "35m"
+fn countdown(duration: chrono::Duration) -> String {
+    let minutes = duration.num_minutes().max(0);
+    let (days, hours, mins) = (minutes / 1440, minutes / 60 % 24, minutes % 60);
+    if days > 0 {
+        format!("{}d {}h", days, hours)
+    } else if hours > 0 {
+        format!("{}h {}m", hours, mins)
+    } else {
+        format!("{}m", mins)
+    }
+}
+
+fn render_alert_banner(f: &mut Frame, app: &App, alerts: &[(String, String)], area: Rect) {
+    let line = if alerts.is_empty() {
+        let until = app.alert_state.snoozed_until
+            .and_then(|ts| chrono::DateTime::from_timestamp(ts, 0))
+            .map(|t| t.with_timezone(&Local).format("%H:%M").to_string())
+            .unwrap_or_default();
+        Line::from(format!(" {} {}  |  {}", tr("Alerts snoozed until"), until, tr("z=Change snooze")))
+            .style(Style::default().fg(theme().muted))
+    } else {
+        let list = alerts
+            .iter()
+            .map(|(symbol, rule)| format!("{} {}", symbol, rule))
+            .collect::<Vec<_>>()
+            .join(" · ");
+        Line::from(vec![
+            Span::styled(format!(" ⚑ {}  ", list), Style::default().fg(theme().alert).add_modifier(Modifier::BOLD)),
+            Span::styled(format!("|  {} ", tr("m=Mute  z=Snooze")), Style::default().fg(theme().muted)),
+        ])
+    };
+    f.render_widget(Paragraph::new(line), area);
+}
+
+fn render_alert_popup(f: &mut Frame, popup: &AlertPopup, pending: usize) {
+    let area = centered_rect(40, 30, f.area());
+    f.render_widget(Clear, area);
+
+    let mut lines = vec![
+        Line::from(""),
+        Line::from(format!("  {}", popup.symbol)).style(Style::default().add_modifier(Modifier::BOLD)),
+        Line::from(""),
+        Line::from(format!("  {} {}", tr("Rule: "), popup.rule)),
+        Line::from(format!("  {} {:.2}", tr("Price:"), popup.price)),
+        Line::from(format!("  {} {}", tr("Time: "), popup.time.format("%H:%M:%S"))),
+        Line::from(""),
+    ];
+    if pending > 1 {
+        lines.push(Line::from(format!("  +{} {}", pending - 1, tr("more pending"))).style(Style::default().fg(theme().header)));
+    }
+    lines.push(Line::from(tr("  Enter=Dismiss, m=Mute, z=Snooze all")).style(Style::default().fg(theme().muted)));
+
+    let paragraph = Paragraph::new(lines)
+        .block(Block::default().borders(Borders::ALL).title(tr(" ⚑ Alert ")).border_style(Style::default().fg(theme().alert)));
+
+    f.render_widget(paragraph, area);
+}
+
+/// Footer items a config `footer` list can name, in the default order
+const FOOTER_ITEMS: [&str; 24] = [
+    "portfolio", "nav", "detail", "sort", "add", "edit", "delete", "hide", "title", "live", "refresh", "quit",
+    "changes", "news", "archive", "dca", "exposure", "fx", "expand", "lots", "replay", "equity", "allocation",
+    "filter",
+];
+/// Items shown when the config has no `footer` list
+const DEFAULT_FOOTER: [&str; 12] = ["portfolio", "nav", "detail", "sort", "add", "edit", "delete", "hide", "title", "live", "refresh", "quit"];
+
+/// A footer shortcut: its label and the key a click on it replays (None for plain hints)
+struct FooterItem {
+    label: String,
+    key: Option<KeyCode>,
+    style: Style,
+}
+
+/// The footer item called `name` in `FOOTER_ITEMS`, labelled for the current state
+fn footer_item(app: &App, name: &str) -> Option<FooterItem> {
+    let (label, key): (&str, Option<KeyCode>) = match name {
+        "portfolio" => (tr("0-9=Portfolio"), None),
+        "nav" => (tr("↑↓jk=Nav"), None),
+        "detail" => (tr("Enter=Detail"), Some(KeyCode::Enter)),
+        "sort" => (tr("Sort:pcygG"), Some(KeyCode::Char('S'))),
+        "add" => (tr("a=Add"), Some(KeyCode::Char('a'))),
+        "edit" => (tr("e=Edit"), Some(KeyCode::Char('e'))),
+        "delete" => (tr("d=Del"), Some(KeyCode::Char('d'))),
+        "hide" => (tr(if app.hide_positions { "H=Show" } else { "H=Hide" }), Some(KeyCode::Char('H'))),
+        "title" => (if app.show_gain_amount { "T=$" } else { "T=%" }, Some(KeyCode::Char('T'))),
+        "live" => (tr(if app.live_mode { "L=Live:ON" } else { "L=Live" }), Some(KeyCode::Char('L'))),
+        "refresh" => (tr("r=Refresh"), Some(KeyCode::Char('r'))),
+        "quit" => (tr("q=Quit"), Some(KeyCode::Char('q'))),
+        "changes" => (tr("U=Changes"), Some(KeyCode::Char('U'))),
+        "news" => (tr("N=News"), Some(KeyCode::Char('N'))),
+        "archive" => (tr("O=Closed"), Some(KeyCode::Char('O'))),
+        "dca" => (tr("D=DCA"), Some(KeyCode::Char('D'))),
+        "exposure" => (tr("E=Exposure"), Some(KeyCode::Char('E'))),
+        "fx" => (tr(if app.fx_neutral { "F=FX:neutral" } else { "F=FX" }), Some(KeyCode::Char('F'))),
+        "expand" => (tr("i=Expand"), Some(KeyCode::Char('i'))),
+        "lots" => (tr(if app.show_lots { "K=Lots:ON" } else { "K=Lots" }), Some(KeyCode::Char('K'))),
+        "replay" => (tr("I=Replay"), Some(KeyCode::Char('I'))),
+        "equity" => (tr("v=Equity"), Some(KeyCode::Char('v'))),
+        "allocation" => (tr("B=Allocation"), Some(KeyCode::Char('B'))),
+        "filter" => (tr("/=Filter"), Some(KeyCode::Char('/'))),
+        _ => return None,
+    };
+    let style = if name == "live" && app.live_mode {
+        Style::default().fg(theme().gain).add_modifier(Modifier::BOLD)
+    } else {
+        Style::default().fg(theme().header)
+    };
+    Some(FooterItem { label: label.to_string(), key, style })
+}
+
+fn render_footer(f: &mut Frame, app: &mut App, area: Rect) {
+    let names: Vec<&str> = if app.config.footer.is_empty() {
+        DEFAULT_FOOTER.to_vec()
+    } else {
+        app.config.footer.iter().map(String::as_str).collect()
+    };
+    let items: Vec<FooterItem> = names.iter().filter_map(|name| footer_item(app, name)).collect();
+
+    // Lay the items out once, recording each clickable label's area as it goes
+    // (in terminal columns, since CJK labels are double width)
+    let separator = " | ";
+    let mut spans = vec![Span::raw(" ")];
+    let mut x = area.x + 1;
+    for (i, item) in items.into_iter().enumerate() {
+        if i > 0 {
+            spans.push(Span::styled(separator, Style::default().fg(theme().header)));
+            x += separator.width() as u16;
+        }
+        let width = item.label.width() as u16;
+        if let Some(key) = item.key {
+            app.clickable_regions.footer_buttons.push((Rect::new(x, area.y, width, 1), key));
+        }
+        spans.push(Span::styled(item.label, item.style));
+        x += width;
+    }
+
+    let mut lines = vec![Line::from(spans)];
+    if let InputMode::Find(query) = &app.input_mode {
+        lines.push(Line::from(format!(" {} {}█  ({})", tr("Find:"), query, tr("Enter/Esc=Done"))).style(Style::default().fg(theme().header)));
+    } else if matches!(app.input_mode, InputMode::Filter) {
+        let (shown, total) = app.filter_counts();
+        lines.push(Line::from(format!(" {} {}█  {}/{} {}  ({})", tr("Filter:"), app.filter, shown, total, tr("shown"), tr("Enter=Keep, Esc=Clear"))).style(Style::default().fg(theme().header)));
+    } else if let Some((message, _)) = app.status_message.as_ref().filter(|(_, t)| t.elapsed().as_secs() < STATUS_MESSAGE_SECS) {
+        lines.push(Line::from(format!(" {}", message)).style(Style::default().fg(theme().border)));
+    } else if !app.marked.is_empty() {
+        lines.push(
+            Line::from(format!(" {} {}  |  {}", app.marked.len(), tr("marked"), tr("d=Delete M=Move t=Tag Space=Toggle V=Range Esc=Clear")))
+                .style(Style::default().fg(Color::LightBlue)),
+        );
+    } else if !app.filter.is_empty() {
+        let (shown, total) = app.filter_counts();
+        lines.push(Line::from(format!(" {} {}  |  {}/{} {}  {}", tr("Filter:"), app.filter, shown, total, tr("shown"), tr("/=Edit Esc=Clear"))).style(Style::default().fg(theme().header)));
+    }
+
+    let paragraph = Paragraph::new(lines);
+    f.render_widget(paragraph, area);
+}
+
+fn render_add_dialog(f: &mut Frame, state: &AddStockState) {
+    let area = centered_rect(50, 50, f.area());
+    f.render_widget(Clear, area);
+
+    let prompts = ["Symbol:", "Display name:", "Description:", "Quantity:", "Cost basis:"].map(tr);
+    let values = [&state.symbol, &state.display, &state.name, &state.quantity, &state.cost_basis];
+
+    let mut lines: Vec<Line> = vec![Line::from(""), Line::from(tr("  Taiwan stocks auto-detected (e.g., 2330 → 2330.TW)")), Line::from("")];
+
+    for (i, (prompt, value)) in prompts.iter().zip(values.iter()).enumerate() {
+        let style = if i == state.step {
+            Style::default().fg(theme().header).bold()
+        } else if i < state.step {
+            Style::default().fg(theme().gain)
+        } else {
+            Style::default().fg(theme().muted)
+        };
+
+        let cursor = if i == state.step { "█" } else { "" };
+        lines.push(Line::from(vec![
+            Span::styled(format!("  {} ", prompt), style),
+            Span::styled(format!("{}{}", value, cursor), style),
+        ]));
+    }
+
+    lines.push(Line::from(""));
+    // Average costs may legitimately fall between ticks (fees, several lots), so only warn
+    let mut symbol = state.symbol.trim().to_uppercase();
+    if symbol.chars().all(|c| c.is_ascii_digit()) && !symbol.is_empty() {
+        symbol.push_str(".TW");
+    }
+    if let Some(tick) = state.cost_basis.parse().ok().and_then(|cost| off_tick(&symbol, cost)) {
+        lines.push(Line::from(format!("  {} {} ({})", tr("Cost is off the tick of"), tick, tr("fine for an average cost"))).style(Style::default().fg(theme().header)));
+    }
+    lines.push(Line::from(tr("  Press Enter to continue, Esc to cancel")).style(Style::default().fg(theme().muted)));
+
+    let paragraph = Paragraph::new(lines)
+        .block(Block::default().borders(Borders::ALL).title(tr(" Add Stock ")).border_style(Style::default().fg(theme().header)));
+
+    f.render_widget(paragraph, area);
+}
+
+fn render_edit_dialog(f: &mut Frame, state: &EditStockState) {
+    let area = centered_rect(40, 30, f.area());
+    f.render_widget(Clear, area);
+
+    let (qty_style, cost_style) = if state.step == 0 {
+        (Style::default().fg(theme().header), Style::default())
+    } else {
+        (Style::default(), Style::default().fg(theme().header))
+    };
+
+    let qty_cursor = if state.step == 0 { "█" } else { "" };
+    let cost_cursor = if state.step == 1 { "█" } else { "" };
+
+    let lines = vec![
+        Line::from(""),
+        Line::from(format!("  {} {}", tr("Editing:"), state.symbol)),
+        Line::from(""),
+        Line::from(vec![
+            Span::raw(format!("  {} ", tr("Quantity:"))),
+            Span::styled(format!("{}{}", state.quantity, qty_cursor), qty_style),
+        ]),
+        Line::from(""),
+        Line::from(vec![
+            Span::raw(format!("  {} ", tr("Cost basis:"))),
+            Span::styled(format!("{}{}", state.cost_basis, cost_cursor), cost_style),
+        ]),
+        Line::from(""),
+        Line::from(tr("  Tab=Switch, Enter=Save, Esc=Cancel")).style(Style::default().fg(theme().muted)),
+    ];
+
+    let paragraph = Paragraph::new(lines)
+        .block(Block::default().borders(Borders::ALL).title(tr(" Edit Holdings ")).border_style(Style::default().fg(theme().border)));
+
+    f.render_widget(paragraph, area);
+}
+
+fn render_sort_menu(f: &mut Frame, app: &App, selected: usize) {
+    let area = centered_rect(40, 50, f.area());
+    f.render_widget(Clear, area);
+
+    let entries = app.sort_menu_entries();
+    let selected = selected.min(entries.len() - 1);
+    let (sort_column, sort_direction) = app.sorts[app.active_section];
+    let arrow = match sort_direction {
+        SortDirection::Ascending => "▲",
+        SortDirection::Descending => "▼",
+    };
+
+    let mut lines = vec![Line::from("")];
+    for (i, (column, label)) in entries.iter().enumerate() {
+        let marker = if sort_column == Some(*column) { arrow } else { " " };
+        let style = if i == selected {
+            Style::default().fg(Color::Black).bg(theme().border)
+        } else if sort_column == Some(*column) {
+            Style::default().fg(theme().header)
+        } else {
+            Style::default()
+        };
+        lines.push(Line::from(format!(" {} {}", marker, label)).style(style));
+    }
+    lines.push(Line::from(""));
+    lines.push(Line::from(tr("  Enter=Sort (again to reverse), Esc=Close")).style(Style::default().fg(theme().muted)));
+
+    let paragraph = Paragraph::new(lines)
+        .block(Block::default().borders(Borders::ALL).title(format!(" {} ", tr(["Sort TW by", "Sort US by", "Sort crypto by"][app.active_section.min(2)]))).border_style(Style::default().fg(theme().border)));
+
+    f.render_widget(paragraph, area);
+}
+
+fn render_palette(f: &mut Frame, app: &App, state: &PaletteState) {
+    let area = centered_rect(60, 60, f.area());
+    f.render_widget(Clear, area);
+
+    let matches = palette_matches(app, &state.query);
+    let selected = state.selected.min(matches.len().saturating_sub(1));
+    let visible = area.height.saturating_sub(5) as usize;
+    let offset = selected.saturating_sub(visible.saturating_sub(1));
+
+    let mut lines = vec![
+        Line::from(vec![
+            Span::raw(" > "),
+            Span::styled(format!("{}█", state.query), Style::default().fg(theme().header)),
+        ]),
+        Line::from(""),
+    ];
+    for (i, (label, _)) in matches.iter().enumerate().skip(offset).take(visible) {
+        let style = if i == selected {
+            Style::default().fg(Color::Black).bg(theme().border)
+        } else {
+            Style::default()
+        };
+        lines.push(Line::from(format!("  {}", label)).style(style));
+    }
+    if matches.is_empty() {
+        lines.push(Line::from(tr("  No matching commands")).style(Style::default().fg(theme().muted)));
+    }
+
+    let paragraph = Paragraph::new(lines)
+        .block(Block::default().borders(Borders::ALL).title(tr(" Commands (↑↓ Select, Enter=Run, Esc=Close) ")).border_style(Style::default().fg(theme().border)));
+
+    f.render_widget(paragraph, area);
+}
+
+fn render_alert_dialog(f: &mut Frame, state: &AlertEditState) {
+    let area = centered_rect(40, 30, f.area());
+    f.render_widget(Clear, area);
+
+    let field = |label: &str, value: &str, active: bool| {
+        Line::from(vec![
+            Span::raw(format!("  {} ", label)),
+            Span::styled(
+                format!("{}{}", value, if active { "█" } else { "" }),
+                if active { Style::default().fg(theme().header) } else { Style::default() },
+            ),
+        ])
+    };
+
+    let lines = vec![
+        Line::from(""),
+        Line::from(format!("  {} {}", tr("Alerts for:"), state.symbol)),
+        Line::from(""),
+        field(tr("Alert above:"), &state.above, state.step == 0),
+        Line::from(""),
+        field(tr("Alert below:"), &state.below, state.step == 1),
+        Line::from(""),
+        Line::from(tr("  Tab=Switch, Enter=Save, Esc=Cancel (empty = off)")).style(Style::default().fg(theme().muted)),
+    ];
+
+    let paragraph = Paragraph::new(lines)
+        .block(Block::default().borders(Borders::ALL).title(tr(" Price Alerts ")).border_style(Style::default().fg(theme().border)));
+
+    f.render_widget(paragraph, area);
+}
+
+fn render_trade_dialog(f: &mut Frame, app: &App, state: &TradeState) {
+    let area = centered_rect(45, 45, f.area());
+    f.render_widget(Clear, area);
+
+    let is_sell = state.side == TradeSide::Sell;
+    let prompts = ["Shares:", "Price:", "Fees:", "Date:"].map(tr);
+    let values = [&state.shares, &state.price, &state.fees, &state.date];
+
+    let mut lines: Vec<Line> = vec![
+        Line::from(""),
+        Line::from(format!("  {} {}", tr(if is_sell { "Selling:" } else { "Buying more:" }), state.symbol)),
+        Line::from(""),
+    ];
+
+    for (i, (prompt, value)) in prompts.iter().zip(values.iter()).enumerate() {
+        let style = if i == state.step { Style::default().fg(theme().header) } else { Style::default() };
+        let cursor = if i == state.step { "█" } else { "" };
+        lines.push(Line::from(vec![
+            Span::raw(format!("  {} ", prompt)),
+            Span::styled(format!("{}{}", value, cursor), style),
+        ]));
+    }
+
+    // Preview the resulting position
+    let current = app.get_selected_stock().filter(|s| s.symbol == state.symbol);
+    let shares: f64 = state.shares.parse().unwrap_or(0.0);
+    let price: f64 = state.price.parse().unwrap_or(0.0);
+    let fees: f64 = state.fees.parse().unwrap_or(0.0);
+    let preview = current.map(|s| {
+        if is_sell {
+            let sold = shares.min(s.quantity);
+            let realized = (price - s.cost_basis) * sold * s.multiplier() - fees;
+            let remaining = s.quantity - sold;
+            let after = if remaining <= 0.0 { tr("closed → archive").to_string() } else { format!("{:.0} {}", remaining, tr("left")) };
+            format!("  {} {:+.2}  ({})", tr("Realized:"), realized, after)
+        } else {
+            let new_qty = s.quantity + shares;
+            let new_cost = if new_qty > 0.0 { (s.quantity * s.cost_basis + shares * price + fees / s.multiplier()) / new_qty } else { 0.0 };
+            format!("  {:.0} @ {:.2}  →  {:.0} @ {:.2}", s.quantity, s.cost_basis, new_qty, new_cost)
+        }
+    }).unwrap_or_default();
+
+    lines.push(Line::from(""));
+    if let Some(tick) = off_tick(&state.symbol, price) {
+        lines.push(Line::from(format!("  {} {}", tr("Price must be a multiple of the tick"), tick)).style(Style::default().fg(theme().loss)));
+    }
+    lines.push(Line::from(preview).style(Style::default().fg(theme().border)));
+    lines.push(Line::from(""));
+    lines.push(Line::from(tr("  Tab=Switch, Enter=Next/Confirm, Esc=Cancel")).style(Style::default().fg(theme().muted)));
+
+    let (title, color) = if is_sell { (tr(" Sell "), theme().loss) } else { (tr(" Buy More "), theme().gain) };
+    let paragraph = Paragraph::new(lines)
+        .block(Block::default().borders(Borders::ALL).title(title).border_style(Style::default().fg(color)));
+
+    f.render_widget(paragraph, area);
+}
+
+fn render_delete_dialog(f: &mut Frame, symbol: &str) {
+    let area = centered_rect(40, 20, f.area());
+    f.render_widget(Clear, area);
+
+    let lines = vec![
+        Line::from(""),
+        Line::from(format!("  {} {}?", tr("Delete"), symbol)),
+        Line::from(""),
+        Line::from(tr("  Y=Delete, A=Archive (keep in closed positions)")).style(Style::default().fg(theme().muted)),
+        Line::from(tr("  Any other key cancels")).style(Style::default().fg(theme().muted)),
+    ];
+
+    let paragraph = Paragraph::new(lines)
+        .block(Block::default().borders(Borders::ALL).title(tr(" Confirm Delete ")).border_style(Style::default().fg(theme().loss)));
+
+    f.render_widget(paragraph, area);
+}
+
+fn render_prompt_dialog(f: &mut Frame, title: &str, prompt: &str, value: &str) {
+    let area = centered_rect(50, 20, f.area());
+    f.render_widget(Clear, area);
+
+    let lines = vec![
+        Line::from(""),
+        Line::from(format!("  {}", prompt)),
+        Line::from(""),
+        Line::from(vec![
+            Span::raw("  "),
+            Span::styled(format!("{}█", value), Style::default().fg(theme().header)),
+        ]),
+        Line::from(""),
+        Line::from(tr("  Enter=Apply, Esc=Cancel")).style(Style::default().fg(theme().muted)),
+    ];
+
+    let paragraph = Paragraph::new(lines)
+        .block(Block::default().borders(Borders::ALL).title(title.to_string()).border_style(Style::default().fg(theme().border)));
+
+    f.render_widget(paragraph, area);
+}
+
+fn render_new_portfolio_dialog(f: &mut Frame, state: &NewPortfolioState) {
+    let area = centered_rect(50, 60, f.area());
+    f.render_widget(Clear, area);
+
+    let mut lines = vec![
+        Line::from(""),
+        Line::from(tr("  Enter portfolio name:")),
+        Line::from(""),
+        Line::from(vec![
+            Span::raw("  "),
+            Span::styled(format!("{}█", state.name), Style::default().fg(theme().header)),
+        ]),
+        Line::from(""),
+        Line::from(tr("  Start from:")),
+    ];
+    // Keep the highlighted source in view when the list is taller than the dialog
+    let visible = (area.height as usize).saturating_sub(lines.len() + 5).max(1);
+    let first = state.selected.saturating_sub(visible - 1);
+    for (i, source) in state.sources.iter().enumerate().skip(first).take(visible) {
+        let style = if i == state.selected {
+            Style::default().fg(Color::Black).bg(theme().border)
+        } else {
+            Style::default()
+        };
+        lines.push(Line::from(format!("   {}", source.label())).style(style));
+    }
+    lines.push(Line::from(""));
+    lines.push(Line::from(tr("  ↑↓=Start from, Enter=Create, Esc=Cancel")).style(Style::default().fg(theme().muted)));
+
+    let paragraph = Paragraph::new(lines)
+        .block(Block::default().borders(Borders::ALL).title(tr(" New Portfolio ")).border_style(Style::default().fg(theme().alert)));
+
+    f.render_widget(paragraph, area);
+}
+
+fn render_detail_view(f: &mut Frame, app: &App, symbol: &str) {
+    let area = centered_rect(80, 70, f.area());
+    f.render_widget(Clear, area);
+
+    // Find the stock in all vectors
+    let stock = app.tw_stocks.iter()
+        .chain(app.us_stocks.iter())
+        .chain(app.crypto_stocks.iter())
+        .chain(app.combined_tw_stocks.iter())
+        .chain(app.combined_us_stocks.iter())
+        .chain(app.combined_crypto_stocks.iter())
+        .find(|s| s.symbol == symbol);
+
+    let Some(stock) = stock else {
+        let paragraph = Paragraph::new(tr("Stock not found"))
+            .block(Block::default().borders(Borders::ALL).title(tr(" Detail View ")));
+        f.render_widget(paragraph, area);
+        return;
+    };
+
+    // Render border
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .title(format!(" {} - {} ", stock.display, stock.name))
+        .border_style(Style::default().fg(theme().border));
+    f.render_widget(block, area);
+
+    // Info section
+    let (price, change_pct) = stock.price_data.as_ref()
+        .map(|d| (d.price, d.change_percent))
+        .unwrap_or((0.0, 0.0));
+
+    let price_color = if change_pct >= 0.0 { theme().gain } else { theme().loss };
+    let arrow = if change_pct >= 0.0 { "↑" } else { "↓" };
+
+    // Price vs total return over the fetched range, dividends reinvested or held as cash
+    let total_return_line = stock.historical.as_ref()
+        .filter(|h| !h.dividends.is_empty())
+        .map(|h| {
+            let price_return = (h.closes[h.closes.len() - 1] / h.closes[0] - 1.0) * 100.0;
+            format!(
+                "  {} {}: {} {:+.2}%  |  {} {:+.2}%",
+                HISTORY_RANGE,
+                tr("return"),
+                tr("price"),
+                price_return,
+                tr(if app.reinvest_dividends { "total (DRIP)" } else { "total (cash dividends)" }),
+                h.total_return(0, app.reinvest_dividends),
+            )
+        });
+
+    // Calculate 30-day high/low/avg from the chart window of historical
+    let (high, low, avg, trend_str) = stock.historical.as_ref()
+        .map(|h| {
+            let closes = &h.closes[h.chart_start()..];
+            let high = closes.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+            let low = closes.iter().cloned().fold(f64::INFINITY, f64::min);
+            let avg = closes.iter().sum::<f64>() / closes.len() as f64;
+            let (trend, _) = App::calculate_trend(closes);
+            (high, low, avg, trend.to_string())
+        })
+        .unwrap_or((0.0, 0.0, 0.0, "·".to_string()));
+
+    let mut info_text = vec![
+        Line::from(vec![
+            Span::raw(format!("  {} ", tr("Current:"))),
+            Span::styled(format!("{:.*}", price_decimals(stock, price), price), Style::default().fg(price_color).bold()),
+            Span::raw("  "),
+            Span::styled(format!("{}{:.2}%", arrow, change_pct), Style::default().fg(price_color)),
+            Span::raw(format!("  |  {} {}", tr("30d Trend:"), trend_str)),
+        ]),
+        Line::from(""),
+        Line::from(vec![
+            Span::styled(format!("  {} {:.2}", tr("30-Day High:"), high), Style::default().fg(theme().gain)),
+            Span::raw("  |  "),
+            Span::styled(format!("{} {:.2}", tr("Low:"), low), Style::default().fg(theme().loss)),
+            Span::raw("  |  "),
+            Span::raw(format!("{} {:.2}", tr("Avg:"), avg)),
+        ]),
+    ];
+    let session = stock.price_data.as_ref().and_then(|d| Some((d.day_high?, d.day_low?, d.day_position()?)));
+    if let Some((day_high, day_low, position)) = session {
+        info_text.insert(2, Line::from(vec![
+            Span::styled(format!("  {} {:.*}", tr("Today High:"), price_decimals(stock, day_high), day_high), Style::default().fg(theme().gain)),
+            Span::raw("  |  "),
+            Span::styled(format!("{} {:.*}", tr("Low:"), price_decimals(stock, day_low), day_low), Style::default().fg(theme().loss)),
+            Span::raw(format!("  |  {} {:.0}%", tr("position in range"), position * 100.0)),
+        ]));
+    }
+    if let Some(data) = stock.price_data.as_ref().filter(|d| d.prev_close.is_some() || d.open.is_some()) {
+        let level = |l: Option<f64>| l.map(|l| format!("{:.*}", price_decimals(stock, l), l)).unwrap_or_else(|| "--".to_string());
+        let mut spans = vec![Span::raw(format!("  {} {}  |  {} {}", tr("Prev Close:"), level(data.prev_close), tr("Open:"), level(data.open)))];
+        if let Some(gap) = data.gap_percent() {
+            let color = if gap >= 0.0 { theme().gain } else { theme().loss };
+            spans.push(Span::styled(format!("  ({} {:+.2}%)", tr("gap"), gap), Style::default().fg(color)));
+        }
+        let at = if session.is_some() { 3 } else { 2 };
+        info_text.insert(at, Line::from(spans));
+    }
+    if let Some(line) = total_return_line {
+        info_text.push(Line::from(line));
+    }
+    // TWSE three-institutional-investor net flows, in lots of 1,000 shares
+    let flows = app.institutional_flows(&stock.symbol);
+    if flows.is_empty() && app.twse_loading && stock.symbol.ends_with(".TW") {
+        info_text.push(Line::from(format!("  {} {}", tr("Institutions"), tr("loading..."))).style(Style::default().fg(theme().dim)));
+    }
+    if !flows.is_empty() {
+        let lots = |shares: f64| shares / 1000.0;
+        let colored = |label: &str, shares: f64| {
+            let color = if shares >= 0.0 { theme().gain } else { theme().loss };
+            Span::styled(format!("{} {:+.0}", label, lots(shares)), Style::default().fg(color))
+        };
+        let sum = |f: fn(&InstitutionalFlow) -> f64| flows.iter().map(|(_, flow)| f(flow)).sum::<f64>();
+        let trend: String = flows.iter().map(|(_, flow)| if flow.total >= 0.0 { '▲' } else { '▼' }).collect();
+        info_text.push(Line::from(vec![
+            Span::raw(format!("  {} {}d ({}): ", tr("Institutions"), flows.len(), tr("lots"))),
+            colored(tr("Foreign"), sum(|f| f.foreign)),
+            Span::raw("  "),
+            colored(tr("Trust"), sum(|f| f.trust)),
+            Span::raw("  "),
+            colored(tr("Dealer"), sum(|f| f.dealer)),
+            Span::raw(format!("  |  {} {} ", tr("daily total"), trend)),
+            colored(tr("latest"), flows[flows.len() - 1].1.total),
+        ]));
+    }
+    // TWSE margin purchase / short sale balances, with the change over the window
+    let balances = app.margin_balances(&stock.symbol);
+    if let (Some((_, first)), Some((_, last))) = (balances.first(), balances.last()) {
+        let change = |label: &str, now: f64, then: f64| {
+            let delta = now - then;
+            let color = if delta >= 0.0 { theme().gain } else { theme().loss };
+            vec![
+                Span::raw(format!("{} {:.0} ", label, now)),
+                Span::styled(format!("({:+.0})", delta), Style::default().fg(color)),
+            ]
+        };
+        let trend: String = balances.windows(2)
+            .map(|pair| if pair[1].1.margin >= pair[0].1.margin { '▲' } else { '▼' })
+            .collect();
+        let mut spans = vec![Span::raw(format!("  {} {}d ({}): ", tr("Margin"), balances.len(), tr("lots")))];
+        spans.extend(change(tr("Margin"), last.margin, first.margin));
+        spans.push(Span::raw(format!(" {}  |  ", trend)));
+        spans.extend(change(tr("Short"), last.short, first.short));
+        if let Some(ratio) = last.short_ratio() {
+            spans.push(Span::raw(format!("  |  {} {:.1}%", tr("short/margin"), ratio)));
+        }
+        info_text.push(Line::from(spans));
+    }
+    if app.fundamentals_requested.contains(&stock.symbol) {
+        info_text.push(Line::from(format!("  {} {}", tr("Analyst data"), tr("loading..."))).style(Style::default().fg(theme().dim)));
+    }
+    if let Some((_, Some(fundamentals))) = app.fundamentals_cache.get(&stock.symbol) {
+        let level = |l: Option<f64>| l.map(|l| format!("{:.2}", l)).unwrap_or_else(|| "--".to_string());
+        if let Some(target) = fundamentals.target_mean {
+            let upside = if price > 0.0 { (target / price - 1.0) * 100.0 } else { 0.0 };
+            let mut spans = vec![
+                Span::raw(format!("  {} {} ({}–{})  ", tr("Analyst target:"), level(Some(target)), level(fundamentals.target_low), level(fundamentals.target_high))),
+                Span::styled(format!("{} {:+.1}%", tr("upside"), upside), Style::default().fg(if upside >= 0.0 { theme().gain } else { theme().loss })),
+            ];
+            if let Some(recommendation) = &fundamentals.recommendation {
+                let count = fundamentals.analysts.map(|n| format!(" ({} {})", n, tr("analysts"))).unwrap_or_default();
+                spans.push(Span::raw(format!("  |  {}{}", recommendation, count)));
+            }
+            info_text.push(Line::from(spans));
+        }
+        if !fundamentals.quarters.is_empty() {
+            let mut spans = vec![Span::raw(format!("  {}", tr("EPS actual/est:")))];
+            for quarter in fundamentals.quarters.iter().rev().take(4).rev() {
+                spans.push(Span::raw(format!("  {} {}/{}", quarter.label, level(quarter.actual), level(quarter.estimate))));
+                if let Some(surprise) = quarter.surprise_pct {
+                    let color = if surprise >= 0.0 { theme().gain } else { theme().loss };
+                    spans.push(Span::styled(format!(" {:+.1}%", surprise), Style::default().fg(color)));
+                }
+            }
+            info_text.push(Line::from(spans));
+        }
+    }
+    let currency = if stock.is_tw() { "TWD" } else { "USD" };
+    let risk_free = app.config.assumptions.risk_free(currency);
+    if let Some((annual, vol, sharpe)) = stock.historical.as_ref().and_then(|h| h.sharpe(risk_free)) {
+        info_text.push(Line::from(format!(
+            "  {} {}: {} {:+.1}%  |  {} {:.1}%  |  Sharpe {:.2} ({} {:.2}%)",
+            HISTORY_RANGE, tr("risk"), tr("annualized return"), annual, tr("volatility"), vol, sharpe, tr("risk-free"), risk_free
+        )));
+    }
+    // Relative performance against the benchmark over the chart window
+    let benchmark = app.benchmark_for(stock.benchmark.as_deref(), &stock.symbol);
+    let window_return = |h: &HistoricalData| {
+        let closes = &h.closes[h.chart_start()..];
+        closes.first().zip(closes.last()).map(|(first, last)| (last / first - 1.0) * 100.0)
+    };
+    let relative = stock.historical.as_ref().and_then(window_return)
+        .zip(app.historical_cache.get(&benchmark).and_then(window_return));
+    if let Some((own, bench)) = relative {
+        let diff = own - bench;
+        info_text.push(Line::from(vec![
+            Span::raw(format!("  30d {} {}: {:+.2}% {} {:+.2}%  →  ", tr("vs"), benchmark, own, tr("vs"), bench)),
+            Span::styled(
+                format!("{:+.2} pp", diff),
+                Style::default().fg(if diff >= 0.0 { theme().gain } else { theme().loss }),
+            ),
+        ]));
+    }
+    if let Some(contract) = &stock.contract {
+        let days = contract.days_to_expiry();
+        let expiry_color = if days <= OPTION_EXPIRY_WARN_DAYS { theme().header } else { theme().dim };
+        info_text.push(Line::from(vec![
+            Span::raw(format!(
+                "  {} {} {} {:.2} × {:.0}  |  ",
+                contract.underlying,
+                if contract.is_call { "CALL" } else { "PUT" },
+                tr("strike"),
+                contract.strike,
+                OPTION_MULTIPLIER
+            )),
+            Span::styled(
+                format!("{} {} ({}d)", tr("expires"), app.config.time.date(contract.expiry), days),
+                Style::default().fg(expiry_color),
+            ),
+        ]));
+    }
+    if let Some(bond) = &stock.bond {
+        let price = stock.price_data.as_ref().map(|d| d.price).unwrap_or(0.0);
+        let maturity = match (bond.maturity, bond.years_to_maturity()) {
+            (Some(date), Some(years)) => format!("{} {} ({:.1}y)", tr("matures"), app.config.time.date(date), years),
+            _ => tr("no maturity set").to_string(),
+        };
+        let current_yield = if price > 0.0 { bond.coupon / price * 100.0 } else { 0.0 };
+        info_text.push(Line::from(format!(
+            "  {} {} {:.0}  |  {} {:.2}%  |  {}  |  {} {:.0}/yr  |  {} {:.2}%",
+            tr("Bond:"),
+            tr("face"),
+            bond.face,
+            tr("coupon"),
+            bond.coupon,
+            maturity,
+            tr("income"),
+            stock.quantity * bond.face * bond.coupon / 100.0,
+            tr("current yield"),
+            current_yield
+        )));
+    }
+    if !stock.tags.is_empty() {
+        info_text.push(Line::from(format!("  {} {}", tr("Tags:"), stock.tags.join(", "))).style(Style::default().fg(theme().border)));
+    }
+    if let (Some(date), Some(days)) = (stock.purchase_date(), stock.held_days()) {
+        let (term, color) = if days > 365 { (tr("long-term"), theme().gain) } else { (tr("short-term"), theme().header) };
+        info_text.push(Line::from(vec![
+            Span::raw(format!(
+                "  {} {}d ({:.1}y) {} {}{}  |  ",
+                tr("Held:"),
+                days,
+                days as f64 / 365.25,
+                tr("since"),
+                app.config.time.date(date),
+                if stock.purchased.is_none() { tr(" (ledger)") } else { "" }
+            )),
+            Span::styled(term, Style::default().fg(color)),
+        ]));
+    }
+    if let (Some(fraction), Some(avg)) = (stock.volume_fraction(), stock.avg_volume()) {
+        let limit = app.config.liquidity_fraction;
+        let color = if limit > 0.0 && fraction > limit { Color::LightRed } else { theme().dim };
+        info_text.push(Line::from(format!(
+            "  {} {:.1}% {} {}d ({:.0}/day)",
+            tr("Liquidity: position is"),
+            fraction * 100.0,
+            tr("of average volume over"),
+            LIQUIDITY_DAYS,
+            avg
+        )).style(Style::default().fg(color)));
+    }
+    if stock.alert_above.is_some() || stock.alert_below.is_some() {
+        let level = |l: Option<f64>| l.map(|l| format!("{:.2}", l)).unwrap_or_else(|| "--".to_string());
+        let mut spans = vec![Span::raw(format!(
+            "  {} {}  |  {} {}",
+            tr("Alert above:"),
+            level(stock.alert_above),
+            tr("Alert below:"),
+            level(stock.alert_below)
+        ))];
+        if let Some(rule) = stock.triggered_alert() {
+            spans.push(Span::styled(
+                format!("  ⚑ {} ({})", tr("triggered"), rule),
+                Style::default().fg(theme().alert).add_modifier(Modifier::BOLD),
+            ));
+        }
+        info_text.push(Line::from(spans));
+    }
+    if let Some(deposit) = &stock.deposit {
+        let maturity = match deposit.maturity {
+            Some(date) => format!("{} {} ({}d)", tr("matures"), app.config.time.date(date), (date - Local::now().date_naive()).num_days()),
+            None => tr("no fixed term").to_string(),
+        };
+        info_text.push(Line::from(format!(
+            "  {} {:.0} @ {:.2}%  |  {} {}  |  {}  |  {} {:.2}",
+            tr("Deposit:"),
+            stock.quantity,
+            deposit.rate,
+            tr("since"),
+            app.config.time.date(deposit.start),
+            maturity,
+            tr("accrued interest"),
+            stock.quantity * (deposit.accrued_price() - 1.0)
+        )));
+    }
+    if stock.is_metal() {
+        let grams = stock.quantity * stock.multiplier() * stock.metal_quote_grams();
+        let price = stock.price_data.as_ref().map(|d| d.price).unwrap_or(0.0);
+        let per_gram = price / stock.metal_quote_grams();
+        let currency = if stock.is_tw() { "TWD" } else { "USD" };
+        info_text.push(Line::from(format!(
+            "  {} {:.2} g ({:.3} oz)  |  {:.2} {}/g  |  {:.2} {}/oz",
+            tr("Metal:"),
+            grams,
+            grams / TROY_OUNCE_GRAMS,
+            per_gram,
+            currency,
+            per_gram * TROY_OUNCE_GRAMS,
+            currency
+        )));
+    }
+    if stock.is_future() {
+        info_text.push(Line::from(format!(
+            "  {} × {}  |  {} ${:.0}",
+            tr("Futures contract"),
+            stock.multiplier(),
+            tr("notional per contract"),
+            stock.price_data.as_ref().map(|d| d.price).unwrap_or(0.0) * stock.multiplier()
+        )));
+    }
+    // Split area into sections; the info header grows with the optional lines above
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Length(info_text.len().max(7) as u16),  // Info header
+            Constraint::Min(10),    // Chart
+            Constraint::Length(2),  // Footer
+        ])
+        .margin(1)
+        .split(area);
+
+    let info_para = Paragraph::new(info_text);
+    f.render_widget(info_para, chunks[0]);
+
+    // Dividend history beside the price chart, when the symbol pays any
+    let dividend_years = app.historical_cache
+        .get(&format!("{}@{}", stock.symbol, DIVIDEND_HISTORY_RANGE))
+        .map(|h| h.dividends_by_year())
+        .unwrap_or_default();
+    let chart_area = if dividend_years.is_empty() {
+        chunks[1]
+    } else {
+        let split = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Min(30), Constraint::Length(dividend_years.len() as u16 * 7 + 3)])
+            .split(chunks[1]);
+        render_dividend_panel(f, split[1], &dividend_years);
+        split[0]
+    };
+
+    // Chart section
+    if let Some(historical) = &stock.historical {
+        let start = historical.chart_start();
+        let closes = &historical.closes[start..];
+        if !closes.is_empty() {
+            // Create chart data points: (x, y) where x is day index within the window
+            let data: Vec<(f64, f64)> = closes.iter()
+                .enumerate()
+                .map(|(i, &p)| (i as f64, p))
+                .collect();
+
+            // Bands are computed over the full fetched range so the window has lookback
+            let bands: Vec<(usize, f64, f64, f64)> = if app.show_bollinger {
+                App::bollinger_bands(&historical.closes, BOLLINGER_PERIOD, BOLLINGER_STDDEV)
+                    .into_iter()
+                    .filter(|(i, ..)| *i >= start)
+                    .map(|(i, mid, upper, lower)| (i - start, mid, upper, lower))
+                    .collect()
+            } else {
+                Vec::new()
+            };
+            let upper_data: Vec<(f64, f64)> = bands.iter().map(|&(i, _, upper, _)| (i as f64, upper)).collect();
+            let middle_data: Vec<(f64, f64)> = bands.iter().map(|&(i, mid, _, _)| (i as f64, mid)).collect();
+            let lower_data: Vec<(f64, f64)> = bands.iter().map(|&(i, _, _, lower)| (i as f64, lower)).collect();
+
+            let max_x = closes.len() as f64;
+            let chart_date = |i: usize| {
+                historical.timestamps.get(i)
+                    .and_then(|&t| chrono::DateTime::from_timestamp(t, 0))
+                    .map(|d| d.with_timezone(&Local).format(&app.config.time.chart_date_format).to_string())
+                    .unwrap_or_default()
+            };
+
+            // Horizontal support/resistance lines spanning the whole chart
+            let (supports, resistances) = if app.show_levels {
+                App::support_resistance(historical, price, SWING_WINDOW)
+            } else {
+                (Vec::new(), Vec::new())
+            };
+            let level_line = |level: f64| vec![(0.0, level), (max_x, level)];
+            let support_data: Vec<Vec<(f64, f64)>> = supports.iter().map(|&l| level_line(l)).collect();
+            let resistance_data: Vec<Vec<(f64, f64)>> = resistances.iter().map(|&l| level_line(l)).collect();
+
+            let min_y = closes.iter().chain(lower_data.iter().map(|(_, y)| y)).chain(supports.iter())
+                .cloned().fold(f64::INFINITY, f64::min) * 0.98;
+            let max_y = closes.iter().chain(upper_data.iter().map(|(_, y)| y)).chain(resistances.iter())
+                .cloned().fold(f64::NEG_INFINITY, f64::max) * 1.02;
+
+            let mut datasets = vec![
+                Dataset::default()
+                    .name(tr("Price"))
+                    .marker(symbols::Marker::Braille)
+                    .graph_type(GraphType::Line)
+                    .style(Style::default().fg(theme().border))
+                    .data(&data),
+            ];
+            if !bands.is_empty() {
+                datasets.push(
+                    Dataset::default()
+                        .name(format!("BB({}, {})", BOLLINGER_PERIOD, BOLLINGER_STDDEV))
+                        .marker(symbols::Marker::Braille)
+                        .graph_type(GraphType::Line)
+                        .style(Style::default().fg(theme().alert))
+                        .data(&upper_data),
+                );
+                datasets.push(
+                    Dataset::default()
+                        .marker(symbols::Marker::Braille)
+                        .graph_type(GraphType::Line)
+                        .style(Style::default().fg(theme().header))
+                        .data(&middle_data),
+                );
+                datasets.push(
+                    Dataset::default()
+                        .marker(symbols::Marker::Braille)
+                        .graph_type(GraphType::Line)
+                        .style(Style::default().fg(theme().alert))
+                        .data(&lower_data),
+                );
+            }
+
+            for (i, line) in support_data.iter().enumerate() {
+                let mut dataset = Dataset::default()
+                    .marker(symbols::Marker::Braille)
+                    .graph_type(GraphType::Line)
+                    .style(Style::default().fg(theme().gain))
+                    .data(line);
+                if i == 0 {
+                    dataset = dataset.name(format!("{} {:.2}", tr("Support"), supports[0]));
+                }
+                datasets.push(dataset);
+            }
+            for (i, line) in resistance_data.iter().enumerate() {
+                let mut dataset = Dataset::default()
+                    .marker(symbols::Marker::Braille)
+                    .graph_type(GraphType::Line)
+                    .style(Style::default().fg(theme().loss))
+                    .data(line);
+                if i == 0 {
+                    dataset = dataset.name(format!("{} {:.2}", tr("Resistance"), resistances[0]));
+                }
+                datasets.push(dataset);
+            }
 
-    // Check footer buttons
-    for (rect, action_name) in &regions.footer_buttons {
-        if point_in_rect(x, y, *rect) {
-            return match *action_name {
-                "live" => Action::ToggleLive,
-                "hide" => Action::ToggleHide,
-                "refresh" => Action::Refresh,
-                "quit" => Action::Quit,
-                _ => Action::None,
-            };
+            let chart = Chart::new(datasets)
+                .block(Block::default().borders(Borders::ALL).title(tr(" 30-Day Price History ")))
+                .x_axis(
+                    Axis::default()
+                        .title(tr("Days"))
+                        .style(Style::default().fg(theme().dim))
+                        .bounds([0.0, max_x])
+                        .labels(vec![
+                            Span::raw(chart_date(start)),
+                            Span::raw(chart_date(historical.timestamps.len() - 1)),
+                        ]),
+                )
+                .y_axis(
+                    Axis::default()
+                        .title(tr("Price"))
+                        .style(Style::default().fg(theme().dim))
+                        .bounds([min_y, max_y])
+                        .labels(vec![
+                            Span::raw(format!("{:.1}", min_y)),
+                            Span::raw(format!("{:.1}", max_y)),
+                        ]),
+                );
+
+            f.render_widget(chart, chart_area);
         }
+    } else {
+        let no_data = Paragraph::new(tr("  No historical data available"))
+            .block(Block::default().borders(Borders::ALL).title(tr(" 30-Day Price History ")))
+            .style(Style::default().fg(theme().muted));
+        f.render_widget(no_data, chart_area);
     }
 
-    // Click on table area but not on a row - activate that section
-    if point_in_rect(x, y, regions.tw_table) {
-        app.active_section = 0;
-    } else if point_in_rect(x, y, regions.us_table) {
-        app.active_section = 1;
-    }
+    // Footer
+    let bollinger_key = tr(if app.show_bollinger { "b=BB:ON" } else { "b=BB" });
+    let levels_key = tr(if app.show_levels { "v=Levels:ON" } else { "v=Levels" });
+    let dividends_key = tr(if app.reinvest_dividends { "d=DRIP:ON" } else { "d=DRIP" });
+    let footer = Paragraph::new(format!(
+        "  {} | {} {} {} | {}",
+        tr("Press Esc or Enter to close"),
+        bollinger_key,
+        levels_key,
+        dividends_key,
+        tr("x=Export CSV")
+    ))
+        .style(Style::default().fg(theme().muted));
+    f.render_widget(footer, chunks[2]);
+}
 
-    Action::None
+/// Bars of yearly dividends per share; the current (incomplete) year is dimmed
+fn render_dividend_panel(f: &mut Frame, area: Rect, years: &[(i32, f64)]) {
+    let this_year = Local::now().year();
+    let bars: Vec<Bar> = years.iter().map(|&(year, total)| {
+        let color = if year == this_year { theme().muted } else { theme().border };
+        Bar::default()
+            .value((total * 1000.0).round() as u64)
+            .text_value(format!("{:.2}", total))
+            .label(Line::from(format!("{}", year % 100)))
+            .style(Style::default().fg(color))
+            .value_style(Style::default().fg(Color::Black).bg(color))
+    }).collect();
+    let chart = BarChart::default()
+        .block(Block::default().borders(Borders::ALL).title(tr(" Dividends/yr ")))
+        .data(BarGroup::default().bars(&bars))
+        .bar_width(6)
+        .bar_gap(1);
+    f.render_widget(chart, area);
 }
 
-fn ui(f: &mut Frame, app: &mut App) {
-    // Clear clickable regions before each render
-    app.clickable_regions = ClickableRegions::default();
+fn render_exposure_view(f: &mut Frame, state: &ExposureState) {
+    let area = centered_rect(80, 80, f.area());
+    f.render_widget(Clear, area);
 
     let chunks = Layout::default()
         .direction(Direction::Vertical)
-        .constraints([
-            Constraint::Length(3),  // Tabs
-            Constraint::Min(10),    // Main content
-            Constraint::Length(8),  // Summary
-            Constraint::Length(2),  // Footer
-        ])
-        .split(f.area());
+        .constraints([Constraint::Min(5), Constraint::Length(2)])
+        .split(area);
 
-    render_tabs(f, app, chunks[0]);
-    render_stock_tables(f, app, chunks[1]);
-    render_summary(f, app, chunks[2]);
-    render_footer(f, app, chunks[3]);
+    let header = Row::new(["Underlying", "Direct", "Via ETFs", "Total", "Weight", "Through"].map(tr))
+        .style(Style::default().fg(theme().header).add_modifier(Modifier::BOLD));
 
-    // Render dialogs
-    match &app.input_mode {
-        InputMode::AddStock(state) => render_add_dialog(f, state),
-        InputMode::EditStock(state) => render_edit_dialog(f, state),
-        InputMode::DeleteConfirm(symbol) => render_delete_dialog(f, symbol),
-        InputMode::NewPortfolio(name) => render_new_portfolio_dialog(f, name),
-        InputMode::DetailView(symbol) => render_detail_view(f, app, symbol),
-        InputMode::Normal => {}
-    }
+    let rows: Vec<Row> = state.rows.iter().skip(state.scroll).map(|r| {
+        let total = r.direct + r.via_etfs;
+        let weight = if state.total_value > 0.0 { total / state.total_value * 100.0 } else { 0.0 };
+        Row::new(vec![
+            Cell::from(r.symbol.clone()),
+            Cell::from(Line::from(format!("{:.0}", r.direct)).alignment(Alignment::Right)),
+            Cell::from(Line::from(format!("{:.0}", r.via_etfs)).alignment(Alignment::Right)),
+            Cell::from(Line::from(format!("{:.0}", total)).alignment(Alignment::Right)),
+            Cell::from(Line::from(format!("{:.1}%", weight)).alignment(Alignment::Right)),
+            Cell::from(r.etfs.join(", ")).style(Style::default().fg(theme().muted)),
+        ])
+    }).collect();
+
+    let table = Table::new(rows, [
+        Constraint::Length(12),
+        Constraint::Length(12),
+        Constraint::Length(12),
+        Constraint::Length(12),
+        Constraint::Length(8),
+        Constraint::Min(10),
+    ])
+        .header(header)
+        .block(Block::default().borders(Borders::ALL).title(tr(" ETF Look-Through Exposure (TWD) "))
+            .border_style(Style::default().fg(theme().border)));
+    f.render_widget(table, chunks[0]);
+
+    let note = if state.missing.is_empty() {
+        format!("  {}  |  {} ~/.config/stock-tui/etf/<SYMBOL>.conf", tr("↑↓=Scroll, Esc=Close"), tr("ETF holdings from"))
+    } else {
+        format!("  {}  |  {} {}", tr("↑↓=Scroll, Esc=Close"), tr("No holdings file for:"), state.missing.join(", "))
+    };
+    f.render_widget(Paragraph::new(note).style(Style::default().fg(theme().muted)), chunks[1]);
 }
 
-fn render_tabs(f: &mut Frame, app: &mut App, area: Rect) {
-    let mut titles: Vec<Line> = vec![
-        if app.view_combined {
-            Line::from(" `/0:ALL ").magenta().bold()
-        } else {
-            Line::from(" `/0:ALL ").dark_gray()
+/// Tile colors per market (TW, US, crypto), alternated so neighbouring tiles stay apart
+const ALLOCATION_COLORS: [[Color; 2]; 3] = [
+    [Color::Blue, Color::LightBlue],
+    [Color::Green, Color::LightGreen],
+    [Color::Magenta, Color::LightMagenta],
+];
+
+/// Slice-and-dice treemap: each weight in turn takes its share of the remaining area along
+/// the longer side (cells are about twice as tall as wide). Weights left once the area is
+/// too small to split are returned unplaced, for a single "more" tile over the rest.
+fn treemap_tiles(weights: &[f64], area: Rect) -> (Vec<Rect>, Rect) {
+    let mut tiles = Vec::new();
+    let mut rest = area;
+    let mut remaining: f64 = weights.iter().sum();
+    for (i, &weight) in weights.iter().enumerate() {
+        if i + 1 == weights.len() {
+            tiles.push(rest);
+            return (tiles, Rect::default());
         }
-    ];
-
-    // Track tab widths for click detection
-    let mut tab_widths: Vec<usize> = vec![9]; // " `/0:ALL " = 9 chars
-
-    for (i, p) in app.portfolios.iter().enumerate() {
-        let title = format!(" {}:{} ", i + 1, p.name);
-        tab_widths.push(title.len());
-        if !app.view_combined && i == app.current_portfolio_idx {
-            titles.push(Line::from(title).cyan().bold());
+        let share = if remaining > 0.0 { weight / remaining } else { 0.0 };
+        remaining -= weight;
+        let tile = if rest.width >= rest.height * 2 {
+            let width = (rest.width as f64 * share).round() as u16;
+            if width == 0 || width >= rest.width {
+                break;
+            }
+            let tile = Rect { width, ..rest };
+            rest = Rect { x: rest.x + width, width: rest.width - width, ..rest };
+            tile
         } else {
-            titles.push(Line::from(title).dark_gray());
-        }
-    }
-
-    // Calculate clickable regions for tabs (inside the border)
-    let inner_x = area.x + 1; // Account for left border
-    let tab_y = area.y + 1;   // Account for top border
-    let mut current_x = inner_x;
-
-    for (i, width) in tab_widths.iter().enumerate() {
-        let tab_rect = Rect::new(current_x, tab_y, *width as u16, 1);
-        app.clickable_regions.portfolio_tabs.push((tab_rect, i));
-        current_x += *width as u16 + 1; // +1 for divider "|"
+            let height = (rest.height as f64 * share).round() as u16;
+            if height == 0 || height >= rest.height {
+                break;
+            }
+            let tile = Rect { height, ..rest };
+            rest = Rect { y: rest.y + height, height: rest.height - height, ..rest };
+            tile
+        };
+        tiles.push(tile);
     }
-
-    let tabs = Tabs::new(titles)
-        .block(Block::default().borders(Borders::ALL).title(" Portfolios "))
-        .divider("|");
-
-    f.render_widget(tabs, area);
+    (tiles, rest)
 }
 
-fn render_stock_tables(f: &mut Frame, app: &mut App, area: Rect) {
+fn render_allocation_view(f: &mut Frame, state: &AllocationState, hide_positions: bool) {
+    let area = centered_rect(80, 80, f.area());
+    f.render_widget(Clear, area);
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .title(tr(if state.treemap { " Allocation: Treemap " } else { " Allocation " }))
+        .border_style(Style::default().fg(theme().border));
+    f.render_widget(block, area);
+
+    const MARKETS: [&str; 3] = ["Taiwan", "US", "Crypto"];
+    let markets: Vec<(usize, f64)> = (0..MARKETS.len())
+        .map(|section| (section, state.holdings.iter().filter(|h| h.1 == section).map(|h| h.2).sum::<f64>()))
+        .filter(|(_, value)| *value > 0.0)
+        .collect();
     let chunks = Layout::default()
         .direction(Direction::Vertical)
-        .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
+        .constraints([
+            Constraint::Length(2),                         // Concentration
+            Constraint::Length(markets.len() as u16 + 1),  // By market
+            Constraint::Min(4),                            // By symbol
+            Constraint::Length(1),                         // Footer
+        ])
+        .margin(1)
         .split(area);
-
-    // Record table areas for click detection
-    app.clickable_regions.tw_table = chunks[0];
-    app.clickable_regions.us_table = chunks[1];
-
-    // Get stock counts first to avoid borrow issues
-    let tw_count = if app.view_combined { app.combined_tw_stocks.len() } else { app.tw_stocks.len() };
-    let us_count = if app.view_combined { app.combined_us_stocks.len() } else { app.us_stocks.len() };
-
-    // Calculate row regions (rows start after border + header)
-    let tw_row_start_y = chunks[0].y + 2; // +1 border, +1 header
-    let tw_row_width = chunks[0].width.saturating_sub(2); // -2 for borders
-    let tw_row_x = chunks[0].x + 1;
-    for i in 0..tw_count {
-        let row_y = tw_row_start_y + i as u16;
-        if row_y < chunks[0].y + chunks[0].height - 1 { // Don't exceed table bounds
-            let row_rect = Rect::new(tw_row_x, row_y, tw_row_width, 1);
-            app.clickable_regions.tw_rows.push((row_rect, i));
-        }
+    let footer = tr(if state.treemap { "  t=Bars, Esc=Close" } else { "  t=Treemap, ↑↓=Scroll, Esc=Close" });
+    f.render_widget(Paragraph::new(footer).style(Style::default().fg(theme().muted)), chunks[3]);
+    if state.total_value <= 0.0 {
+        f.render_widget(Paragraph::new(tr("  No priced holdings in this view.")), chunks[0]);
+        return;
     }
 
-    let us_row_start_y = chunks[1].y + 2;
-    let us_row_width = chunks[1].width.saturating_sub(2);
-    let us_row_x = chunks[1].x + 1;
-    for i in 0..us_count {
-        let row_y = us_row_start_y + i as u16;
-        if row_y < chunks[1].y + chunks[1].height - 1 {
-            let row_rect = Rect::new(us_row_x, row_y, us_row_width, 1);
-            app.clickable_regions.us_rows.push((row_rect, i));
-        }
+    // Concentration: the largest positions' share and the effective number of equal-sized
+    // positions (1 / sum of squared weights)
+    let weight = |value: f64| value / state.total_value * 100.0;
+    let top = |n: usize| state.holdings.iter().take(n).map(|h| weight(h.2)).sum::<f64>();
+    let effective = 1.0 / state.holdings.iter().map(|h| (h.2 / state.total_value).powi(2)).sum::<f64>();
+    let mut summary = format!(
+        "  {} {:.1}%  ·  {} {:.1}%  ·  {} {:.1} / {}",
+        tr("Largest"), top(1), tr("Top 5"), top(5), tr("Effective holdings"), effective, state.holdings.len()
+    );
+    if !hide_positions {
+        summary.push_str(&format!("  ·  {} {:.0} TWD", tr("Total"), state.total_value));
     }
-
-    let tw_stocks = app.get_active_tw_stocks();
-    let us_stocks = app.get_active_us_stocks();
-
-    // Sort indicator
-    let sort_arrow = match app.sort_direction {
-        SortDirection::Ascending => "▲",
-        SortDirection::Descending => "▼",
-    };
-
-    let header_col = |name: &str, col: Option<SortColumn>| -> String {
-        if app.sort_column == col {
-            format!("{}{}", name, sort_arrow)
-        } else {
-            name.to_string()
-        }
-    };
-
-    let header_style = Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD);
-
-    // Build header based on hide_positions state
-    let header = if app.hide_positions {
-        let mut cols = vec![
-            "Symbol".to_string(),
-            "Name".to_string(),
-            header_col("Price", Some(SortColumn::Price)),
-            header_col("Change", Some(SortColumn::Change)),
+    f.render_widget(Paragraph::new(summary), chunks[0]);
+
+    let bar_width = (chunks[1].width as usize).saturating_sub(44).clamp(8, 60);
+    let bar_line = |label: &str, value: f64, scale: f64, color: Color| -> Line<'static> {
+        let mut spans = vec![
+            Span::raw(format!("  {:<12} ", fit_width(label, 12))),
+            Span::styled(fill_bar(value / scale * 100.0, bar_width), Style::default().fg(color)),
+            Span::raw(format!(" {:>6.1}%", weight(value))),
         ];
-        if app.view_combined {
-            cols.push("Portfolio".to_string());
+        if !hide_positions {
+            spans.push(Span::styled(format!(" {:>13.0}", value), Style::default().fg(theme().muted)));
         }
-        Row::new(cols).style(header_style).height(1)
-    } else if app.view_combined {
-        Row::new(vec![
-            "Symbol".to_string(),
-            "Name".to_string(),
-            header_col("Price", Some(SortColumn::Price)),
-            header_col("Change", Some(SortColumn::Change)),
-            header_col("Qty", Some(SortColumn::Quantity)),
-            "Cost".to_string(),
-            header_col("Gain", Some(SortColumn::Gain)),
-            header_col("Gain %", Some(SortColumn::GainPercent)),
-            "Portfolio".to_string(),
-        ])
-            .style(header_style)
-            .height(1)
-    } else {
-        Row::new(vec![
-            "Symbol".to_string(),
-            "Name".to_string(),
-            header_col("Price", Some(SortColumn::Price)),
-            header_col("Change", Some(SortColumn::Change)),
-            header_col("Qty", Some(SortColumn::Quantity)),
-            "Cost".to_string(),
-            header_col("Gain", Some(SortColumn::Gain)),
-            header_col("Gain %", Some(SortColumn::GainPercent)),
-        ])
-            .style(header_style)
-            .height(1)
+        Line::from(spans)
     };
+    let market_lines: Vec<Line> = markets.iter()
+        .map(|&(section, value)| bar_line(tr(MARKETS[section]), value, state.total_value, ALLOCATION_COLORS[section][0]))
+        .collect();
+    f.render_widget(Paragraph::new(market_lines), chunks[1]);
+
+    if !state.treemap {
+        // Bars scale to the largest holding so small weights stay visible
+        let largest = state.holdings.first().map_or(1.0, |h| h.2);
+        let lines: Vec<Line> = state.holdings.iter()
+            .skip(state.scroll)
+            .map(|(display, section, value)| bar_line(display, *value, largest, ALLOCATION_COLORS[*section][1]))
+            .collect();
+        f.render_widget(Paragraph::new(lines), chunks[2]);
+        return;
+    }
 
-    // Calculate market totals for titles
-    let (tw_value, tw_gain, tw_gain_pct, us_value, us_gain, us_gain_pct) = app.calculate_market_summary();
-    let tw_gain_color = if tw_gain >= 0.0 { Color::Green } else { Color::Red };
-    let us_gain_color = if us_gain >= 0.0 { Color::Green } else { Color::Red };
-
-    // TW Stocks
-    let tw_base = if app.view_combined { "Taiwan Stocks (All)" } else { "Taiwan Stocks" };
-    let tw_title: Line = if app.hide_positions {
-        Line::from(tw_base)
-    } else {
-        let tw_gain_display = if app.show_gain_amount {
-            format!("{:+.0} TWD", tw_gain)
-        } else {
-            format!("{:+.2}%", tw_gain_pct)
-        };
-        Line::from(vec![
-            Span::raw(format!("{} ", tw_base)),
-            Span::styled(format!("{:.0} TWD ", tw_value), Style::default().fg(Color::White)),
-            Span::styled(tw_gain_display, Style::default().fg(tw_gain_color)),
-        ])
-    };
-    let tw_rows: Vec<Row> = tw_stocks.iter().map(|s| stock_to_row(s, app.usd_twd_rate, app.view_combined, app.hide_positions)).collect();
-    let tw_table = Table::new(tw_rows, get_widths(app.view_combined, app.hide_positions))
-        .header(header.clone())
-        .block(Block::default().borders(Borders::ALL).title(tw_title)
-            .border_style(if app.active_section == 0 { Style::default().fg(Color::Cyan) } else { Style::default() }))
-        .row_highlight_style(Style::default().bg(Color::DarkGray));
-
-    f.render_stateful_widget(tw_table, chunks[0], &mut app.table_state_tw.clone());
-
-    // US Stocks
-    let us_base = if app.view_combined { "US Stocks (All)" } else { "US Stocks" };
-    let us_title: Line = if app.hide_positions {
-        Line::from(us_base)
-    } else {
-        let us_gain_display = if app.show_gain_amount {
-            format!("{:+.2} USD", us_gain)
-        } else {
-            format!("{:+.2}%", us_gain_pct)
-        };
-        Line::from(vec![
-            Span::raw(format!("{} ", us_base)),
-            Span::styled(format!("{:.2} USD ", us_value), Style::default().fg(Color::White)),
-            Span::styled(us_gain_display, Style::default().fg(us_gain_color)),
-        ])
+    let weights: Vec<f64> = state.holdings.iter().map(|h| h.2).collect();
+    let (tiles, rest) = treemap_tiles(&weights, chunks[2]);
+    let tile = |f: &mut Frame, rect: Rect, text: Vec<String>, bg: Color| {
+        let fg = if matches!(bg, Color::LightBlue | Color::LightGreen | Color::LightMagenta) { Color::Black } else { Color::White };
+        let lines: Vec<Line> = text.into_iter().map(|t| Line::from(fit_width(&t, rect.width as usize))).collect();
+        f.render_widget(Paragraph::new(lines).style(Style::default().fg(fg).bg(bg)), rect);
     };
-    let us_rows: Vec<Row> = us_stocks.iter().map(|s| stock_to_row(s, app.usd_twd_rate, app.view_combined, app.hide_positions)).collect();
-    let us_table = Table::new(us_rows, get_widths(app.view_combined, app.hide_positions))
-        .header(header)
-        .block(Block::default().borders(Borders::ALL).title(us_title)
-            .border_style(if app.active_section == 1 { Style::default().fg(Color::Cyan) } else { Style::default() }))
-        .row_highlight_style(Style::default().bg(Color::DarkGray));
-
-    f.render_stateful_widget(us_table, chunks[1], &mut app.table_state_us.clone());
+    for (i, rect) in tiles.iter().enumerate() {
+        let (display, section, value) = &state.holdings[i];
+        tile(f, *rect, vec![display.clone(), format!("{:.1}%", weight(*value))], ALLOCATION_COLORS[*section][i % 2]);
+    }
+    if rest.width > 0 && rest.height > 0 {
+        let others = &state.holdings[tiles.len()..];
+        let value: f64 = others.iter().map(|h| h.2).sum();
+        tile(f, rest, vec![format!("+{} {}", others.len(), tr("more")), format!("{:.1}%", weight(value))], Color::DarkGray);
+    }
 }
 
-fn get_widths(combined: bool, hide_positions: bool) -> Vec<Constraint> {
-    if hide_positions {
-        let mut widths = vec![
-            Constraint::Length(10),  // Symbol
-            Constraint::Length(16),  // Name
-            Constraint::Length(12),  // Price
-            Constraint::Length(10),  // Change
+fn render_archive_view(f: &mut Frame, state: &ArchiveState, combined: bool, time: &TimeConfig) {
+    let area = centered_rect(80, 80, f.area());
+    f.render_widget(Clear, area);
+
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Min(5), Constraint::Length(2)])
+        .split(area);
+
+    let arrow = if state.ascending { "▲" } else { "▼" };
+    let title = |name: &str, column: ArchiveSort| {
+        if state.sort == column { format!("{}{}", name, arrow) } else { name.to_string() }
+    };
+    let mut columns = vec![
+        title(tr("Symbol"), ArchiveSort::Symbol),
+        tr("Name").to_string(),
+        tr("Qty").to_string(),
+        tr("Cost").to_string(),
+        tr("Exit").to_string(),
+        tr("Opened").to_string(),
+        title(tr("Closed"), ArchiveSort::Closed),
+        title(tr("Held"), ArchiveSort::Held),
+        title(tr("Realized"), ArchiveSort::Realized),
+    ];
+    if combined {
+        columns.push(tr("Portfolio").to_string());
+    }
+    let header = Row::new(columns).style(Style::default().fg(theme().header).add_modifier(Modifier::BOLD));
+
+    let rows: Vec<Row> = state.rows.iter().skip(state.scroll).map(|(portfolio, c)| {
+        let color = if c.realized >= 0.0 { theme().gain } else { theme().loss };
+        let mut cells = vec![
+            Cell::from(c.display.clone()),
+            Cell::from(fit_width(&c.name, 14)),
+            Cell::from(Line::from(format!("{:.0}", c.quantity)).alignment(Alignment::Right)),
+            Cell::from(Line::from(format!("{:.2}", c.cost_basis)).alignment(Alignment::Right)),
+            Cell::from(Line::from(format!("{:.2}", c.exit_price)).alignment(Alignment::Right)),
+            Cell::from(c.opened.map(|d| time.date(d)).unwrap_or_else(|| "--".to_string())),
+            Cell::from(time.date(c.closed_on)),
+            Cell::from(Line::from(c.holding_days().map(|d| format!("{}d", d)).unwrap_or_else(|| "--".to_string())).alignment(Alignment::Right)),
+            Cell::from(Line::from(format!("{:+.0}", c.realized)).alignment(Alignment::Right)).style(Style::default().fg(color)),
         ];
         if combined {
-            widths.push(Constraint::Length(12));  // Portfolio
+            cells.push(Cell::from(portfolio.clone()).style(Style::default().fg(theme().muted)));
         }
-        widths
-    } else if combined {
-        vec![
-            Constraint::Length(8),   // Symbol
-            Constraint::Length(10),  // Name
-            Constraint::Length(10),  // Price
-            Constraint::Length(9),   // Change
-            Constraint::Length(8),   // Qty
-            Constraint::Length(8),   // Cost
-            Constraint::Length(12),  // Gain
-            Constraint::Length(8),   // Gain %
-            Constraint::Length(10),  // Portfolio
-        ]
+        Row::new(cells)
+    }).collect();
+
+    let table = Table::new(rows, [
+        Constraint::Length(10),
+        Constraint::Length(15),
+        Constraint::Length(8),
+        Constraint::Length(10),
+        Constraint::Length(10),
+        Constraint::Length(11),
+        Constraint::Length(11),
+        Constraint::Length(7),
+        Constraint::Length(12),
+        Constraint::Min(10),
+    ])
+        .header(header)
+        .block(Block::default().borders(Borders::ALL).title(format!("{}({}) ", tr(" Closed Positions "), state.rows.len()))
+            .border_style(Style::default().fg(theme().border)));
+    f.render_widget(table, chunks[0]);
+
+    // Realized totals per currency, since TW and US rows can't be summed directly
+    let (tw_realized, us_realized) = state.rows.iter().fold((0.0, 0.0), |(tw, us), (_, c)| {
+        if c.symbol.contains(".TW") { (tw + c.realized, us) } else { (tw, us + c.realized) }
+    });
+    let note = if state.rows.is_empty() {
+        tr("  Nothing archived yet: sell a position to zero, or delete with A  |  Esc=Close").to_string()
     } else {
-        vec![
-            Constraint::Length(8),   // Symbol
-            Constraint::Length(12),  // Name
-            Constraint::Length(10),  // Price
-            Constraint::Length(9),   // Change
-            Constraint::Length(8),   // Qty
-            Constraint::Length(8),   // Cost
-            Constraint::Length(12),  // Gain
-            Constraint::Length(8),   // Gain %
-        ]
-    }
+        format!(
+            "  {} {:+.0} TWD, {:+.2} USD  |  {}",
+            tr("Realized:"), tw_realized, us_realized, tr("s/c/h/r=Sort by symbol/closed/held/realized, ↑↓=Scroll, Esc=Close")
+        )
+    };
+    f.render_widget(Paragraph::new(note).style(Style::default().fg(theme().muted)), chunks[1]);
 }
 
-fn stock_to_row(stock: &Stock, usd_twd_rate: f64, show_portfolio: bool, hide_positions: bool) -> Row<'static> {
-    let (price, change_pct) = stock.price_data.as_ref()
-        .map(|d| (d.price, d.change_percent))
-        .unwrap_or((0.0, 0.0));
-
-    let arrow = if change_pct >= 0.0 { "↑" } else { "↓" };
-    let color = if change_pct >= 0.0 { Color::Green } else { Color::Red };
+fn render_reconcile_view(f: &mut Frame, state: &ReconcileState) {
+    let area = centered_rect(80, 80, f.area());
+    f.render_widget(Clear, area);
 
-    let mut cells = vec![
-        Cell::from(stock.display.clone()),
-        Cell::from(if show_portfolio { stock.name.chars().take(8).collect::<String>() } else { stock.name.chars().take(10).collect::<String>() }),
-        Cell::from(Line::from(format!("{:.2}", price)).alignment(Alignment::Right)).style(Style::default().fg(color)),
-        Cell::from(Line::from(format!("{}{:.1}%", arrow, change_pct)).alignment(Alignment::Right)).style(Style::default().fg(color)),
-    ];
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Min(5), Constraint::Length(4)])
+        .split(area);
 
-    // Only show position columns if not hidden
-    if !hide_positions {
-        let is_tw = stock.symbol.contains(".TW");
-        let (gain, gain_pct) = if stock.quantity > 0.0 && stock.cost_basis > 0.0 {
-            let current_value = stock.quantity * price;
-            let cost_value = stock.quantity * stock.cost_basis;
-            let mut gain = current_value - cost_value;
-            if !is_tw {
-                gain *= usd_twd_rate;
-            }
-            let pct = (gain / (cost_value * if is_tw { 1.0 } else { usd_twd_rate })) * 100.0;
-            (gain, pct)
+    let header = Row::new(["Portfolio", "Line", "Content", "Problem"].map(tr))
+        .style(Style::default().fg(theme().header).add_modifier(Modifier::BOLD));
+    let rows: Vec<Row> = state.issues.iter().enumerate().map(|(idx, issue)| {
+        let style = if idx == state.selected {
+            Style::default().bg(theme().highlight)
         } else {
-            (0.0, 0.0)
+            Style::default()
         };
-
-        let gain_color = if gain >= 0.0 { Color::Green } else { Color::Red };
-        let gain_str = format!("{:+.0}", gain);
-        let gain_pct_str = format!("{:+.1}%", gain_pct);
-
-        cells.push(Cell::from(Line::from(format!("{:.0}", stock.quantity)).alignment(Alignment::Right)));
-        cells.push(Cell::from(Line::from(format!("{:.1}", stock.cost_basis)).alignment(Alignment::Right)));
-        cells.push(Cell::from(Line::from(gain_str).alignment(Alignment::Right)).style(Style::default().fg(gain_color)));
-        cells.push(Cell::from(Line::from(gain_pct_str).alignment(Alignment::Right)).style(Style::default().fg(gain_color)));
-    }
-
-    if show_portfolio {
-        cells.push(Cell::from(stock.portfolio_name.clone()).style(Style::default().fg(Color::DarkGray)));
-    }
-
-    Row::new(cells)
-}
-
-fn render_summary(f: &mut Frame, app: &App, area: Rect) {
-    let title = if app.view_combined {
-        " Combined Summary (All Portfolios) "
-    } else {
-        " Summary "
+        Row::new(vec![
+            Cell::from(issue.portfolio.clone()),
+            Cell::from(Line::from((issue.line + 1).to_string()).alignment(Alignment::Right)),
+            Cell::from(fit_width(&issue.text, 34)),
+            Cell::from(issue.problem.clone()).style(Style::default().fg(theme().alert)),
+        ]).style(style)
+    }).collect();
+    let visible = chunks[0].height.saturating_sub(3) as usize;
+    let mut table_state = TableState::default().with_selected(Some(state.selected));
+    *table_state.offset_mut() = state.selected.saturating_sub(visible.saturating_sub(1));
+    let table = Table::new(rows, [
+        Constraint::Length(12),
+        Constraint::Length(5),
+        Constraint::Length(35),
+        Constraint::Min(20),
+    ])
+        .header(header)
+        .block(Block::default().borders(Borders::ALL).title(format!(" {} ({}) ", tr("Malformed portfolio lines"), state.issues.len()))
+            .border_style(Style::default().fg(theme().alert)));
+    f.render_stateful_widget(table, chunks[0], &mut table_state);
+
+    let lines = match &state.editing {
+        Some(text) => vec![
+            Line::from(vec![Span::raw("  "), Span::styled(format!("{}█", text), Style::default().fg(theme().header))]),
+            Line::from(format!("  {} SYMBOL|Display|Name|Quantity|Cost[|key=value...]", tr("Format:"))).style(Style::default().fg(theme().muted)),
+            Line::from(tr("  Enter=Save, Esc=Cancel")).style(Style::default().fg(theme().muted)),
+        ],
+        None => vec![
+            Line::from(tr("  These lines would load with a quantity or cost of 0, or not at all.")).style(Style::default().fg(theme().muted)),
+            Line::from(format!("  {} {}", tr("c fills in:"), complete_line(&state.issues[state.selected].text))).style(Style::default().fg(theme().muted)),
+            Line::from(tr("  ↑↓=Select, e=Edit line, c=Fill missing fields, d=Delete line, Esc=Leave as is")).style(Style::default().fg(theme().muted)),
+        ],
     };
+    f.render_widget(Paragraph::new(lines), chunks[1]);
+}
 
-    let time_str = Local::now().format("%H:%M:%S").to_string();
+fn render_equity_view(f: &mut Frame, app: &App, state: &EquityState) {
+    let area = centered_rect(80, 80, f.area());
+    f.render_widget(Clear, area);
 
-    // Status indicator: refreshing, live mode countdown, or nothing
-    let status_indicator = if app.is_fetching {
-        "  |  Refreshing...".to_string()
-    } else if app.live_mode {
-        let elapsed = app.last_live_refresh.elapsed().as_secs();
-        let remaining = LIVE_REFRESH_INTERVAL_SECS.saturating_sub(elapsed);
-        format!("  |  LIVE ({}s)", remaining)
+    let key = &state.keys[state.selected.min(state.keys.len() - 1)];
+    let name = if key == "*" { tr("All portfolios") } else { key.as_str() };
+    let title = if state.keys.len() > 1 {
+        format!(" {} {} ({}/{}) ", tr("Equity Curve:"), name, state.selected + 1, state.keys.len())
     } else {
-        String::new()
+        format!(" {} {} ", tr("Equity Curve:"), name)
     };
-
-    let status_color = if app.is_fetching { Color::Yellow } else { Color::Green };
-
-    let text = if app.hide_positions {
-        // Show minimal info when positions are hidden
-        vec![
-            Line::from(vec![
-                Span::styled(format!("Updated: {}  |  USD/TWD: {:.2}", time_str, app.usd_twd_rate), Style::default().fg(Color::DarkGray)),
-                Span::styled(status_indicator.clone(), Style::default().fg(status_color).add_modifier(Modifier::BOLD)),
-            ]),
-            Line::from(""),
-            Line::from(vec![
-                Span::styled("  Positions hidden (press H to show)", Style::default().fg(Color::Yellow)),
-            ]),
-        ]
-    } else {
-        let (total_cost, total_value, total_gain, total_gain_percent, stock_count, holdings) = app.calculate_summary();
-        let gain_color = if total_gain >= 0.0 { Color::Green } else { Color::Red };
-
-        vec![
-            Line::from(vec![
-                Span::styled(format!("Updated: {}  |  USD/TWD: {:.2}", time_str, app.usd_twd_rate), Style::default().fg(Color::DarkGray)),
-                Span::styled(status_indicator, Style::default().fg(status_color).add_modifier(Modifier::BOLD)),
-            ]),
-            Line::from(""),
-            Line::from(format!("  Total Cost:   {:>15.2} TWD", total_cost)),
-            Line::from(format!("  Total Value:  {:>15.2} TWD", total_value)),
-            Line::from(vec![
-                Span::raw("  Total Gain:   "),
-                Span::styled(format!("{:>15.2} TWD ({:+.2}%)", total_gain, total_gain_percent), Style::default().fg(gain_color)),
-            ]),
-            Line::from(format!("  Stocks: {}  |  Holdings: {}", stock_count, holdings)),
-        ]
+    let block = Block::default().borders(Borders::ALL).title(title).border_style(Style::default().fg(theme().border));
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Length(2), // Totals
+            Constraint::Min(6),    // Chart
+            Constraint::Length(1), // Footer
+        ])
+        .margin(1)
+        .split(area);
+    f.render_widget(block, area);
+    let footer = tr(if state.keys.len() > 1 { "  ←/→=Portfolio, Esc=Close" } else { "  Esc=Close" });
+    f.render_widget(Paragraph::new(footer).style(Style::default().fg(theme().muted)), chunks[2]);
+
+    let parse = |date: &String| chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d").ok();
+    let values: Vec<(chrono::NaiveDate, f64)> = app.value_history.get(key).into_iter().flatten()
+        .filter_map(|(date, value)| Some((parse(date)?, *value)))
+        .collect();
+    if values.len() < 2 {
+        let text = tr("  Not enough history yet. The total value is recorded once a day after a completed\n  refresh, so the curve appears from the second day.");
+        f.render_widget(Paragraph::new(text), chunks[0]);
+        return;
+    }
+    let first = values[0].0;
+    let day = |date: chrono::NaiveDate| (date - first).num_days() as f64;
+    let value_points: Vec<(f64, f64)> = values.iter().map(|&(date, value)| (day(date), value)).collect();
+    // Days recorded before cost was tracked have no cost point
+    let cost_points: Vec<(f64, f64)> = app.cost_history.get(key).into_iter().flatten()
+        .filter_map(|(date, cost)| Some((day(parse(date)?), *cost)))
+        .filter(|(x, _)| *x >= 0.0)
+        .collect();
+
+    let (start, (last_date, latest)) = (values[0].1, values[values.len() - 1]);
+    let change = latest - start;
+    let change_pct = if start > 0.0 { change / start * 100.0 } else { 0.0 };
+    let mut peak = f64::MIN;
+    let drawdown = values.iter().fold(0.0f64, |worst, &(_, value)| {
+        peak = peak.max(value);
+        if peak > 0.0 { worst.min((value / peak - 1.0) * 100.0) } else { worst }
+    });
+    let colored = |amount: f64| Style::default().fg(if amount >= 0.0 { theme().gain } else { theme().loss });
+    let mut totals = vec![Span::raw(format!("  {} {}: ", tr("Since"), first.format("%Y-%m-%d")))];
+    if app.hide_positions {
+        totals.push(Span::styled(format!("{:+.2}%", change_pct), colored(change)));
+    } else {
+        totals.push(Span::raw(format!("{:.0} → {:.0} TWD ", start, latest)));
+        totals.push(Span::styled(format!("{:+.0} ({:+.2}%)", change, change_pct), colored(change)));
+    }
+    totals.push(Span::raw(format!("   {} {:.2}%", tr("Max drawdown"), drawdown)));
+    let mut lines = vec![Line::from(totals)];
+    if let Some(&(_, cost)) = cost_points.last().filter(|(x, _)| *x == day(last_date)) {
+        let gain = latest - cost;
+        let gain_pct = if cost > 0.0 { gain / cost * 100.0 } else { 0.0 };
+        lines.push(Line::from(if app.hide_positions {
+            vec![Span::raw(format!("  {} ", tr("Gain over cost:"))), Span::styled(format!("{:+.2}%", gain_pct), colored(gain))]
+        } else {
+            vec![
+                Span::raw(format!("  {} {:.0} TWD, {} ", tr("Cost"), cost, tr("gain"))),
+                Span::styled(format!("{:+.0} ({:+.2}%)", gain, gain_pct), colored(gain)),
+            ]
+        }));
+    }
+    f.render_widget(Paragraph::new(lines), chunks[0]);
+
+    let (low, high) = value_points.iter().chain(&cost_points)
+        .fold((f64::MAX, f64::MIN), |(lo, hi), &(_, v)| (lo.min(v), hi.max(v)));
+    let pad = ((high - low) * 0.1).max(1.0);
+    let datasets = vec![
+        Dataset::default()
+            .name(tr("Value"))
+            .marker(symbols::Marker::Braille)
+            .graph_type(GraphType::Line)
+            .style(Style::default().fg(theme().border))
+            .data(&value_points),
+        Dataset::default()
+            .name(tr("Cost"))
+            .marker(symbols::Marker::Braille)
+            .graph_type(GraphType::Line)
+            .style(Style::default().fg(theme().dim))
+            .data(&cost_points),
+    ];
+    let y_labels = if app.hide_positions {
+        vec![Span::raw(""), Span::raw("")]
+    } else {
+        vec![Span::raw(format!("{:.0}", low - pad)), Span::raw(format!("{:.0}", high + pad))]
     };
-
-    let paragraph = Paragraph::new(text)
-        .block(Block::default().borders(Borders::ALL).title(title)
-            .title_style(if app.view_combined { Style::default().fg(Color::Magenta).bold() } else { Style::default() }));
-
-    f.render_widget(paragraph, area);
+    let chart = Chart::new(datasets)
+        .block(Block::default().borders(Borders::ALL).title(tr(" Daily value (TWD) ")))
+        .x_axis(
+            Axis::default()
+                .style(Style::default().fg(theme().dim))
+                .bounds([0.0, day(last_date).max(1.0)])
+                .labels(vec![Span::raw(first.format("%Y-%m-%d").to_string()), Span::raw(last_date.format("%Y-%m-%d").to_string())]),
+        )
+        .y_axis(
+            Axis::default()
+                .style(Style::default().fg(theme().dim))
+                .bounds([low - pad, high + pad])
+                .labels(y_labels),
+        );
+    f.render_widget(chart, chunks[1]);
 }
 
-fn render_footer(f: &mut Frame, app: &mut App, area: Rect) {
-    let hide_key = if app.hide_positions { "H=Show" } else { "H=Hide" };
-    let live_key = if app.live_mode { "L=Live:ON" } else { "L=Live" };
-    let title_key = if app.show_gain_amount { "T=$" } else { "T=%" };
-
-    let base_keys = format!(" 0-9=Portfolio | ↑↓jk=Nav | Enter=Detail | Sort:pcygG | a=Add e=Edit d=Del | {} {} | ", hide_key, title_key);
+fn render_changes_view(f: &mut Frame, state: &ChangesState, time: &TimeConfig) {
+    let area = centered_rect(80, 80, f.area());
+    f.render_widget(Clear, area);
 
-    // Calculate button positions for click detection
-    let base_len = base_keys.len() as u16;
-    let live_len = live_key.len() as u16;
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Length(6), Constraint::Min(5), Constraint::Length(2)])
+        .split(area);
+    let colored = |amount: f64| Style::default().fg(if amount >= 0.0 { theme().gain } else { theme().loss });
+    let list = |holdings: &[SnapshotHolding]| -> String {
+        if holdings.is_empty() {
+            return tr("none").to_string();
+        }
+        holdings.iter().map(|h| format!("{} ({})", h.display, format_quantity(h.quantity))).collect::<Vec<_>>().join(", ")
+    };
 
-    // Hide button position (find "H=Show" or "H=Hide" in base_keys)
-    if let Some(hide_pos) = base_keys.find(hide_key) {
-        let hide_rect = Rect::new(area.x + hide_pos as u16, area.y, hide_key.len() as u16, 1);
-        app.clickable_regions.footer_buttons.push((hide_rect, "hide"));
-    }
+    let Some(since) = state.since else {
+        let text = tr("  No earlier snapshot yet. One is kept after each completed refresh, so the
+  comparison is available from tomorrow.");
+        f.render_widget(Paragraph::new(text).block(Block::default().borders(Borders::ALL).title(format!(" {} ", tr("Changes")))
+            .border_style(Style::default().fg(theme().border))), chunks[0]);
+        f.render_widget(Paragraph::new(tr("  Esc=Close")).style(Style::default().fg(theme().muted)), chunks[2]);
+        return;
+    };
 
-    // Live button position (after base_keys)
-    let live_rect = Rect::new(area.x + base_len, area.y, live_len, 1);
-    app.clickable_regions.footer_buttons.push((live_rect, "live"));
+    let change = state.current_total - state.previous_total;
+    let change_pct = if state.previous_total > 0.0 { change / state.previous_total * 100.0 } else { 0.0 };
+    let overview = vec![
+        Line::from(vec![
+            Span::raw(format!("  {} {:.0} → {:.0} TWD  ", tr("Value:"), state.previous_total, state.current_total)),
+            Span::styled(format!("{:+.0} ({:+.2}%)", change, change_pct), colored(change).add_modifier(Modifier::BOLD)),
+        ]),
+        Line::from(vec![
+            Span::raw(format!("  {} (USD/TWD {:.3} → {:.3}): ", tr("FX impact"), state.previous_rate, state.current_rate)),
+            Span::styled(format!("{:+.0} TWD", state.fx_impact), colored(state.fx_impact)),
+        ]),
+        Line::from(vec![Span::styled(format!("  {} ", tr("New:")), Style::default().fg(theme().gain)), Span::raw(list(&state.new))]),
+        Line::from(vec![Span::styled(format!("  {} ", tr("Closed:")), Style::default().fg(theme().loss)), Span::raw(list(&state.closed))]),
+    ];
+    f.render_widget(Paragraph::new(overview).block(Block::default().borders(Borders::ALL)
+        .title(format!(" {} {} ", tr("Changes since"), time.date(since))).border_style(Style::default().fg(theme().border))), chunks[0]);
+
+    let header = Row::new(["Symbol", "Qty", "Price then", "Price now", "Value then", "Value now", "Change", "%"].map(tr))
+        .style(Style::default().fg(theme().header).add_modifier(Modifier::BOLD));
+    let right = |text: String| Cell::from(Line::from(text).alignment(Alignment::Right));
+    let rows: Vec<Row> = state.changed.iter().skip(state.scroll).map(|(then, now)| {
+        let delta = now.value_twd - then.value_twd;
+        let pct = if then.value_twd > 0.0 { delta / then.value_twd * 100.0 } else { 0.0 };
+        let quantity = if then.quantity == now.quantity {
+            format_quantity(now.quantity)
+        } else {
+            format!("{}→{}", format_quantity(then.quantity), format_quantity(now.quantity))
+        };
+        Row::new(vec![
+            Cell::from(now.display.clone()),
+            right(quantity),
+            right(format!("{:.2}", then.price)),
+            right(format!("{:.2}", now.price)),
+            right(format!("{:.0}", then.value_twd)),
+            right(format!("{:.0}", now.value_twd)),
+            right(format!("{:+.0}", delta)).style(colored(delta)),
+            right(format!("{:+.2}%", pct)).style(colored(delta)),
+        ])
+    }).collect();
+    let table = Table::new(rows, [
+        Constraint::Length(10),
+        Constraint::Length(12),
+        Constraint::Length(11),
+        Constraint::Length(11),
+        Constraint::Length(12),
+        Constraint::Length(12),
+        Constraint::Length(11),
+        Constraint::Length(9),
+    ])
+        .header(header)
+        .block(Block::default().borders(Borders::ALL).title(format!(" {} ", tr("Biggest value changes (TWD)"))).border_style(Style::default().fg(theme().border)));
+    f.render_widget(table, chunks[1]);
+    f.render_widget(Paragraph::new(tr("  ↑↓=Scroll, Esc=Close")).style(Style::default().fg(theme().muted)), chunks[2]);
+}
 
-    // Refresh button position
-    let refresh_start = base_len + live_len + 3; // " | " = 3 chars
-    let refresh_rect = Rect::new(area.x + refresh_start, area.y, 9, 1); // "r=Refresh" = 9
-    app.clickable_regions.footer_buttons.push((refresh_rect, "refresh"));
+fn render_news_view(f: &mut Frame, state: &NewsState, config: &Config) {
+    let area = centered_rect(85, 85, f.area());
+    f.render_widget(Clear, area);
 
-    // Quit button position
-    let quit_start = refresh_start + 9 + 3; // "r=Refresh" + " | "
-    let quit_rect = Rect::new(area.x + quit_start, area.y, 6, 1); // "q=Quit" = 6
-    app.clickable_regions.footer_buttons.push((quit_rect, "quit"));
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Min(5), Constraint::Length(2)])
+        .split(area);
 
-    let spans = if app.live_mode {
-        vec![
-            Span::styled(base_keys, Style::default().fg(Color::Yellow)),
-            Span::styled(live_key, Style::default().fg(Color::Green).add_modifier(Modifier::BOLD)),
-            Span::styled(" | r=Refresh | q=Quit ", Style::default().fg(Color::Yellow)),
-        ]
+    let visible = state.visible();
+    let filter = state.filters.get(state.filter).map(|(label, _)| label.as_str()).unwrap_or(tr("All"));
+    let title_width = chunks[0].width.saturating_sub(32) as usize;
+    let list_height = chunks[0].height.saturating_sub(3) as usize;
+    let offset = state.selected.saturating_sub(list_height.saturating_sub(1));
+    let date_format = format!("{} %H:%M", config.time.chart_date_format);
+
+    let rows: Vec<Row> = visible.iter().enumerate().skip(offset).map(|(i, item)| {
+        let when = item.published
+            .map(|d| d.with_timezone(&Local).format(&date_format).to_string())
+            .unwrap_or_default();
+        let style = if i == state.selected { Style::default().bg(theme().highlight) } else { Style::default() };
+        Row::new(vec![
+            Cell::from(when).style(Style::default().fg(theme().dim)),
+            Cell::from(fit_width(&item.source, 12)).style(Style::default().fg(theme().border)),
+            Cell::from(fit_width(&item.title, title_width)),
+        ]).style(style)
+    }).collect();
+
+    let header = Row::new(["Time", "Source", "Headline"].map(tr)).style(Style::default().fg(theme().header).add_modifier(Modifier::BOLD));
+    let table = Table::new(rows, [Constraint::Length(12), Constraint::Length(13), Constraint::Min(10)])
+        .header(header)
+        .block(Block::default().borders(Borders::ALL)
+            .title(format!(" {} {} ({}) ", tr("News:"), filter, visible.len()))
+            .border_style(Style::default().fg(theme().border)));
+    f.render_widget(table, chunks[0]);
+
+    let first_line = if config.feeds.is_empty() {
+        tr("  No feeds configured: add [[feeds]] entries (name, url) to config.toml").to_string()
+    } else if let Some(item) = visible.get(state.selected) {
+        format!("  {}", item.link)
+    } else if state.pending > 0 {
+        format!("  {} ({})", tr("Loading feeds..."), state.pending)
+    } else if !state.errors.is_empty() {
+        format!("  {} {}", tr("Failed:"), state.errors.join("; "))
     } else {
-        vec![
-            Span::styled(base_keys, Style::default().fg(Color::Yellow)),
-            Span::styled(live_key, Style::default().fg(Color::Yellow)),
-            Span::styled(" | r=Refresh | q=Quit ", Style::default().fg(Color::Yellow)),
-        ]
+        tr("  No headlines match this filter").to_string()
     };
-
-    let paragraph = Paragraph::new(Line::from(spans));
-    f.render_widget(paragraph, area);
+    let note = vec![
+        Line::from(first_line),
+        Line::from(tr("  f=Filter (all/holdings/each holding), r=Reload, ↑↓=Select, Esc=Close")),
+    ];
+    f.render_widget(Paragraph::new(note).style(Style::default().fg(theme().muted)), chunks[1]);
 }
 
-fn render_add_dialog(f: &mut Frame, state: &AddStockState) {
-    let area = centered_rect(50, 50, f.area());
+fn render_dca_dialog(f: &mut Frame, state: &DcaState) {
+    let area = centered_rect(50, 40, f.area());
     f.render_widget(Clear, area);
 
-    let prompts = ["Symbol:", "Display name:", "Description:", "Quantity:", "Cost basis:"];
-    let values = [&state.symbol, &state.display, &state.name, &state.quantity, &state.cost_basis];
+    let drip = if state.drip { tr("yes").to_string() } else { tr("no").to_string() };
+    let prompts = ["Symbol:", "Monthly amount:", "Years:", "Reinvest dividends (y/n):"].map(tr);
+    let values = [&state.symbol, &state.amount, &state.years, &drip];
 
-    let mut lines: Vec<Line> = vec![Line::from(""), Line::from("  Taiwan stocks auto-detected (e.g., 2330 → 2330.TW)"), Line::from("")];
+    let mut lines: Vec<Line> = vec![
+        Line::from(""),
+        Line::from(tr("  Backtest buying a fixed amount on the first trading day of each month")),
+        Line::from(tr("  (amount is in the symbol's own currency)")),
+        Line::from(""),
+    ];
 
     for (i, (prompt, value)) in prompts.iter().zip(values.iter()).enumerate() {
         let style = if i == state.step {
-            Style::default().fg(Color::Yellow).bold()
-        } else if i < state.step {
-            Style::default().fg(Color::Green)
+            Style::default().fg(theme().header).bold()
         } else {
-            Style::default().fg(Color::DarkGray)
+            Style::default().fg(theme().muted)
         };
-
         let cursor = if i == state.step { "█" } else { "" };
         lines.push(Line::from(vec![
             Span::styled(format!("  {} ", prompt), style),
@@ -1963,223 +12463,247 @@ fn render_add_dialog(f: &mut Frame, state: &AddStockState) {
     }
 
     lines.push(Line::from(""));
-    lines.push(Line::from("  Press Enter to continue, Esc to cancel").style(Style::default().fg(Color::DarkGray)));
+    lines.push(Line::from(tr("  Tab=Switch, Enter=Next/Run, Esc=Cancel")).style(Style::default().fg(theme().muted)));
 
     let paragraph = Paragraph::new(lines)
-        .block(Block::default().borders(Borders::ALL).title(" Add Stock ").border_style(Style::default().fg(Color::Yellow)));
+        .block(Block::default().borders(Borders::ALL).title(format!(" {} ", tr("DCA Simulator"))).border_style(Style::default().fg(theme().header)));
 
     f.render_widget(paragraph, area);
 }
 
-fn render_edit_dialog(f: &mut Frame, state: &EditStockState) {
-    let area = centered_rect(40, 30, f.area());
+fn render_replay_setup_dialog(f: &mut Frame, date: &str) {
+    let area = centered_rect(40, 20, f.area());
     f.render_widget(Clear, area);
 
-    let (qty_style, cost_style) = if state.step == 0 {
-        (Style::default().fg(Color::Yellow), Style::default())
-    } else {
-        (Style::default(), Style::default().fg(Color::Yellow))
-    };
-
-    let qty_cursor = if state.step == 0 { "█" } else { "" };
-    let cost_cursor = if state.step == 1 { "█" } else { "" };
-
     let lines = vec![
         Line::from(""),
-        Line::from(format!("  Editing: {}", state.symbol)),
-        Line::from(""),
-        Line::from(vec![
-            Span::raw("  Quantity: "),
-            Span::styled(format!("{}{}", state.quantity, qty_cursor), qty_style),
-        ]),
+        Line::from(tr("  Replay the session on (YYYY-MM-DD):")),
         Line::from(""),
         Line::from(vec![
-            Span::raw("  Cost basis: "),
-            Span::styled(format!("{}{}", state.cost_basis, cost_cursor), cost_style),
+            Span::raw("  "),
+            Span::styled(format!("{}█", date), Style::default().fg(theme().header)),
         ]),
         Line::from(""),
-        Line::from("  Tab=Switch, Enter=Save, Esc=Cancel").style(Style::default().fg(Color::DarkGray)),
+        Line::from(tr("  Enter=Load, Esc=Cancel")).style(Style::default().fg(theme().muted)),
     ];
 
     let paragraph = Paragraph::new(lines)
-        .block(Block::default().borders(Borders::ALL).title(" Edit Holdings ").border_style(Style::default().fg(Color::Cyan)));
+        .block(Block::default().borders(Borders::ALL).title(format!(" {} ", tr("Intraday Replay"))).border_style(Style::default().fg(theme().alert)));
 
     f.render_widget(paragraph, area);
 }
 
-fn render_delete_dialog(f: &mut Frame, symbol: &str) {
-    let area = centered_rect(40, 20, f.area());
+fn render_replay_view(f: &mut Frame, state: &ReplayState, hide_positions: bool) {
+    let area = centered_rect(80, 80, f.area());
     f.render_widget(Clear, area);
 
-    let lines = vec![
-        Line::from(""),
-        Line::from(format!("  Delete {}?", symbol)),
-        Line::from(""),
-        Line::from("  Press Y to confirm, any key to cancel").style(Style::default().fg(Color::DarkGray)),
-    ];
-
-    let paragraph = Paragraph::new(lines)
-        .block(Block::default().borders(Borders::ALL).title(" Confirm Delete ").border_style(Style::default().fg(Color::Red)));
-
-    f.render_widget(paragraph, area);
-}
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Length(1),  // Clock and totals
+            Constraint::Min(6),     // Day gain chart
+            Constraint::Length(state.holdings.len().min(10) as u16 + 3), // Holdings
+            Constraint::Length(1),  // Footer
+        ])
+        .margin(1)
+        .split(area);
 
-fn render_new_portfolio_dialog(f: &mut Frame, name: &str) {
-    let area = centered_rect(40, 20, f.area());
-    f.render_widget(Clear, area);
+    let clock = |t: i64| chrono::DateTime::from_timestamp(t, 0)
+        .map(|d| d.with_timezone(&Local).format("%H:%M").to_string())
+        .unwrap_or_default();
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .title(format!(" {} {} ", tr("Replay"), state.date))
+        .border_style(Style::default().fg(theme().border));
+    f.render_widget(block, area);
 
-    let lines = vec![
-        Line::from(""),
-        Line::from("  Enter portfolio name:"),
-        Line::from(""),
-        Line::from(vec![
-            Span::raw("  "),
-            Span::styled(format!("{}█", name), Style::default().fg(Color::Yellow)),
-        ]),
-        Line::from(""),
-        Line::from("  Enter=Create, Esc=Cancel").style(Style::default().fg(Color::DarkGray)),
+    let holdings = state.holdings_at(state.cursor);
+    let curve = state.gain_curve();
+    let gain = curve.last().map_or(0.0, |&(_, g)| g);
+    let gain_color = if gain >= 0.0 { theme().gain } else { theme().loss };
+    let speed = REPLAY_SPEEDS[state.speed] * INTRADAY_INTERVAL_SECS as u64;
+    let mut status = vec![
+        Span::styled(format!("  {} ", clock(state.times[state.cursor])), Style::default().fg(theme().header).add_modifier(Modifier::BOLD)),
+        Span::styled(format!("({}/{})  ", state.cursor + 1, state.times.len()), Style::default().fg(theme().dim)),
+        Span::raw(format!("{} {}×  ", if state.playing { "▶" } else { "⏸" }, speed)),
     ];
+    if !hide_positions {
+        status.push(Span::raw(format!("{} ", tr("Day"))));
+        status.push(Span::styled(format!("{:+.0} TWD", gain), Style::default().fg(gain_color)));
+    }
+    f.render_widget(Paragraph::new(Line::from(status)), chunks[0]);
+
+    // The x axis spans the whole session so the line grows towards the close
+    let last = state.times.len().saturating_sub(1).max(1) as f64;
+    let (low, high) = curve.iter().fold((0.0f64, 0.0f64), |(lo, hi), &(_, g)| (lo.min(g), hi.max(g)));
+    let pad = ((high - low) * 0.1).max(1.0);
+    let datasets = vec![
+        Dataset::default()
+            .marker(symbols::Marker::Braille)
+            .graph_type(GraphType::Line)
+            .style(Style::default().fg(gain_color))
+            .data(&curve),
+    ];
+    let y_labels = if hide_positions {
+        vec![Span::raw(""), Span::raw("")]
+    } else {
+        vec![Span::raw(format!("{:.0}", low - pad)), Span::raw(format!("{:.0}", high + pad))]
+    };
+    let chart = Chart::new(datasets)
+        .block(Block::default().borders(Borders::ALL).title(format!(" {} (TWD) ", tr("Day gain"))))
+        .x_axis(
+            Axis::default()
+                .style(Style::default().fg(theme().dim))
+                .bounds([0.0, last])
+                .labels(vec![
+                    Span::raw(clock(state.times[0])),
+                    Span::raw(clock(state.times[state.times.len() - 1])),
+                ]),
+        )
+        .y_axis(
+            Axis::default()
+                .style(Style::default().fg(theme().dim))
+                .bounds([low - pad, high + pad])
+                .labels(y_labels),
+        );
+    f.render_widget(chart, chunks[1]);
+
+    // Biggest movers at the cursor first
+    let mut holdings = holdings;
+    holdings.sort_by(|a, b| {
+        let change = |s: &Stock| s.price_data.as_ref().map_or(0.0, |d| d.change_percent.abs());
+        change(b).partial_cmp(&change(a)).unwrap_or(std::cmp::Ordering::Equal)
+    });
+    let mut columns = vec![tr("Symbol"), tr("Price"), tr("Change")];
+    if !hide_positions {
+        columns.push(tr("Day gain"));
+    }
+    let header = Row::new(columns).style(Style::default().fg(theme().header).add_modifier(Modifier::BOLD));
+    let rows: Vec<Row> = holdings.iter().map(|stock| {
+        let data = stock.price_data.as_ref();
+        let change = data.map_or(0.0, |d| d.change_percent);
+        let color = if change >= 0.0 { theme().gain } else { theme().loss };
+        let mut cells = vec![
+            Cell::from(stock.display.clone()),
+            Cell::from(Line::from(format!("{:.2}", data.map_or(0.0, |d| d.price))).alignment(Alignment::Right)),
+            Cell::from(Line::from(format!("{:+.2}%", change)).alignment(Alignment::Right)).style(Style::default().fg(color)),
+        ];
+        if !hide_positions {
+            let day = stock.day_gain_twd(state.usd_twd).unwrap_or(0.0);
+            cells.push(Cell::from(Line::from(format!("{:+.0}", day)).alignment(Alignment::Right)).style(Style::default().fg(color)));
+        }
+        Row::new(cells)
+    }).collect();
+    let table = Table::new(rows, [Constraint::Length(12), Constraint::Length(12), Constraint::Length(10), Constraint::Min(12)])
+        .header(header)
+        .block(Block::default().borders(Borders::ALL).title(format!(" {} ({}) ", tr("Holdings"), holdings.len())));
+    f.render_widget(table, chunks[2]);
 
-    let paragraph = Paragraph::new(lines)
-        .block(Block::default().borders(Borders::ALL).title(" New Portfolio ").border_style(Style::default().fg(Color::Magenta)));
-
-    f.render_widget(paragraph, area);
+    f.render_widget(
+        Paragraph::new(tr("  Space=Play/pause  ←→=Step  +/-=Speed  0=Restart  Esc=Close")).style(Style::default().fg(theme().muted)),
+        chunks[3],
+    );
 }
 
-fn render_detail_view(f: &mut Frame, app: &App, symbol: &str) {
+fn render_dca_report(f: &mut Frame, report: &DcaReport) {
     let area = centered_rect(80, 70, f.area());
     f.render_widget(Clear, area);
 
-    // Find the stock in all vectors
-    let stock = app.tw_stocks.iter()
-        .chain(app.us_stocks.iter())
-        .chain(app.combined_tw_stocks.iter())
-        .chain(app.combined_us_stocks.iter())
-        .find(|s| s.symbol == symbol);
-
-    let Some(stock) = stock else {
-        let paragraph = Paragraph::new("Stock not found")
-            .block(Block::default().borders(Borders::ALL).title(" Detail View "));
-        f.render_widget(paragraph, area);
-        return;
-    };
-
-    // Split area into sections
     let chunks = Layout::default()
         .direction(Direction::Vertical)
         .constraints([
-            Constraint::Length(6),  // Info header
-            Constraint::Min(10),    // Chart
-            Constraint::Length(2),  // Footer
+            Constraint::Length(7),  // Report
+            Constraint::Min(8),     // Chart
+            Constraint::Length(1),  // Footer
         ])
         .margin(1)
         .split(area);
 
-    // Render border
     let block = Block::default()
         .borders(Borders::ALL)
-        .title(format!(" {} - {} ", stock.display, stock.name))
-        .border_style(Style::default().fg(Color::Cyan));
+        .title(format!(" DCA: {} × {:.2}/{} {}{} ", report.symbol, report.monthly_amount, tr("month"), report.years, tr("y")))
+        .border_style(Style::default().fg(theme().border));
     f.render_widget(block, area);
 
-    // Info section
-    let (price, change_pct) = stock.price_data.as_ref()
-        .map(|d| (d.price, d.change_percent))
-        .unwrap_or((0.0, 0.0));
-
-    let price_color = if change_pct >= 0.0 { Color::Green } else { Color::Red };
-    let arrow = if change_pct >= 0.0 { "↑" } else { "↓" };
-
-    // Calculate 30-day high/low/avg from historical
-    let (high, low, avg, trend_str) = stock.historical.as_ref()
-        .map(|h| {
-            let closes = &h.closes;
-            let high = closes.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
-            let low = closes.iter().cloned().fold(f64::INFINITY, f64::min);
-            let avg = closes.iter().sum::<f64>() / closes.len() as f64;
-            let (trend, _) = App::calculate_trend(closes);
-            (high, low, avg, trend.to_string())
-        })
-        .unwrap_or((0.0, 0.0, 0.0, "·".to_string()));
-
-    let info_text = vec![
-        Line::from(vec![
-            Span::raw("  Current: "),
-            Span::styled(format!("{:.2}", price), Style::default().fg(price_color).bold()),
-            Span::raw("  "),
-            Span::styled(format!("{}{:.2}%", arrow, change_pct), Style::default().fg(price_color)),
-            Span::raw(format!("  |  30d Trend: {}", trend_str)),
-        ]),
-        Line::from(""),
+    let gain_color = if report.gain() >= 0.0 { theme().gain } else { theme().loss };
+    let lines = vec![
+        Line::from(format!(
+            "  {} {}  |  {} {:.4}  |  {} {:.2}",
+            tr("Purchases:"), report.purchases, tr("Shares:"), report.shares, tr("Avg cost:"), report.average_cost()
+        )),
+        Line::from(format!("  {:<10} {:>15.2}", tr("Invested:"), report.invested)),
+        Line::from(format!("  {:<10} {:>15.2}", tr("Value:"), report.final_value)),
+        Line::from(format!(
+            "  {:<10} {:>15.2} ({})",
+            tr("Dividends:"),
+            report.dividends,
+            if report.drip { tr("reinvested") } else { tr("held as cash") }
+        )),
         Line::from(vec![
-            Span::styled(format!("  30-Day High: {:.2}", high), Style::default().fg(Color::Green)),
-            Span::raw("  |  "),
-            Span::styled(format!("Low: {:.2}", low), Style::default().fg(Color::Red)),
-            Span::raw("  |  "),
-            Span::raw(format!("Avg: {:.2}", avg)),
+            Span::raw(format!("  {:<10} ", tr("Return:"))),
+            Span::styled(format!("{:>15.2} ({:+.2}%)", report.gain(), report.return_percent()), Style::default().fg(gain_color)),
         ]),
+        Line::from(format!(
+            "  {:<10} {:>15.2} {}",
+            tr("Projected:"),
+            report.projected_value(),
+            tr("after {years}y more at {rate}%/yr")
+                .replace("{years}", &report.years.to_string())
+                .replace("{rate}", &format!("{:.1}", report.expected_return))
+        )).style(Style::default().fg(theme().dim)),
     ];
-    let info_para = Paragraph::new(info_text);
-    f.render_widget(info_para, chunks[0]);
-
-    // Chart section
-    if let Some(historical) = &stock.historical {
-        let closes = &historical.closes;
-        if !closes.is_empty() {
-            // Create chart data points: (x, y) where x is day index
-            let data: Vec<(f64, f64)> = closes.iter()
-                .enumerate()
-                .map(|(i, &p)| (i as f64, p))
-                .collect();
+    f.render_widget(Paragraph::new(lines), chunks[0]);
 
-            let min_y = closes.iter().cloned().fold(f64::INFINITY, f64::min) * 0.98;
-            let max_y = closes.iter().cloned().fold(f64::NEG_INFINITY, f64::max) * 1.02;
-            let max_x = closes.len() as f64;
+    let invested: Vec<(f64, f64)> = report.curve.iter().enumerate().map(|(i, &(_, inv, _))| (i as f64, inv)).collect();
+    let value: Vec<(f64, f64)> = report.curve.iter().enumerate().map(|(i, &(_, _, val))| (i as f64, val)).collect();
+    let max_x = report.curve.len().max(1) as f64;
+    let max_y = report.curve.iter().map(|&(_, inv, val)| inv.max(val)).fold(0.0, f64::max) * 1.05;
 
-            let datasets = vec![
-                Dataset::default()
-                    .name("Price")
-                    .marker(symbols::Marker::Braille)
-                    .graph_type(GraphType::Line)
-                    .style(Style::default().fg(Color::Cyan))
-                    .data(&data),
-            ];
+    let date_label = |t: Option<&(i64, f64, f64)>| {
+        t.and_then(|&(t, _, _)| chrono::DateTime::from_timestamp(t, 0))
+            .map(|d| d.with_timezone(&Local).format("%Y-%m").to_string())
+            .unwrap_or_default()
+    };
 
-            let chart = Chart::new(datasets)
-                .block(Block::default().borders(Borders::ALL).title(" 30-Day Price History "))
-                .x_axis(
-                    Axis::default()
-                        .title("Days")
-                        .style(Style::default().fg(Color::Gray))
-                        .bounds([0.0, max_x])
-                        .labels(vec![
-                            Span::raw("30d ago"),
-                            Span::raw("Today"),
-                        ]),
-                )
-                .y_axis(
-                    Axis::default()
-                        .title("Price")
-                        .style(Style::default().fg(Color::Gray))
-                        .bounds([min_y, max_y])
-                        .labels(vec![
-                            Span::raw(format!("{:.1}", min_y)),
-                            Span::raw(format!("{:.1}", max_y)),
-                        ]),
-                );
+    let datasets = vec![
+        Dataset::default()
+            .name(tr("Invested"))
+            .marker(symbols::Marker::Braille)
+            .graph_type(GraphType::Line)
+            .style(Style::default().fg(theme().muted))
+            .data(&invested),
+        Dataset::default()
+            .name(tr("Value"))
+            .marker(symbols::Marker::Braille)
+            .graph_type(GraphType::Line)
+            .style(Style::default().fg(gain_color))
+            .data(&value),
+    ];
 
-            f.render_widget(chart, chunks[1]);
-        }
-    } else {
-        let no_data = Paragraph::new("  No historical data available")
-            .block(Block::default().borders(Borders::ALL).title(" 30-Day Price History "))
-            .style(Style::default().fg(Color::DarkGray));
-        f.render_widget(no_data, chunks[1]);
-    }
+    let chart = Chart::new(datasets)
+        .block(Block::default().borders(Borders::ALL).title(format!(" {} ", tr("Invested vs Value"))))
+        .x_axis(
+            Axis::default()
+                .style(Style::default().fg(theme().dim))
+                .bounds([0.0, max_x])
+                .labels(vec![
+                    Span::raw(date_label(report.curve.first())),
+                    Span::raw(date_label(report.curve.last())),
+                ]),
+        )
+        .y_axis(
+            Axis::default()
+                .style(Style::default().fg(theme().dim))
+                .bounds([0.0, max_y])
+                .labels(vec![
+                    Span::raw("0"),
+                    Span::raw(format!("{:.0}", max_y)),
+                ]),
+        );
+    f.render_widget(chart, chunks[1]);
 
-    // Footer
-    let footer = Paragraph::new("  Press Esc or Enter to close")
-        .style(Style::default().fg(Color::DarkGray));
+    let footer = Paragraph::new(tr("  Press Esc or Enter to close"))
+        .style(Style::default().fg(theme().muted));
     f.render_widget(footer, chunks[2]);
 }
 
@@ -2202,3 +12726,364 @@ fn centered_rect(percent_x: u16, percent_y: u16, r: Rect) -> Rect {
         ])
         .split(popup_layout[1])[1]
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ratatui::backend::TestBackend;
+
+    /// Holdings in the synthetic portfolio the render benchmark draws
+    const BENCH_HOLDINGS: usize = 250;
+    /// Frames timed per measurement
+    const BENCH_FRAMES: u32 = 200;
+    /// Budget for a redraw with no table content changed
+    const BENCH_FRAME_BUDGET: Duration = Duration::from_millis(1);
+
+    /// Alternating TW and US holdings with manual prices, so nothing touches the network
+    fn bench_app() -> App {
+        let (mut app, _) = App::unloaded();
+        let lines: Vec<String> = (0..BENCH_HOLDINGS).map(|i| if i % 2 == 0 {
+            format!("{}.TW|{}|TW holding {}|{}|{}|mark={}", 1000 + i, 1000 + i, i, 1000 + i, 50 + i % 400, 55 + i % 380)
+        } else {
+            format!("BN{}|BN{}|US holding {}|{}|{}|mark={}", i, i, i, 10 + i % 90, 20 + i % 300, 25 + i % 280)
+        }).collect();
+        let mut stocks = App::parse_stocks(&lines.join("\n"));
+        for (i, stock) in stocks.iter_mut().enumerate() {
+            stock.apply_manual_price();
+            if let Some(data) = stock.price_data.as_mut() {
+                data.change_percent = (i % 13) as f64 - 6.0;
+            }
+            stock.portfolio_name = "Bench".to_string();
+        }
+        app.tw_stocks = stocks.iter().filter(|s| s.section() == 0).cloned().collect();
+        app.us_stocks = stocks.iter().filter(|s| s.section() == 1).cloned().collect();
+        app.stocks = stocks;
+        app.table_state_tw.select(Some(0));
+        app
+    }
+
+    /// Average time of a frame drawn to an offscreen 160x50 terminal
+    fn time_frames(app: &mut App, rebuild: bool, select: bool) -> Duration {
+        let mut terminal = Terminal::new(TestBackend::new(160, 50)).unwrap();
+        let started = Instant::now();
+        for _ in 0..BENCH_FRAMES {
+            if rebuild {
+                app.invalidate_rows();
+            }
+            if select {
+                app.next_row();
+            }
+            terminal.draw(|f| ui(f, app)).unwrap();
+        }
+        started.elapsed() / BENCH_FRAMES
+    }
+
+    #[test]
+    #[cfg_attr(debug_assertions, ignore = "the budget is for release builds: cargo test --release")]
+    fn cached_redraw_stays_within_budget() {
+        let mut app = bench_app();
+        let rebuilt = time_frames(&mut app, true, false);
+        let cached = time_frames(&mut app, false, false);
+        let moved = time_frames(&mut app, false, true);
+        println!("rebuild {:?}, cached {:?}, cursor move {:?}", rebuilt, cached, moved);
+        assert!(cached <= BENCH_FRAME_BUDGET, "cached frame took {:?}", cached);
+        assert!(moved <= BENCH_FRAME_BUDGET, "cursor move frame took {:?}", moved);
+    }
+
+    #[test]
+    fn zh_tw_locale_file_parses() {
+        assert_eq!(translation("Symbol"), Some("代號"));
+        assert_eq!(translation("  Esc=Close"), Some("  Esc=關閉"));
+    }
+
+    /// A portfolio file and its ledger in a fresh temp directory
+    fn portfolio_files(name: &str, conf: &str, ledger: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("stock-tui-test-{}-{}", std::process::id(), name));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("main.conf");
+        fs::write(&path, conf).unwrap();
+        fs::write(path.with_extension("ledger"), ledger).unwrap();
+        path
+    }
+
+    #[test]
+    fn ledger_replays_each_lot_separately() {
+        let path = portfolio_files(
+            "lots",
+            "AAPL|Apple|Apple Inc|110|100\nAAPL|Apple|Apple Inc|50|200\n",
+            "2026-01-02|BUY|AAPL|100|100|0\n2026-02-02|BUY|AAPL|10|100|0\n",
+        );
+        let stocks = App::load_stocks_from_file(&path).unwrap();
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+        assert_eq!(stocks.iter().map(|s| s.lot).collect::<Vec<_>>(), [0, 1]);
+        assert!(stocks[0].ledger && !stocks[1].ledger);
+        let merged = merge_lots(stocks);
+        assert_eq!(merged[0].quantity, 160.0);
+        assert!((merged[0].cost_basis - 131.25).abs() < 1e-9);
+    }
+
+    #[test]
+    fn ledger_lot_saves_without_numbers() {
+        let path = portfolio_files(
+            "placeholder",
+            "AAPL|Apple|Apple Inc|-|-\nAAPL|Apple|Apple Inc|5|300|lot=1\n",
+            "2026-01-02|BUY|AAPL|10|100|2\n2026-03-02|SELL|AAPL|4|150|0\n2026-03-02|BUY|AAPL|1|90|0|lot=1\n",
+        );
+        let stocks = App::load_stocks_from_file(&path).unwrap();
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+        // Buy fees join the cost; the sell leaves the average alone
+        assert_eq!((stocks[0].quantity, stocks[0].cost_basis), (6.0, 100.2));
+        assert_eq!(stocks[0].to_conf_line(), "AAPL|Apple|Apple Inc|-|-");
+        // The numbers stand until the lot's trades account for all of it
+        assert_eq!((stocks[1].quantity, stocks[1].cost_basis), (5.0, 300.0));
+        assert_eq!(stocks[1].to_conf_line(), "AAPL|Apple|Apple Inc|5|300|lot=1");
+    }
+
+    #[test]
+    fn date_format_with_time_specifiers_falls_back() {
+        let mut time = TimeConfig { date_format: "%Y-%m-%d %H:%M".to_string(), ..TimeConfig::default() };
+        assert_eq!(time.sanitize().as_deref(), Some("%Y-%m-%d %H:%M"));
+        assert_eq!(time.date(chrono::NaiveDate::from_ymd_opt(2026, 3, 9).unwrap()), "2026-03-09");
+
+        let mut time = TimeConfig { time_format: "%H:%M %z".to_string(), chart_date_format: "%b %d".to_string(), ..TimeConfig::default() };
+        assert_eq!(time.sanitize(), None);
+        assert_eq!(time.time_format, "%H:%M %z");
+    }
+
+    /// A holding priced at its `mark=`, parsed from a portfolio line
+    fn marked_stock(line: &str) -> Stock {
+        let mut stock = App::parse_stocks(line).remove(0);
+        stock.apply_manual_price();
+        stock
+    }
+
+    #[test]
+    fn expr_follows_precedence_and_associativity() {
+        let stock = marked_stock("AAPL|Apple|Apple Inc|10|100|mark=120");
+        let eval = |input: &str| Expr::parse(input).unwrap().eval(&stock, 32.0);
+        assert_eq!(eval("1 + 2 * 3 ^ 2"), Some(19.0));
+        assert_eq!(eval("(1 + 2) * 3"), Some(9.0));
+        assert_eq!(eval("10 - 4 - 3"), Some(3.0));
+        assert_eq!(eval("8 / 4 / 2"), Some(1.0));
+        assert_eq!(eval("2 ^ 3 ^ 2"), Some(512.0));
+        assert_eq!(eval("-price + max(qty, 3) * 2"), Some(-100.0));
+        assert_eq!(eval("PRICE / Cost"), Some(1.2));
+    }
+
+    #[test]
+    fn expr_reports_errors() {
+        assert_eq!(Expr::parse("1 +").unwrap_err(), "unexpected end of expression");
+        assert_eq!(Expr::parse("(1 + 2").unwrap_err(), "expected ')'");
+        assert_eq!(Expr::parse("max(1, 2").unwrap_err(), "expected ')'");
+        assert_eq!(Expr::parse("1 2").unwrap_err(), "unexpected '2'");
+        assert_eq!(Expr::parse("price $ 2").unwrap_err(), "unexpected character '$'");
+        assert_eq!(Expr::parse("1..2").unwrap_err(), "bad number '1..2'");
+        assert_eq!(Expr::parse("* 2").unwrap_err(), "unexpected '*'");
+
+        // Unknown names, missing data and non-finite results evaluate to nothing
+        let stock = marked_stock("AAPL|Apple|Apple Inc|10|100|mark=120");
+        let eval = |input: &str| Expr::parse(input).unwrap().eval(&stock, 32.0);
+        assert_eq!(eval("bogus * 2"), None);
+        assert_eq!(eval("target"), None);
+        assert_eq!(eval("price / 0"), None);
+
+        // Yield on cost needs a cost: gifted or zero-cost shares have none
+        let mut free = marked_stock("KO|Coke|Coca-Cola|10|0|mark=60");
+        free.dividends_ttm = Some(2.0);
+        assert_eq!(Expr::parse("yoc").unwrap().eval(&free, 32.0), None);
+        free.cost_basis = 40.0;
+        assert_eq!(Expr::parse("yoc").unwrap().eval(&free, 32.0), Some(5.0));
+    }
+
+    #[test]
+    fn ledger_opening_lot_and_close_out() {
+        let trade = |line: &str| Transaction::parse(line).unwrap();
+        let ledger = [
+            trade("2025-06-02|BUY|AAPL|8|90|0"),
+            trade("2025-06-03|SELL|AAPL|8|95|0"),
+            trade("2026-01-05|BUY|AAPL|5|120|0"),
+            trade("2026-01-06|BUY|AAPL|1|50|0|lot=1"),
+        ];
+        let closed = [ClosedPosition::parse("~AAPL|Apple|Apple Inc|8|90|exit=95|closed=2025-06-03|realized=40").unwrap()];
+
+        // Only lot 0's trades since the close-out count
+        let trades = open_trades(&ledger, "AAPL", 0, &closed);
+        assert_eq!(trades.iter().map(|t| t.date.to_string()).collect::<Vec<_>>(), ["2025-06-03", "2026-01-05"]);
+        let trades = open_trades(&ledger, "AAPL", 0, &[]);
+        assert_eq!(net_shares(&trades), 5.0);
+
+        // 10 held at 110 with 5 of them bought at 120: the other 5 opened at 100
+        let stock = marked_stock("AAPL|Apple|Apple Inc|10|110|bought=2025-12-01");
+        let trades = open_trades(&ledger, "AAPL", 0, &closed);
+        let opening = opening_lot(&stock, &trades[1..]).unwrap();
+        assert_eq!((opening.shares, opening.price, opening.date.to_string()), (5.0, 100.0, "2025-12-01".to_string()));
+        let (quantity, cost) = ledger_position(&[&opening, trades[1]], (0.0, 0.0), 1.0);
+        assert_eq!((quantity, cost), (10.0, 110.0));
+
+        // A sell past the position empties it
+        let (quantity, cost) = ledger_position(&[trades[1], &trade("2026-02-01|SELL|AAPL|9|130|0")], (0.0, 0.0), 1.0);
+        assert_eq!((quantity, cost), (0.0, 0.0));
+    }
+
+    #[test]
+    fn tw_prices_off_tick() {
+        assert_eq!(off_tick("2330.TW", 1005.0), None);
+        assert_eq!(off_tick("2330.TW", 1003.0), Some(5.0));
+        assert_eq!(off_tick("2330.TW", 99.9), None);
+        assert_eq!(off_tick("2330.TW", 100.1), Some(0.5));
+        assert_eq!(off_tick("2330.TW", 9.99), None);
+        assert_eq!(off_tick("2330.TW", 10.01), Some(0.05));
+        assert_eq!(off_tick("6488.TWO", 480.5), None);
+        // ETFs tick at 0.05 from 50 up
+        assert_eq!(off_tick("0050.TW", 49.99), None);
+        assert_eq!(off_tick("0050.TW", 150.05), None);
+        assert_eq!(off_tick("0050.TW", 150.01), Some(0.05));
+        assert_eq!(off_tick("AAPL", 1.234), None);
+    }
+
+    #[test]
+    fn total_return_with_and_without_reinvesting() {
+        let history = HistoricalData {
+            timestamps: vec![100, 200, 300],
+            opens: vec![10.0; 3],
+            highs: vec![10.0; 3],
+            lows: vec![10.0; 3],
+            closes: vec![10.0, 10.0, 20.0],
+            volumes: vec![0; 3],
+            dividends: vec![(150, 1.0)],
+            last_fetched: Instant::now(),
+        };
+        // The dividend is paid in cash, or buys 0.1 shares at the next close (10)
+        assert!((history.total_return(0, false) - 110.0).abs() < 1e-9);
+        assert!((history.total_return(0, true) - 120.0).abs() < 1e-9);
+        // Dividends before the start don't count
+        assert!((history.total_return(1, true) - 100.0).abs() < 1e-9);
+        assert_eq!(history.total_return(5, true), 0.0);
+    }
+
+    #[test]
+    fn zone_offsets_follow_dst() {
+        let at = |s: &str| chrono::DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&chrono::Utc);
+        let hours = |zone: &str, s: &str| zone_offset(zone, at(s)).unwrap().1.local_minus_utc() / 3600;
+        // New York springs forward at 02:00 EST on the second Sunday of March and falls
+        // back at 02:00 EDT on the first Sunday of November
+        assert_eq!(hours("America/New_York", "2026-03-08T06:59:00Z"), -5);
+        assert_eq!(hours("America/New_York", "2026-03-08T07:00:00Z"), -4);
+        assert_eq!(hours("America/New_York", "2026-11-01T05:59:00Z"), -4);
+        assert_eq!(hours("America/New_York", "2026-11-01T06:00:00Z"), -5);
+        // Europe changes at 01:00 UTC on the last Sundays of March and October
+        assert_eq!(hours("Europe/London", "2026-03-29T00:59:00Z"), 0);
+        assert_eq!(hours("Europe/London", "2026-03-29T01:00:00Z"), 1);
+        assert_eq!(hours("Europe/Berlin", "2026-10-25T01:00:00Z"), 1);
+        assert_eq!(hours("Asia/Taipei", "2026-07-01T00:00:00Z"), 8);
+        assert_eq!(zone_offset("-03:30", at("2026-07-01T00:00:00Z")).unwrap().1.local_minus_utc(), -12600);
+        assert!(zone_offset("Mars/Olympus", at("2026-07-01T00:00:00Z")).is_none());
+    }
+
+    #[test]
+    fn missing_holiday_calendars_are_reported() {
+        let config = MarketConfig::default();
+        assert!(config.missing_calendars(2026).is_empty());
+        assert_eq!(config.missing_calendars(2027), ["tw_holidays"]);
+        assert_eq!(config.missing_calendars(2028), ["tw_holidays", "us_holidays"]);
+        let config = MarketConfig { tw_holidays: vec!["2027-02-05".to_string()], ..MarketConfig::default() };
+        assert!(config.missing_calendars(2027).is_empty());
+    }
+
+    #[test]
+    fn market_sessions_skip_holidays() {
+        let at = |s: &str| chrono::DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&chrono::Utc);
+        let config = MarketConfig::default();
+        // Lunar New Year: closed from 2026-02-16 through the weekend after
+        assert_eq!(
+            Market::Tw.session(at("2026-02-16T02:00:00Z"), &config),
+            MarketSession::Closed { opens: at("2026-02-23T01:00:00Z"), holiday: true }
+        );
+        assert_eq!(Market::Tw.session(at("2026-02-24T02:00:00Z"), &config), MarketSession::Open { closes: at("2026-02-24T05:30:00Z") });
+        let config = MarketConfig { tw_holidays: vec!["2026-02-24".to_string()], ..MarketConfig::default() };
+        assert!(matches!(Market::Tw.session(at("2026-02-24T02:00:00Z"), &config), MarketSession::Closed { holiday: true, .. }));
+        // NYSE opens 09:30 New York time across the DST change and closes early the day after Thanksgiving
+        let config = MarketConfig::default();
+        assert_eq!(
+            Market::Us.session(at("2026-03-06T22:00:00Z"), &config),
+            MarketSession::Closed { opens: at("2026-03-09T13:30:00Z"), holiday: false }
+        );
+        assert_eq!(Market::Us.session(at("2026-11-27T17:00:00Z"), &config), MarketSession::Open { closes: at("2026-11-27T18:00:00Z") });
+    }
+
+    #[test]
+    fn feeds_parse_rss_and_atom() {
+        let rss = r#"<rss><channel><title>Markets</title>
+            <item><title><![CDATA[TSMC <beats> estimates]]></title><link>https://example.com/a?x=1&amp;y=2</link>
+            <pubDate>Tue, 14 Jul 2026 08:30:00 +0800</pubDate></item>
+            <item><title>Fed &amp; rates</title><link>https://example.com/b</link><pubDate>not a date</pubDate></item>
+            <item><link>https://example.com/untitled</link></item>
+        </channel></rss>"#;
+        let items = parse_feed("Wire", rss);
+        assert_eq!(items.len(), 2);
+        assert_eq!((items[0].source.as_str(), items[0].title.as_str()), ("Wire", "TSMC <beats> estimates"));
+        assert_eq!(items[0].link, "https://example.com/a?x=1&y=2");
+        assert_eq!(items[0].published.unwrap().to_rfc3339(), "2026-07-14T08:30:00+08:00");
+        assert_eq!(items[1].title, "Fed & rates");
+        assert!(items[1].published.is_none());
+
+        let atom = r#"<feed><title>Blog</title>
+            <entry><title>Q3 results</title><link rel="alternate" href="https://example.com/q3?a=1&amp;b=2"/>
+            <updated>2026-10-01T09:00:00Z</updated></entry>
+        </feed>"#;
+        let items = parse_feed("Blog", atom);
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].link, "https://example.com/q3?a=1&b=2");
+        assert_eq!(items[0].published.unwrap().to_rfc3339(), "2026-10-01T09:00:00+00:00");
+        assert!(parse_feed("Empty", "<html></html>").is_empty());
+    }
+
+    #[test]
+    fn t86_rows_parse_by_code() {
+        let report = serde_json::json!({
+            "stat": "OK",
+            "data": [
+                ["2330  ", "台積電", "20,000", "5,000", "15,000", "0", "0", "0", "3,000", "1,000", "2,000", "-500", "0", "0", "0", "0", "0", "0", "16,500"],
+                ["0050", "元大台灣50", "1", "2", "-1", "0", "0", "0", "0", "0", "0", "--", "0", "0", "0", "0", "0", "0", "-1"],
+                ["short row"],
+            ],
+        });
+        let flows = InstitutionalFlow::parse_t86(&report).unwrap();
+        assert_eq!(flows.len(), 2);
+        let tsmc = flows["2330"];
+        assert_eq!((tsmc.foreign, tsmc.trust, tsmc.dealer, tsmc.total), (15000.0, 2000.0, -500.0, 16500.0));
+        // Cells that aren't numbers count as zero
+        assert_eq!((flows["0050"].dealer, flows["0050"].total), (0.0, -1.0));
+        // Holidays and sessions not published yet
+        assert!(InstitutionalFlow::parse_t86(&serde_json::json!({ "stat": "很抱歉，沒有符合條件的資料!" })).is_none());
+    }
+
+    #[test]
+    fn palette_keys_are_builtin() {
+        let (app, _) = App::unloaded();
+        for (label, command) in palette_commands(&app) {
+            if let PaletteCommand::Key(KeyCode::Char(key)) = command {
+                assert!(BUILTIN_KEYS.contains(key) || app.config.commands.iter().any(|c| c.key == key), "{}", label);
+            }
+        }
+    }
+
+    #[test]
+    fn hidden_positions_are_left_out_of_summary() {
+        let (mut app, _) = App::unloaded();
+        app.stocks = vec![marked_stock("AAPL|AAPL|Apple|12|150|mark=200")];
+        app.hide_positions = true;
+        let summary = app.handle_rpc("get_summary", &serde_json::Value::Null).unwrap();
+        assert_eq!(summary["positions_hidden"], true);
+        assert!(summary["summary"].is_null());
+        let holding = &summary["holdings"][0];
+        assert_eq!(holding["price"], 200.0);
+        for key in ["quantity", "cost_basis", "value_twd", "gain_twd", "day_gain_twd"] {
+            assert!(holding[key].is_null(), "{}", key);
+        }
+        let page = summary_html(&summary);
+        assert!(page.contains("Positions hidden") && !page.contains("Value"));
+        app.hide_positions = false;
+        assert_eq!(app.handle_rpc("get_summary", &serde_json::Value::Null).unwrap()["holdings"][0]["quantity"], 12.0);
+    }
+}